@@ -1,4 +1,8 @@
-use crate::utils::build_swarm;
+use crate::{
+    commands::storage::VerifiedStorageRead,
+    report::{optional_field, OutputFormat, Report},
+    utils::build_swarm,
+};
 use codec::Decode;
 use futures::FutureExt;
 use futures::StreamExt;
@@ -10,7 +14,7 @@ use jsonrpsee::{
 use libp2p::{
     identify::Info,
     identity::Keypair,
-    kad::{record::Key as KademliaKey, GetRecordOk, KademliaEvent, QueryId, QueryResult},
+    kad::{record::Key as KademliaKey, Event as KademliaEvent, GetRecordOk, QueryId, QueryResult},
     multiaddr,
     swarm::{DialError, SwarmEvent},
     Multiaddr, PeerId, Swarm,
@@ -18,10 +22,12 @@ use libp2p::{
 use multihash_codetable::{Code, MultihashDigest};
 use prost::Message;
 use rand::{seq::SliceRandom, thread_rng};
+use serde::Serialize;
 use std::collections::{HashMap, HashSet};
 use subp2p_explorer::{
     peer_behavior::PeerInfoEvent,
     transport::{TransportBuilder, MIB},
+    trie::storage_value_key,
     Behaviour, BehaviourEvent,
 };
 
@@ -79,12 +85,55 @@ async fn runtime_api_autorities(
         .strip_prefix("0x")
         .expect("Substrate API returned invalid hex");
 
-    let bytes = hex::decode(&raw)?;
+    let bytes = hex::decode(raw)?;
 
     let authorities: Vec<sr25519::PublicKey> = Decode::decode(&mut &bytes[..])?;
     Ok(authorities)
 }
 
+/// Read the current set of authorities from the storage of peers.
+///
+/// The keys of the authority discovery pallet are read at the best block of a peer
+/// and verified against the state root of its header.
+///
+/// This mirrors the `AuthorityDiscoveryApi_authorities` runtime API, which returns
+/// the deduplicated current and next authorities.
+async fn p2p_authorities(
+    swarm: &mut Swarm<Behaviour>,
+    timeout: std::time::Duration,
+) -> Result<Vec<sr25519::PublicKey>, Box<dyn std::error::Error>> {
+    let keys: Vec<_> = ["Keys", "NextKeys"]
+        .iter()
+        .map(|item| storage_value_key("AuthorityDiscovery", item))
+        .collect();
+    let mut read = VerifiedStorageRead::new(keys);
+
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+
+    loop {
+        let event = tokio::select! {
+            _ = &mut deadline => return Err("Timeout while reading the authorities from peers".into()),
+            event = swarm.select_next_some() => event,
+        };
+
+        let Some(storage) = read.on_event(swarm, &event) else {
+            continue;
+        };
+        if storage.values.iter().all(Option::is_none) {
+            return Err("The chain does not use the authority discovery pallet".into());
+        }
+
+        let mut authorities = Vec::new();
+        for value in storage.values.into_iter().flatten() {
+            authorities.extend(Vec::<sr25519::PublicKey>::decode(&mut &value[..])?);
+        }
+        authorities.sort();
+        authorities.dedup();
+        return Ok(authorities);
+    }
+}
+
 /// Hash the authority ID to obtain the kademlia key at which the record
 /// of the authority is stored on the p2p network.
 fn hash_authority_id(id: &[u8]) -> KademliaKey {
@@ -123,7 +172,7 @@ fn decode_dht_record(
     // Decode and verify the authority signature.
     let payload = schema::SignedAuthorityRecord::decode(value.as_slice())?;
     let auth_signature = sr25519::Signature::decode(&mut &payload.auth_signature[..])?;
-    if !sr25519::verify(&auth_signature, &payload.record, &authority_id) {
+    if !sr25519::verify(&auth_signature, &payload.record, authority_id) {
         return Err("Cannot verify DHT payload".into());
     }
 
@@ -148,11 +197,7 @@ fn decode_dht_record(
         .into());
     }
 
-    let peer_id = peer_ids
-        .iter()
-        .next()
-        .expect("At least one peerId; qed")
-        .clone();
+    let peer_id = *peer_ids.iter().next().expect("At least one peerId; qed");
 
     // Verify peer signature.
     let Some(peer_signature) = payload.peer_signature else {
@@ -162,7 +207,7 @@ fn decode_dht_record(
     if peer_id != public_key.to_peer_id() {
         return Err("PeerId does not match the public key".into());
     }
-    if !public_key.verify(&payload.record.as_slice(), &peer_signature.signature) {
+    if !public_key.verify(payload.record.as_slice(), &peer_signature.signature) {
         return Err("Peer signature verification failed".into());
     }
 
@@ -257,7 +302,7 @@ impl AuthorityDiscovery {
             self.records_keys.insert(key.clone(), authority);
 
             let id = self.swarm.behaviour_mut().discovery.get_record(key);
-            self.queries.insert(id, authority.clone());
+            self.queries.insert(id, authority);
         }
     }
 
@@ -292,7 +337,7 @@ impl AuthorityDiscovery {
         // Add more DHT queries.
         while self.queries.len() < MAX_QUERIES {
             if let Some(next) = self.authorities.get(self.query_index) {
-                self.query_dht_records(std::iter::once(next.clone()));
+                self.query_dht_records(std::iter::once(*next));
                 self.query_index += 1;
             } else {
                 if self.queries.is_empty() {
@@ -340,134 +385,126 @@ impl AuthorityDiscovery {
 
     /// Handle a swarm event from the p2p network.
     fn handle_swarm<T>(&mut self, event: SwarmEvent<BehaviourEvent, T>) {
-        match event {
-            // Discovery DHT record.
-            SwarmEvent::Behaviour(behavior_event) => {
-                match behavior_event {
-                    BehaviourEvent::Discovery(KademliaEvent::OutboundQueryProgressed {
-                        id,
-                        result: QueryResult::GetRecord(record),
-                        ..
-                    }) => {
-                        // Has received at least one answer for this and can advance the queries.
-                        self.queries.remove(&id);
-
-                        match record {
-                            Ok(GetRecordOk::FoundRecord(peer_record)) => {
-                                let key = peer_record.record.key;
-                                let value = peer_record.record.value;
-
-                                let Some(authority) = self.records_keys.get(&key) else {
-                                    return;
-                                };
-                                let authority = *authority;
-
-                                let (peer_id, addresses) =
-                                    match decode_dht_record(value, &authority) {
-                                        Ok((peer_id, addresses)) => (peer_id, addresses),
-                                        Err(e) => {
-                                            log::debug!(
-                                                " Decoding DHT failed for authority {:?}: {:?}",
-                                                authority,
-                                                e
-                                            );
-                                            self.dht_errors += 1;
-                                            return;
-                                        }
-                                    };
-
-                                self.authority_to_details
-                                    .entry(authority)
-                                    .and_modify(|entry| entry.extend(addresses.clone()))
-                                    .or_insert_with(|| addresses.iter().cloned().collect());
-
-                                self.peer_details
-                                    .entry(peer_id)
-                                    .and_modify(|entry| entry.addresses.extend(addresses.clone()))
-                                    .or_insert_with(|| PeerDetails {
-                                        authority_id: authority,
-                                        addresses: addresses.iter().cloned().collect(),
-                                    });
-
-                                log::debug!(
-                                    "{}/{} (err {}) authority: {:?} peer_id {:?} Addresses: {:?}",
-                                    self.authority_to_details.len(),
-                                    self.authorities.len(),
-                                    self.dht_errors,
-                                    authority,
-                                    peer_id,
-                                    addresses
-                                );
-
-                                let now = std::time::Instant::now();
-                                if now.duration_since(self.old_log)
-                                    > std::time::Duration::from_secs(10)
-                                {
-                                    self.old_log = now;
-                                    log::info!(
-                                        "... DHT records {}/{} (err {}) | Identified {}/{} | Active peer queries {} | authority={:?} peer_id={:?} addresses={:?}",
-                                        self.authority_to_details.len(),
-                                        self.authorities.len(),
-                                        self.dht_errors,
-
-                                        self.peer_details.keys().filter_map(|peer| self.peer_info.get(peer)).count(),
-                                        self.peer_details.keys().count(),
-
-                                        self.queries_discovery.len(),
-
-                                        authority,
-                                        peer_id,
-                                        addresses
-                                    );
-                                }
-
-                                self.remaining_authorities.remove(&authority);
-                                self.advance_dht_queries();
-                            }
-                            _ => (),
+        // Discovery DHT record.
+        let SwarmEvent::Behaviour(behavior_event) = event else {
+            return;
+        };
+
+        match behavior_event {
+            BehaviourEvent::Discovery(KademliaEvent::OutboundQueryProgressed {
+                id,
+                result: QueryResult::GetRecord(record),
+                ..
+            }) => {
+                // Has received at least one answer for this and can advance the queries.
+                self.queries.remove(&id);
+
+                if let Ok(GetRecordOk::FoundRecord(peer_record)) = record {
+                    let key = peer_record.record.key;
+                    let value = peer_record.record.value;
+
+                    let Some(authority) = self.records_keys.get(&key) else {
+                        return;
+                    };
+                    let authority = *authority;
+
+                    let (peer_id, addresses) = match decode_dht_record(value, &authority) {
+                        Ok((peer_id, addresses)) => (peer_id, addresses),
+                        Err(e) => {
+                            log::debug!(
+                                " Decoding DHT failed for authority {:?}: {:?}",
+                                authority,
+                                e
+                            );
+                            self.dht_errors += 1;
+                            return;
                         }
+                    };
+
+                    self.authority_to_details
+                        .entry(authority)
+                        .and_modify(|entry| entry.extend(addresses.clone()))
+                        .or_insert_with(|| addresses.iter().cloned().collect());
+
+                    self.peer_details
+                        .entry(peer_id)
+                        .and_modify(|entry| entry.addresses.extend(addresses.clone()))
+                        .or_insert_with(|| PeerDetails {
+                            authority_id: authority,
+                            addresses: addresses.iter().cloned().collect(),
+                        });
+
+                    log::debug!(
+                        "{}/{} (err {}) authority: {:?} peer_id {:?} Addresses: {:?}",
+                        self.authority_to_details.len(),
+                        self.authorities.len(),
+                        self.dht_errors,
+                        authority,
+                        peer_id,
+                        addresses
+                    );
+
+                    let now = std::time::Instant::now();
+                    if now.duration_since(self.old_log) > std::time::Duration::from_secs(10) {
+                        self.old_log = now;
+                        log::info!(
+                            "... DHT records {}/{} (err {}) | Identified {}/{} | Active peer queries {} | authority={:?} peer_id={:?} addresses={:?}",
+                            self.authority_to_details.len(),
+                            self.authorities.len(),
+                            self.dht_errors,
+                            self.peer_details
+                                .keys()
+                                .filter_map(|peer| self.peer_info.get(peer))
+                                .count(),
+                            self.peer_details.keys().count(),
+                            self.queries_discovery.len(),
+                            authority,
+                            peer_id,
+                            addresses
+                        );
                     }
 
-                    BehaviourEvent::Discovery(KademliaEvent::OutboundQueryProgressed {
-                        id,
-                        result: QueryResult::GetClosestPeers(_),
-                        ..
-                    }) => {
+                    self.remaining_authorities.remove(&authority);
+                    self.advance_dht_queries();
+                }
+            }
+
+            BehaviourEvent::Discovery(KademliaEvent::OutboundQueryProgressed {
+                id,
+                result: QueryResult::GetClosestPeers(_),
+                ..
+            }) => {
+                if self.finished_query {
+                    log::debug!(" Discovered closes peers of {:?}", id);
+                }
+
+                self.queries_discovery.remove(&id);
+                self.query_peer_info();
+            }
+
+            BehaviourEvent::PeerInfo(info_event) => {
+                match info_event {
+                    PeerInfoEvent::Identified { peer_id, info } => {
                         if self.finished_query {
-                            log::debug!(" Discovered closes peers of {:?}", id);
+                            let discovered = self
+                                .peer_details
+                                .keys()
+                                .filter_map(|peer| self.peer_info.get(peer))
+                                .count();
+
+                            log::debug!(
+                                " {}/{} Info event {:?}",
+                                discovered,
+                                self.authorities.len(),
+                                peer_id
+                            );
                         }
 
-                        self.queries_discovery.remove(&id);
-                        self.query_peer_info();
-                    }
-
-                    BehaviourEvent::PeerInfo(info_event) => {
-                        match info_event {
-                            PeerInfoEvent::Identified { peer_id, info } => {
-                                if self.finished_query {
-                                    let discovered = self
-                                        .peer_details
-                                        .keys()
-                                        .filter_map(|peer| self.peer_info.get(peer))
-                                        .count();
-
-                                    log::debug!(
-                                        " {}/{} Info event {:?}",
-                                        discovered,
-                                        self.authorities.len(),
-                                        peer_id
-                                    );
-                                }
-
-                                // Save the record.
-                                self.peer_info.insert(peer_id, info);
-                            }
-                        };
+                        // Save the record.
+                        self.peer_info.insert(peer_id, info);
                     }
-                    _ => (),
-                }
+                };
             }
-
             _ => (),
         }
     }
@@ -543,8 +580,12 @@ impl PeerInfo {
             let identify = libp2p::identify::Behaviour::new(identify_config);
 
             let local_peer_id = PeerId::from(local_key.public());
-            libp2p::swarm::SwarmBuilder::with_tokio_executor(transport, identify, local_peer_id)
-                .build()
+            Swarm::new(
+                transport,
+                identify,
+                local_peer_id,
+                libp2p::swarm::Config::with_tokio_executor(),
+            )
         };
 
         // These are the initial peers for which the queries are performed against.
@@ -561,12 +602,9 @@ impl PeerInfo {
             let event = self.swarm.select_next_some().await;
 
             match event {
-                SwarmEvent::Behaviour(behavior) => match behavior {
-                    libp2p::identify::Event::Received { info, .. } => {
-                        return Ok(info);
-                    }
-                    _ => (),
-                },
+                SwarmEvent::Behaviour(libp2p::identify::Event::Received { info, .. }) => {
+                    return Ok(info);
+                }
 
                 SwarmEvent::OutgoingConnectionError { error, .. } => return Err(error),
 
@@ -608,95 +646,247 @@ fn to_ss58(key: &sr25519::PublicKey, version: u16) -> String {
 }
 
 /// Entry function called from the CLI.
+#[allow(clippy::too_many_arguments)]
 pub async fn discover_authorities(
-    url: String,
+    url: Option<String>,
     genesis: String,
     bootnodes: Vec<String>,
+    fork_id: Option<String>,
     timeout: std::time::Duration,
-    address_format: String,
+    version: u16,
     raw_output: bool,
+    output: OutputFormat,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let format_registry =
-        ss58_registry::Ss58AddressFormatRegistry::try_from(address_format.as_str())
-            .map_err(|err| format!("Cannot parse the provided address format: {:?}", err))?;
-    let version: ss58_registry::Ss58AddressFormat = format_registry.into();
-    let version = version.prefix();
-    log::info!(
-        "Address format {:?} with version prefix {:?}",
-        format_registry,
-        version
-    );
-
-    let url = Url::parse(&url)?;
-
-    // Extract the authorities from the runtime API.
-    let authorities = runtime_api_autorities(url).await?;
+    log::info!("Address format with version prefix {:?}", version);
+
+    let (mut swarm, _) = build_swarm(genesis.clone(), bootnodes, fork_id)?;
+
+    // Extract the authorities from the runtime API, or from the storage of peers.
+    let authorities = match url {
+        Some(url) => runtime_api_autorities(Url::parse(&url)?).await?,
+        None => {
+            // Perform the kademlia bootstrap.
+            let local_peer_id = *swarm.local_peer_id();
+            let _query_id = swarm
+                .behaviour_mut()
+                .discovery
+                .get_closest_peers(local_peer_id);
+
+            p2p_authorities(&mut swarm, timeout).await?
+        }
+    };
+    log::info!("Found {} authorities", authorities.len());
 
     // Perform DHT queries to find the authorities on the network.
     // Then, record the addresses of the authorities and the responses
     // from the identify protocol.
-    let swarm = build_swarm(genesis.clone(), bootnodes)?;
     let mut authority_discovery = AuthorityDiscovery::new(swarm, authorities.clone(), timeout);
     authority_discovery.discover().await;
     log::info!("Finished discovery\n");
 
-    let mut reached_peers = 0;
+    let authorities: Vec<_> = authorities
+        .iter()
+        .map(|authority| {
+            let mut entry = AuthorityEntry {
+                authority: to_ss58(authority, version),
+                status: AuthorityStatus::NoDhtResponse,
+                peer_id: None,
+                addresses: Vec::new(),
+                agent_version: None,
+            };
+
+            let Some(details) = authority_discovery.authority_to_details.get(authority) else {
+                return entry;
+            };
+            entry.addresses = details.iter().map(|addr| addr.to_string()).collect();
+
+            let Some(addr) = details.iter().next() else {
+                entry.status = AuthorityStatus::NoAddresses;
+                return entry;
+            };
+
+            let peer_id = get_peer_id(addr).expect("All must have valid peerIDs");
+            entry.peer_id = Some(peer_id.to_string());
+
+            match authority_discovery.peer_info.get(&peer_id) {
+                Some(info) => {
+                    entry.status = AuthorityStatus::Reached;
+                    entry.agent_version = Some(info.agent_version.clone());
+                }
+                None => entry.status = AuthorityStatus::Unreachable,
+            }
+            entry
+        })
+        .collect();
+
+    let peers = if raw_output {
+        authority_discovery
+            .peer_info
+            .iter()
+            .map(|(peer_id, info)| PeerEntry {
+                peer_id: peer_id.to_string(),
+                agent_version: info.agent_version.clone(),
+                protocols: info
+                    .protocols
+                    .iter()
+                    .map(|proto| proto.to_string())
+                    .collect(),
+                listen_addrs: info
+                    .listen_addrs
+                    .iter()
+                    .map(|addr| addr.to_string())
+                    .collect(),
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
 
-    for authority in &authorities {
-        let Some(details) = authority_discovery.authority_to_details.get(authority) else {
-            println!(
-                "authority={:?} - No dht response",
-                to_ss58(authority, version),
-            );
-            continue;
-        };
+    let report = AuthorityReport {
+        reached_authorities: authorities
+            .iter()
+            .filter(|entry| entry.status == AuthorityStatus::Reached)
+            .count(),
+        total_authorities: authorities.len(),
+        discovered_peers: authority_discovery.peer_info.len(),
+        authorities,
+        peers,
+        raw_output,
+    };
+    report.print(output)
+}
 
-        let Some(addr) = details.iter().next() else {
-            println!(
-                "authority={:?} - No addresses found in DHT record",
-                to_ss58(authority, version),
-            );
-            continue;
-        };
+/// The outcome of the discovery of an authority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthorityStatus {
+    /// The authority responded to the identify protocol.
+    Reached,
+    /// The addresses of the authority are known, but it cannot be reached.
+    Unreachable,
+    /// The DHT record of the authority was not found.
+    NoDhtResponse,
+    /// The DHT record of the authority does not contain addresses.
+    NoAddresses,
+}
 
-        let peer_id = get_peer_id(addr).expect("All must have valid peerIDs");
-
-        let info = authority_discovery.peer_info.get(&peer_id).cloned();
-        if let Some(info) = info {
-            reached_peers += 1;
-
-            println!(
-                "authority={:?} peer_id={:?} addresses={:?} version={:?} ",
-                to_ss58(authority, version),
-                peer_id,
-                info.agent_version,
-                details,
-            );
-        } else {
-            println!(
-                "authority={:?} peer_id={:?} addresses={:?} - Cannot be reached",
-                to_ss58(authority, version),
-                peer_id,
-                details,
-            );
+impl AuthorityStatus {
+    /// Returns the name of the status.
+    fn name(&self) -> &'static str {
+        match self {
+            AuthorityStatus::Reached => "reached",
+            AuthorityStatus::Unreachable => "unreachable",
+            AuthorityStatus::NoDhtResponse => "no_dht_response",
+            AuthorityStatus::NoAddresses => "no_addresses",
         }
     }
+}
 
-    println!(
-        "\n\n  Discovered {}/{} authorities",
-        reached_peers,
-        authorities.len()
-    );
+/// The discovery details of an authority.
+#[derive(Debug, Serialize)]
+pub struct AuthorityEntry {
+    /// The SS58 address of the authority discovery key.
+    authority: String,
+    status: AuthorityStatus,
+    peer_id: Option<String>,
+    /// The addresses published in the DHT record.
+    addresses: Vec<String>,
+    /// The agent version reported by the identify protocol.
+    agent_version: Option<String>,
+}
 
-    println!(" Discovered peers {}", authority_discovery.peer_info.len());
+/// The identify details of a discovered peer.
+#[derive(Debug, Serialize)]
+pub struct PeerEntry {
+    peer_id: String,
+    agent_version: String,
+    protocols: Vec<String>,
+    listen_addrs: Vec<String>,
+}
 
-    if raw_output {
-        println!("\n Raw output of the discovered peers:");
+/// Report of the `authorities` command.
+#[derive(Debug, Serialize)]
+pub struct AuthorityReport {
+    /// The number of authorities that responded to the identify protocol.
+    reached_authorities: usize,
+    total_authorities: usize,
+    /// The number of peers that responded to the identify protocol.
+    discovered_peers: usize,
+    authorities: Vec<AuthorityEntry>,
+    /// The discovered peers, populated with `--raw-output`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    peers: Vec<PeerEntry>,
+    #[serde(skip)]
+    raw_output: bool,
+}
 
-        for (peer_id, info) in &authority_discovery.peer_info {
-            println!("peer_id={:?} info={:?}", peer_id, info);
-        }
+impl Report for AuthorityReport {
+    const CSV_HEADER: &'static [&'static str] = &[
+        "authority",
+        "status",
+        "peer_id",
+        "agent_version",
+        "addresses",
+    ];
+
+    fn csv_rows(&self) -> Vec<Vec<String>> {
+        self.authorities
+            .iter()
+            .map(|entry| {
+                vec![
+                    entry.authority.clone(),
+                    entry.status.name().to_string(),
+                    optional_field(&entry.peer_id),
+                    optional_field(&entry.agent_version),
+                    entry.addresses.join(" "),
+                ]
+            })
+            .collect()
     }
 
-    Ok(())
+    fn print_table(&self) {
+        for entry in &self.authorities {
+            let peer_id = entry.peer_id.as_deref().unwrap_or_default();
+            match entry.status {
+                AuthorityStatus::NoDhtResponse => {
+                    println!("authority={} - No dht response", entry.authority)
+                }
+                AuthorityStatus::NoAddresses => {
+                    println!(
+                        "authority={} - No addresses found in DHT record",
+                        entry.authority
+                    )
+                }
+                AuthorityStatus::Reached => println!(
+                    "authority={} peer_id={} addresses={:?} version={}",
+                    entry.authority,
+                    peer_id,
+                    entry.addresses,
+                    entry.agent_version.as_deref().unwrap_or_default(),
+                ),
+                AuthorityStatus::Unreachable => println!(
+                    "authority={} peer_id={} addresses={:?} - Cannot be reached",
+                    entry.authority, peer_id, entry.addresses,
+                ),
+            }
+        }
+
+        println!(
+            "\n\n  Discovered {}/{} authorities",
+            self.reached_authorities, self.total_authorities
+        );
+
+        println!(" Discovered peers {}", self.discovered_peers);
+
+        if self.raw_output {
+            println!("\n Raw output of the discovered peers:");
+
+            for peer in &self.peers {
+                println!(
+                    "peer_id={} version={} protocols={:?} listen_addrs={:?}",
+                    peer.peer_id, peer.agent_version, peer.protocols, peer.listen_addrs
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,132 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+use crate::utils::build_swarm;
+use futures::StreamExt;
+use libp2p::{swarm::SwarmEvent, PeerId};
+use std::{collections::VecDeque, error::Error, time::Duration};
+use subp2p_explorer::{
+    notifications::behavior::NotificationsToSwarm,
+    requests::block::{BlockData, BlockRequest, BlockRequestsEvent},
+    BehaviourEvent,
+};
+
+/// Fetch blocks from peers on the block request protocol.
+///
+/// The request is sent to the first peer that opens the block announces protocol,
+/// or to the provided peer. Failed requests are retried with the next peer.
+pub async fn fetch_blocks(
+    genesis: String,
+    bootnodes: Vec<String>,
+    fork_id: Option<String>,
+    peer: Option<PeerId>,
+    request: BlockRequest,
+    raw_output: bool,
+    timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let (mut swarm, protocols) = build_swarm(genesis, bootnodes, fork_id)?;
+
+    // Perform the kademlia bootstrap.
+    let local_peer_id = *swarm.local_peer_id();
+    let _query_id = swarm
+        .behaviour_mut()
+        .discovery
+        .get_closest_peers(local_peer_id);
+
+    // Peers that accepted our block announces substream.
+    let mut candidates = VecDeque::new();
+    let mut in_flight = None;
+
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+
+    loop {
+        let event = tokio::select! {
+            _ = &mut deadline => return Err("Timeout while fetching blocks".into()),
+            event = swarm.select_next_some() => event,
+        };
+
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Notifications(
+                NotificationsToSwarm::CustomProtocolOpen { peer_id, index, .. },
+            )) if index == protocols.block_announces
+                && peer.map_or(true, |peer| peer == peer_id)
+                && !candidates.contains(&peer_id) =>
+            {
+                candidates.push_back(peer_id);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::BlockRequests(event)) => match event {
+                BlockRequestsEvent::Response {
+                    peer_id,
+                    request_id,
+                    response: blocks,
+                } if in_flight == Some(request_id) => {
+                    println!("Peer {peer_id} responded with num={} blocks", blocks.len());
+                    for block in &blocks {
+                        print_block(block, raw_output);
+                    }
+                    return Ok(());
+                }
+                BlockRequestsEvent::RequestFailed {
+                    peer_id,
+                    request_id,
+                    error,
+                } if in_flight == Some(request_id) => {
+                    log::info!("Block request failed peer={:?} error={}", peer_id, error);
+                    in_flight = None;
+                }
+                _ => (),
+            },
+
+            _ => (),
+        }
+
+        if in_flight.is_none() {
+            if let Some(peer_id) = candidates.pop_front() {
+                log::info!("Block request peer={:?} request={:?}", peer_id, request);
+
+                in_flight = Some(
+                    swarm
+                        .behaviour_mut()
+                        .block_requests
+                        .send_request(&peer_id, &request),
+                );
+            }
+        }
+    }
+}
+
+/// Print the details of a single block.
+fn print_block(block: &BlockData, raw_output: bool) {
+    match &block.header {
+        Some(header) => println!(
+            "Block number={} hash={:?} parent={:?}",
+            header.number, block.hash, header.parent_hash
+        ),
+        None => println!("Block hash={:?}", block.hash),
+    }
+
+    if let Some(body) = &block.body {
+        println!("  Extrinsics num={}", body.len());
+        for extrinsic in body {
+            if raw_output {
+                println!(
+                    "    hash={:?} 0x{}",
+                    extrinsic.hash(),
+                    hex::encode(&extrinsic.0)
+                );
+            } else {
+                println!("    hash={:?} len={}", extrinsic.hash(), extrinsic.0.len());
+            }
+        }
+    }
+
+    for (engine, justification) in block.justifications.iter().flatten() {
+        println!(
+            "  Justification engine={} len={}",
+            String::from_utf8_lossy(engine),
+            justification.len()
+        );
+    }
+}
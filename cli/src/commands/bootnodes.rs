@@ -2,14 +2,15 @@
 // This file is dual-licensed as Apache-2.0 or GPL-3.0.
 // see LICENSE for license details.
 
+use crate::report::{optional_field, OutputFormat, Report};
 use futures::StreamExt;
 use libp2p::{
     identify::{self},
     identity,
-    swarm::{SwarmBuilder, SwarmEvent},
+    swarm::{self, SwarmEvent},
     Multiaddr, PeerId, Swarm,
 };
-
+use serde::Serialize;
 use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::time::Duration;
@@ -23,6 +24,8 @@ struct Bootnodes {
     bootnodes: HashMap<PeerId, Vec<Multiaddr>>,
     /// Genesis hash.
     genesis: Option<String>,
+    /// The fork id of the chain.
+    fork_id: Option<String>,
     /// The list of bootnodes that did not respond yet to the `identify` protocol.
     pending_peer_responses: HashSet<PeerId>,
     /// The identify data collected for peers.
@@ -34,12 +37,17 @@ struct Bootnodes {
 
 impl Bootnodes {
     /// Construct a new [`BootnodesStateMachine`] with the provided bootnodes.
-    pub fn new(bootnodes: HashMap<PeerId, Vec<Multiaddr>>, genesis: Option<String>) -> Self {
+    pub fn new(
+        bootnodes: HashMap<PeerId, Vec<Multiaddr>>,
+        genesis: Option<String>,
+        fork_id: Option<String>,
+    ) -> Self {
         let pending_peer_responses = bootnodes.keys().cloned().collect();
 
         Self {
             bootnodes,
             genesis,
+            fork_id,
             pending_peer_responses,
             identify_data: Default::default(),
         }
@@ -57,7 +65,12 @@ impl Bootnodes {
                 .with_cache_size(0),
         );
 
-        SwarmBuilder::with_tokio_executor(transport, behavior, local_peer_id).build()
+        Swarm::new(
+            transport,
+            behavior,
+            local_peer_id,
+            swarm::Config::with_tokio_executor(),
+        )
     }
 
     /// Dial the provided bootnodes and capture the `idenitify::Info` details of each peer.
@@ -67,7 +80,7 @@ impl Bootnodes {
         for remotes in self.bootnodes.values() {
             for remote in remotes {
                 swarm.dial(remote.clone())?;
-                println!("Dialed {remote}")
+                log::info!("Dialed {remote}")
             }
         }
 
@@ -82,13 +95,13 @@ impl Bootnodes {
                         self.pending_peer_responses.remove(&peer_id);
                     }
                     identify::Event::Sent { peer_id } => {
-                        println!("Sent identify info to {peer_id:?}");
+                        log::debug!("Sent identify info to {peer_id:?}");
                     }
                     identify::Event::Pushed { peer_id } => {
-                        println!("Pushed identify info to {peer_id:?}");
+                        log::debug!("Pushed identify info to {peer_id:?}");
                     }
                     identify::Event::Error { peer_id, error } => {
-                        println!("Error sending identify info to {peer_id:?}: {error:?}");
+                        log::warn!("Error sending identify info to {peer_id:?}: {error:?}");
                     }
                 }
             }
@@ -100,6 +113,7 @@ impl Bootnodes {
     /// A peer is valid when:
     /// - it has responded to the identify protocol
     /// - the p2p protocols are derived from the genesis hash (when the genesis hash is provided).
+    /// - the p2p protocols are derived from the fork id (when the fork id is provided).
     pub fn is_peer_valid(&self, peer: &PeerId) -> bool {
         self.identify_data
             .get(peer)
@@ -107,9 +121,14 @@ impl Bootnodes {
                 self.genesis
                     .as_ref()
                     .map(|genesis| {
+                        let prefix = subp2p_explorer::protocol_name(
+                            genesis.trim_start_matches("0x"),
+                            self.fork_id.as_deref(),
+                            "",
+                        );
                         info.protocols
                             .iter()
-                            .any(|proto| proto.as_ref().contains(genesis))
+                            .any(|proto| proto.as_ref().starts_with(&prefix))
                     })
                     .unwrap_or(true)
             })
@@ -117,11 +136,8 @@ impl Bootnodes {
     }
 }
 
-pub async fn verify_bootnodes(
-    bootnodes: Vec<String>,
-    genesis: Option<String>,
-) -> Result<(), Box<dyn Error>> {
-    // Parse the provided bootnodes as `PeerId` and `MultiAddress`.
+/// Parse the provided bootnodes as `PeerId` and `MultiAddress`.
+fn parse_bootnodes(bootnodes: Vec<String>) -> HashMap<PeerId, Vec<Multiaddr>> {
     let mut nodes = HashMap::new();
 
     for bootnode in bootnodes {
@@ -136,38 +152,141 @@ pub async fn verify_bootnodes(
             .push(multiaddress);
     }
 
-    let mut state = Bootnodes::new(nodes.clone(), genesis);
+    nodes
+}
+
+/// Returns the first of the candidate genesis hashes that derives the p2p protocols
+/// of a bootnode.
+///
+/// The bootnodes are dialed and their identify protocols are matched against the
+/// `/{genesis}/{fork_id}/` prefix of every candidate. The query ends with the first
+/// bootnode that supports one of the candidates, or fails after 25 seconds.
+pub async fn resolve_genesis(
+    bootnodes: Vec<String>,
+    candidates: Vec<String>,
+    fork_id: Option<&str>,
+) -> Result<String, Box<dyn Error>> {
+    let mut swarm = Bootnodes::build_swarm();
+    for remotes in parse_bootnodes(bootnodes).values() {
+        for remote in remotes {
+            swarm.dial(remote.clone())?;
+        }
+    }
+
+    let resolve = async {
+        loop {
+            let SwarmEvent::Behaviour(identify::Event::Received { peer_id, info }) =
+                swarm.select_next_some().await
+            else {
+                continue;
+            };
+
+            let genesis = candidates.iter().find(|genesis| {
+                let prefix =
+                    subp2p_explorer::protocol_name(genesis.trim_start_matches("0x"), fork_id, "");
+                info.protocols
+                    .iter()
+                    .any(|proto| proto.as_ref().starts_with(&prefix))
+            });
+            match genesis {
+                Some(genesis) => return genesis.clone(),
+                None => log::debug!("Bootnode {peer_id:?} supports none of the genesis hashes"),
+            }
+        }
+    };
+
+    tokio::time::timeout(Duration::from_secs(25), resolve)
+        .await
+        .map_err(|_| {
+            "No bootnode supports the genesis hash of the chain spec, provide `--state-version` or `--genesis`"
+                .into()
+        })
+}
+
+pub async fn verify_bootnodes(
+    bootnodes: Vec<String>,
+    genesis: Option<String>,
+    fork_id: Option<String>,
+    output: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    let nodes = parse_bootnodes(bootnodes);
+
+    let mut state = Bootnodes::new(nodes.clone(), genesis, fork_id);
     let _ = tokio::time::timeout(Duration::from_secs(25), state.verify_bootnodes()).await;
-    println!();
 
-    let valid_bootnodes: Vec<_> = nodes
-        .iter()
-        .filter(|(peer, _)| state.is_peer_valid(peer))
-        .collect();
-    let invalid_bootnodes: Vec<_> = nodes
+    let mut bootnodes: Vec<_> = nodes
         .iter()
-        .filter(|(peer, _)| !state.is_peer_valid(peer))
+        .flat_map(|(peer, addresses)| {
+            let valid = state.is_peer_valid(peer);
+            let agent_version = state
+                .identify_data
+                .get(peer)
+                .map(|info| info.agent_version.clone());
+            addresses.iter().map(move |address| BootnodeEntry {
+                address: address.to_string(),
+                peer_id: peer.to_string(),
+                valid,
+                agent_version: agent_version.clone(),
+            })
+        })
         .collect();
+    bootnodes.sort_by(|a, b| a.address.cmp(&b.address));
 
-    if !valid_bootnodes.is_empty() {
-        println!("Valid bootnodes:");
-        for (_, multiaddr) in valid_bootnodes {
-            for addr in multiaddr {
-                println!(" {addr}");
-            }
-        }
-        println!();
+    BootnodeReport { bootnodes }.print(output)
+}
+
+/// The verification result of a bootnode address.
+#[derive(Debug, Serialize)]
+pub struct BootnodeEntry {
+    address: String,
+    peer_id: String,
+    /// The bootnode responded to the identify protocol and supports the chain.
+    valid: bool,
+    /// The agent version reported by the identify protocol.
+    agent_version: Option<String>,
+}
+
+/// Report of the `verify-bootnodes` command.
+#[derive(Debug, Serialize)]
+pub struct BootnodeReport {
+    bootnodes: Vec<BootnodeEntry>,
+}
+
+impl Report for BootnodeReport {
+    const CSV_HEADER: &'static [&'static str] = &["address", "peer_id", "valid", "agent_version"];
+
+    fn csv_rows(&self) -> Vec<Vec<String>> {
+        self.bootnodes
+            .iter()
+            .map(|entry| {
+                vec![
+                    entry.address.clone(),
+                    entry.peer_id.clone(),
+                    entry.valid.to_string(),
+                    optional_field(&entry.agent_version),
+                ]
+            })
+            .collect()
     }
 
-    if !invalid_bootnodes.is_empty() {
-        println!("Invalid bootnodes:");
-        for (_, multiaddr) in invalid_bootnodes {
-            for addr in multiaddr {
-                println!(" {addr}");
+    fn print_table(&self) {
+        println!();
+
+        for (valid, title) in [(true, "Valid bootnodes:"), (false, "Invalid bootnodes:")] {
+            let mut entries = self
+                .bootnodes
+                .iter()
+                .filter(|entry| entry.valid == valid)
+                .peekable();
+            if entries.peek().is_none() {
+                continue;
+            }
+
+            println!("{title}");
+            for entry in entries {
+                println!(" {}", entry.address);
             }
+            println!();
         }
-        println!();
     }
-
-    Ok(())
 }
@@ -2,25 +2,48 @@
 // This file is dual-licensed as Apache-2.0 or GPL-3.0.
 // see LICENSE for license details.
 
-use crate::utils::{build_swarm, is_public_address, Location, Locator};
+use crate::{
+    commands::monitor::{label, DiscoveryMetrics},
+    report::{optional_field, unknown_field, OutputFormat, Report},
+    utils::{
+        build_swarm_with_options, is_public_address, AsnLocator, Location, Locator,
+        NotificationProtocols, SwarmOptions,
+    },
+};
 use codec::Decode;
 use futures::StreamExt;
+use ip_network::IpNetwork;
 use libp2p::{
     identify::Info,
-    kad::{GetClosestPeersError, GetClosestPeersOk, KademliaEvent, QueryId, QueryResult},
+    kad::{Event as KademliaEvent, GetClosestPeersError, GetClosestPeersOk, QueryId, QueryResult},
     multiaddr::Protocol,
     swarm::SwarmEvent,
     Multiaddr, PeerId, Swarm,
 };
-use std::time::Duration;
-use std::{cmp::Reverse, error::Error};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use std::{cmp::Reverse, error::Error, path::PathBuf};
 use std::{
     collections::{hash_map::Entry, HashMap, HashSet},
     net::IpAddr,
 };
 use subp2p_explorer::{
-    notifications::{behavior::NotificationsToSwarm, messages::ProtocolRole},
+    address_hygiene::{analyze_addresses, connected_address, PeerAddresses},
+    agent_version::{AgentVersion, Version},
+    concentration::{group_peers, ip_subnet, GroupCount},
+    crawl::{BucketKeys, CrawlState, RoutingTableCrawl},
+    dial::{classify_dial_error, strip_peer_id, DialClass},
+    notifications::{
+        behavior::NotificationsToSwarm,
+        chain_state::DEFAULT_BEST_BLOCK_PEERS,
+        messages::{BlockHash, BlockNumber, ProtocolRole},
+    },
     peer_behavior::PeerInfoEvent,
+    peer_store::{
+        unix_time, DialOutcome, JsonLinesPeerStore, MemoryPeerStore, PeerObservation, PeerStore,
+    },
+    requests::kademlia::{FindNodeRequest, KademliaPeer, KademliaRequestsEvent},
+    topology::TopologyGraph,
     Behaviour, BehaviourEvent,
 };
 use trust_dns_resolver::{
@@ -28,9 +51,11 @@ use trust_dns_resolver::{
     TokioAsyncResolver,
 };
 
-struct NetworkDiscovery {
+pub struct NetworkDiscovery {
     /// Drive the network behavior.
     swarm: Swarm<Behaviour>,
+    /// Registered notification protocols.
+    protocols: NotificationProtocols,
     /// In flight kademlia queries.
     queries: HashSet<QueryId>,
     /// Discovered peers by kademlia queries.
@@ -41,18 +66,124 @@ struct NetworkDiscovery {
     peer_role: HashMap<PeerId, ProtocolRole>,
     /// Peers dialed.
     dialed_peers: HashMap<PeerId, usize>,
+    /// The outcome of the dial attempts by peer and address.
+    dial_outcomes: HashMap<PeerId, HashMap<Multiaddr, Vec<DialClass>>>,
+    /// The number of dial attempts by outcome.
+    dial_classes: HashMap<DialClass, usize>,
+    /// The legacy protocol id of the chain.
+    legacy_protocol_id: Option<String>,
+    /// Peers that negotiated the legacy block announces protocol.
+    legacy_peers: HashSet<PeerId>,
+    /// Records the observed peers across runs.
+    peer_store: Box<dyn PeerStore>,
+    /// The last time peers were connected or identified.
+    peer_last_reached: HashMap<PeerId, Instant>,
+    /// The last time peers were observed, to forget the stale peers while monitoring.
+    peer_last_seen: HashMap<PeerId, Instant>,
+    /// The address of the last connection established with the peer.
+    peer_observed_addr: HashMap<PeerId, Multiaddr>,
+    /// The metrics exported while monitoring the network.
+    metrics: Option<DiscoveryMetrics>,
+    /// The rolling window of the metrics of the reached peers.
+    metrics_window: Duration,
+    /// The peers returned by the routing table of each peer.
+    topology: Option<TopologyGraph>,
+    /// Peers that were requested their routing table.
+    topology_requests: HashSet<PeerId>,
+    /// The crawl of the routing tables, replacing the random walks.
+    crawl: Option<RoutingTableCrawl>,
 }
 
+/// The number of countries, providers and subnets printed in the table.
+const TOP_GROUPS: usize = 10;
+
+/// The maximum number of peers crawled concurrently.
+const MAX_CRAWLED_PEERS: usize = 64;
+
+/// The number of the most recent dial outcomes kept for every address.
+const MAX_DIAL_OUTCOMES: usize = 16;
+
 impl NetworkDiscovery {
     /// Constructs a new [`NetworkDiscovery`].
-    pub fn new(swarm: Swarm<Behaviour>) -> Self {
+    pub fn new(
+        swarm: Swarm<Behaviour>,
+        protocols: NotificationProtocols,
+        legacy_protocol_id: Option<String>,
+        peer_store: Box<dyn PeerStore>,
+    ) -> Self {
         Self {
             swarm,
+            protocols,
             queries: HashSet::with_capacity(1024),
             discovered_with_addresses: HashMap::with_capacity(1024),
             peer_details: HashMap::with_capacity(1024),
             peer_role: HashMap::with_capacity(1024),
             dialed_peers: HashMap::with_capacity(1024),
+            dial_outcomes: HashMap::with_capacity(1024),
+            dial_classes: HashMap::new(),
+            legacy_protocol_id,
+            legacy_peers: HashSet::with_capacity(1024),
+            peer_store,
+            peer_last_reached: HashMap::with_capacity(1024),
+            peer_last_seen: HashMap::with_capacity(1024),
+            peer_observed_addr: HashMap::with_capacity(1024),
+            metrics: None,
+            metrics_window: Duration::MAX,
+            topology: None,
+            topology_requests: HashSet::with_capacity(1024),
+            crawl: None,
+        }
+    }
+
+    /// Export the metrics of the discovery, computed over the provided rolling window.
+    pub fn with_metrics(mut self, metrics: DiscoveryMetrics, window: Duration) -> Self {
+        self.metrics = Some(metrics);
+        self.metrics_window = window;
+        self
+    }
+
+    /// Request the routing table of every discovered peer to build the topology graph.
+    pub fn with_topology(mut self) -> Self {
+        self.topology = Some(TopologyGraph::new());
+        self
+    }
+
+    /// Crawl the full routing table of every peer instead of randomly walking the DHT.
+    ///
+    /// The discovery finishes once the routing tables no longer contain new peers.
+    pub fn with_crawl(mut self) -> Self {
+        self.crawl = Some(RoutingTableCrawl::new(BucketKeys::generate()));
+        self
+    }
+
+    /// Seed the routing table with the addresses of the peers known from previous runs.
+    pub fn seed_known_peers(&mut self) -> usize {
+        let mut seeded = 0;
+        for (peer_id, record) in self.peer_store.peers() {
+            for address in &record.addresses {
+                self.swarm
+                    .behaviour_mut()
+                    .discovery
+                    .add_address(peer_id, address.clone());
+            }
+            if !record.addresses.is_empty() {
+                seeded += 1;
+            }
+        }
+        seeded
+    }
+
+    /// Record an observation of the peer in the peer store.
+    fn record(&mut self, peer_id: PeerId, observation: PeerObservation) {
+        self.peer_last_seen.insert(peer_id, Instant::now());
+        if let (Some(metrics), PeerObservation::Discovered { .. }) = (&self.metrics, &observation) {
+            if !self.discovered_with_addresses.contains_key(&peer_id) {
+                metrics.peers_discovered.inc();
+            }
+        }
+
+        if let Err(err) = self.peer_store.record(peer_id, observation) {
+            log::warn!("Failed to record peer={:?} error={}", peer_id, err);
         }
     }
 
@@ -71,6 +202,96 @@ impl NetworkDiscovery {
         }
     }
 
+    /// Request the routing table of the peer once, if the topology is recorded.
+    ///
+    /// The key is random, such that the remote returns the peers of a random
+    /// region of its routing table. These requests replace the random walks of
+    /// the DHT, the returned peers are discovered and requested in turn.
+    fn request_routing_table(&mut self, peer_id: PeerId) {
+        // The crawl already requests the full routing table of the peers.
+        if self.topology.is_none()
+            || self.crawl.is_some()
+            || !self.topology_requests.insert(peer_id)
+        {
+            return;
+        }
+
+        self.swarm
+            .behaviour_mut()
+            .kademlia_requests
+            .send_request(&peer_id, &FindNodeRequest::new(&PeerId::random()));
+    }
+
+    /// Start crawling the queued peers, up to [`MAX_CRAWLED_PEERS`] at a time.
+    fn crawl_next_peers(&mut self) {
+        let Some(crawl) = &mut self.crawl else { return };
+
+        while let Some((peer_id, requests)) = crawl.next_peer(MAX_CRAWLED_PEERS) {
+            for request in &requests {
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia_requests
+                    .send_request(&peer_id, request);
+            }
+        }
+    }
+
+    /// Queue the peers of the local routing table for crawling.
+    fn seed_crawl(&mut self) {
+        let Some(crawl) = &mut self.crawl else { return };
+
+        for bucket in self.swarm.behaviour_mut().discovery.kbuckets() {
+            for entry in bucket.iter() {
+                crawl.add_peer(*entry.node.key.preimage());
+            }
+        }
+    }
+
+    /// Record the peers of the routing table of a peer, returned by a crawl or
+    /// topology request.
+    fn on_routing_table_response(&mut self, peer_id: PeerId, peers: &[KademliaPeer]) {
+        let local_peer_id = *self.swarm.local_peer_id();
+        if let Some(topology) = &mut self.topology {
+            topology.add_response(peer_id, peers.iter().map(|peer| peer.peer_id));
+        }
+
+        let peers: Vec<_> = peers
+            .iter()
+            .filter(|peer| peer.peer_id != local_peer_id)
+            .cloned()
+            .collect();
+        if let Some(crawl) = &mut self.crawl {
+            crawl.on_response(peer_id, &peers);
+        }
+
+        for peer in peers {
+            for address in &peer.addresses {
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia_requests
+                    .add_address(&peer.peer_id, address.clone());
+            }
+            self.record(
+                peer.peer_id,
+                PeerObservation::Discovered {
+                    addresses: peer.addresses.clone(),
+                },
+            );
+            self.request_routing_table(peer.peer_id);
+            self.discovered_with_addresses
+                .entry(peer.peer_id)
+                .or_default()
+                .extend(peer.addresses);
+        }
+    }
+
+    /// Returns true if the protocol name is a legacy name of the chain (ie `/dot/kad`).
+    fn is_legacy_protocol(&self, name: &str) -> bool {
+        self.legacy_protocol_id
+            .as_ref()
+            .is_some_and(|protocol_id| name.starts_with(&format!("/{protocol_id}/")))
+    }
+
     /// Track the dialed peers in response of an [`SwarmEvent::Dialing`] event.
     fn dialed_peer(&mut self, peer_id: Option<PeerId>) {
         // Record how many times have we dialed a peer.
@@ -82,29 +303,204 @@ impl NetworkDiscovery {
             .or_insert(0);
     }
 
+    /// Record the outcome of a dial attempt of the peer.
+    fn record_dial(&mut self, peer_id: PeerId, address: Option<&Multiaddr>, class: DialClass) {
+        *self.dial_classes.entry(class).or_default() += 1;
+        if let Some(address) = address {
+            let outcomes = self
+                .dial_outcomes
+                .entry(peer_id)
+                .or_default()
+                .entry(strip_peer_id(address))
+                .or_default();
+            outcomes.push(class);
+            if outcomes.len() > MAX_DIAL_OUTCOMES {
+                outcomes.remove(0);
+            }
+        }
+
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .dials
+                .get_or_create(&label("outcome", class.name()))
+                .inc();
+        }
+    }
+
+    /// Update the metrics from the rolling statistics of the peers.
+    fn update_metrics(&mut self) {
+        // The metrics share their values with the registry.
+        let Some(metrics) = self.metrics.clone() else {
+            return;
+        };
+
+        // Forget the peers reached before the rolling window.
+        let window = self.metrics_window;
+        self.peer_last_reached
+            .retain(|_, reached| reached.elapsed() <= window);
+        self.forget_stale_peers();
+
+        metrics
+            .peers_known
+            .set(self.discovered_with_addresses.len() as i64);
+        metrics
+            .peers_reachable
+            .set(self.peer_last_reached.len() as i64);
+        metrics
+            .peers_connected
+            .set(self.swarm.connected_peers().count() as i64);
+
+        let mut versions: HashMap<&str, i64> = HashMap::new();
+        let mut roles: HashMap<&str, i64> = HashMap::new();
+        for peer in self.peer_last_reached.keys() {
+            if let Some(info) = self.peer_details.get(peer) {
+                // Drop the node name to keep the number of labels bounded.
+                let version = info.agent_version.split(" (").next().unwrap_or_default();
+                *versions.entry(version).or_default() += 1;
+            }
+            if let Some(role) = self.peer_role.get(peer) {
+                *roles.entry(role.name()).or_default() += 1;
+            }
+        }
+        metrics.agent_versions.clear();
+        for (version, count) in versions {
+            metrics
+                .agent_versions
+                .get_or_create(&label("version", version))
+                .set(count);
+        }
+        metrics.roles.clear();
+        for (role, count) in roles {
+            metrics.roles.get_or_create(&label("role", role)).set(count);
+        }
+
+        let (reachable, unreachable): (Vec<_>, Vec<_>) = self
+            .peer_role
+            .iter()
+            .filter(|(_, role)| **role == ProtocolRole::Authority)
+            .partition(|(peer, _)| self.peer_last_reached.contains_key(peer));
+        metrics
+            .authorities
+            .get_or_create(&label("state", "reachable"))
+            .set(reachable.len() as i64);
+        metrics
+            .authorities
+            .get_or_create(&label("state", "unreachable"))
+            .set(unreachable.len() as i64);
+    }
+
+    /// Forget the peers that were not observed during the rolling window.
+    ///
+    /// Keeps the memory bounded while monitoring the network continuously.
+    fn forget_stale_peers(&mut self) {
+        let window = self.metrics_window;
+        let stale: Vec<_> = self
+            .peer_last_seen
+            .iter()
+            .filter(|(peer, seen)| seen.elapsed() > window && !self.swarm.is_connected(peer))
+            .map(|(peer, _)| *peer)
+            .collect();
+
+        for peer in &stale {
+            self.peer_last_seen.remove(peer);
+            self.discovered_with_addresses.remove(peer);
+            self.peer_details.remove(peer);
+            self.peer_role.remove(peer);
+            self.dialed_peers.remove(peer);
+            self.dial_outcomes.remove(peer);
+            self.legacy_peers.remove(peer);
+            self.peer_observed_addr.remove(peer);
+            self.topology_requests.remove(peer);
+        }
+        if !stale.is_empty() {
+            log::debug!("Forgot num={} stale peers", stale.len());
+        }
+    }
+
     /// Drive the network behavior events.
+    ///
+    /// Runs until the crawl finishes, or forever when randomly walking the DHT.
     pub async fn drive_events(&mut self) {
-        // Start by performing 128 queries.
-        self.insert_queries(128);
+        if self.crawl.is_some() {
+            self.seed_crawl();
+            self.crawl_next_peers();
+        } else {
+            // Start by performing 128 queries.
+            self.insert_queries(128);
+        }
 
         let mut old_log_time = std::time::Instant::now();
+        let mut metrics_interval = tokio::time::interval(Duration::from_secs(10));
 
         loop {
-            let event = self.swarm.select_next_some().await;
+            let event = tokio::select! {
+                event = self.swarm.select_next_some() => event,
+                _ = metrics_interval.tick(), if self.metrics.is_some() => {
+                    self.update_metrics();
+                    if let Err(err) = self.peer_store.flush() {
+                        log::warn!("Failed to flush the peer store error={}", err);
+                    }
+                    continue;
+                }
+            };
 
             match event {
                 SwarmEvent::Dialing { peer_id, .. } => {
                     self.dialed_peer(peer_id);
                 }
 
+                SwarmEvent::ConnectionEstablished {
+                    peer_id, endpoint, ..
+                } => {
+                    self.peer_last_reached.insert(peer_id, Instant::now());
+                    self.peer_observed_addr
+                        .insert(peer_id, connected_address(&endpoint));
+
+                    if endpoint.is_dialer() {
+                        self.record(peer_id, PeerObservation::Dialed(DialOutcome::Success));
+                        self.record_dial(
+                            peer_id,
+                            Some(endpoint.get_remote_address()),
+                            DialClass::Success,
+                        );
+                    }
+                }
+
+                SwarmEvent::OutgoingConnectionError {
+                    peer_id: Some(peer_id),
+                    error,
+                    ..
+                } => {
+                    log::debug!("Dial failed peer={:?} error={}", peer_id, error);
+                    self.record(
+                        peer_id,
+                        PeerObservation::Dialed(DialOutcome::Failure(error.to_string())),
+                    );
+                    for (address, class) in classify_dial_error(&error) {
+                        self.record_dial(peer_id, address.as_ref(), class);
+                    }
+                }
+
                 SwarmEvent::Behaviour(BehaviourEvent::Discovery(event)) => match event {
                     KademliaEvent::OutboundQueryProgressed {
                         id,
                         result: QueryResult::GetClosestPeers(result),
+                        stats,
                         ..
                     } => {
                         self.queries.remove(&id);
 
+                        if let Some(metrics) = &self.metrics {
+                            let outcome = if result.is_ok() { "ok" } else { "timeout" };
+                            metrics
+                                .dht_queries
+                                .get_or_create(&label("outcome", outcome))
+                                .inc();
+                            if let Some(duration) = stats.duration() {
+                                metrics.dht_query_duration.observe(duration.as_secs_f64());
+                            }
+                        }
+
                         // It might be possible that the query did not finish in 5 minutes.
                         // However we capture the provided peers.
                         // Peers are later reported by kademila events handled below.
@@ -120,7 +516,11 @@ impl NetworkDiscovery {
                             log::info!("...Discovery in progress last_query_num={num_discovered}");
                         }
 
-                        if self.queries.is_empty() {
+                        // The topology requests continue the walk once bootstrapped.
+                        if self.queries.is_empty()
+                            && self.crawl.is_none()
+                            && self.topology.is_none()
+                        {
                             self.insert_queries(128);
                         }
                     }
@@ -128,6 +528,13 @@ impl NetworkDiscovery {
                     KademliaEvent::RoutingUpdated {
                         peer, addresses, ..
                     } => {
+                        self.record(
+                            peer,
+                            PeerObservation::Discovered {
+                                addresses: addresses.iter().cloned().collect(),
+                            },
+                        );
+                        self.request_routing_table(peer);
                         match self.discovered_with_addresses.entry(peer) {
                             Entry::Occupied(mut occupied) => {
                                 occupied.get_mut().extend(addresses.into_vec());
@@ -140,6 +547,13 @@ impl NetworkDiscovery {
 
                     KademliaEvent::RoutablePeer { peer, address }
                     | KademliaEvent::PendingRoutablePeer { peer, address } => {
+                        self.record(
+                            peer,
+                            PeerObservation::Discovered {
+                                addresses: vec![address.clone()],
+                            },
+                        );
+                        self.request_routing_table(peer);
                         match self.discovered_with_addresses.entry(peer) {
                             Entry::Occupied(mut occupied) => {
                                 occupied.get_mut().insert(address);
@@ -154,9 +568,45 @@ impl NetworkDiscovery {
                     _ => (),
                 },
 
+                SwarmEvent::Behaviour(BehaviourEvent::KademliaRequests(event)) => match event {
+                    KademliaRequestsEvent::Response {
+                        peer_id, response, ..
+                    } => {
+                        log::debug!(
+                            "Routing table peer={:?} num_peers={}",
+                            peer_id,
+                            response.len()
+                        );
+                        self.on_routing_table_response(peer_id, &response);
+                    }
+                    KademliaRequestsEvent::RequestFailed { peer_id, error, .. } => {
+                        log::debug!(
+                            "Routing table request failed peer={:?} error={}",
+                            peer_id,
+                            error
+                        );
+                        if let Some(crawl) = &mut self.crawl {
+                            crawl.on_failure(peer_id);
+                        }
+                    }
+                },
+
                 SwarmEvent::Behaviour(BehaviourEvent::PeerInfo(info_event)) => match info_event {
                     PeerInfoEvent::Identified { peer_id, info } => {
                         log::debug!("Identified peer_id={:?} info={:?}", peer_id, info);
+                        self.peer_last_reached.insert(peer_id, Instant::now());
+                        self.record(
+                            peer_id,
+                            PeerObservation::Identified {
+                                agent_version: info.agent_version.clone(),
+                                protocols: info
+                                    .protocols
+                                    .iter()
+                                    .map(|proto| proto.to_string())
+                                    .collect(),
+                                listen_addrs: info.listen_addrs.clone(),
+                            },
+                        );
                         self.peer_details.insert(peer_id, info);
                     }
                 },
@@ -166,118 +616,203 @@ impl NetworkDiscovery {
                         peer_id,
                         index,
                         received_handshake,
+                        negotiated_name,
                         inbound,
                         ..
                     },
                 )) => {
-                    if let Ok(role) = ProtocolRole::decode(&mut &received_handshake[..]) {
-                        log::debug!("Identified peer_id={:?} role={:?}", peer_id, role);
-                        self.peer_role.insert(peer_id, role);
+                    // The block announces handshake starts with the role of the peer.
+                    if index == self.protocols.block_announces {
+                        if let Ok(role) = ProtocolRole::decode(&mut &received_handshake[..]) {
+                            log::debug!("Identified peer_id={:?} role={:?}", peer_id, role);
+                            self.record(peer_id, PeerObservation::Role(role.clone()));
+                            self.peer_role.insert(peer_id, role);
+                        }
+
+                        if self.is_legacy_protocol(&negotiated_name) {
+                            self.legacy_peers.insert(peer_id);
+                        }
                     }
 
                     log::debug!(
-                        "Protocol open peer={:?} index={:?} handshake={:?} inbound={:?}",
+                        "Protocol open peer={:?} index={:?} name={:?} handshake={:?} inbound={:?}",
                         peer_id,
                         index,
+                        negotiated_name,
                         received_handshake,
                         inbound
                     );
                 }
 
+                SwarmEvent::Behaviour(BehaviourEvent::Notifications(
+                    NotificationsToSwarm::PeerBestBlockUpdated {
+                        peer_id,
+                        best_number,
+                        best_hash,
+                    },
+                )) => {
+                    log::debug!(
+                        "Best block peer={:?} number={:?} hash={:?}",
+                        peer_id,
+                        best_number,
+                        best_hash
+                    );
+                    // Advertise the best head to stay connected with peers that
+                    // deprioritise unsynced nodes.
+                    if let Err(err) = self
+                        .swarm
+                        .behaviour()
+                        .notifications
+                        .advance_best_block(DEFAULT_BEST_BLOCK_PEERS)
+                    {
+                        log::debug!("Failed to advertise the best block error={:?}", err);
+                    }
+                }
+
                 _ => (),
             }
+
+            if let Some(crawl) = &self.crawl {
+                if crawl.is_finished() {
+                    log::info!(
+                        "Crawled the routing tables of num={} peers",
+                        crawl.num_discovered()
+                    );
+                    return;
+                }
+            }
+            self.crawl_next_peers();
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn discover_network(
     genesis: String,
     bootnodes: Vec<String>,
+    options: SwarmOptions,
+    peer_store: Option<PathBuf>,
     num_cities: Option<usize>,
     raw_geolocation: bool,
     only_authorities: bool,
+    version_policy: VersionPolicy,
+    topology: Option<(PathBuf, TopologyFormat)>,
+    crawl: bool,
+    shared_ip_threshold: usize,
+    asn_db: Option<PathBuf>,
     timeout: std::time::Duration,
+    output: OutputFormat,
 ) -> Result<(), Box<dyn Error>> {
-    let swarm = build_swarm(genesis.clone(), bootnodes)?;
-    let mut network_discovery = NetworkDiscovery::new(swarm);
+    let asn_locator = asn_db.map(|path| AsnLocator::open(&path)).transpose()?;
+
+    // Peers of a chain relaunched with the same genesis only differ by the fork id.
+    let chain_prefix = subp2p_explorer::protocol_name(
+        genesis.trim_start_matches("0x"),
+        options.fork_id.as_deref(),
+        "",
+    );
+    let legacy_protocol_id = options.legacy_protocol_id.clone();
+    let (swarm, protocols) = build_swarm_with_options(genesis.clone(), bootnodes, options)?;
+    let peer_store: Box<dyn PeerStore> = match peer_store {
+        Some(path) => Box::new(JsonLinesPeerStore::open(path)?),
+        None => Box::<MemoryPeerStore>::default(),
+    };
+    let mut network_discovery =
+        NetworkDiscovery::new(swarm, protocols, legacy_protocol_id, peer_store);
+    if topology.is_some() {
+        network_discovery = network_discovery.with_topology();
+    }
+    if crawl {
+        network_discovery = network_discovery.with_crawl();
+    }
+
+    let known_peers = network_discovery.peer_store.peers().len();
+    if known_peers > 0 {
+        let seeded = network_discovery.seed_known_peers();
+        log::info!("Seeded num={seeded} peers from the peer store of num={known_peers} peers");
+    }
 
     // Drive network events for a few minutes.
+    let run_start = unix_time();
+    let crawl_start = Instant::now();
     let _ = tokio::time::timeout(timeout, network_discovery.drive_events()).await;
+    let crawl = network_discovery.crawl.as_ref().map(|crawl| CrawlSummary {
+        finished: crawl.is_finished(),
+        duration_secs: crawl_start.elapsed().as_secs(),
+        discovered_peers: crawl.num_discovered(),
+        crawled_peers: crawl.num_in_state(CrawlState::Crawled),
+        unreachable_peers: crawl.num_in_state(CrawlState::Unreachable),
+    });
+    network_discovery.peer_store.flush()?;
 
-    println!("Dialed num={} peers", network_discovery.dialed_peers.len());
-    println!(
-        "Discovered num={} peers",
-        network_discovery.discovered_with_addresses.len()
-    );
+    // Peers that are more than a few blocks behind are lagging.
+    const LAGGING_THRESHOLD: BlockNumber = 5;
 
     let infos: HashMap<_, _> = network_discovery
         .peer_details
         .iter()
         .filter(|(_peer, info)| {
-            info.protocols
-                .iter()
-                .any(|stream_proto| stream_proto.as_ref().contains(&genesis))
+            info.protocols.iter().any(|stream_proto| {
+                stream_proto.as_ref().starts_with(&chain_prefix)
+                    || network_discovery.is_legacy_protocol(stream_proto.as_ref())
+            })
         })
         .collect();
 
-    println!(
-        "Peers with identity num={}",
-        network_discovery.peer_details.len()
-    );
-    println!("Peers that support our genesis hash {:?}", infos.len());
+    let peer_store = (known_peers > 0).then(|| {
+        let peers = network_discovery.peer_store.peers();
+        let seen_peers = peers
+            .values()
+            .filter(|record| record.last_seen >= Some(run_start))
+            .count();
+        PeerStoreSummary {
+            known_peers: peers.len(),
+            seen_peers,
+            new_peers: peers
+                .values()
+                .filter(|record| record.first_seen >= run_start)
+                .count(),
+            unseen_peers: peers.len() - seen_peers,
+        }
+    });
 
-    let peers_with_public_addr: HashMap<_, _> = infos
-        .iter()
-        .filter(|(_peer, info)| info.listen_addrs.iter().any(is_public_address))
+    // The chain state is tracked for the peers connected on the block announces protocol.
+    let genesis_hash = BlockHash::from_slice(&hex::decode(genesis.trim_start_matches("0x"))?);
+    let chain_state = network_discovery
+        .swarm
+        .behaviour()
+        .notifications
+        .chain_state();
+    // A single peer announcing a far ahead best block does not move the reference.
+    let best_number = chain_state
+        .supported_best_block(&genesis_hash, DEFAULT_BEST_BLOCK_PEERS)
+        .map(|(number, _)| number);
+    let lagging: HashMap<_, _> = chain_state
+        .lagging_peers(&genesis_hash, DEFAULT_BEST_BLOCK_PEERS, LAGGING_THRESHOLD)
+        .into_iter()
         .collect();
-    println!(
-        "  Peers with public addresses {:?}",
-        peers_with_public_addr.len()
-    );
-    println!(
-        "  Peers with private addresses {:?}",
-        infos.len() - peers_with_public_addr.len()
-    );
-
-    println!(
-        "Peers with role associated num={}",
-        network_discovery.peer_role.len()
-    );
-
-    if only_authorities {
-        let authorities = network_discovery
-            .peer_role
-            .iter()
-            .filter_map(|(peer, role)| {
-                if *role == ProtocolRole::Authority {
-                    Some(peer)
-                } else {
-                    None
-                }
-            });
-
-        for peer in authorities {
-            println!(
-                "authority={peer} version={:?}",
-                network_discovery
-                    .peer_details
-                    .get(peer)
-                    .map(|info| info.agent_version.clone())
-            );
-        }
-    }
+    let forked: HashSet<_> = chain_state
+        .forked_peers(&genesis_hash)
+        .into_iter()
+        .collect();
+    let peers_with_best_block = chain_state
+        .peers()
+        .filter(|(_, state)| state.genesis_hash == genesis_hash)
+        .count();
 
     let locator = Locator::new();
     let mut cities: HashMap<String, usize> = HashMap::new();
     let mut geolocated_peers: HashMap<PeerId, Location> = HashMap::new();
+    // The public IP of the peers, preferably the geolocated one.
+    let mut peer_ips: HashMap<PeerId, IpAddr> = HashMap::new();
 
     // Resolver for DNS addresses.
     let resolver = TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default());
-    for (peer, info) in infos {
+    for (peer, info) in &infos {
         for addr in &info.listen_addrs {
-            let located = match addr.iter().next() {
-                Some(Protocol::Ip4(ip)) => locator.locate(IpAddr::V4(ip)),
-                Some(Protocol::Ip6(ip)) => locator.locate(IpAddr::V6(ip)),
+            let ips: Vec<IpAddr> = match addr.iter().next() {
+                Some(Protocol::Ip4(ip)) => vec![IpAddr::V4(ip)],
+                Some(Protocol::Ip6(ip)) => vec![IpAddr::V6(ip)],
                 Some(Protocol::Dns(dns))
                 | Some(Protocol::Dns4(dns))
                 | Some(Protocol::Dns6(dns)) => {
@@ -285,39 +820,842 @@ pub async fn discover_network(
                         continue;
                     };
 
-                    lookup.iter().find_map(|ip| locator.locate(ip))
+                    lookup.iter().collect()
                 }
                 _ => continue,
             };
 
-            let Some(located) = located else { continue };
+            let mut ips = ips
+                .into_iter()
+                .filter(|ip| IpNetwork::from(*ip).is_global())
+                .peekable();
+            if let Some(ip) = ips.peek() {
+                peer_ips.entry(**peer).or_insert(*ip);
+            }
+
+            let Some((ip, located)) = ips.find_map(|ip| Some((ip, locator.locate(ip)?))) else {
+                continue;
+            };
+            peer_ips.insert(**peer, ip);
 
-            cities
-                .entry(located.city.clone())
-                .and_modify(|num| *num += 1)
-                .or_insert(1);
+            if let Some(city) = &located.city {
+                cities
+                    .entry(city.clone())
+                    .and_modify(|num| *num += 1)
+                    .or_insert(1);
+            }
 
-            geolocated_peers.insert(peer.clone(), located);
+            geolocated_peers.insert(**peer, located);
 
             break;
         }
     }
 
-    // Print top k cities.
-    let mut cities: Vec<_> = cities.iter().collect();
-    cities.sort_by_key(|data| Reverse(*data.1));
-    let iter = cities.iter().take(num_cities.unwrap_or(10));
-    for (city, count) in iter {
-        println!("   City={city} peers={count}");
+    // Cities in decreasing order by the number of peers.
+    let mut cities: Vec<_> = cities
+        .into_iter()
+        .map(|(city, peers)| CityReport { city, peers })
+        .collect();
+    cities.sort_by_key(|city| Reverse(city.peers));
+
+    // Peers that support the genesis hash, either identified or that opened the
+    // block announces protocol.
+    let mut chain_peers: Vec<_> = infos
+        .keys()
+        .copied()
+        .chain(network_discovery.peer_role.keys())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    chain_peers.sort();
+
+    // Addresses that were dialed without success.
+    let mut unreachable_addresses = Vec::new();
+    for (peer, outcomes) in &network_discovery.dial_outcomes {
+        if only_authorities
+            && network_discovery.peer_role.get(peer) != Some(&ProtocolRole::Authority)
+        {
+            continue;
+        }
+
+        let listen_addrs: HashSet<_> = network_discovery
+            .peer_details
+            .get(peer)
+            .map(|info| info.listen_addrs.iter().map(strip_peer_id).collect())
+            .unwrap_or_default();
+
+        for (address, classes) in outcomes {
+            if classes.contains(&DialClass::Success) {
+                continue;
+            }
+
+            let mut outcomes: Vec<_> = classes.clone();
+            outcomes.sort();
+            outcomes.dedup();
+            unreachable_addresses.push(UnreachableAddress {
+                peer_id: peer.to_string(),
+                address: address.to_string(),
+                advertised: listen_addrs.contains(address),
+                failures: classes.len(),
+                outcomes: outcomes.iter().map(DialClass::name).collect(),
+            });
+        }
     }
+    unreachable_addresses.sort_by(|a, b| {
+        a.peer_id
+            .cmp(&b.peer_id)
+            .then_with(|| a.address.cmp(&b.address))
+    });
 
-    if raw_geolocation {
-        println!();
+    let mut dial_outcomes: Vec<_> = network_discovery
+        .dial_classes
+        .iter()
+        .map(|(class, dials)| DialOutcomeCount {
+            outcome: class.name(),
+            dials: *dials,
+        })
+        .collect();
+    dial_outcomes.sort_by_key(|count| Reverse(count.dials));
 
-        for (peer, location) in geolocated_peers {
-            println!("   Peer {peer}: {location:?}");
+    // Analyze the addresses of the identified peers and of the peers found in the DHT.
+    let mut addresses: HashMap<PeerId, PeerAddresses> = HashMap::new();
+    for (peer, info) in &network_discovery.peer_details {
+        addresses.entry(*peer).or_default().listen_addrs = info.listen_addrs.clone();
+    }
+    for (peer, dht_addrs) in &network_discovery.discovered_with_addresses {
+        addresses.entry(*peer).or_default().dht_addrs = dht_addrs.iter().cloned().collect();
+    }
+    for (peer, observed) in &network_discovery.peer_observed_addr {
+        if let Some(addresses) = addresses.get_mut(peer) {
+            addresses.observed_addr = Some(observed.clone());
         }
     }
+    let address_findings: Vec<_> = analyze_addresses(&addresses, shared_ip_threshold)
+        .into_iter()
+        .filter(|(peer, _)| {
+            !only_authorities
+                || network_discovery.peer_role.get(peer) == Some(&ProtocolRole::Authority)
+        })
+        .map(|(peer, findings)| PeerAddressFindings {
+            peer_id: peer.to_string(),
+            findings: findings
+                .iter()
+                .map(|finding| AddressFindingEntry {
+                    finding: finding.name(),
+                    detail: finding.to_string(),
+                })
+                .collect(),
+        })
+        .collect();
+
+    // The distinct kinds of findings of every peer, findings are sorted by kind.
+    let mut findings_by_peer: HashMap<String, Vec<&'static str>> = HashMap::new();
+    let mut address_finding_counts: HashMap<&'static str, usize> = HashMap::new();
+    for peer in &address_findings {
+        let mut names: Vec<_> = peer.findings.iter().map(|entry| entry.finding).collect();
+        names.dedup();
+        for name in &names {
+            *address_finding_counts.entry(name).or_default() += 1;
+        }
+        findings_by_peer.insert(peer.peer_id.clone(), names);
+    }
+    let mut address_finding_counts: Vec<_> = address_finding_counts
+        .into_iter()
+        .map(|(finding, peers)| AddressFindingCount { finding, peers })
+        .collect();
+    address_finding_counts.sort_by(|a, b| b.peers.cmp(&a.peers).then(a.finding.cmp(b.finding)));
+
+    let mut unreachable_by_peer: HashMap<String, Vec<String>> = HashMap::new();
+    for address in &unreachable_addresses {
+        unreachable_by_peer
+            .entry(address.peer_id.clone())
+            .or_default()
+            .push(address.address.clone());
+    }
+
+    let peers = chain_peers
+        .into_iter()
+        .map(|peer| {
+            let info = network_discovery.peer_details.get(peer);
+            let agent = info.map(|info| AgentVersion::parse(&info.agent_version));
+            let ip = peer_ips.get(peer).copied();
+            let autonomous_system =
+                ip.and_then(|ip| asn_locator.as_ref().and_then(|locator| locator.locate(ip)));
+            let version = agent.as_ref().and_then(|agent| agent.version);
+            DiscoveredPeer {
+                peer_id: peer.to_string(),
+                role: network_discovery
+                    .peer_role
+                    .get(peer)
+                    .map(ProtocolRole::name),
+                agent_version: info.map(|info| info.agent_version.clone()),
+                implementation: agent.as_ref().map(|agent| agent.implementation.clone()),
+                version: version.map(|version| version.to_string()),
+                commit: agent.as_ref().and_then(|agent| agent.commit.clone()),
+                node_name: agent.as_ref().and_then(|agent| agent.node_name.clone()),
+                outdated: version_policy.outdated_reason(version),
+                best_number: chain_state
+                    .peer(peer)
+                    .filter(|state| state.genesis_hash == genesis_hash)
+                    .map(|state| state.best_number),
+                lagging: lagging.get(peer).copied(),
+                forked: forked.contains(peer),
+                public: info.is_some_and(|info| info.listen_addrs.iter().any(is_public_address)),
+                legacy: network_discovery.legacy_peers.contains(peer),
+                listen_addrs: info
+                    .map(|info| {
+                        info.listen_addrs
+                            .iter()
+                            .map(|addr| addr.to_string())
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                address_findings: findings_by_peer
+                    .remove(&peer.to_string())
+                    .unwrap_or_default(),
+                unreachable_addrs: unreachable_by_peer
+                    .remove(&peer.to_string())
+                    .unwrap_or_default(),
+                subnet: ip.map(|ip| ip_subnet(ip).to_string()),
+                asn: autonomous_system.as_ref().map(|system| system.number),
+                as_organization: autonomous_system.and_then(|system| system.organization),
+                location: geolocated_peers.remove(peer),
+            }
+        })
+        .filter(|peer| !only_authorities || peer.role == Some(ProtocolRole::Authority.name()))
+        .collect::<Vec<_>>();
+
+    // The version distribution of the reported peers.
+    let mut versions: HashMap<(Option<&String>, Option<&String>), VersionCount> = HashMap::new();
+    for peer in &peers {
+        versions
+            .entry((peer.implementation.as_ref(), peer.version.as_ref()))
+            .or_insert_with(|| VersionCount {
+                implementation: peer.implementation.clone(),
+                version: peer.version.clone(),
+                peers: 0,
+                outdated: peer.outdated.is_some(),
+            })
+            .peers += 1;
+    }
+    let mut versions: Vec<_> = versions.into_values().collect();
+    versions.sort_by(|a, b| {
+        b.peers
+            .cmp(&a.peers)
+            .then_with(|| a.implementation.cmp(&b.implementation))
+            .then_with(|| a.version.cmp(&b.version))
+    });
+    let outdated_peers = peers.iter().filter(|peer| peer.outdated.is_some()).count();
+
+    let is_authority = |peer: &DiscoveredPeer| peer.role == Some(ProtocolRole::Authority.name());
+    let countries = group_peers(peers.iter().map(|peer| {
+        let country = peer
+            .location
+            .as_ref()
+            .and_then(|location| location.country.clone());
+        (country, is_authority(peer))
+    }));
+    let providers = asn_locator.is_some().then(|| {
+        group_peers(peers.iter().map(|peer| {
+            let provider = peer.asn.map(|asn| match &peer.as_organization {
+                Some(organization) => format!("AS{asn} {organization}"),
+                None => format!("AS{asn}"),
+            });
+            (provider, is_authority(peer))
+        }))
+    });
+    let subnets = group_peers(
+        peers
+            .iter()
+            .map(|peer| (peer.subnet.clone(), is_authority(peer))),
+    );
+
+    let topology = match (topology, &network_discovery.topology) {
+        (Some((path, format)), Some(graph)) => {
+            std::fs::write(&path, format.export(graph))?;
+            log::info!("Wrote the topology graph to {}", path.display());
+
+            let stats = graph.stats();
+            Some(TopologySummary {
+                path,
+                requested_peers: network_discovery.topology_requests.len(),
+                responders: stats.responders,
+                nodes: stats.nodes,
+                edges: stats.edges,
+                max_in_degree: stats.max_in_degree,
+                mean_in_degree: stats.mean_in_degree,
+                components: stats.components,
+                single_referrer_peers: stats
+                    .single_referrer_peers
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+                unreferenced_responders: stats
+                    .unreferenced_responders
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect(),
+            })
+        }
+        _ => None,
+    };
+
+    let public_peers = infos
+        .values()
+        .filter(|info| info.listen_addrs.iter().any(is_public_address))
+        .count();
+
+    let report = DiscoveryReport {
+        dialed_peers: network_discovery.dialed_peers.len(),
+        discovered_peers: network_discovery.discovered_with_addresses.len(),
+        identified_peers: network_discovery.peer_details.len(),
+        genesis_peers: infos.len(),
+        legacy_peers: network_discovery
+            .legacy_protocol_id
+            .is_some()
+            .then_some(network_discovery.legacy_peers.len()),
+        public_peers,
+        private_peers: infos.len() - public_peers,
+        peers_with_role: network_discovery.peer_role.len(),
+        peers_with_best_block,
+        best_number,
+        lagging_threshold: LAGGING_THRESHOLD,
+        lagging_peers: lagging.len(),
+        forked_peers: forked.len(),
+        peer_store,
+        cities,
+        min_version: version_policy
+            .min_version
+            .map(|version| version.to_string()),
+        bad_versions: version_policy
+            .bad_versions
+            .iter()
+            .map(|version| version.to_string())
+            .collect(),
+        outdated_peers,
+        versions,
+        crawl,
+        topology,
+        countries,
+        providers,
+        subnets,
+        dial_outcomes,
+        unreachable_addresses,
+        address_finding_counts,
+        address_findings,
+        peers,
+        num_cities: num_cities.unwrap_or(10),
+        only_authorities,
+        raw_geolocation,
+    };
+    report.print(output)
+}
+
+/// The peers of the peer store seen during the discovery.
+#[derive(Debug, Serialize)]
+pub struct PeerStoreSummary {
+    /// The number of peers in the peer store.
+    known_peers: usize,
+    /// The number of peers dialed or identified during the discovery.
+    seen_peers: usize,
+    /// The number of peers observed for the first time.
+    new_peers: usize,
+    /// The number of known peers not dialed or identified during the discovery.
+    unseen_peers: usize,
+}
+
+/// The progress of the crawl of the routing tables.
+#[derive(Debug, Serialize)]
+pub struct CrawlSummary {
+    /// The routing tables no longer contain new peers.
+    finished: bool,
+    duration_secs: u64,
+    /// Peers returned by the routing tables.
+    discovered_peers: usize,
+    /// Peers that returned their routing table.
+    crawled_peers: usize,
+    /// Peers that failed every request of their routing table.
+    unreachable_peers: usize,
+}
+
+/// The format of the exported topology graph.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TopologyFormat {
+    /// The GraphML format of graph tools (ie Gephi).
+    #[default]
+    Graphml,
+    /// The Graphviz DOT format.
+    Dot,
+    /// A JSON object with `nodes` and `edges` arrays.
+    Json,
+}
+
+impl TopologyFormat {
+    /// Export the graph in this format.
+    fn export(&self, graph: &TopologyGraph) -> String {
+        match self {
+            TopologyFormat::Graphml => graph.to_graphml(),
+            TopologyFormat::Dot => graph.to_dot(),
+            TopologyFormat::Json => graph.to_json(),
+        }
+    }
+}
+
+/// The statistics of the topology graph built from the routing tables of the peers.
+#[derive(Debug, Serialize)]
+pub struct TopologySummary {
+    /// The file the graph was written to.
+    path: PathBuf,
+    /// Peers that were requested their routing table.
+    requested_peers: usize,
+    /// Peers that responded with their routing table.
+    responders: usize,
+    nodes: usize,
+    edges: usize,
+    max_in_degree: usize,
+    mean_in_degree: f64,
+    /// The size of the weakly connected components, in decreasing order.
+    components: Vec<usize>,
+    /// Peers returned by a single referrer.
+    single_referrer_peers: Vec<String>,
+    /// Responders not returned by any other peer.
+    unreferenced_responders: Vec<String>,
+}
+
+/// The number of peers located in a city.
+#[derive(Debug, Serialize)]
+pub struct CityReport {
+    city: String,
+    peers: usize,
+}
+
+/// The minimum and the known bad versions of the nodes.
+#[derive(Debug, Default, Clone)]
+pub struct VersionPolicy {
+    /// Peers below this version are outdated.
+    pub min_version: Option<Version>,
+    /// Versions with known bad releases.
+    pub bad_versions: Vec<Version>,
+}
+
+impl VersionPolicy {
+    /// Returns the reason the version is outdated, if any.
+    ///
+    /// Unknown versions are not flagged.
+    fn outdated_reason(&self, version: Option<Version>) -> Option<&'static str> {
+        let version = version?;
+        if self.bad_versions.contains(&version) {
+            Some("bad_release")
+        } else if self
+            .min_version
+            .is_some_and(|min_version| version < min_version)
+        {
+            Some("below_min_version")
+        } else {
+            None
+        }
+    }
+}
+
+/// The number of peers running a version.
+#[derive(Debug, Serialize)]
+pub struct VersionCount {
+    implementation: Option<String>,
+    version: Option<String>,
+    peers: usize,
+    /// The version is below the minimum version or a known bad release.
+    outdated: bool,
+}
+
+/// A peer that supports the genesis hash of the chain.
+#[derive(Debug, Serialize)]
+pub struct DiscoveredPeer {
+    peer_id: String,
+    /// The role announced in the block announces handshake.
+    role: Option<&'static str>,
+    /// The agent version reported by the identify protocol.
+    agent_version: Option<String>,
+    /// The implementation parsed from the agent version.
+    implementation: Option<String>,
+    /// The `major.minor.patch` version parsed from the agent version.
+    version: Option<String>,
+    commit: Option<String>,
+    node_name: Option<String>,
+    /// The reason the version is outdated (`below_min_version` or `bad_release`).
+    outdated: Option<&'static str>,
+    /// The last best block number reported by the peer, if connected.
+    best_number: Option<BlockNumber>,
+    /// The number of blocks the peer is behind the best block, if lagging.
+    lagging: Option<BlockNumber>,
+    /// The best block of the peer differs from the majority of peers at the same height.
+    forked: bool,
+    /// The peer listens on at least one public address.
+    public: bool,
+    /// The peer negotiated the legacy protocol names.
+    legacy: bool,
+    listen_addrs: Vec<String>,
+    /// The dialed addresses that never connected.
+    unreachable_addrs: Vec<String>,
+    /// The kinds of the address findings of the peer.
+    address_findings: Vec<&'static str>,
+    /// The /24 (IPv4) or /48 (IPv6) subnet of the public IP of the peer.
+    subnet: Option<String>,
+    /// The autonomous system announcing the public IP of the peer.
+    asn: Option<u32>,
+    /// The organization of the autonomous system (ie the hosting provider).
+    as_organization: Option<String>,
+    location: Option<Location>,
+}
+
+/// The number of dial attempts with the same outcome.
+#[derive(Debug, Serialize)]
+pub struct DialOutcomeCount {
+    outcome: &'static str,
+    dials: usize,
+}
+
+/// A finding of the address analysis.
+#[derive(Debug, Serialize)]
+pub struct AddressFindingEntry {
+    finding: &'static str,
+    /// The finding with the affected address.
+    detail: String,
+}
+
+/// The address findings of a peer.
+#[derive(Debug, Serialize)]
+pub struct PeerAddressFindings {
+    peer_id: String,
+    findings: Vec<AddressFindingEntry>,
+}
+
+/// The number of peers with the same kind of address finding.
+#[derive(Debug, Serialize)]
+pub struct AddressFindingCount {
+    finding: &'static str,
+    peers: usize,
+}
 
-    Ok(())
+/// An address of a peer that was dialed without success.
+#[derive(Debug, Serialize)]
+pub struct UnreachableAddress {
+    peer_id: String,
+    address: String,
+    /// The peer advertises the address in its identify listen addresses.
+    advertised: bool,
+    failures: usize,
+    /// The distinct outcomes of the dial attempts.
+    outcomes: Vec<&'static str>,
+}
+
+/// Report of the `discover-network` command.
+#[derive(Debug, Serialize)]
+pub struct DiscoveryReport {
+    dialed_peers: usize,
+    discovered_peers: usize,
+    /// Peers that responded to the identify protocol.
+    identified_peers: usize,
+    /// Identified peers that support the genesis hash of the chain.
+    genesis_peers: usize,
+    /// Peers that negotiated the legacy protocol names, if a legacy protocol id is provided.
+    legacy_peers: Option<usize>,
+    public_peers: usize,
+    private_peers: usize,
+    peers_with_role: usize,
+    /// Connected peers of our genesis with a known best block.
+    peers_with_best_block: usize,
+    best_number: Option<BlockNumber>,
+    lagging_threshold: BlockNumber,
+    /// Peers lagging more than `lagging_threshold` blocks behind `best_number`.
+    lagging_peers: usize,
+    /// Peers whose best block differs from the majority of peers at the same height.
+    forked_peers: usize,
+    peer_store: Option<PeerStoreSummary>,
+    cities: Vec<CityReport>,
+    /// The peers by country, in decreasing order by the number of peers.
+    countries: Vec<GroupCount>,
+    /// The peers by autonomous system, if an ASN database is provided.
+    providers: Option<Vec<GroupCount>>,
+    /// The peers by subnet, in decreasing order by the number of peers.
+    subnets: Vec<GroupCount>,
+    min_version: Option<String>,
+    bad_versions: Vec<String>,
+    /// Peers below `min_version` or running one of the `bad_versions`.
+    outdated_peers: usize,
+    /// The version distribution of the peers, in decreasing order by the number of peers.
+    versions: Vec<VersionCount>,
+    crawl: Option<CrawlSummary>,
+    topology: Option<TopologySummary>,
+    /// The number of dial attempts by outcome, in decreasing order.
+    dial_outcomes: Vec<DialOutcomeCount>,
+    unreachable_addresses: Vec<UnreachableAddress>,
+    /// The number of peers by kind of address finding, in decreasing order.
+    address_finding_counts: Vec<AddressFindingCount>,
+    address_findings: Vec<PeerAddressFindings>,
+    peers: Vec<DiscoveredPeer>,
+    /// The number of cities printed in the table.
+    #[serde(skip)]
+    num_cities: usize,
+    #[serde(skip)]
+    only_authorities: bool,
+    #[serde(skip)]
+    raw_geolocation: bool,
+}
+
+impl Report for DiscoveryReport {
+    const CSV_HEADER: &'static [&'static str] = &[
+        "peer_id",
+        "role",
+        "agent_version",
+        "implementation",
+        "version",
+        "commit",
+        "node_name",
+        "outdated",
+        "best_number",
+        "lagging",
+        "forked",
+        "public",
+        "legacy",
+        "city",
+        "country",
+        "subnet",
+        "asn",
+        "as_organization",
+        "latitude",
+        "longitude",
+        "listen_addrs",
+        "unreachable_addrs",
+        "address_findings",
+    ];
+
+    fn csv_rows(&self) -> Vec<Vec<String>> {
+        self.peers
+            .iter()
+            .map(|peer| {
+                let location = peer.location.as_ref();
+                vec![
+                    peer.peer_id.clone(),
+                    optional_field(&peer.role),
+                    optional_field(&peer.agent_version),
+                    optional_field(&peer.implementation),
+                    optional_field(&peer.version),
+                    optional_field(&peer.commit),
+                    optional_field(&peer.node_name),
+                    optional_field(&peer.outdated),
+                    optional_field(&peer.best_number),
+                    optional_field(&peer.lagging),
+                    peer.forked.to_string(),
+                    peer.public.to_string(),
+                    peer.legacy.to_string(),
+                    optional_field(&location.and_then(|location| location.city.as_ref())),
+                    optional_field(&location.and_then(|location| location.country.as_ref())),
+                    optional_field(&peer.subnet),
+                    optional_field(&peer.asn),
+                    optional_field(&peer.as_organization),
+                    optional_field(&location.and_then(|location| location.latitude)),
+                    optional_field(&location.and_then(|location| location.longitude)),
+                    peer.listen_addrs.join(" "),
+                    peer.unreachable_addrs.join(" "),
+                    peer.address_findings.join(" "),
+                ]
+            })
+            .collect()
+    }
+
+    fn print_table(&self) {
+        println!("Dialed num={} peers", self.dialed_peers);
+        for count in &self.dial_outcomes {
+            println!("   Dials outcome={} num={}", count.outcome, count.dials);
+        }
+        println!("Discovered num={} peers", self.discovered_peers);
+
+        if let Some(peer_store) = &self.peer_store {
+            println!("Peer store num={} peers", peer_store.known_peers);
+            println!(
+                "  Peers seen in this run num={} new={}",
+                peer_store.seen_peers, peer_store.new_peers
+            );
+            println!(
+                "  Peers not seen in this run num={}",
+                peer_store.unseen_peers
+            );
+        }
+
+        println!("Peers with identity num={}", self.identified_peers);
+        println!(
+            "Peers that support our genesis hash {:?}",
+            self.genesis_peers
+        );
+        if let Some(legacy_peers) = self.legacy_peers {
+            println!("  Peers that negotiated legacy protocols {legacy_peers:?}");
+        }
+        println!("  Peers with public addresses {:?}", self.public_peers);
+        println!("  Peers with private addresses {:?}", self.private_peers);
+        println!("Peers with role associated num={}", self.peers_with_role);
+
+        if let Some(best_number) = self.best_number {
+            println!(
+                "Peers with best block num={} best_number={}",
+                self.peers_with_best_block, best_number
+            );
+            println!(
+                "  Peers lagging more than {} blocks num={}",
+                self.lagging_threshold, self.lagging_peers
+            );
+            println!("  Peers on a fork num={}", self.forked_peers);
+        }
+
+        if self.only_authorities {
+            for peer in &self.peers {
+                println!(
+                    "authority={} version={}",
+                    peer.peer_id,
+                    peer.agent_version.as_deref().unwrap_or("unknown")
+                );
+            }
+        }
+
+        println!("Versions of the peers num={}", self.peers.len());
+        for count in &self.versions {
+            println!(
+                "   Implementation={} version={} peers={}{}",
+                count.implementation.as_deref().unwrap_or("unknown"),
+                count.version.as_deref().unwrap_or("unknown"),
+                count.peers,
+                if count.outdated { " outdated" } else { "" }
+            );
+        }
+        if self.min_version.is_some() || !self.bad_versions.is_empty() {
+            println!(
+                "Outdated peers num={} min_version={} bad_versions={:?}",
+                self.outdated_peers,
+                self.min_version.as_deref().unwrap_or("none"),
+                self.bad_versions
+            );
+            for peer in &self.peers {
+                if let Some(reason) = peer.outdated {
+                    println!(
+                        "   Peer {} version={} reason={reason}",
+                        peer.peer_id,
+                        peer.version.as_deref().unwrap_or("unknown")
+                    );
+                }
+            }
+        }
+
+        if let Some(crawl) = &self.crawl {
+            println!(
+                "Crawled routing tables num={} unreachable={} discovered={} finished={} duration={}s",
+                crawl.crawled_peers,
+                crawl.unreachable_peers,
+                crawl.discovered_peers,
+                crawl.finished,
+                crawl.duration_secs
+            );
+        }
+
+        if let Some(topology) = &self.topology {
+            println!(
+                "Topology graph path={} requested={} responders={}",
+                topology.path.display(),
+                topology.requested_peers,
+                topology.responders
+            );
+            println!(
+                "  Nodes num={} edges={} max_in_degree={} mean_in_degree={:.2}",
+                topology.nodes, topology.edges, topology.max_in_degree, topology.mean_in_degree
+            );
+            println!(
+                "  Connected components num={} largest={}",
+                topology.components.len(),
+                topology.components.first().copied().unwrap_or_default()
+            );
+            println!(
+                "  Peers known through a single referrer num={}",
+                topology.single_referrer_peers.len()
+            );
+            println!(
+                "  Responders not returned by other peers num={}",
+                topology.unreferenced_responders.len()
+            );
+        }
+
+        let advertised: Vec<_> = self
+            .unreachable_addresses
+            .iter()
+            .filter(|address| address.advertised)
+            .collect();
+        println!(
+            "Peers advertising unreachable addresses num={}",
+            advertised
+                .iter()
+                .map(|address| &address.peer_id)
+                .collect::<HashSet<_>>()
+                .len()
+        );
+        for address in advertised {
+            println!(
+                "   Peer {} address={} failures={} outcomes={}",
+                address.peer_id,
+                address.address,
+                address.failures,
+                address.outcomes.join(",")
+            );
+        }
+
+        println!(
+            "Peers with address findings num={}",
+            self.address_findings.len()
+        );
+        for count in &self.address_finding_counts {
+            println!("   Finding={} peers={}", count.finding, count.peers);
+        }
+        for peer in &self.address_findings {
+            println!("   Peer {}", peer.peer_id);
+            for entry in &peer.findings {
+                println!("      {}", entry.detail);
+            }
+        }
+
+        // Print top k cities.
+        for city in self.cities.iter().take(self.num_cities) {
+            println!("   City={} peers={}", city.city, city.peers);
+        }
+
+        let print_groups = |title: &str, label: &str, groups: &[GroupCount]| {
+            println!("{title} num={}", groups.len());
+            for group in groups.iter().take(TOP_GROUPS) {
+                println!(
+                    "   {label}={} peers={} authorities={}",
+                    group.name, group.peers, group.authorities
+                );
+            }
+        };
+        print_groups("Countries", "Country", &self.countries);
+        if let Some(providers) = &self.providers {
+            print_groups("Hosting providers", "Provider", providers);
+        }
+        print_groups("Subnets", "Subnet", &self.subnets);
+
+        if self.raw_geolocation {
+            println!();
+
+            for peer in &self.peers {
+                if let Some(location) = &peer.location {
+                    println!(
+                        "   Peer {} city={} country={} country_code={} latitude={} longitude={} accuracy_radius={}km time_zone={}",
+                        peer.peer_id,
+                        unknown_field(&location.city),
+                        unknown_field(&location.country),
+                        unknown_field(&location.country_code),
+                        unknown_field(&location.latitude),
+                        unknown_field(&location.longitude),
+                        unknown_field(&location.accuracy_radius),
+                        unknown_field(&location.time_zone),
+                    );
+                }
+            }
+        }
+    }
 }
@@ -2,92 +2,330 @@
 // This file is dual-licensed as Apache-2.0 or GPL-3.0.
 // see LICENSE for license details.
 
-use crate::utils::build_swarm;
+use crate::utils::{build_swarm, NotificationProtocols};
+use codec::Decode;
 use futures::StreamExt;
-use libp2p::swarm::SwarmEvent;
-use std::collections::HashMap;
-use std::error::Error;
+use libp2p::{request_response::RequestId, swarm::SwarmEvent, Swarm};
+use std::{collections::HashMap, error::Error, time::Duration};
 use subp2p_explorer::{
-    notifications::behavior::NotificationsToSwarm, peer_behavior::PeerInfoEvent, BehaviourEvent,
-    TRANSACTIONS_INDEX,
+    notifications::{
+        behavior::NotificationsToSwarm,
+        chain_state::DEFAULT_BEST_BLOCK_PEERS,
+        messages::{BlockAnnounce, BlockHash},
+        transactions::{InclusionWatcher, TransactionBroadcaster},
+    },
+    peer_behavior::PeerInfoEvent,
+    requests::block::BlockRequestsEvent,
+    Behaviour, BehaviourEvent,
 };
 
-/// Submit extrinsics on the p2p network.
-pub async fn submit_extrinsics(
-    genesis: String,
-    bootnodes: Vec<String>,
-    extrinsics: String,
-) -> Result<(), Box<dyn Error>> {
-    let mut swarm = build_swarm(genesis, bootnodes)?;
-    let payload = hex::decode(extrinsics.trim_start_matches("0x"))?;
+struct ExtrinsicSubmission {
+    /// Drive the network behavior.
+    swarm: Swarm<Behaviour>,
+    /// Registered notification protocols.
+    protocols: NotificationProtocols,
+    /// Broadcast the extrinsics to peers.
+    broadcaster: TransactionBroadcaster,
+    /// True while the extrinsics are submitted to newly opened substreams.
+    broadcasting: bool,
+    /// Confirm the inclusion of the extrinsics, if requested.
+    watcher: Option<InclusionWatcher>,
+    /// In flight block requests.
+    block_requests: HashMap<RequestId, BlockHash>,
+}
 
-    // Perform the kademlia bootstrap.
-    let local_peer_id = *swarm.local_peer_id();
-    let _query_id = swarm
-        .behaviour_mut()
-        .discovery
-        .get_closest_peers(local_peer_id);
+impl ExtrinsicSubmission {
+    /// Returns true if the broadcast and the inclusion confirmation are done.
+    fn is_complete(&self) -> bool {
+        !self.broadcasting && self.watcher.as_ref().map_or(true, |w| w.is_complete())
+    }
+
+    /// Handle a single swarm event.
+    fn handle_event<E>(&mut self, event: SwarmEvent<BehaviourEvent, E>) {
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Notifications(tx)) => {
+                self.handle_notification(tx)
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::BlockRequests(event)) => {
+                self.handle_block_request(event)
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::PeerInfo(info_event)) => match info_event {
+                PeerInfoEvent::Identified { peer_id, info } => {
+                    log::info!("Peer identified peer_id={:?} info={:?}", peer_id, info);
+                }
+            },
 
-    // Keep track of protocol handlers to submit messages.
-    let mut protocol_senders = HashMap::new();
-    // Close after 30 notifications.
-    let mut close_after = 30;
-    loop {
-        let event = swarm.select_next_some().await;
+            _ => (),
+        }
+    }
 
+    fn handle_notification(&mut self, event: NotificationsToSwarm) {
         match event {
-            SwarmEvent::Behaviour(BehaviourEvent::Notifications(tx)) => match tx {
-                NotificationsToSwarm::CustomProtocolOpen {
+            NotificationsToSwarm::CustomProtocolOpen {
+                peer_id,
+                connection_id,
+                received_handshake,
+                negotiated_name,
+                inbound,
+                index,
+                mut sender,
+            } => {
+                log::info!(
+                    "Protocol open peer={:?} index={:?} name={:?} handshake={:?} inbound={:?}",
                     peer_id,
+                    index,
+                    negotiated_name,
                     received_handshake,
-                    inbound,
+                    inbound
+                );
+
+                if self.broadcasting
+                    && index == self.protocols.transactions
+                    && self
+                        .broadcaster
+                        .on_protocol_open(peer_id, connection_id, &mut sender)
+                {
+                    log::info!("Submit transaction for peer={:?}", peer_id);
+                }
+            }
+            NotificationsToSwarm::CustomProtocolClosed {
+                peer_id,
+                connection_id,
+                index,
+                reason,
+            } => {
+                log::info!(
+                    "Protocol closed peer={:?} index={:?} reason={:?}",
+                    peer_id,
                     index,
-                    sender,
-                } => {
+                    reason
+                );
+
+                if index == self.protocols.transactions {
+                    self.broadcaster.on_protocol_closed(peer_id, connection_id);
+                }
+            }
+            NotificationsToSwarm::NotificationsFlushed {
+                peer_id,
+                connection_id,
+                index,
+                count,
+            } => {
+                if index == self.protocols.transactions
+                    && self.broadcaster.on_flushed(peer_id, connection_id, count)
+                {
                     log::info!(
-                        "Protocol open peer={:?} index={:?} handshake={:?} inbound={:?}",
+                        "Transaction delivered peer={:?} reached={}",
                         peer_id,
-                        received_handshake,
-                        inbound,
-                        index
+                        self.broadcaster.reached(),
                     );
-
-                    protocol_senders.insert((peer_id, index), sender);
-                }
-                NotificationsToSwarm::CustomProtocolClosed { peer_id, index } => {
-                    log::info!("Protocol closed peer={:?} index={:?}", peer_id, index);
                 }
-                NotificationsToSwarm::Notification {
-                    peer_id,
-                    message,
-                    index,
-                } => {
-                    if close_after == 0 {
-                        break;
-                    }
-                    close_after -= 1;
+            }
+            NotificationsToSwarm::Notification {
+                peer_id,
+                message,
+                index,
+            } => {
+                if index == self.protocols.block_announces {
+                    match BlockAnnounce::decode(&mut &message[..]) {
+                        Ok(announce) => {
+                            log::info!(
+                                "Block announce peer={:?} number={:?} hash={:?} best={:?}",
+                                peer_id,
+                                announce.header.number,
+                                announce.hash(),
+                                announce.is_best()
+                            );
 
+                            if let Some(request) = self
+                                .watcher
+                                .as_mut()
+                                .and_then(|watcher| watcher.on_block_announce(&announce))
+                            {
+                                let request_id = self
+                                    .swarm
+                                    .behaviour_mut()
+                                    .block_requests
+                                    .send_request(&peer_id, &request);
+                                self.block_requests.insert(request_id, announce.hash());
+                            }
+                        }
+                        Err(err) => {
+                            log::debug!("Invalid block announce peer={:?} error={:?}", peer_id, err)
+                        }
+                    }
+                } else if index == self.protocols.transactions {
+                    match self.broadcaster.on_transactions(peer_id, &message) {
+                        Ok(echoed) => {
+                            for hash in echoed {
+                                log::info!("Transaction echoed hash={:?} peer={:?}", hash, peer_id);
+                            }
+                        }
+                        Err(err) => {
+                            log::debug!("Invalid transactions peer={:?} error={:?}", peer_id, err)
+                        }
+                    }
+                } else {
                     log::info!(
                         "Protocol notification peer={:?} index={:?} message={:?}",
-                        index,
                         peer_id,
+                        index,
                         message
                     );
+                }
+            }
+            NotificationsToSwarm::PeerBestBlockUpdated {
+                peer_id,
+                best_number,
+                best_hash,
+            } => {
+                log::info!(
+                    "Best block updated peer={:?} number={:?} hash={:?}",
+                    peer_id,
+                    best_number,
+                    best_hash
+                );
+
+                if let Err(err) = self
+                    .swarm
+                    .behaviour()
+                    .notifications
+                    .advance_best_block(DEFAULT_BEST_BLOCK_PEERS)
+                {
+                    log::debug!("Failed to advertise the best block error={:?}", err);
+                }
+            }
+        }
+    }
 
-                    if let Some(sender) = protocol_senders.get_mut(&(peer_id, TRANSACTIONS_INDEX)) {
-                        log::info!("Submit transaction for peer={:?}", peer_id);
+    fn handle_block_request(&mut self, event: BlockRequestsEvent) {
+        let Some(watcher) = self.watcher.as_mut() else {
+            return;
+        };
 
-                        let _ = sender.start_send(payload.clone());
-                    }
+        match event {
+            BlockRequestsEvent::Response {
+                request_id,
+                response: blocks,
+                ..
+            } => {
+                if self.block_requests.remove(&request_id).is_none() {
+                    return;
                 }
-            },
-            SwarmEvent::Behaviour(BehaviourEvent::PeerInfo(info_event)) => match info_event {
-                PeerInfoEvent::Identified { peer_id, info } => {
-                    log::info!("Peer identified peer_id={:?} info={:?}", peer_id, info);
+
+                for inclusion in watcher.on_blocks(&blocks) {
+                    log::info!(
+                        "Transaction included hash={:?} block_number={:?} block_hash={:?}",
+                        inclusion.hash,
+                        inclusion.block_number,
+                        inclusion.block_hash
+                    );
                 }
-            },
+            }
+            BlockRequestsEvent::RequestFailed {
+                peer_id,
+                request_id,
+                error,
+            } => {
+                log::debug!("Block request failed peer={:?} error={:?}", peer_id, error);
 
-            _ => (),
+                if let Some(block_hash) = self.block_requests.remove(&request_id) {
+                    watcher.on_request_failed(&block_hash);
+                }
+            }
+        }
+    }
+
+    /// Drive the network behavior events until the submission is complete.
+    async fn drive_events(&mut self, timeout: Duration, inclusion_timeout: Duration) {
+        let broadcast_deadline = tokio::time::sleep(timeout);
+        tokio::pin!(broadcast_deadline);
+        let inclusion_deadline = tokio::time::sleep(inclusion_timeout);
+        tokio::pin!(inclusion_deadline);
+
+        while !self.is_complete() {
+            tokio::select! {
+                _ = &mut broadcast_deadline, if self.broadcasting => self.broadcasting = false,
+                _ = &mut inclusion_deadline, if self.watcher.is_some() => break,
+                event = self.swarm.select_next_some() => self.handle_event(event),
+            }
+
+            if self.broadcaster.is_complete() {
+                self.broadcasting = false;
+            }
+        }
+    }
+}
+
+/// Submit extrinsics on the p2p network.
+///
+/// The extrinsics are broadcasted until `target_peers` peers received them or the
+/// timeout expires. When `confirm_inclusion` is provided, the announced blocks
+/// are searched for the extrinsics for at most the given duration.
+pub async fn submit_extrinsics(
+    genesis: String,
+    bootnodes: Vec<String>,
+    fork_id: Option<String>,
+    extrinsics: String,
+    target_peers: usize,
+    timeout: Duration,
+    confirm_inclusion: Option<Duration>,
+) -> Result<(), Box<dyn Error>> {
+    let (mut swarm, protocols) = build_swarm(genesis, bootnodes, fork_id)?;
+    let payload = hex::decode(extrinsics.trim_start_matches("0x"))?;
+    let watcher = match confirm_inclusion {
+        Some(_) => Some(
+            InclusionWatcher::new(&payload).map_err(|e| format!("Invalid extrinsics: {}", e))?,
+        ),
+        None => None,
+    };
+    let broadcaster = TransactionBroadcaster::new(payload, target_peers)
+        .map_err(|e| format!("Invalid extrinsics: {}", e))?;
+
+    // Perform the kademlia bootstrap.
+    let local_peer_id = *swarm.local_peer_id();
+    let _query_id = swarm
+        .behaviour_mut()
+        .discovery
+        .get_closest_peers(local_peer_id);
+
+    let mut submission = ExtrinsicSubmission {
+        swarm,
+        protocols,
+        broadcaster,
+        broadcasting: true,
+        watcher,
+        block_requests: HashMap::new(),
+    };
+    submission
+        .drive_events(timeout, confirm_inclusion.unwrap_or_default())
+        .await;
+
+    let report = submission.broadcaster.report();
+    println!(
+        "Extrinsics delivered to {}/{} peers",
+        report.reached.len(),
+        target_peers
+    );
+    for (peer_id, flushes) in &report.reached {
+        println!("  peer={peer_id} flushes={flushes}");
+    }
+    for (hash, peers) in &report.echoes {
+        println!("Extrinsic hash={hash:?} echoed_by={} peers", peers.len());
+        for peer_id in peers {
+            println!("  peer={peer_id}");
+        }
+    }
+
+    if let Some(watcher) = &submission.watcher {
+        for inclusion in watcher.included() {
+            println!(
+                "Extrinsic hash={:?} included in block number={} hash={:?}",
+                inclusion.hash, inclusion.block_number, inclusion.block_hash
+            );
+        }
+        for hash in watcher.pending() {
+            println!("Extrinsic hash={hash:?} not included before the timeout");
         }
     }
 
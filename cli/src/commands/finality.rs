@@ -0,0 +1,291 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+use crate::{commands::storage::VerifiedStorageRead, utils::build_grandpa_swarm};
+use futures::{channel::mpsc, StreamExt};
+use libp2p::{
+    swarm::{ConnectionId, SwarmEvent},
+    PeerId,
+};
+use std::{collections::HashMap, error::Error, time::Duration};
+use subp2p_explorer::{
+    notifications::{
+        behavior::NotificationsToSwarm,
+        chain_state::DEFAULT_BEST_BLOCK_PEERS,
+        grandpa::{
+            AuthorityId, AuthoritySet, GrandpaEvent, GrandpaObserver, RoundNumber, SetId,
+            VoteTarget,
+        },
+        messages::{BlockHash, BlockNumber},
+    },
+    BehaviourEvent,
+};
+
+/// Send our neighbor packet to the peer.
+fn send_neighbor_packet(observer: &GrandpaObserver, sender: &mut mpsc::Sender<Vec<u8>>) {
+    if let Some(packet) = observer.neighbor_packet() {
+        if let Err(err) = sender.try_send(packet) {
+            log::debug!("Failed to send the neighbor packet error={:?}", err);
+        }
+    }
+}
+
+/// Print the number of committed rounds each authority of the set voted in.
+fn print_participation(observer: &GrandpaObserver, authorities: &AuthoritySet) {
+    let committed: Vec<_> = observer
+        .rounds(authorities.set_id)
+        .filter(|(_, votes)| votes.commit.is_some())
+        .map(|(_, votes)| votes.voters())
+        .collect();
+
+    let mut participation: Vec<(AuthorityId, usize)> = authorities
+        .ids()
+        .map(|id| {
+            let rounds = committed
+                .iter()
+                .filter(|voters| voters.contains(id))
+                .count();
+            (*id, rounds)
+        })
+        .collect();
+    participation.sort_by_key(|(id, rounds)| (*rounds, *id));
+
+    println!(
+        "Participation of num={} authorities of set={} over num={} committed rounds",
+        participation.len(),
+        authorities.set_id,
+        committed.len()
+    );
+    for (id, rounds) in participation {
+        println!(
+            "  0x{} voted in {}/{}",
+            hex::encode(id),
+            rounds,
+            committed.len()
+        );
+    }
+}
+
+/// Watch the finality of the chain from the GRANDPA gossip.
+///
+/// The authority set is read from the storage of peers and read again when the peers
+/// advance to a new voter set. Votes and commits of keys outside of the authority set are
+/// dropped. For every commit signed by a supermajority of the authorities, prints the
+/// finalized block, the finality lag behind the best block of peers and the authorities
+/// that were not observed voting.
+pub async fn watch_finality(
+    genesis: String,
+    bootnodes: Vec<String>,
+    fork_id: Option<String>,
+    timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let genesis_hash = BlockHash::from_slice(&hex::decode(genesis.trim_start_matches("0x"))?);
+    let (mut swarm, protocols) = build_grandpa_swarm(genesis, bootnodes, fork_id)?;
+    let grandpa = protocols.grandpa.expect("Grandpa is registered; qed");
+
+    // Perform the kademlia bootstrap.
+    let local_peer_id = *swarm.local_peer_id();
+    let _query_id = swarm
+        .behaviour_mut()
+        .discovery
+        .get_closest_peers(local_peer_id);
+
+    let mut observer = GrandpaObserver::new();
+    // Senders of the open grandpa substreams.
+    let mut senders: HashMap<(PeerId, ConnectionId), mpsc::Sender<Vec<u8>>> = HashMap::new();
+    let mut authorities_read = Some(VerifiedStorageRead::new(AuthoritySet::storage_keys()));
+
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+
+    loop {
+        let event = tokio::select! {
+            _ = &mut deadline => break,
+            event = swarm.select_next_some() => event,
+        };
+
+        if let Some(read) = authorities_read.as_mut() {
+            if let Some(storage) = read.on_event(&mut swarm, &event) {
+                match AuthoritySet::decode_storage(&storage.values) {
+                    Ok(Some(set)) => {
+                        println!(
+                            "Authority set={} with num={} authorities at block #{} ({:?})",
+                            set.set_id,
+                            set.authorities.len(),
+                            storage.number,
+                            storage.hash
+                        );
+                        authorities_read = None;
+                        // Our view moves to the voter set of the authorities.
+                        if observer.set_authorities(set).is_some() {
+                            for sender in senders.values_mut() {
+                                send_neighbor_packet(&observer, sender);
+                            }
+                        }
+                    }
+                    Ok(None) => return Err("The chain does not use the grandpa pallet".into()),
+                    Err(err) => log::debug!("Invalid authority set error={}", err),
+                }
+            }
+        }
+
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Notifications(
+                NotificationsToSwarm::CustomProtocolOpen {
+                    peer_id,
+                    connection_id,
+                    index,
+                    mut sender,
+                    ..
+                },
+            )) if index == grandpa => {
+                log::info!("Grandpa opened peer={:?}", peer_id);
+
+                send_neighbor_packet(&observer, &mut sender);
+                senders.insert((peer_id, connection_id), sender);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Notifications(
+                NotificationsToSwarm::CustomProtocolClosed {
+                    peer_id,
+                    connection_id,
+                    index,
+                    ..
+                },
+            )) if index == grandpa => {
+                senders.remove(&(peer_id, connection_id));
+                if !senders.keys().any(|(peer, _)| *peer == peer_id) {
+                    observer.remove_peer(&peer_id);
+                }
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Notifications(
+                NotificationsToSwarm::Notification {
+                    peer_id,
+                    index,
+                    message,
+                },
+            )) if index == grandpa => {
+                let events = match observer.on_message(peer_id, &message) {
+                    Ok(events) => events,
+                    Err(err) => {
+                        log::debug!("Invalid grandpa message peer={:?} error={}", peer_id, err);
+                        continue;
+                    }
+                };
+
+                for event in events {
+                    match event {
+                        GrandpaEvent::ViewUpdated(view) => {
+                            log::info!(
+                                "View updated round={} set={} finalized=#{}",
+                                view.round,
+                                view.set_id,
+                                view.commit_finalized_height
+                            );
+                            // Peers gossip the votes of a round only to neighbors in the same round.
+                            for sender in senders.values_mut() {
+                                send_neighbor_packet(&observer, sender);
+                            }
+
+                            let outdated = observer
+                                .authorities()
+                                .is_some_and(|set| set.set_id < view.set_id);
+                            if outdated && authorities_read.is_none() {
+                                authorities_read =
+                                    Some(VerifiedStorageRead::new(AuthoritySet::storage_keys()));
+                            }
+                        }
+                        GrandpaEvent::Vote {
+                            set_id,
+                            round,
+                            authority,
+                            vote,
+                        } => {
+                            log::debug!(
+                                "Vote set={} round={} authority=0x{} vote={:?}",
+                                set_id,
+                                round,
+                                hex::encode(authority),
+                                vote
+                            );
+                        }
+                        GrandpaEvent::Commit {
+                            set_id,
+                            round,
+                            target,
+                            signers,
+                        } => print_commit(
+                            &observer,
+                            swarm
+                                .behaviour()
+                                .notifications
+                                .chain_state()
+                                .supported_best_block(&genesis_hash, DEFAULT_BEST_BLOCK_PEERS)
+                                .map(|(number, _)| number),
+                            set_id,
+                            round,
+                            target,
+                            signers,
+                        ),
+                    }
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    println!(
+        "Observed num={} rounds with num={} invalid signatures, num={} votes outside of the authority set and num={} commits without supermajority",
+        observer
+            .view()
+            .map_or(0, |view| observer.rounds(view.set_id).count()),
+        observer.invalid_signatures(),
+        observer.unknown_voters(),
+        observer.rejected_commits()
+    );
+    if let Some(authorities) = observer.authorities() {
+        print_participation(&observer, authorities);
+    }
+
+    Ok(())
+}
+
+/// Print the finalized block of a validated commit together with the authority votes
+/// observed in the round.
+fn print_commit(
+    observer: &GrandpaObserver,
+    best_number: Option<BlockNumber>,
+    set_id: SetId,
+    round: RoundNumber,
+    target: VoteTarget,
+    signers: usize,
+) {
+    // Commits are validated against the authority set of the round.
+    let (Some(votes), Some(authorities)) = (observer.round(set_id, round), observer.authorities())
+    else {
+        return;
+    };
+    let lag = best_number
+        .map(|best| best.saturating_sub(target.target_number).to_string())
+        .unwrap_or_else(|| "?".into());
+    let total = authorities.authorities.len();
+
+    println!(
+        "Round={} set={} finalized #{} ({:?}) lag={} prevotes={}/{} precommits={}/{} signers={}",
+        round,
+        set_id,
+        target.target_number,
+        target.target_hash,
+        lag,
+        votes.prevotes.len(),
+        total,
+        votes.precommits.len(),
+        total,
+        signers
+    );
+
+    for id in votes.missing(authorities.ids()) {
+        println!("  missing 0x{}", hex::encode(id));
+    }
+}
@@ -3,6 +3,12 @@
 // see LICENSE for license details.
 
 pub mod authorities;
+pub mod blocks;
 pub mod bootnodes;
 pub mod discovery;
 pub mod extrinsics;
+pub mod finality;
+pub mod monitor;
+pub mod statements;
+pub mod storage;
+pub mod transactions;
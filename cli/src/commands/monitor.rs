@@ -0,0 +1,239 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+use crate::{
+    commands::discovery::NetworkDiscovery,
+    utils::{build_swarm_with_options, SwarmOptions},
+};
+use prometheus_client::{
+    encoding::text::encode,
+    metrics::{
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{exponential_buckets, Histogram},
+    },
+    registry::Registry,
+};
+use std::{error::Error, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use subp2p_explorer::peer_store::{JsonLinesPeerStore, MemoryPeerStore, PeerStore};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Labels of the metric families.
+type Labels = Vec<(String, String)>;
+
+/// The Prometheus metrics of the network discovery.
+#[derive(Debug, Clone)]
+pub struct DiscoveryMetrics {
+    /// The number of peers discovered by kademlia queries.
+    ///
+    /// Peers forgotten after the rolling window are counted again when rediscovered.
+    pub peers_discovered: Counter,
+    /// The number of discovered peers observed during the rolling window.
+    pub peers_known: Gauge,
+    /// The number of peers reached during the rolling window.
+    pub peers_reachable: Gauge,
+    /// The number of currently connected peers.
+    pub peers_connected: Gauge,
+    /// The number of reached peers by agent version.
+    pub agent_versions: Family<Labels, Gauge>,
+    /// The number of reached peers by role.
+    pub roles: Family<Labels, Gauge>,
+    /// The number of authorities by reachability.
+    pub authorities: Family<Labels, Gauge>,
+    /// The number of dials by outcome.
+    pub dials: Family<Labels, Counter>,
+    /// The number of kademlia queries by outcome.
+    pub dht_queries: Family<Labels, Counter>,
+    /// The duration of the kademlia queries.
+    pub dht_query_duration: Histogram,
+}
+
+impl DiscoveryMetrics {
+    /// Constructs the metrics and registers them in the provided registry.
+    pub fn register(registry: &mut Registry) -> Self {
+        let metrics = DiscoveryMetrics {
+            peers_discovered: Counter::default(),
+            peers_known: Gauge::default(),
+            peers_reachable: Gauge::default(),
+            peers_connected: Gauge::default(),
+            agent_versions: Family::default(),
+            roles: Family::default(),
+            authorities: Family::default(),
+            dials: Family::default(),
+            dht_queries: Family::default(),
+            dht_query_duration: Histogram::new(exponential_buckets(0.25, 2.0, 10)),
+        };
+
+        registry.register(
+            "peers_discovered",
+            "Peers discovered by kademlia queries",
+            metrics.peers_discovered.clone(),
+        );
+        registry.register(
+            "peers_known",
+            "Discovered peers observed during the rolling window",
+            metrics.peers_known.clone(),
+        );
+        registry.register(
+            "peers_reachable",
+            "Peers reached during the rolling window",
+            metrics.peers_reachable.clone(),
+        );
+        registry.register(
+            "peers_connected",
+            "Currently connected peers",
+            metrics.peers_connected.clone(),
+        );
+        registry.register(
+            "agent_versions",
+            "Peers reached during the rolling window by agent version",
+            metrics.agent_versions.clone(),
+        );
+        registry.register(
+            "peer_roles",
+            "Peers reached during the rolling window by role",
+            metrics.roles.clone(),
+        );
+        registry.register(
+            "authorities",
+            "Authorities by reachability during the rolling window",
+            metrics.authorities.clone(),
+        );
+        registry.register("dials", "Dials by outcome", metrics.dials.clone());
+        registry.register(
+            "dht_queries",
+            "Kademlia queries by outcome",
+            metrics.dht_queries.clone(),
+        );
+        registry.register(
+            "dht_query_duration_seconds",
+            "Duration of the kademlia queries",
+            metrics.dht_query_duration.clone(),
+        );
+
+        metrics
+    }
+}
+
+/// Returns the labels of a metric family with a single label.
+pub fn label(name: &str, value: &str) -> Labels {
+    vec![(name.to_string(), value.to_string())]
+}
+
+/// The maximum size of the headers of a metrics request.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// The time allowed to read a metrics request or to write the response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Read the request line and the headers of an HTTP request.
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        if request.len() >= MAX_REQUEST_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Request headers too large",
+            ));
+        }
+
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+    Ok(request)
+}
+
+/// Respond to a single HTTP request with the encoded metrics.
+///
+/// Reading the request and writing the response are bounded by [`REQUEST_TIMEOUT`].
+async fn serve_request(mut stream: TcpStream, registry: &Registry) -> std::io::Result<()> {
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await??;
+    let request = String::from_utf8_lossy(&request);
+
+    let response = if request.starts_with("GET /metrics ") {
+        let mut body = String::new();
+        encode(&mut body, registry).expect("Writing to a string cannot fail; qed");
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+
+    tokio::time::timeout(REQUEST_TIMEOUT, async {
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    })
+    .await?
+}
+
+/// Serve the metrics of the registry on `GET /metrics`.
+async fn serve_metrics(listener: TcpListener, registry: Arc<Registry>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                log::warn!("Failed to accept metrics connection error={:?}", err);
+                continue;
+            }
+        };
+
+        let registry = registry.clone();
+        tokio::spawn(async move {
+            if let Err(err) = serve_request(stream, &registry).await {
+                log::debug!("Failed to serve metrics error={:?}", err);
+            }
+        });
+    }
+}
+
+/// Crawl the p2p network continuously and export the statistics as Prometheus metrics.
+///
+/// The statistics of the reached peers are computed over a rolling window.
+pub async fn monitor_network(
+    genesis: String,
+    bootnodes: Vec<String>,
+    options: SwarmOptions,
+    peer_store: Option<PathBuf>,
+    prometheus_addr: SocketAddr,
+    window: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let mut registry = Registry::with_prefix("subp2p");
+    let metrics = DiscoveryMetrics::register(&mut registry);
+
+    let listener = TcpListener::bind(prometheus_addr).await?;
+    log::info!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+    tokio::spawn(serve_metrics(listener, Arc::new(registry)));
+
+    let legacy_protocol_id = options.legacy_protocol_id.clone();
+    let (swarm, protocols) = build_swarm_with_options(genesis, bootnodes, options)?;
+    let peer_store: Box<dyn PeerStore> = match peer_store {
+        Some(path) => Box::new(JsonLinesPeerStore::open(path)?),
+        None => Box::<MemoryPeerStore>::default(),
+    };
+    let mut network_discovery =
+        NetworkDiscovery::new(swarm, protocols, legacy_protocol_id, peer_store)
+            .with_metrics(metrics, window);
+
+    let seeded = network_discovery.seed_known_peers();
+    if seeded > 0 {
+        log::info!("Seeded num={seeded} peers from the peer store");
+    }
+
+    network_discovery.drive_events().await;
+    Ok(())
+}
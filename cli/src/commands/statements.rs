@@ -0,0 +1,255 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+use crate::utils::build_statement_swarm;
+use futures::StreamExt;
+use libp2p::{swarm::SwarmEvent, PeerId};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    time::Duration,
+};
+use subp2p_explorer::{
+    notifications::{
+        behavior::NotificationsToSwarm,
+        statements::{decode_statements, SignatureVerification, Statement, StatementBroadcaster},
+    },
+    BehaviourEvent,
+};
+
+/// Print the fields of a statement.
+fn print_statement(statement: &Statement, peer_id: Option<PeerId>) {
+    let signer = match statement.verify_signature() {
+        SignatureVerification::Valid(signer) => format!("0x{}", hex::encode(signer)),
+        verification => format!("{verification:?}"),
+    };
+    println!(
+        "Statement hash={:?} signer={} priority={:?} data={} bytes",
+        statement.hash(),
+        signer,
+        statement.priority,
+        statement.data.as_ref().map_or(0, Vec::len)
+    );
+    if let Some(peer_id) = peer_id {
+        println!("  peer={peer_id}");
+    }
+    if let Some(channel) = &statement.channel {
+        println!("  channel=0x{}", hex::encode(channel));
+    }
+    for topic in &statement.topics {
+        println!("  topic=0x{}", hex::encode(topic));
+    }
+}
+
+/// Watch the statements propagated on the p2p network.
+///
+/// Prints every statement the first time it is received, together with the
+/// verified signer.
+pub async fn watch_statements(
+    genesis: String,
+    bootnodes: Vec<String>,
+    fork_id: Option<String>,
+    timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let (mut swarm, protocols) = build_statement_swarm(genesis, bootnodes, fork_id)?;
+    let statement_protocol = protocols
+        .statements
+        .expect("Statements are registered; qed");
+
+    // Perform the kademlia bootstrap.
+    let local_peer_id = *swarm.local_peer_id();
+    let _query_id = swarm
+        .behaviour_mut()
+        .discovery
+        .get_closest_peers(local_peer_id);
+
+    let mut seen = HashSet::new();
+    // The number of statements relayed by each peer.
+    let mut relays: HashMap<PeerId, usize> = HashMap::new();
+
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+
+    loop {
+        let event = tokio::select! {
+            _ = &mut deadline => break,
+            event = swarm.select_next_some() => event,
+        };
+
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Notifications(
+                NotificationsToSwarm::CustomProtocolOpen { peer_id, index, .. },
+            )) if index == statement_protocol => {
+                log::info!("Statement protocol opened peer={:?}", peer_id);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::Notifications(
+                NotificationsToSwarm::Notification {
+                    peer_id,
+                    index,
+                    message,
+                },
+            )) if index == statement_protocol => {
+                let statements = match decode_statements(&message) {
+                    Ok(statements) => statements,
+                    Err(err) => {
+                        log::debug!("Invalid statements peer={:?} error={:?}", peer_id, err);
+                        continue;
+                    }
+                };
+
+                *relays.entry(peer_id).or_default() += statements.len();
+                for statement in statements {
+                    if seen.insert(statement.hash()) {
+                        print_statement(&statement, Some(peer_id));
+                    }
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    println!(
+        "Observed num={} statements from num={} peers",
+        seen.len(),
+        relays.len()
+    );
+    for (peer_id, count) in relays {
+        println!("  peer={peer_id} statements={count}");
+    }
+
+    Ok(())
+}
+
+/// Submit signed statements on the p2p network.
+///
+/// The statements are verified and broadcasted until `target_peers` peers received
+/// them or the timeout expires.
+pub async fn submit_statements(
+    genesis: String,
+    bootnodes: Vec<String>,
+    fork_id: Option<String>,
+    statements: String,
+    target_peers: usize,
+    timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let payload = hex::decode(statements.trim_start_matches("0x"))?;
+    let decoded = decode_statements(&payload).map_err(|e| format!("Invalid statements: {}", e))?;
+    for statement in &decoded {
+        match statement.verify_signature() {
+            SignatureVerification::Valid(_) => (),
+            // Peers drop statements with invalid proofs.
+            SignatureVerification::Invalid => {
+                return Err(
+                    format!("Statement {:?} has an invalid signature", statement.hash()).into(),
+                )
+            }
+            verification => log::warn!(
+                "Cannot verify statement hash={:?} verification={:?}",
+                statement.hash(),
+                verification
+            ),
+        }
+        print_statement(statement, None);
+    }
+    let mut broadcaster = StatementBroadcaster::new(payload, target_peers)?;
+
+    let (mut swarm, protocols) = build_statement_swarm(genesis, bootnodes, fork_id)?;
+    let statement_protocol = protocols
+        .statements
+        .expect("Statements are registered; qed");
+
+    // Perform the kademlia bootstrap.
+    let local_peer_id = *swarm.local_peer_id();
+    let _query_id = swarm
+        .behaviour_mut()
+        .discovery
+        .get_closest_peers(local_peer_id);
+
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+
+    while !broadcaster.is_complete() {
+        let event = tokio::select! {
+            _ = &mut deadline => break,
+            event = swarm.select_next_some() => event,
+        };
+
+        let SwarmEvent::Behaviour(BehaviourEvent::Notifications(event)) = event else {
+            continue;
+        };
+        match event {
+            NotificationsToSwarm::CustomProtocolOpen {
+                peer_id,
+                connection_id,
+                index,
+                mut sender,
+                ..
+            } if index == statement_protocol => {
+                let submitted = broadcaster.on_protocol_open(peer_id, connection_id, &mut sender);
+                log::info!(
+                    "Statement protocol opened peer={:?} submitted={}",
+                    peer_id,
+                    submitted
+                );
+            }
+            NotificationsToSwarm::CustomProtocolClosed {
+                peer_id,
+                connection_id,
+                index,
+                ..
+            } if index == statement_protocol => {
+                broadcaster.on_protocol_closed(peer_id, connection_id);
+            }
+            NotificationsToSwarm::NotificationsFlushed {
+                peer_id,
+                connection_id,
+                index,
+                count,
+            } if index == statement_protocol
+                && broadcaster.on_flushed(peer_id, connection_id, count) =>
+            {
+                log::info!(
+                    "Statements delivered peer={:?} reached={}",
+                    peer_id,
+                    broadcaster.reached()
+                );
+            }
+            NotificationsToSwarm::Notification {
+                peer_id,
+                index,
+                message,
+            } if index == statement_protocol => match decode_statements(&message) {
+                Ok(statements) => {
+                    for hash in broadcaster.on_statements(peer_id, &statements) {
+                        log::info!("Statement echoed hash={:?} peer={:?}", hash, peer_id);
+                    }
+                }
+                Err(err) => {
+                    log::debug!("Invalid statements peer={:?} error={:?}", peer_id, err)
+                }
+            },
+
+            _ => (),
+        }
+    }
+
+    let report = broadcaster.report();
+    println!(
+        "Statements delivered to {}/{} peers",
+        report.reached.len(),
+        target_peers
+    );
+    for (peer_id, flushes) in &report.reached {
+        println!("  peer={peer_id} flushes={flushes}");
+    }
+    for (hash, peers) in &report.echoes {
+        println!("Statement hash={hash:?} echoed_by={} peers", peers.len());
+        for peer_id in peers {
+            println!("  peer={peer_id}");
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,384 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+use crate::utils::build_swarm;
+use futures::StreamExt;
+use libp2p::{request_response::RequestId, swarm::SwarmEvent, PeerId, Swarm};
+use std::{collections::VecDeque, error::Error, time::Duration};
+use subp2p_explorer::{
+    notifications::{
+        behavior::NotificationsToSwarm,
+        chain_state::DEFAULT_BEST_BLOCK_PEERS,
+        messages::{BlockHash, BlockNumber},
+    },
+    requests::{
+        block::{BlockAttributes, BlockRequest, BlockRequestsEvent, Direction, FromBlock},
+        light::{verify_read, LightRequest, LightRequestsEvent, LightResponse},
+        state::{StateRequest, StateRequestsEvent, StoragePrefixQuery},
+    },
+    Behaviour, BehaviourEvent,
+};
+
+/// Storage values verified against the header of a block that several peers agree on.
+#[derive(Debug, Clone)]
+pub struct VerifiedStorage {
+    /// The number of the block.
+    pub number: BlockNumber,
+    /// The hash of the block.
+    pub hash: BlockHash,
+    /// The values of the keys in the order of the request.
+    pub values: Vec<Option<Vec<u8>>>,
+}
+
+/// Read storage keys at the best block of peers.
+///
+/// A single peer could fabricate a header together with the storage. Instead, the block
+/// is the highest best block reported with the same hash by at least
+/// [`DEFAULT_BEST_BLOCK_PEERS`] peers. The header of the block is fetched on the block
+/// request protocol from one of these peers, and its hash is checked against the agreed
+/// hash. Then, the keys are read on the light client protocol and verified against the
+/// state root of the header. Failed requests are retried with the next best block update.
+///
+/// The block is not anchored to a GRANDPA justification, the values are as trustworthy
+/// as the agreement of the peers.
+#[derive(Debug)]
+pub struct VerifiedStorageRead {
+    /// The storage keys to read.
+    keys: Vec<Vec<u8>>,
+    /// The in flight header request with the hash of the block.
+    header_request: Option<(RequestId, BlockHash)>,
+    /// The in flight read request with the number, hash and state root of the block.
+    read_request: Option<(RequestId, BlockNumber, BlockHash, BlockHash)>,
+}
+
+impl VerifiedStorageRead {
+    /// Constructs a new [`VerifiedStorageRead`].
+    pub fn new(keys: Vec<Vec<u8>>) -> Self {
+        VerifiedStorageRead {
+            keys,
+            header_request: None,
+            read_request: None,
+        }
+    }
+
+    /// Drive the read with the events of the swarm.
+    ///
+    /// Returns the verified values once a peer responded with a valid proof.
+    pub fn on_event<E>(
+        &mut self,
+        swarm: &mut Swarm<Behaviour>,
+        event: &SwarmEvent<BehaviourEvent, E>,
+    ) -> Option<VerifiedStorage> {
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Notifications(
+                NotificationsToSwarm::PeerBestBlockUpdated { .. },
+            )) if self.header_request.is_none() && self.read_request.is_none() => {
+                let (_, best_hash, peers) = swarm
+                    .behaviour()
+                    .notifications
+                    .agreed_best_block(DEFAULT_BEST_BLOCK_PEERS)?;
+                // Spread the requests over the peers that agree on the block.
+                let peer_id = peers[rand::random::<usize>() % peers.len()];
+
+                let request = BlockRequest {
+                    fields: BlockAttributes::HEADER,
+                    from: FromBlock::Hash(best_hash),
+                    direction: Direction::Ascending,
+                    max_blocks: 1,
+                };
+                let request_id = swarm
+                    .behaviour_mut()
+                    .block_requests
+                    .send_request(&peer_id, &request);
+                self.header_request = Some((request_id, best_hash));
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::BlockRequests(event)) => match event {
+                BlockRequestsEvent::Response {
+                    peer_id,
+                    request_id,
+                    response: blocks,
+                } if self.header_request.map(|(id, _)| id) == Some(*request_id) => {
+                    let (_, block_hash) = self.header_request.take()?;
+                    // The hash is computed locally, peers cannot provide a different state root.
+                    let Some(header) = blocks
+                        .iter()
+                        .filter_map(|block| block.header.as_ref())
+                        .find(|header| header.hash() == block_hash)
+                    else {
+                        log::debug!("Missing header peer={:?} hash={:?}", peer_id, block_hash);
+                        return None;
+                    };
+
+                    let request = LightRequest::RemoteRead {
+                        block: block_hash,
+                        keys: self.keys.clone(),
+                    };
+                    let request_id = swarm
+                        .behaviour_mut()
+                        .light_requests
+                        .send_request(peer_id, &request);
+                    self.read_request =
+                        Some((request_id, header.number, block_hash, header.state_root));
+                }
+                BlockRequestsEvent::RequestFailed {
+                    peer_id,
+                    request_id,
+                    error,
+                } if self.header_request.map(|(id, _)| id) == Some(*request_id) => {
+                    log::debug!("Header request failed peer={:?} error={}", peer_id, error);
+                    self.header_request = None;
+                }
+                _ => (),
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::LightRequests(event)) => match event {
+                LightRequestsEvent::Response {
+                    peer_id,
+                    request_id,
+                    response,
+                } if self.read_request.map(|(id, ..)| id) == Some(*request_id) => {
+                    let (_, number, hash, state_root) = self.read_request.take()?;
+                    let LightResponse::RemoteRead(Some(proof)) = response else {
+                        log::debug!("Missing read proof peer={:?}", peer_id);
+                        return None;
+                    };
+
+                    match verify_read(&state_root, &self.keys, proof.clone()) {
+                        Ok(values) => {
+                            return Some(VerifiedStorage {
+                                number,
+                                hash,
+                                values,
+                            })
+                        }
+                        Err(err) => {
+                            log::debug!("Invalid read proof peer={:?} error={}", peer_id, err)
+                        }
+                    }
+                }
+                LightRequestsEvent::RequestFailed {
+                    peer_id,
+                    request_id,
+                    error,
+                } if self.read_request.map(|(id, ..)| id) == Some(*request_id) => {
+                    log::debug!("Read request failed peer={:?} error={}", peer_id, error);
+                    self.read_request = None;
+                }
+                _ => (),
+            },
+
+            _ => (),
+        }
+
+        None
+    }
+}
+
+/// Dump the storage under a prefix from peers on the state request protocol.
+///
+/// The requests are sent to the first peer that opens the block announces protocol,
+/// or to the provided peer. Failed requests are retried with the next peer.
+///
+/// By default, the key-values are served by a single peer and printed unverified. When
+/// `proof` is set, storage proofs are requested instead. The header of the block is
+/// fetched and checked against the block hash, then the proofs are verified against its
+/// state root before printing the key-values.
+#[allow(clippy::too_many_arguments)]
+pub async fn dump_storage(
+    genesis: String,
+    bootnodes: Vec<String>,
+    fork_id: Option<String>,
+    peer: Option<PeerId>,
+    block: BlockHash,
+    prefix: Vec<u8>,
+    proof: bool,
+    timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let (mut swarm, protocols) = build_swarm(genesis, bootnodes, fork_id)?;
+
+    // Perform the kademlia bootstrap.
+    let local_peer_id = *swarm.local_peer_id();
+    let _query_id = swarm
+        .behaviour_mut()
+        .discovery
+        .get_closest_peers(local_peer_id);
+
+    let mut query = StoragePrefixQuery::new(block, prefix.clone());
+    // The state root of the block, verifying the proofs.
+    let mut state_root = None;
+    // Peers that accepted our block announces substream.
+    let mut candidates = VecDeque::new();
+    let mut in_flight = None;
+
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+
+    loop {
+        let event = tokio::select! {
+            _ = &mut deadline => return Err("Timeout while fetching the storage".into()),
+            event = swarm.select_next_some() => event,
+        };
+
+        match event {
+            SwarmEvent::Behaviour(BehaviourEvent::Notifications(
+                NotificationsToSwarm::CustomProtocolOpen { peer_id, index, .. },
+            )) if index == protocols.block_announces
+                && peer.map_or(true, |peer| peer == peer_id)
+                && !candidates.contains(&peer_id) =>
+            {
+                candidates.push_back(peer_id);
+            }
+            SwarmEvent::Behaviour(BehaviourEvent::BlockRequests(event)) => match event {
+                BlockRequestsEvent::Response {
+                    peer_id,
+                    request_id,
+                    response: blocks,
+                } if in_flight == Some(request_id) => {
+                    in_flight = None;
+
+                    // The hash is computed locally, peers cannot provide a different state root.
+                    match blocks
+                        .iter()
+                        .filter_map(|block| block.header.as_ref())
+                        .find(|header| header.hash() == block)
+                    {
+                        Some(header) => {
+                            log::info!(
+                                "Header #{} state_root={:?}",
+                                header.number,
+                                header.state_root
+                            );
+                            state_root = Some(header.state_root);
+                            candidates.push_front(peer_id);
+                        }
+                        None => log::info!("Missing header peer={:?} hash={:?}", peer_id, block),
+                    }
+                }
+                BlockRequestsEvent::RequestFailed {
+                    peer_id,
+                    request_id,
+                    error,
+                } if in_flight == Some(request_id) => {
+                    log::info!("Header request failed peer={:?} error={}", peer_id, error);
+                    in_flight = None;
+                }
+                _ => (),
+            },
+            SwarmEvent::Behaviour(BehaviourEvent::StateRequests(event)) => match event {
+                StateRequestsEvent::Response {
+                    peer_id,
+                    request_id,
+                    response,
+                } if in_flight == Some(request_id) => {
+                    in_flight = None;
+
+                    let collected = query.entries().len();
+                    let valid = match state_root {
+                        Some(state_root) => {
+                            let result = match response.proof.as_ref() {
+                                Some(proof) => query.on_proof(&state_root, proof),
+                                None => Ok(0),
+                            };
+                            match result {
+                                // Peers that do not make progress are not asked again.
+                                Ok(0) if !query.is_complete() => {
+                                    log::info!("Truncated state proof peer={:?}", peer_id);
+                                    false
+                                }
+                                Ok(_) => true,
+                                Err(err) => {
+                                    log::info!(
+                                        "Invalid state proof peer={:?} error={}",
+                                        peer_id,
+                                        err
+                                    );
+                                    false
+                                }
+                            }
+                        }
+                        None => {
+                            query.on_response(&response);
+                            true
+                        }
+                    };
+
+                    if valid {
+                        log::info!(
+                            "State response peer={:?} entries={}",
+                            peer_id,
+                            query.entries().len() - collected
+                        );
+
+                        if query.is_complete() {
+                            let label = match state_root {
+                                Some(_) => "verified against the state root",
+                                None => "unverified, served by a single peer",
+                            };
+                            println!(
+                                "Storage prefix=0x{} at block={:?} contains num={} entries ({})",
+                                hex::encode(&prefix),
+                                block,
+                                query.entries().len(),
+                                label
+                            );
+                            for (key, value) in query.entries() {
+                                println!("  0x{} => 0x{}", hex::encode(key), hex::encode(value));
+                            }
+                            return Ok(());
+                        }
+
+                        // Continue the query with the same peer.
+                        candidates.push_front(peer_id);
+                    }
+                }
+                StateRequestsEvent::RequestFailed {
+                    peer_id,
+                    request_id,
+                    error,
+                } if in_flight == Some(request_id) => {
+                    log::info!("State request failed peer={:?} error={}", peer_id, error);
+                    in_flight = None;
+                }
+                _ => (),
+            },
+
+            _ => (),
+        }
+
+        if in_flight.is_none() {
+            if let Some(peer_id) = candidates.pop_front() {
+                // The header provides the state root to verify the proofs.
+                if proof && state_root.is_none() {
+                    let request = BlockRequest {
+                        fields: BlockAttributes::HEADER,
+                        from: FromBlock::Hash(block),
+                        direction: Direction::Ascending,
+                        max_blocks: 1,
+                    };
+                    let request_id = swarm
+                        .behaviour_mut()
+                        .block_requests
+                        .send_request(&peer_id, &request);
+                    in_flight = Some(request_id);
+                    continue;
+                }
+
+                let request = match query.next_request() {
+                    Some(request) if proof => StateRequest {
+                        no_proof: false,
+                        ..request
+                    },
+                    Some(request) => request,
+                    None => return Ok(()),
+                };
+                log::info!("State request peer={:?} request={:?}", peer_id, request);
+
+                let request_id = swarm
+                    .behaviour_mut()
+                    .state_requests
+                    .send_request(&peer_id, &request);
+                in_flight = Some(request_id);
+            }
+        }
+    }
+}
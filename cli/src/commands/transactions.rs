@@ -0,0 +1,163 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+use crate::utils::build_swarm;
+use futures::StreamExt;
+use libp2p::swarm::SwarmEvent;
+use std::{error::Error, time::Duration};
+use subp2p_explorer::{
+    notifications::{
+        behavior::NotificationsToSwarm,
+        transactions::{TransactionEvent, TransactionTracker},
+    },
+    BehaviourEvent,
+};
+
+/// Upper bounds of the latency histogram buckets.
+const LATENCY_BUCKETS: [Duration; 8] = [
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+    Duration::from_secs(30),
+];
+
+/// Interval at which the histogram is printed.
+const REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Transactions are forgotten after this interval.
+const TRANSACTION_MAX_AGE: Duration = Duration::from_secs(5 * 60);
+
+/// Histogram of the propagation latencies.
+#[derive(Debug, Default)]
+struct LatencyHistogram {
+    /// Number of latencies per bucket of [`LATENCY_BUCKETS`].
+    buckets: [usize; LATENCY_BUCKETS.len()],
+    /// Latencies above the highest bucket.
+    overflow: usize,
+}
+
+impl LatencyHistogram {
+    /// Record a propagation latency.
+    fn record(&mut self, latency: Duration) {
+        match LATENCY_BUCKETS.iter().position(|bound| latency <= *bound) {
+            Some(index) => self.buckets[index] += 1,
+            None => self.overflow += 1,
+        }
+    }
+
+    /// Print the histogram.
+    fn print(&self) {
+        let total = self.buckets.iter().sum::<usize>() + self.overflow;
+        println!("Propagation latency num={total}");
+        if total == 0 {
+            return;
+        }
+
+        let print_bucket = |label: String, count: usize| {
+            let bar = "#".repeat(count * 50 / total);
+            println!("  {label:>10} {count:>8} {bar}");
+        };
+
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.buckets) {
+            print_bucket(format!("<={bound:?}"), count);
+        }
+        print_bucket(
+            format!(">{:?}", LATENCY_BUCKETS[LATENCY_BUCKETS.len() - 1]),
+            self.overflow,
+        );
+    }
+}
+
+/// Watch the transactions propagated on the p2p network.
+pub async fn watch_transactions(
+    genesis: String,
+    bootnodes: Vec<String>,
+    fork_id: Option<String>,
+    timeout: Duration,
+) -> Result<(), Box<dyn Error>> {
+    let (mut swarm, protocols) = build_swarm(genesis, bootnodes, fork_id)?;
+
+    // Perform the kademlia bootstrap.
+    let local_peer_id = *swarm.local_peer_id();
+    let _query_id = swarm
+        .behaviour_mut()
+        .discovery
+        .get_closest_peers(local_peer_id);
+
+    let mut tracker = TransactionTracker::new();
+    let mut histogram = LatencyHistogram::default();
+    let mut report = tokio::time::interval(REPORT_INTERVAL);
+    report.tick().await;
+
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+
+    loop {
+        tokio::select! {
+            _ = &mut deadline => break,
+            _ = report.tick() => {
+                tracker.prune(TRANSACTION_MAX_AGE);
+                histogram.print();
+            }
+            event = swarm.select_next_some() => {
+                let SwarmEvent::Behaviour(BehaviourEvent::Notifications(
+                    NotificationsToSwarm::Notification {
+                        peer_id,
+                        index,
+                        message,
+                    },
+                )) = event
+                else {
+                    continue;
+                };
+
+                if index != protocols.transactions {
+                    continue;
+                }
+
+                match tracker.on_transactions(peer_id, &message) {
+                    Ok(events) => {
+                        for event in events {
+                            handle_event(event, &mut histogram);
+                        }
+                    }
+                    Err(err) => {
+                        log::debug!("Invalid transactions peer={:?} error={:?}", peer_id, err);
+                    }
+                }
+            }
+        }
+    }
+
+    println!();
+    histogram.print();
+
+    Ok(())
+}
+
+/// Print the live feed of transactions.
+fn handle_event(event: TransactionEvent, histogram: &mut LatencyHistogram) {
+    match event {
+        TransactionEvent::FirstSeen { hash, peer_id } => {
+            println!("Transaction hash={hash:?} first_peer={peer_id}");
+        }
+        TransactionEvent::Relayed {
+            hash,
+            peer_id,
+            latency,
+        } => {
+            log::info!(
+                "Transaction relayed hash={:?} peer={:?} latency={:?}",
+                hash,
+                peer_id,
+                latency
+            );
+            histogram.record(latency);
+        }
+    }
+}
@@ -3,14 +3,36 @@
 // see LICENSE for license details.
 
 mod commands;
+mod report;
 mod utils;
 
 use clap::Parser as ClapParser;
 use commands::{
-    authorities::discover_authorities, bootnodes::verify_bootnodes, discovery::discover_network,
+    authorities::discover_authorities,
+    blocks::fetch_blocks,
+    bootnodes::{resolve_genesis, verify_bootnodes},
+    discovery::{discover_network, TopologyFormat, VersionPolicy},
     extrinsics::submit_extrinsics,
+    finality::watch_finality,
+    monitor::monitor_network,
+    statements::{submit_statements, watch_statements},
+    storage::dump_storage,
+    transactions::watch_transactions,
 };
-use std::{error::Error, io::Read, path::PathBuf};
+use libp2p::PeerId;
+use report::OutputFormat;
+use std::{error::Error, path::PathBuf};
+use subp2p_explorer::{
+    address_hygiene::DEFAULT_SHARED_IP_THRESHOLD,
+    agent_version::Version,
+    chain_spec::{ChainSpec, ChainSpecError},
+    notifications::messages::BlockHash,
+    requests::block::{
+        BlockAttributes, BlockRequest, Direction, FromBlock, MAX_BLOCKS_IN_RESPONSE,
+    },
+    trie::StateVersion,
+};
+use utils::SwarmOptions;
 
 /// Command for interacting with the CLI.
 #[derive(Debug, ClapParser)]
@@ -18,24 +40,27 @@ enum Command {
     Authorities(Authorities),
     SendExtrinisic(SendExtrinisicOpts),
     DiscoverNetwork(DiscoverNetworkOpts),
+    Monitor(MonitorOpts),
     VerifyBootnodes(BootnodesOpts),
+    WatchTransactions(WatchTransactionsOpts),
+    FetchBlocks(FetchBlocksOpts),
+    DumpStorage(DumpStorageOpts),
+    WatchFinality(WatchFinalityOpts),
+    SubmitStatements(SubmitStatementsOpts),
+    WatchStatements(WatchStatementsOpts),
 }
 
 /// Discover the authorities of the p2p network.
 #[derive(Debug, ClapParser)]
 pub struct Authorities {
     /// The URL of the chain RPC endpoint.
-    #[clap(long, short)]
-    url: String,
-    /// Hex-encoded genesis hash of the chain.
     ///
-    /// For example, "781e4046b4e8b5e83d33dde04b32e7cb5d43344b1f19b574f6d31cbbd99fe738"
+    /// When omitted, the authorities are read from the storage of peers and
+    /// verified against the state root of their best block.
     #[clap(long, short)]
-    genesis: String,
-    /// Bootnodes of the chain, must contain a multiaddress together with the peer ID.
-    /// For example, "/ip4/127.0.0.1/tcp/30333/ws/p2p/12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp".
-    #[clap(long, use_value_delimiter = true, value_parser)]
-    bootnodes: Vec<String>,
+    url: Option<String>,
+    #[clap(flatten)]
+    chain: ChainOpts,
     /// The number of seconds the authority discovery process should run for.
     #[clap(long, short, value_parser = parse_duration)]
     timeout: std::time::Duration,
@@ -46,42 +71,44 @@ pub struct Authorities {
     /// - "polkadot" for Polkadot
     /// - "substrate" for Substrate
     /// - "kusama" for Kusama
-    #[clap(long, short)]
-    address_format: String,
+    ///
+    /// Defaults to the `ss58Format` of the chain spec.
+    #[clap(long, short, value_parser = parse_address_format)]
+    address_format: Option<u16>,
     /// Print the raw identity list of discovered peers.
     #[clap(long, short)]
     raw_output: bool,
+    /// The output format of the report.
+    #[clap(long, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
 }
 
 /// Send extrinsic on the p2p network.
 #[derive(Debug, ClapParser)]
 pub struct SendExtrinisicOpts {
-    /// Hex-encoded genesis hash of the chain.
-    ///
-    /// For example, "781e4046b4e8b5e83d33dde04b32e7cb5d43344b1f19b574f6d31cbbd99fe738"
-    #[clap(long, short)]
-    genesis: String,
-    /// Bootnodes of the chain, must contain a multiaddress together with the peer ID.
-    /// For example, "/ip4/127.0.0.1/tcp/30333/ws/p2p/12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp".
-    #[clap(long, use_value_delimiter = true, value_parser)]
-    bootnodes: Vec<String>,
+    #[clap(flatten)]
+    chain: ChainOpts,
     /// Hex-encoded scale-encoded vector of extrinsics to submit to peers.
     #[clap(long, short)]
     extrinsics: String,
+    /// The number of peers that should receive the extrinsics.
+    #[clap(long, short, default_value_t = 30)]
+    peers: usize,
+    /// The maximum number of seconds the extrinsics should be broadcasted for.
+    #[clap(long, short, value_parser = parse_duration, default_value = "60")]
+    timeout: std::time::Duration,
+    /// Confirm the inclusion of the extrinsics from the p2p network.
+    ///
+    /// The number of seconds to wait for the extrinsics to be included in a block.
+    #[clap(long, value_parser = parse_duration)]
+    confirm_inclusion: Option<std::time::Duration>,
 }
 
 /// Discover the p2p network.
 #[derive(Debug, ClapParser)]
 pub struct DiscoverNetworkOpts {
-    /// Hex-encoded genesis hash of the chain.
-    ///
-    /// For example, "781e4046b4e8b5e83d33dde04b32e7cb5d43344b1f19b574f6d31cbbd99fe738"
-    #[clap(long, short)]
-    genesis: String,
-    /// Bootnodes of the chain, must contain a multiaddress together with the peer ID.
-    /// For example, "/ip4/127.0.0.1/tcp/30333/ws/p2p/12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp".
-    #[clap(long, use_value_delimiter = true, value_parser)]
-    bootnodes: Vec<String>,
+    #[clap(flatten)]
+    chain: ChainOpts,
     /// The number of cities to print in decreasing order by the number of peers.
     ///
     /// Defaults to 10.
@@ -93,11 +120,245 @@ pub struct DiscoverNetworkOpts {
     /// Show only authorities.
     #[clap(long, short)]
     only_authorities: bool,
+    /// Flag the peers running a version below the provided version as outdated.
+    ///
+    /// For example, "1.5.0".
+    #[clap(long)]
+    min_version: Option<Version>,
+    /// Flag the peers running one of the provided versions as outdated.
+    ///
+    /// For example, "1.4.0,1.4.1" for known bad releases.
+    #[clap(long, use_value_delimiter = true, value_parser)]
+    bad_versions: Vec<Version>,
+    /// The legacy protocol id of the chain, negotiated with nodes that predate
+    /// the genesis hash protocol names.
+    ///
+    /// For example, "dot" for `/dot/kad` and `/dot/block-announces/1`.
+    #[clap(long)]
+    legacy_protocol_id: Option<String>,
+    /// Record the observed peers in the provided JSON-lines file.
+    ///
+    /// The peers known from previous runs seed the discovery.
+    #[clap(long, value_parser)]
+    peer_store: Option<PathBuf>,
+    /// Request the routing table of every discovered peer and write the
+    /// resulting topology graph to the provided file.
+    #[clap(long, value_parser)]
+    topology: Option<PathBuf>,
+    /// The format of the topology graph.
+    #[clap(long, value_enum, default_value_t = TopologyFormat::Graphml)]
+    topology_format: TopologyFormat,
+    /// Crawl the full routing table of every peer instead of randomly walking the DHT.
+    ///
+    /// The discovery stops once the routing tables no longer contain new peers,
+    /// or when the timeout expires.
+    #[clap(long)]
+    crawl: bool,
+    /// Flag the public IPs used by at least this many distinct peers.
+    #[clap(long, default_value_t = DEFAULT_SHARED_IP_THRESHOLD)]
+    shared_ip_threshold: usize,
+    /// Attribute the peers to hosting providers with a GeoLite2-ASN `.mmdb` database.
+    #[clap(long, value_parser)]
+    asn_db: Option<PathBuf>,
+    /// The output format of the report.
+    #[clap(long, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
     /// The number of seconds the discovery process should run for.
     #[clap(long, short, value_parser = parse_duration)]
     timeout: std::time::Duration,
 }
 
+/// Crawl the p2p network continuously and export Prometheus metrics.
+///
+/// The metrics are served on `http://{prometheus_addr}/metrics`.
+#[derive(Debug, ClapParser)]
+pub struct MonitorOpts {
+    #[clap(flatten)]
+    chain: ChainOpts,
+    /// The legacy protocol id of the chain, negotiated with nodes that predate
+    /// the genesis hash protocol names.
+    #[clap(long)]
+    legacy_protocol_id: Option<String>,
+    /// Record the observed peers in the provided JSON-lines file.
+    #[clap(long, value_parser)]
+    peer_store: Option<PathBuf>,
+    /// The address of the Prometheus metrics endpoint.
+    #[clap(long, default_value = "127.0.0.1:9615")]
+    prometheus_addr: std::net::SocketAddr,
+    /// The number of seconds of the rolling window of the reached peers statistics.
+    #[clap(long, value_parser = parse_duration, default_value = "3600")]
+    window: std::time::Duration,
+}
+
+/// Watch the transactions propagated on the p2p network.
+///
+/// Prints a live feed of the transactions and a histogram of the propagation latency.
+#[derive(Debug, ClapParser)]
+pub struct WatchTransactionsOpts {
+    #[clap(flatten)]
+    chain: ChainOpts,
+    /// The number of seconds the transactions should be watched for.
+    #[clap(long, short, value_parser = parse_duration)]
+    timeout: std::time::Duration,
+}
+
+/// Watch the finality of the chain from the GRANDPA gossip.
+///
+/// Prints the finality lag and the authorities missing from every round, and a
+/// participation summary of the authorities at the end.
+#[derive(Debug, ClapParser)]
+pub struct WatchFinalityOpts {
+    #[clap(flatten)]
+    chain: ChainOpts,
+    /// The number of seconds the finality should be watched for.
+    #[clap(long, short, value_parser = parse_duration)]
+    timeout: std::time::Duration,
+}
+
+/// Submit signed statements on the p2p network.
+#[derive(Debug, ClapParser)]
+pub struct SubmitStatementsOpts {
+    #[clap(flatten)]
+    chain: ChainOpts,
+    /// Hex-encoded scale-encoded vector of signed statements to submit to peers.
+    #[clap(long, short)]
+    statements: String,
+    /// The number of peers that should receive the statements.
+    #[clap(long, short, default_value_t = 30)]
+    peers: usize,
+    /// The maximum number of seconds the statements should be broadcasted for.
+    #[clap(long, short, value_parser = parse_duration, default_value = "60")]
+    timeout: std::time::Duration,
+}
+
+/// Watch the statements propagated on the p2p network.
+#[derive(Debug, ClapParser)]
+pub struct WatchStatementsOpts {
+    #[clap(flatten)]
+    chain: ChainOpts,
+    /// The number of seconds the statements should be watched for.
+    #[clap(long, short, value_parser = parse_duration)]
+    timeout: std::time::Duration,
+}
+
+/// Fetch blocks from peers on the block request protocol.
+#[derive(Debug, ClapParser)]
+pub struct FetchBlocksOpts {
+    #[clap(flatten)]
+    chain: ChainOpts,
+    /// Request the blocks from this peer only.
+    ///
+    /// By default, the blocks are requested from the first peer that accepts our connection.
+    #[clap(long, short)]
+    peer: Option<PeerId>,
+    /// The first block to fetch, either a block number or a hex-encoded block hash.
+    #[clap(long, short, value_parser = parse_from_block)]
+    from: FromBlock,
+    /// The number of blocks to fetch.
+    #[clap(long, short, default_value_t = 1)]
+    count: u32,
+    /// Fetch the parents of the first block instead of its children.
+    #[clap(long)]
+    descending: bool,
+    /// Fetch the block bodies.
+    #[clap(long)]
+    body: bool,
+    /// Fetch the block justifications.
+    #[clap(long)]
+    justifications: bool,
+    /// Print the hex-encoded extrinsics.
+    #[clap(long, short)]
+    raw_output: bool,
+    /// The maximum number of seconds to wait for the blocks.
+    #[clap(long, short, value_parser = parse_duration, default_value = "60")]
+    timeout: std::time::Duration,
+}
+
+impl FetchBlocksOpts {
+    /// Returns the block request of the provided options.
+    fn request(&self) -> Result<BlockRequest, Box<dyn Error>> {
+        if self.count == 0 || self.count > MAX_BLOCKS_IN_RESPONSE {
+            return Err(format!("`--count` must be between 1 and {MAX_BLOCKS_IN_RESPONSE}").into());
+        }
+
+        let mut fields = BlockAttributes::HEADER;
+        if self.body {
+            fields = fields | BlockAttributes::BODY;
+        }
+        if self.justifications {
+            fields = fields | BlockAttributes::JUSTIFICATION;
+        }
+
+        Ok(BlockRequest {
+            fields,
+            from: self.from,
+            direction: if self.descending {
+                Direction::Descending
+            } else {
+                Direction::Ascending
+            },
+            max_blocks: self.count,
+        })
+    }
+}
+
+/// Dump the storage of a block from peers on the state request protocol.
+#[derive(Debug, ClapParser)]
+pub struct DumpStorageOpts {
+    #[clap(flatten)]
+    chain: ChainOpts,
+    /// Request the storage from this peer only.
+    ///
+    /// By default, the storage is requested from the first peer that accepts our connection.
+    #[clap(long, short)]
+    peer: Option<PeerId>,
+    /// Hex-encoded hash of the block.
+    ///
+    /// Peers only serve the state of recent blocks, unless they run as archive nodes.
+    #[clap(long, short, value_parser = parse_block_hash)]
+    block: BlockHash,
+    /// Hex-encoded prefix of the storage keys.
+    ///
+    /// For example, "26aa394eea5630e07c48ae0c9558cef7" for the `System` pallet.
+    /// Defaults to the entire storage.
+    #[clap(long, default_value = "")]
+    prefix: String,
+    /// Request storage proofs instead of the key-values.
+    ///
+    /// The proofs are verified against the state root of the block header. Without
+    /// this flag, the key-values are printed as served by a single peer.
+    #[clap(long)]
+    proof: bool,
+    /// The maximum number of seconds to wait for the storage.
+    #[clap(long, short, value_parser = parse_duration, default_value = "60")]
+    timeout: std::time::Duration,
+}
+
+fn parse_block_hash(arg: &str) -> Result<BlockHash, String> {
+    let bytes = hex::decode(arg.trim_start_matches("0x")).map_err(|e| e.to_string())?;
+    if bytes.len() != BlockHash::len_bytes() {
+        return Err(format!("Invalid block hash length: {}", bytes.len()));
+    }
+    Ok(BlockHash::from_slice(&bytes))
+}
+
+fn parse_from_block(arg: &str) -> Result<FromBlock, String> {
+    if arg.starts_with("0x") {
+        return parse_block_hash(arg).map(FromBlock::Hash);
+    }
+
+    arg.parse()
+        .map(FromBlock::Number)
+        .map_err(|e| format!("Invalid block number: {}", e))
+}
+
+fn parse_address_format(arg: &str) -> Result<u16, String> {
+    let format_registry = ss58_registry::Ss58AddressFormatRegistry::try_from(arg)
+        .map_err(|err| format!("Cannot parse the provided address format: {:?}", err))?;
+    let version: ss58_registry::Ss58AddressFormat = format_registry.into();
+    Ok(version.prefix())
+}
+
 fn parse_duration(arg: &str) -> Result<std::time::Duration, std::num::ParseIntError> {
     let seconds = arg.parse()?;
     Ok(std::time::Duration::from_secs(seconds))
@@ -107,92 +368,301 @@ fn parse_duration(arg: &str) -> Result<std::time::Duration, std::num::ParseIntEr
 ///
 /// This will attempt to connect ot each provided bootnode and
 #[derive(Debug, ClapParser)]
+#[command(mut_arg("genesis", |arg| arg.long_help(
+    "Hex-encoded genesis hash of the chain.\n\n\
+     When this is provided, the supported p2p protocols of the bootnodes will be \
+     verified against the provided genesis hash.",
+)))]
 pub struct BootnodesOpts {
-    /// Bootnodes of the chain, must contain a multiaddress together with the peer ID.
+    #[clap(flatten)]
+    chain: ChainOpts,
+    /// The output format of the report.
+    #[clap(long, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
+}
+
+/// The chain options shared by every command.
+///
+/// The genesis hash, bootnodes and fork id default to the values of the chain spec.
+#[derive(Debug, clap::Args)]
+pub struct ChainOpts {
+    /// The chain spec of the chain.
     ///
-    /// For example, "/ip4/127.0.0.1/tcp/30333/ws/p2p/12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp".
-    #[clap(long, use_value_delimiter = true, value_parser)]
-    bootnodes: Vec<String>,
-    /// Hex-encoded genesis hash of the chain.
+    /// The genesis hash is computed from the genesis storage of a raw chain spec.
+    /// The bootnodes of the chain spec are replaced by `--bootnodes`, if provided.
+    #[clap(long, value_parser)]
+    chain_spec: Option<PathBuf>,
+    /// The state version of the genesis storage of the chain spec (0 or 1).
     ///
-    /// When this is provided, the supported p2p protocols of the bootnodes will be
-    /// verified against the provided genesis hash.
+    /// Defaults to the state version of the runtime code. When the runtime code is
+    /// compressed, the state version is unknown: the bootnodes are dialed and the
+    /// genesis hash is picked from the p2p protocols of the first one that responds.
+    /// Provide the state version to compute the genesis hash without this probe.
+    #[clap(long, value_parser = clap::value_parser!(u8).range(0..=1))]
+    state_version: Option<u8>,
+    /// Hex-encoded genesis hash of the chain.
     ///
     /// For example, "781e4046b4e8b5e83d33dde04b32e7cb5d43344b1f19b574f6d31cbbd99fe738"
     #[clap(long, short)]
     genesis: Option<String>,
-
-    /// Verify the bootnodes using the provided chain spec.
+    /// Bootnodes of the chain, must contain a multiaddress together with the peer ID.
+    /// For example, "/ip4/127.0.0.1/tcp/30333/ws/p2p/12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp".
+    #[clap(long, use_value_delimiter = true, value_parser)]
+    bootnodes: Vec<String>,
+    /// The fork id of the chain, for chains relaunched with the same genesis hash.
     ///
-    /// This is incompatible with `--bootnodes`.
-    #[clap(long, value_parser)]
-    chain_spec: Option<PathBuf>,
+    /// The protocol names become `/{genesis}/{fork_id}/..`.
+    #[clap(long)]
+    fork_id: Option<String>,
 }
 
-impl BootnodesOpts {
-    /// Verify the bootnodes.
-    pub async fn verify_bootnodes(&self) -> Result<(), Box<dyn Error>> {
-        match (&self.bootnodes, &self.genesis, &self.chain_spec) {
-            (bootnodes, _, Some(_)) if !bootnodes.is_empty() => {
-                Err("`--bootnodes` is incompatible with `--chain-spec`".into())
-            }
-            (bootnodes, _, None) => verify_bootnodes(bootnodes.clone(), self.genesis.clone()).await,
-            (_, genesis, Some(spec)) => {
-                let mut file = std::fs::File::open(spec)?;
-                let mut bytes = Vec::new();
-                file.read_to_end(&mut bytes)?;
-
-                let spec = serde_json::from_slice::<serde_json::Value>(&bytes)
-                    .map_err(|e| format!("Invalid chain spec: {}", e))?;
-
-                let bootnodes = spec
-                    .get("bootNodes")
-                    .ok_or("Missing `bootNodes`")?
-                    .as_array()
-                    .ok_or("Invalid `bootNodes` format, expected array")?
-                    .iter()
-                    .map(|node| {
-                        node.as_str()
-                            .map(|s| s.to_string())
-                            .ok_or("Invalid `bootNodes` format, expected string")
-                    })
-                    .collect::<Result<Vec<_>, _>>()?;
-
-                verify_bootnodes(bootnodes, genesis.clone()).await
-            }
-        }
+/// The chain details resolved from the [`ChainOpts`].
+#[derive(Debug, Default)]
+pub struct Chain {
+    /// Hex-encoded genesis hash of the chain.
+    genesis: Option<String>,
+    /// Bootnodes of the chain.
+    bootnodes: Vec<String>,
+    /// The fork id of the chain.
+    fork_id: Option<String>,
+    /// The legacy protocol id of the chain.
+    protocol_id: Option<String>,
+    /// The SS58 address format of the chain.
+    ss58_format: Option<u16>,
+}
+
+impl Chain {
+    /// Returns the genesis hash of the chain.
+    fn genesis(&self) -> Result<String, Box<dyn Error>> {
+        self.genesis
+            .clone()
+            .ok_or_else(|| "Provide `--genesis` or a raw `--chain-spec`".into())
+    }
+}
+
+impl ChainOpts {
+    /// Resolve the chain details, the command line arguments take precedence
+    /// over the chain spec.
+    async fn resolve(self) -> Result<Chain, Box<dyn Error>> {
+        let Some(path) = self.chain_spec else {
+            return Ok(Chain {
+                genesis: self.genesis,
+                bootnodes: self.bootnodes,
+                fork_id: self.fork_id,
+                ..Default::default()
+            });
+        };
+
+        let bytes = std::fs::read(path)?;
+        let spec = ChainSpec::from_json_bytes(&bytes)?;
+        log::info!("Loaded chain spec name={:?}", spec.name);
+
+        let bootnodes = if self.bootnodes.is_empty() {
+            spec.boot_nodes.clone()
+        } else {
+            self.bootnodes
+        };
+        let fork_id = self.fork_id.or(spec.fork_id.clone());
+        let state_version = self.state_version.map(|version| match version {
+            0 => StateVersion::V0,
+            _ => StateVersion::V1,
+        });
+
+        let genesis = match (self.genesis, state_version) {
+            (Some(genesis), _) => Some(genesis),
+            (None, Some(version)) => Some(hex::encode(spec.genesis_hash_with(version)?)),
+            (None, None) => match spec.genesis_hash() {
+                Ok(hash) => Some(hex::encode(hash)),
+                // The compressed runtime code hides its state version, pick the genesis
+                // hash used by the bootnodes.
+                Err(ChainSpecError::UnknownStateVersion) => {
+                    let candidates = [StateVersion::V0, StateVersion::V1]
+                        .into_iter()
+                        .map(|version| spec.genesis_hash_with(version).map(hex::encode))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    log::info!(
+                        "Unknown state version, querying the bootnodes for the genesis hash"
+                    );
+                    Some(resolve_genesis(bootnodes.clone(), candidates, fork_id.as_deref()).await?)
+                }
+                Err(ChainSpecError::MissingRawGenesis) => {
+                    log::warn!("The chain spec is not raw, cannot compute the genesis hash");
+                    None
+                }
+                Err(err) => return Err(err.into()),
+            },
+        };
+
+        Ok(Chain {
+            genesis,
+            bootnodes,
+            fork_id,
+            protocol_id: spec.protocol_id,
+            ss58_format: spec.ss58_format,
+        })
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    tracing_subscriber::fmt().init();
+    // Keep the standard output for the reports.
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
 
     let args = Command::parse();
     match args {
         Command::SendExtrinisic(opts) => {
-            submit_extrinsics(opts.genesis, opts.bootnodes, opts.extrinsics).await
+            let chain = opts.chain.resolve().await?;
+            submit_extrinsics(
+                chain.genesis()?,
+                chain.bootnodes,
+                chain.fork_id,
+                opts.extrinsics,
+                opts.peers,
+                opts.timeout,
+                opts.confirm_inclusion,
+            )
+            .await
         }
         Command::DiscoverNetwork(opts) => {
+            let chain = opts.chain.resolve().await?;
+            let genesis = chain.genesis()?;
+            let options = SwarmOptions {
+                legacy_protocol_id: opts.legacy_protocol_id.or(chain.protocol_id),
+                fork_id: chain.fork_id,
+                ..Default::default()
+            };
             discover_network(
-                opts.genesis,
-                opts.bootnodes,
+                genesis,
+                chain.bootnodes,
+                options,
+                opts.peer_store,
                 opts.cities,
                 opts.raw_geolocation,
                 opts.only_authorities,
+                VersionPolicy {
+                    min_version: opts.min_version,
+                    bad_versions: opts.bad_versions,
+                },
+                opts.topology.map(|path| (path, opts.topology_format)),
+                opts.crawl,
+                opts.shared_ip_threshold,
+                opts.asn_db,
+                opts.timeout,
+                opts.output,
+            )
+            .await
+        }
+        Command::Monitor(opts) => {
+            let chain = opts.chain.resolve().await?;
+            let genesis = chain.genesis()?;
+            let options = SwarmOptions {
+                legacy_protocol_id: opts.legacy_protocol_id.or(chain.protocol_id),
+                fork_id: chain.fork_id,
+                ..Default::default()
+            };
+            monitor_network(
+                genesis,
+                chain.bootnodes,
+                options,
+                opts.peer_store,
+                opts.prometheus_addr,
+                opts.window,
+            )
+            .await
+        }
+        Command::VerifyBootnodes(opts) => {
+            let chain = opts.chain.resolve().await?;
+            verify_bootnodes(chain.bootnodes, chain.genesis, chain.fork_id, opts.output).await
+        }
+        Command::FetchBlocks(opts) => {
+            let request = opts.request()?;
+            let chain = opts.chain.resolve().await?;
+            fetch_blocks(
+                chain.genesis()?,
+                chain.bootnodes,
+                chain.fork_id,
+                opts.peer,
+                request,
+                opts.raw_output,
+                opts.timeout,
+            )
+            .await
+        }
+        Command::DumpStorage(opts) => {
+            let chain = opts.chain.resolve().await?;
+            let prefix = hex::decode(opts.prefix.trim_start_matches("0x"))
+                .map_err(|e| format!("Invalid prefix: {}", e))?;
+            dump_storage(
+                chain.genesis()?,
+                chain.bootnodes,
+                chain.fork_id,
+                opts.peer,
+                opts.block,
+                prefix,
+                opts.proof,
+                opts.timeout,
+            )
+            .await
+        }
+        Command::WatchTransactions(opts) => {
+            let chain = opts.chain.resolve().await?;
+            watch_transactions(
+                chain.genesis()?,
+                chain.bootnodes,
+                chain.fork_id,
+                opts.timeout,
+            )
+            .await
+        }
+        Command::WatchFinality(opts) => {
+            let chain = opts.chain.resolve().await?;
+            watch_finality(
+                chain.genesis()?,
+                chain.bootnodes,
+                chain.fork_id,
+                opts.timeout,
+            )
+            .await
+        }
+        Command::SubmitStatements(opts) => {
+            let chain = opts.chain.resolve().await?;
+            submit_statements(
+                chain.genesis()?,
+                chain.bootnodes,
+                chain.fork_id,
+                opts.statements,
+                opts.peers,
+                opts.timeout,
+            )
+            .await
+        }
+        Command::WatchStatements(opts) => {
+            let chain = opts.chain.resolve().await?;
+            watch_statements(
+                chain.genesis()?,
+                chain.bootnodes,
+                chain.fork_id,
                 opts.timeout,
             )
             .await
         }
-        Command::VerifyBootnodes(opts) => opts.verify_bootnodes().await,
         Command::Authorities(opts) => {
+            let chain = opts.chain.resolve().await?;
+            let address_format = opts
+                .address_format
+                .or(chain.ss58_format)
+                .ok_or("Provide `--address-format` or a chain spec with `ss58Format`")?;
             discover_authorities(
                 opts.url,
-                opts.genesis,
-                opts.bootnodes,
+                chain.genesis()?,
+                chain.bootnodes,
+                chain.fork_id,
                 opts.timeout,
-                opts.address_format,
+                address_format,
                 opts.raw_output,
+                opts.output,
             )
             .await
         }
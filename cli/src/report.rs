@@ -0,0 +1,71 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+use serde::Serialize;
+use std::error::Error;
+
+/// The output format of the reports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable lines.
+    #[default]
+    Table,
+    /// A single JSON object.
+    Json,
+    /// A header line followed by one line per entry.
+    Csv,
+}
+
+/// Report of a command that can be printed in every [`OutputFormat`].
+///
+/// The field names of the JSON and CSV outputs are part of the CLI interface.
+pub trait Report: Serialize {
+    /// The column names of the CSV output.
+    const CSV_HEADER: &'static [&'static str];
+
+    /// Returns the rows of the CSV output, in the order of [`Report::CSV_HEADER`].
+    fn csv_rows(&self) -> Vec<Vec<String>>;
+
+    /// Print the human readable report.
+    fn print_table(&self);
+
+    /// Print the report in the provided format.
+    fn print(&self, format: OutputFormat) -> Result<(), Box<dyn Error>> {
+        match format {
+            OutputFormat::Table => self.print_table(),
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(self)?),
+            OutputFormat::Csv => {
+                println!("{}", Self::CSV_HEADER.join(","));
+                for row in self.csv_rows() {
+                    let row: Vec<_> = row.iter().map(|field| csv_field(field)).collect();
+                    println!("{}", row.join(","));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Quote the field if it contains a separator, a quote or a new line.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Returns the CSV field of an optional value, empty when missing.
+pub fn optional_field<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(ToString::to_string).unwrap_or_default()
+}
+
+/// Returns the table field of an optional value, `unknown` when missing.
+pub fn unknown_field<T: ToString>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map(ToString::to_string)
+        .unwrap_or_else(|| "unknown".to_string())
+}
@@ -3,19 +3,28 @@
 // see LICENSE for license details.
 
 use ip_network::IpNetwork;
-use libp2p::{identity, multiaddr::Protocol, swarm::SwarmBuilder, Multiaddr, PeerId, Swarm};
-use maxminddb::{geoip2::City, Reader as GeoIpReader};
+use libp2p::{identity, multiaddr::Protocol, swarm, Multiaddr, PeerId, Swarm};
+use maxminddb::{
+    geoip2::{Asn, City},
+    MaxMindDBError, Reader as GeoIpReader,
+};
 use primitive_types::H256;
+use serde::Serialize;
 use std::error::Error;
 use std::net::IpAddr;
+use std::path::Path;
 use std::time::Duration;
 use subp2p_explorer::{
     discovery::DiscoveryBuilder,
     notifications::{
-        behavior::{Notifications, ProtocolsData},
+        behavior::{Notifications, ProtocolIndex, ProtocolsData},
         messages::ProtocolRole,
     },
     peer_behavior::PeerBehaviour,
+    requests::{
+        block::BlockRequests, kademlia::KademliaRequests, light::LightRequests,
+        state::StateRequests,
+    },
     transport::{TransportBuilder, MIB},
     Behaviour,
 };
@@ -26,9 +35,12 @@ pub struct Locator {
 }
 
 /// The location result of an IP query.
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct Location {
-    pub city: String,
+    pub city: Option<String>,
+    pub country: Option<String>,
+    /// The ISO 3166-1 alpha-2 code of the country.
+    pub country_code: Option<String>,
     pub accuracy_radius: Option<u16>,
     pub latitude: Option<f64>,
     pub longitude: Option<f64>,
@@ -48,34 +60,165 @@ impl Locator {
 
     /// Geolocate the IP address and return the location.
     pub fn locate(&self, ip: IpAddr) -> Option<Location> {
-        let City { city, location, .. } = self.db.lookup(ip).ok()?;
+        let City {
+            city,
+            country,
+            location,
+            ..
+        } = self.db.lookup(ip).ok()?;
 
+        // The city and the country are looked up independently, the database lacks
+        // the city of many IPs.
         let city = city
-            .as_ref()?
-            .names
-            .as_ref()?
-            .get("en")?
-            .to_string()
-            .into_boxed_str();
+            .as_ref()
+            .and_then(|city| city.names.as_ref()?.get("en"))
+            .map(|name| name.to_string());
+        let country_name = country
+            .as_ref()
+            .and_then(|country| country.names.as_ref()?.get("en"))
+            .map(|name| name.to_string());
+        if city.is_none() && country_name.is_none() {
+            return None;
+        }
 
         Some(Location {
-            city: city.into_string(),
-            accuracy_radius: location.clone().map(|loc| loc.accuracy_radius).flatten(),
-            latitude: location.clone().map(|loc| loc.latitude).flatten(),
-            longitude: location.clone().map(|loc| loc.longitude).flatten(),
-            metro_code: location.clone().map(|loc| loc.metro_code).flatten(),
-            time_zone: location
-                .map(|loc| loc.time_zone.map(|zone| zone.to_string()))
-                .flatten(),
+            city,
+            country: country_name,
+            country_code: country
+                .as_ref()
+                .and_then(|country| country.iso_code)
+                .map(|code| code.to_string()),
+            accuracy_radius: location.clone().and_then(|loc| loc.accuracy_radius),
+            latitude: location.clone().and_then(|loc| loc.latitude),
+            longitude: location.clone().and_then(|loc| loc.longitude),
+            metro_code: location.clone().and_then(|loc| loc.metro_code),
+            time_zone: location.and_then(|loc| loc.time_zone.map(|zone| zone.to_string())),
+        })
+    }
+}
+
+/// Translate IP addresses to the autonomous systems (ie hosting providers) announcing them.
+pub struct AsnLocator {
+    db: maxminddb::Reader<Vec<u8>>,
+}
+
+/// The autonomous system of an IP query.
+#[derive(Debug, Clone, Serialize)]
+pub struct AutonomousSystem {
+    pub number: u32,
+    pub organization: Option<String>,
+}
+
+impl AsnLocator {
+    /// Open a GeoLite2-ASN database.
+    pub fn open(path: &Path) -> Result<Self, MaxMindDBError> {
+        Ok(Self {
+            db: GeoIpReader::open_readfile(path)?,
         })
     }
+
+    /// Returns the autonomous system announcing the IP address.
+    pub fn locate(&self, ip: IpAddr) -> Option<AutonomousSystem> {
+        let Asn {
+            autonomous_system_number,
+            autonomous_system_organization,
+        } = self.db.lookup(ip).ok()?;
+
+        Some(AutonomousSystem {
+            number: autonomous_system_number?,
+            organization: autonomous_system_organization.map(|org| org.to_string()),
+        })
+    }
+}
+
+/// Notification protocols registered by [`build_swarm`].
+#[derive(Debug, Clone, Copy)]
+pub struct NotificationProtocols {
+    /// Protocol index for block-announces.
+    pub block_announces: ProtocolIndex,
+    /// Protocol index for transactions.
+    pub transactions: ProtocolIndex,
+    /// Protocol index for grandpa, registered by [`build_grandpa_swarm`].
+    pub grandpa: Option<ProtocolIndex>,
+    /// Protocol index for statements, registered by [`build_statement_swarm`].
+    pub statements: Option<ProtocolIndex>,
+}
+
+/// Options of the swarm built by [`build_swarm_with_options`].
+#[derive(Debug, Default, Clone)]
+pub struct SwarmOptions {
+    /// Register the grandpa protocol.
+    pub grandpa: bool,
+    /// Register the statement protocol.
+    pub statements: bool,
+    /// The legacy protocol id of the chain (ie `dot`).
+    ///
+    /// The `/{id}/kad`, `/{id}/block-announces/1` and `/{id}/transactions/1` protocols
+    /// are negotiated with nodes that predate the genesis hash protocol names.
+    pub legacy_protocol_id: Option<String>,
+    /// The fork id of the chain, for chains relaunched with the same genesis hash.
+    pub fork_id: Option<String>,
 }
 
 /// Build the swarm for the CLI.
 pub fn build_swarm(
     genesis: String,
     bootnodes: Vec<String>,
-) -> Result<Swarm<Behaviour>, Box<dyn Error>> {
+    fork_id: Option<String>,
+) -> Result<(Swarm<Behaviour>, NotificationProtocols), Box<dyn Error>> {
+    build_swarm_with_options(
+        genesis,
+        bootnodes,
+        SwarmOptions {
+            fork_id,
+            ..Default::default()
+        },
+    )
+}
+
+/// Build the swarm for the CLI with the grandpa protocol registered.
+///
+/// Peers gossip every finality vote on the protocol, therefore it is registered only
+/// by the commands that observe finality.
+pub fn build_grandpa_swarm(
+    genesis: String,
+    bootnodes: Vec<String>,
+    fork_id: Option<String>,
+) -> Result<(Swarm<Behaviour>, NotificationProtocols), Box<dyn Error>> {
+    build_swarm_with_options(
+        genesis,
+        bootnodes,
+        SwarmOptions {
+            fork_id,
+            grandpa: true,
+            ..Default::default()
+        },
+    )
+}
+
+/// Build the swarm for the CLI with the statement protocol registered.
+pub fn build_statement_swarm(
+    genesis: String,
+    bootnodes: Vec<String>,
+    fork_id: Option<String>,
+) -> Result<(Swarm<Behaviour>, NotificationProtocols), Box<dyn Error>> {
+    build_swarm_with_options(
+        genesis,
+        bootnodes,
+        SwarmOptions {
+            fork_id,
+            statements: true,
+            ..Default::default()
+        },
+    )
+}
+
+/// Build the swarm for the CLI with the provided options.
+pub fn build_swarm_with_options(
+    genesis: String,
+    bootnodes: Vec<String>,
+    options: SwarmOptions,
+) -> Result<(Swarm<Behaviour>, NotificationProtocols), Box<dyn Error>> {
     // Create a random key for ourselves.
     let local_key = identity::Keypair::generate_ed25519();
     let local_peer_id = PeerId::from(local_key.public());
@@ -98,11 +241,32 @@ pub fn build_swarm(
         .collect();
 
     // Craft the specific protocol data.
-    let protocol_data = ProtocolsData {
-        genesis_hash: H256::from_slice(hex::decode(genesis)?.as_slice()),
-        node_role: ProtocolRole::FullNode,
+    let mut protocol_data = ProtocolsData::new(
+        H256::from_slice(hex::decode(genesis)?.as_slice()),
+        ProtocolRole::FullNode,
+    );
+    if let Some(protocol_id) = &options.legacy_protocol_id {
+        protocol_data = protocol_data.with_legacy_protocol_id(protocol_id.clone());
+    }
+    if let Some(fork_id) = &options.fork_id {
+        protocol_data = protocol_data.with_fork_id(fork_id.clone());
+    }
+    let protocols = NotificationProtocols {
+        block_announces: protocol_data.register_block_announces(),
+        transactions: protocol_data.register_transactions(),
+        grandpa: options.grandpa.then(|| protocol_data.register_grandpa()),
+        statements: options
+            .statements
+            .then(|| protocol_data.register_statements()),
     };
 
+    // Nodes that predate the genesis hash protocol names only support `/{protocol_id}/kad`.
+    let kad_fallback_names: Vec<_> = options
+        .legacy_protocol_id
+        .iter()
+        .map(|protocol_id| format!("/{protocol_id}/kad"))
+        .collect();
+
     // Create a Switch (swarm) to manage peers and events.
     let mut swarm: Swarm<Behaviour> = {
         let transport = TransportBuilder::new()
@@ -113,18 +277,35 @@ pub fn build_swarm(
             .record_ttl(Some(Duration::from_secs(0)))
             .provider_ttl(Some(Duration::from_secs(0)))
             .query_timeout(Duration::from_secs(5 * 60))
+            .fallback_names(kad_fallback_names.clone())
+            .fork_id(options.fork_id.clone())
             .build(local_peer_id, genesis);
 
         let peer_info = PeerBehaviour::new(local_key.public());
         let notifications = Notifications::new(protocol_data);
+        let fork_id = options.fork_id.as_deref();
+        let block_requests = BlockRequests::new(genesis, fork_id);
+        let state_requests = StateRequests::new(genesis, fork_id);
+        let light_requests = LightRequests::new(genesis, fork_id);
+        let kademlia_requests =
+            KademliaRequests::with_fallback_names(genesis, fork_id, kad_fallback_names);
 
         let behavior = Behaviour {
             notifications,
             peer_info,
             discovery,
+            block_requests,
+            state_requests,
+            light_requests,
+            kademlia_requests,
         };
 
-        SwarmBuilder::with_tokio_executor(transport, behavior, local_peer_id).build()
+        Swarm::new(
+            transport,
+            behavior,
+            local_peer_id,
+            swarm::Config::with_tokio_executor(),
+        )
     };
 
     // Active set of peers from the kbuckets of kademlia.
@@ -136,7 +317,7 @@ pub fn build_swarm(
             .add_address(peer, multiaddress.clone());
     }
 
-    Ok(swarm)
+    Ok((swarm, protocols))
 }
 
 /// Checks if the p2p address is public.
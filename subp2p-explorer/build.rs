@@ -0,0 +1,11 @@
+fn main() {
+    prost_build::compile_protos(
+        &[
+            "src/schema/api.v1.proto",
+            "src/schema/light.v1.proto",
+            "src/schema/kademlia.proto",
+        ],
+        &["src/schema"],
+    )
+    .unwrap();
+}
@@ -0,0 +1,450 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Analyze the addresses advertised by the peers.
+//!
+//! Misconfigured nodes advertise addresses that others cannot dial, which
+//! degrades the connectivity of the network. The findings point operators to
+//! the configuration to fix.
+
+use crate::dial::strip_peer_id;
+use ip_network::IpNetwork;
+use libp2p::{core::ConnectedPoint, multiaddr::Protocol, Multiaddr, PeerId};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    net::IpAddr,
+};
+
+/// Public IPs used by at least this many distinct peers are flagged.
+pub const DEFAULT_SHARED_IP_THRESHOLD: usize = 5;
+
+/// Where an address was learned from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AddressSource {
+    /// The listen addresses reported by the identify protocol.
+    Listen,
+    /// The addresses of the peer returned by the DHT.
+    Dht,
+    /// The addresses published by an authority in its authority discovery record.
+    Record,
+}
+
+impl AddressSource {
+    /// Returns the name of the source.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AddressSource::Listen => "listen",
+            AddressSource::Dht => "dht",
+            AddressSource::Record => "record",
+        }
+    }
+
+    /// Returns true if the addresses of the source must end with `/p2p/{peer_id}`.
+    ///
+    /// Identify and the DHT report the addresses of a known peer without its peer ID,
+    /// while the addresses of authority discovery records without a peer ID are ignored.
+    pub fn expects_peer_id(&self) -> bool {
+        match self {
+            AddressSource::Listen | AddressSource::Dht => false,
+            AddressSource::Record => true,
+        }
+    }
+}
+
+/// An issue with the addresses of a peer.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AddressFinding {
+    /// The address is a loopback address.
+    Loopback {
+        address: Multiaddr,
+        source: AddressSource,
+    },
+    /// The address is not globally reachable (ie private or link-local ranges).
+    Private {
+        address: Multiaddr,
+        source: AddressSource,
+    },
+    /// The address does not end with `/p2p/{peer_id}`, while its source requires it.
+    MissingPeerId {
+        address: Multiaddr,
+        source: AddressSource,
+    },
+    /// The address ends with the peer ID of a different peer.
+    MismatchedPeerId {
+        address: Multiaddr,
+        source: AddressSource,
+        peer_id: PeerId,
+    },
+    /// The public IP is shared with other peers, which may indicate a Sybil.
+    SharedIp {
+        ip: IpAddr,
+        /// The number of distinct peers using the IP, including this peer.
+        peers: usize,
+    },
+    /// The peer listens on both plain TCP and websocket addresses.
+    MixedTransports,
+    /// The IP the peer was connected on is not one of its listen addresses.
+    ObservedAddressMismatch { observed: Multiaddr },
+}
+
+impl AddressFinding {
+    /// Returns the name of the finding.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AddressFinding::Loopback { .. } => "loopback",
+            AddressFinding::Private { .. } => "private",
+            AddressFinding::MissingPeerId { .. } => "missing_peer_id",
+            AddressFinding::MismatchedPeerId { .. } => "mismatched_peer_id",
+            AddressFinding::SharedIp { .. } => "shared_ip",
+            AddressFinding::MixedTransports => "mixed_transports",
+            AddressFinding::ObservedAddressMismatch { .. } => "observed_address_mismatch",
+        }
+    }
+}
+
+impl fmt::Display for AddressFinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressFinding::Loopback { address, source }
+            | AddressFinding::Private { address, source }
+            | AddressFinding::MissingPeerId { address, source } => {
+                write!(f, "{} {address} ({})", self.name(), source.name())
+            }
+            AddressFinding::MismatchedPeerId {
+                address,
+                source,
+                peer_id,
+            } => write!(
+                f,
+                "{} {address} ({}) expected={peer_id}",
+                self.name(),
+                source.name()
+            ),
+            AddressFinding::SharedIp { ip, peers } => {
+                write!(f, "{} {ip} peers={peers}", self.name())
+            }
+            AddressFinding::MixedTransports => f.write_str(self.name()),
+            AddressFinding::ObservedAddressMismatch { observed } => {
+                write!(f, "{} observed={observed}", self.name())
+            }
+        }
+    }
+}
+
+/// The addresses known for a peer.
+#[derive(Debug, Clone, Default)]
+pub struct PeerAddresses {
+    /// The listen addresses reported by the identify protocol.
+    pub listen_addrs: Vec<Multiaddr>,
+    /// The addresses of the peer returned by the DHT.
+    pub dht_addrs: Vec<Multiaddr>,
+    /// The addresses published in the authority discovery record of the peer.
+    pub record_addrs: Vec<Multiaddr>,
+    /// The address of a connection established with the peer.
+    ///
+    /// See [`connected_address`].
+    pub observed_addr: Option<Multiaddr>,
+}
+
+/// Returns the address a connection with the peer was established on.
+///
+/// This is the dialed address of an outbound connection, and the remote address of
+/// an inbound connection. The peer ID is stripped from the address.
+pub fn connected_address(endpoint: &ConnectedPoint) -> Multiaddr {
+    strip_peer_id(endpoint.get_remote_address())
+}
+
+/// Returns the IP of the address, if the address starts with one.
+pub fn address_ip(address: &Multiaddr) -> Option<IpAddr> {
+    match address.iter().next() {
+        Some(Protocol::Ip4(ip)) => Some(IpAddr::V4(ip)),
+        Some(Protocol::Ip6(ip)) => Some(IpAddr::V6(ip)),
+        _ => None,
+    }
+}
+
+/// Analyze the addresses of the peers.
+///
+/// Returns the findings of every peer with at least one finding.
+pub fn analyze_addresses(
+    peers: &HashMap<PeerId, PeerAddresses>,
+    shared_ip_threshold: usize,
+) -> BTreeMap<PeerId, Vec<AddressFinding>> {
+    // The distinct peers of every public IP.
+    let mut ip_peers: HashMap<IpAddr, HashSet<PeerId>> = HashMap::new();
+    for (peer_id, addresses) in peers {
+        for address in addresses
+            .listen_addrs
+            .iter()
+            .chain(&addresses.dht_addrs)
+            .chain(&addresses.record_addrs)
+        {
+            if let Some(ip) = address_ip(address).filter(|ip| IpNetwork::from(*ip).is_global()) {
+                ip_peers.entry(ip).or_default().insert(*peer_id);
+            }
+        }
+    }
+
+    let mut findings = BTreeMap::new();
+    for (peer_id, addresses) in peers {
+        let mut peer_findings = Vec::new();
+
+        let sources = addresses
+            .listen_addrs
+            .iter()
+            .map(|address| (address, AddressSource::Listen))
+            .chain(
+                addresses
+                    .dht_addrs
+                    .iter()
+                    .map(|address| (address, AddressSource::Dht)),
+            )
+            .chain(
+                addresses
+                    .record_addrs
+                    .iter()
+                    .map(|address| (address, AddressSource::Record)),
+            );
+        let mut shared_ips = HashSet::new();
+        for (address, source) in sources {
+            peer_findings.extend(address_findings(peer_id, address, source));
+
+            if let Some(ip) = address_ip(address) {
+                let peers = ip_peers.get(&ip).map(HashSet::len).unwrap_or_default();
+                if peers >= shared_ip_threshold && shared_ips.insert(ip) {
+                    peer_findings.push(AddressFinding::SharedIp { ip, peers });
+                }
+            }
+        }
+
+        let transports = |websocket: bool| {
+            addresses.listen_addrs.iter().any(|address| {
+                address
+                    .iter()
+                    .any(|protocol| matches!(protocol, Protocol::Tcp(_)))
+                    && address
+                        .iter()
+                        .any(|protocol| matches!(protocol, Protocol::Ws(_) | Protocol::Wss(_)))
+                        == websocket
+            })
+        };
+        if transports(true) && transports(false) {
+            peer_findings.push(AddressFinding::MixedTransports);
+        }
+
+        if let Some(observed) = &addresses.observed_addr {
+            if observed_address_mismatch(observed, &addresses.listen_addrs) {
+                peer_findings.push(AddressFinding::ObservedAddressMismatch {
+                    observed: observed.clone(),
+                });
+            }
+        }
+
+        if !peer_findings.is_empty() {
+            peer_findings.sort();
+            peer_findings.dedup();
+            findings.insert(*peer_id, peer_findings);
+        }
+    }
+
+    findings
+}
+
+/// Returns the findings of a single address.
+fn address_findings(
+    peer_id: &PeerId,
+    address: &Multiaddr,
+    source: AddressSource,
+) -> Vec<AddressFinding> {
+    let mut findings = Vec::new();
+
+    if let Some(ip) = address_ip(address) {
+        if ip.is_loopback() {
+            findings.push(AddressFinding::Loopback {
+                address: address.clone(),
+                source,
+            });
+        } else if !IpNetwork::from(ip).is_global() {
+            findings.push(AddressFinding::Private {
+                address: address.clone(),
+                source,
+            });
+        }
+    }
+
+    match address.iter().last() {
+        Some(Protocol::P2p(address_peer)) if address_peer == *peer_id => (),
+        Some(Protocol::P2p(_)) => findings.push(AddressFinding::MismatchedPeerId {
+            address: address.clone(),
+            source,
+            peer_id: *peer_id,
+        }),
+        _ if source.expects_peer_id() => findings.push(AddressFinding::MissingPeerId {
+            address: address.clone(),
+            source,
+        }),
+        _ => (),
+    }
+
+    findings
+}
+
+/// Returns true if the IP of the observed address is not one of the listen addresses.
+///
+/// A peer dialed on an address it no longer listens on advertises stale addresses,
+/// either in the DHT or in its authority discovery record.
+///
+/// Peers listening on DNS addresses or without IP listen addresses cannot be compared.
+fn observed_address_mismatch(observed: &Multiaddr, listen_addrs: &[Multiaddr]) -> bool {
+    let Some(observed_ip) = address_ip(observed) else {
+        return false;
+    };
+    if listen_addrs.is_empty()
+        || listen_addrs
+            .iter()
+            .any(|address| address_ip(address).is_none())
+    {
+        return false;
+    }
+
+    !listen_addrs
+        .iter()
+        .any(|address| address_ip(address) == Some(observed_ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(address: &str) -> Multiaddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn analyze_peer_addresses() {
+        let peer_id = PeerId::random();
+        let other = PeerId::random();
+
+        let mut peers = HashMap::new();
+        peers.insert(
+            peer_id,
+            PeerAddresses {
+                listen_addrs: vec![
+                    addr("/ip4/127.0.0.1/tcp/30333"),
+                    addr("/ip4/192.168.1.2/tcp/30333"),
+                    addr(&format!("/ip4/8.8.8.8/tcp/30334/ws/p2p/{peer_id}")),
+                ],
+                // The DHT reports addresses without the peer ID.
+                dht_addrs: vec![
+                    addr("/ip4/8.8.8.8/tcp/30334/ws"),
+                    addr(&format!("/ip4/8.8.8.8/tcp/30333/p2p/{other}")),
+                ],
+                record_addrs: vec![addr("/ip4/8.8.8.8/tcp/30333")],
+                observed_addr: Some(addr("/ip4/1.1.1.1/tcp/30333")),
+            },
+        );
+        // A peer with well formed addresses.
+        peers.insert(
+            other,
+            PeerAddresses {
+                listen_addrs: vec![addr("/ip4/8.8.8.8/tcp/30333")],
+                dht_addrs: vec![addr("/ip4/8.8.8.8/tcp/30333")],
+                record_addrs: vec![addr(&format!("/ip4/8.8.8.8/tcp/30333/p2p/{other}"))],
+                observed_addr: Some(addr("/ip4/8.8.8.8/tcp/30333")),
+            },
+        );
+
+        let findings = analyze_addresses(&peers, 3);
+        assert!(!findings.contains_key(&other));
+
+        let names: Vec<_> = findings[&peer_id].iter().map(|f| f.name()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "loopback",
+                "private",
+                "missing_peer_id",
+                "mismatched_peer_id",
+                "mixed_transports",
+                "observed_address_mismatch"
+            ]
+        );
+        assert_eq!(
+            findings[&peer_id][2].to_string(),
+            "missing_peer_id /ip4/8.8.8.8/tcp/30333 (record)"
+        );
+        assert_eq!(
+            findings[&peer_id][3].to_string(),
+            format!(
+                "mismatched_peer_id /ip4/8.8.8.8/tcp/30333/p2p/{other} (dht) expected={peer_id}"
+            )
+        );
+
+        // Both peers share the same public IP.
+        let findings = analyze_addresses(&peers, 2);
+        let shared = AddressFinding::SharedIp {
+            ip: "8.8.8.8".parse().unwrap(),
+            peers: 2,
+        };
+        assert!(findings[&peer_id].contains(&shared));
+        assert_eq!(findings[&other], vec![shared]);
+    }
+
+    #[test]
+    fn observed_address_without_ip_listen_addresses() {
+        let observed = addr("/ip4/1.1.1.1/tcp/30333");
+        assert!(!observed_address_mismatch(&observed, &[]));
+        assert!(!observed_address_mismatch(
+            &observed,
+            &[addr("/dns/example.com/tcp/30333")]
+        ));
+        assert!(observed_address_mismatch(
+            &observed,
+            &[addr("/ip4/8.8.8.8/tcp/30333")]
+        ));
+    }
+
+    #[test]
+    fn observed_address_of_connections() {
+        use libp2p::core::Endpoint;
+
+        let peer_id = PeerId::random();
+        // The DHT still returns a stale address of the peer.
+        let dht_addrs = vec![addr(&format!("/ip4/1.1.1.1/tcp/30333/p2p/{peer_id}"))];
+        let listen_addrs = vec![addr("/ip4/8.8.8.8/tcp/30333")];
+
+        // The peer is dialed on the address found in the DHT, then identified.
+        let dialer = ConnectedPoint::Dialer {
+            address: dht_addrs[0].clone(),
+            role_override: Endpoint::Dialer,
+        };
+        let mut peers = HashMap::new();
+        peers.insert(
+            peer_id,
+            PeerAddresses {
+                listen_addrs: listen_addrs.clone(),
+                dht_addrs,
+                record_addrs: Vec::new(),
+                observed_addr: Some(connected_address(&dialer)),
+            },
+        );
+        assert_eq!(
+            analyze_addresses(&peers, DEFAULT_SHARED_IP_THRESHOLD)[&peer_id],
+            vec![AddressFinding::ObservedAddressMismatch {
+                observed: addr("/ip4/1.1.1.1/tcp/30333"),
+            }]
+        );
+
+        // The remote address of an inbound connection is one of the listen addresses.
+        let listener = ConnectedPoint::Listener {
+            local_addr: addr("/ip4/9.9.9.9/tcp/30333"),
+            send_back_addr: addr("/ip4/8.8.8.8/tcp/41000"),
+        };
+        let peer = peers.get_mut(&peer_id).unwrap();
+        peer.dht_addrs = listen_addrs;
+        peer.observed_addr = Some(connected_address(&listener));
+        assert!(analyze_addresses(&peers, DEFAULT_SHARED_IP_THRESHOLD).is_empty());
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Parse the agent version reported by peers on the identify protocol.
+//!
+//! Substrate nodes report `{implementation}/v{version}-{commit} ({node name})`,
+//! for example `Parity Polkadot/v1.5.0-2b0d7a5c1ab (alice)`.
+
+use std::{fmt, str::FromStr};
+
+/// A `major.minor.patch` version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    /// Parse `[v]major[.minor[.patch]]`, the missing components default to zero.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().trim_start_matches('v');
+        let mut components = s.split('.').map(|component| {
+            component
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid version {s:?}"))
+        });
+
+        let major = components
+            .next()
+            .ok_or_else(|| format!("Invalid version {s:?}"))??;
+        let minor = components.next().transpose()?.unwrap_or_default();
+        let patch = components.next().transpose()?.unwrap_or_default();
+        if components.next().is_some() {
+            return Err(format!("Invalid version {s:?}"));
+        }
+
+        Ok(Version {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+/// The details of an agent version string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentVersion {
+    /// The name of the implementation (ie `Parity Polkadot`).
+    pub implementation: String,
+    /// The version, if it follows `major.minor.patch`.
+    pub version: Option<Version>,
+    /// The pre-release identifier (ie `dev`).
+    pub pre_release: Option<String>,
+    /// The hex-encoded commit the node was built from.
+    pub commit: Option<String>,
+    /// The name of the node.
+    pub node_name: Option<String>,
+}
+
+impl AgentVersion {
+    /// Parse the agent version string of a peer.
+    ///
+    /// Agents that do not follow the substrate format are reported with the full
+    /// string as implementation.
+    pub fn parse(agent: &str) -> Self {
+        let agent = agent.trim();
+
+        let (agent, node_name) = match agent.split_once(" (") {
+            Some((agent, name)) if name.ends_with(')') => {
+                (agent, Some(name[..name.len() - 1].to_string()))
+            }
+            _ => (agent, None),
+        };
+
+        let Some((implementation, version)) = agent.rsplit_once('/') else {
+            return AgentVersion {
+                implementation: agent.to_string(),
+                version: None,
+                pre_release: None,
+                commit: None,
+                node_name,
+            };
+        };
+
+        let (version, suffix) = match version.split_once('-') {
+            Some((version, suffix)) => (version, Some(suffix)),
+            None => (version, None),
+        };
+
+        // The commit is the last hex segment of the suffix, the rest is the pre-release.
+        let (pre_release, commit) = match suffix {
+            Some(suffix) => {
+                let (rest, last) = match suffix.rsplit_once('-') {
+                    Some((rest, last)) => (Some(rest), last),
+                    None => (None, suffix),
+                };
+                if !last.is_empty() && last.chars().all(|c| c.is_ascii_hexdigit()) {
+                    (rest.map(str::to_string), Some(last.to_string()))
+                } else {
+                    (Some(suffix.to_string()), None)
+                }
+            }
+            None => (None, None),
+        };
+
+        AgentVersion {
+            implementation: implementation.to_string(),
+            version: version.parse().ok(),
+            pre_release,
+            commit,
+            node_name,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_agent_versions() {
+        assert_eq!(
+            AgentVersion::parse("Parity Polkadot/v1.5.0-2b0d7a5c1ab (node-name)"),
+            AgentVersion {
+                implementation: "Parity Polkadot".to_string(),
+                version: Some(Version {
+                    major: 1,
+                    minor: 5,
+                    patch: 0
+                }),
+                pre_release: None,
+                commit: Some("2b0d7a5c1ab".to_string()),
+                node_name: Some("node-name".to_string()),
+            }
+        );
+
+        let agent = AgentVersion::parse("Substrate Node/v4.0.0-dev-c0ffee (my node (eu))");
+        assert_eq!(agent.implementation, "Substrate Node");
+        assert_eq!(agent.version, Some("4.0.0".parse().unwrap()));
+        assert_eq!(agent.pre_release.as_deref(), Some("dev"));
+        assert_eq!(agent.commit.as_deref(), Some("c0ffee"));
+        assert_eq!(agent.node_name.as_deref(), Some("my node (eu)"));
+
+        let agent = AgentVersion::parse("smoldot-light/2.0.rc1");
+        assert_eq!(agent.implementation, "smoldot-light");
+        assert_eq!(agent.version, None);
+
+        let agent = AgentVersion::parse("unknown");
+        assert_eq!(agent.implementation, "unknown");
+        assert_eq!(agent.version, None);
+        assert_eq!(agent.node_name, None);
+    }
+
+    #[test]
+    fn order_versions() {
+        let parse = |s: &str| s.parse::<Version>().unwrap();
+        assert!(parse("v1.5.0") > parse("1.4.12"));
+        assert!(parse("1.10") > parse("1.9.9"));
+        assert_eq!(parse("1"), parse("1.0.0"));
+        assert!("1.x".parse::<Version>().is_err());
+        assert!("1.2.3.4".parse::<Version>().is_err());
+    }
+}
@@ -0,0 +1,449 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Chain specification of substrate chains.
+//!
+//! The chain spec is the JSON file that describes a chain: the boot nodes, the names of
+//! the p2p protocols and the genesis storage. The genesis hash is computed from the raw
+//! genesis storage by building the genesis block.
+
+use crate::{
+    notifications::messages::{BlockHash, BlockNumber, Digest, Header},
+    trie::{trie_root, StateVersion, DEFAULT_CHILD_STORAGE_KEY_PREFIX},
+};
+use codec::Decode;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// The storage key of the runtime code.
+pub const CODE_KEY: &[u8] = b":code";
+
+/// The prefix of the runtime code compressed by substrate.
+const COMPRESSED_CODE_PREFIX: [u8; 8] = [0x52, 0xbc, 0x53, 0x76, 0x46, 0xdb, 0x8e, 0x05];
+
+/// The name of the wasm custom section that contains the runtime version.
+const RUNTIME_VERSION_SECTION: &[u8] = b"runtime_version";
+
+/// Errors encountered while parsing a chain spec.
+#[derive(Debug, thiserror::Error)]
+pub enum ChainSpecError {
+    /// The chain spec is not valid JSON.
+    #[error("Invalid chain spec: {0}")]
+    Json(#[from] serde_json::Error),
+    /// A field of the chain spec has an unexpected format.
+    #[error("Invalid `{0}` field")]
+    InvalidField(&'static str),
+    /// The chain spec does not contain the raw genesis storage.
+    #[error("The chain spec does not contain the raw genesis storage")]
+    MissingRawGenesis,
+    /// The runtime code is compressed and its version cannot be read.
+    #[error("Cannot read the state version of the compressed runtime code")]
+    UnknownStateVersion,
+    /// The runtime version embedded in the runtime code is invalid.
+    #[error("Invalid runtime version: {0}")]
+    InvalidRuntimeVersion(codec::Error),
+}
+
+/// The raw genesis storage of a chain spec.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RawGenesis {
+    /// The key-values of the main trie.
+    pub top: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// The key-values of the default child tries, indexed by the key of the child trie
+    /// without the [`DEFAULT_CHILD_STORAGE_KEY_PREFIX`].
+    pub children_default: BTreeMap<Vec<u8>, BTreeMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl RawGenesis {
+    /// Returns the state version of the genesis storage.
+    ///
+    /// The state version is read from the runtime version embedded in the runtime code.
+    pub fn state_version(&self) -> Result<StateVersion, ChainSpecError> {
+        // Substrate defaults to the latest state version without a runtime.
+        let Some(code) = self.top.get(CODE_KEY) else {
+            return Ok(StateVersion::V1);
+        };
+        if code.starts_with(&COMPRESSED_CODE_PREFIX) {
+            return Err(ChainSpecError::UnknownStateVersion);
+        }
+
+        // Runtimes that predate the embedded version also predate the state versions.
+        let Some(mut version) = custom_section(code, RUNTIME_VERSION_SECTION) else {
+            return Ok(StateVersion::V0);
+        };
+        decode_state_version(&mut version).map_err(ChainSpecError::InvalidRuntimeVersion)
+    }
+
+    /// Compute the state root of the genesis storage.
+    pub fn state_root(&self, version: StateVersion) -> BlockHash {
+        if self.children_default.is_empty() {
+            return trie_root(&self.top, version);
+        }
+
+        // The roots of the child tries are stored in the main trie.
+        let mut top = self.top.clone();
+        for (key, child) in &self.children_default {
+            if child.is_empty() {
+                continue;
+            }
+
+            let mut storage_key = DEFAULT_CHILD_STORAGE_KEY_PREFIX.to_vec();
+            storage_key.extend(key);
+            top.insert(storage_key, trie_root(child, version).as_bytes().to_vec());
+        }
+        trie_root(&top, version)
+    }
+
+    /// Build the header of the genesis block.
+    pub fn genesis_header(&self, version: StateVersion) -> Header {
+        Header {
+            parent_hash: BlockHash::zero(),
+            number: BlockNumber::default(),
+            state_root: self.state_root(version),
+            // The genesis block does not contain extrinsics.
+            extrinsics_root: trie_root(&BTreeMap::new(), version),
+            digest: Digest::default(),
+        }
+    }
+}
+
+/// The chain specification of a substrate chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainSpec {
+    /// The name of the chain.
+    pub name: String,
+    /// The boot nodes of the chain.
+    pub boot_nodes: Vec<String>,
+    /// The fork id of the chain, set for chains relaunched with the same genesis.
+    pub fork_id: Option<String>,
+    /// The legacy protocol id of the chain (ie `dot`).
+    pub protocol_id: Option<String>,
+    /// The SS58 address format of the chain.
+    pub ss58_format: Option<u16>,
+    /// The raw genesis storage, if the chain spec is raw.
+    pub genesis: Option<RawGenesis>,
+}
+
+impl ChainSpec {
+    /// Parse the chain spec from JSON.
+    pub fn from_json_bytes(bytes: &[u8]) -> Result<Self, ChainSpecError> {
+        let spec: Value = serde_json::from_slice(bytes)?;
+
+        let name = spec
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or(ChainSpecError::InvalidField("name"))?
+            .to_string();
+
+        let boot_nodes = match spec.get("bootNodes") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::Array(nodes)) => nodes
+                .iter()
+                .map(|node| node.as_str().map(|node| node.to_string()))
+                .collect::<Option<_>>()
+                .ok_or(ChainSpecError::InvalidField("bootNodes"))?,
+            Some(_) => return Err(ChainSpecError::InvalidField("bootNodes")),
+        };
+
+        let fork_id = optional_str(&spec, "forkId")?;
+        let protocol_id = optional_str(&spec, "protocolId")?;
+
+        let ss58_format = match spec
+            .get("properties")
+            .and_then(|props| props.get("ss58Format"))
+        {
+            None | Some(Value::Null) => None,
+            Some(format) => Some(
+                format
+                    .as_u64()
+                    .and_then(|format| u16::try_from(format).ok())
+                    .ok_or(ChainSpecError::InvalidField("properties.ss58Format"))?,
+            ),
+        };
+
+        let genesis = match spec.get("genesis").and_then(|genesis| genesis.get("raw")) {
+            None => None,
+            Some(raw) => Some(RawGenesis {
+                top: raw
+                    .get("top")
+                    .map(|top| decode_storage(top, "genesis.raw.top"))
+                    .transpose()?
+                    .unwrap_or_default(),
+                children_default: match raw.get("childrenDefault") {
+                    None => BTreeMap::new(),
+                    Some(Value::Object(children)) => children
+                        .iter()
+                        .map(|(key, child)| {
+                            let key = decode_hex(key, "genesis.raw.childrenDefault")?;
+                            let child = decode_storage(child, "genesis.raw.childrenDefault")?;
+                            Ok((key, child))
+                        })
+                        .collect::<Result<_, ChainSpecError>>()?,
+                    Some(_) => {
+                        return Err(ChainSpecError::InvalidField("genesis.raw.childrenDefault"))
+                    }
+                },
+            }),
+        };
+
+        Ok(ChainSpec {
+            name,
+            boot_nodes,
+            fork_id,
+            protocol_id,
+            ss58_format,
+            genesis,
+        })
+    }
+
+    /// Compute the genesis hash of the chain from the raw genesis storage.
+    ///
+    /// The state version of the genesis storage is read from the runtime code.
+    pub fn genesis_hash(&self) -> Result<BlockHash, ChainSpecError> {
+        let version = self.raw_genesis()?.state_version()?;
+        self.genesis_hash_with(version)
+    }
+
+    /// Compute the genesis hash of the chain for the provided state version.
+    pub fn genesis_hash_with(&self, version: StateVersion) -> Result<BlockHash, ChainSpecError> {
+        Ok(self.raw_genesis()?.genesis_header(version).hash())
+    }
+
+    /// Returns the raw genesis storage.
+    pub fn raw_genesis(&self) -> Result<&RawGenesis, ChainSpecError> {
+        self.genesis
+            .as_ref()
+            .ok_or(ChainSpecError::MissingRawGenesis)
+    }
+}
+
+/// Returns the optional string field of the chain spec.
+fn optional_str(spec: &Value, field: &'static str) -> Result<Option<String>, ChainSpecError> {
+    match spec.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.clone())),
+        Some(_) => Err(ChainSpecError::InvalidField(field)),
+    }
+}
+
+/// Decode a hex-encoded string prefixed by `0x`.
+fn decode_hex(value: &str, field: &'static str) -> Result<Vec<u8>, ChainSpecError> {
+    hex::decode(value.trim_start_matches("0x")).map_err(|_| ChainSpecError::InvalidField(field))
+}
+
+/// Decode the hex-encoded key-values of a raw storage.
+fn decode_storage(
+    storage: &Value,
+    field: &'static str,
+) -> Result<BTreeMap<Vec<u8>, Vec<u8>>, ChainSpecError> {
+    let storage = storage
+        .as_object()
+        .ok_or(ChainSpecError::InvalidField(field))?;
+
+    storage
+        .iter()
+        .map(|(key, value)| {
+            let value = value.as_str().ok_or(ChainSpecError::InvalidField(field))?;
+            Ok((decode_hex(key, field)?, decode_hex(value, field)?))
+        })
+        .collect()
+}
+
+/// Returns the content of the wasm custom section with the provided name.
+fn custom_section<'a>(code: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    // The wasm magic number followed by the version.
+    let mut input = code.strip_prefix(b"\0asm")?.get(4..)?;
+
+    while let Some((&id, rest)) = input.split_first() {
+        input = rest;
+        let size = read_leb128(&mut input)? as usize;
+        let section = input.get(..size)?;
+        input = &input[size..];
+
+        if id != 0 {
+            continue;
+        }
+        let mut section = section;
+        let name_len = read_leb128(&mut section)? as usize;
+        if section.get(..name_len)? == name {
+            return Some(&section[name_len..]);
+        }
+    }
+
+    None
+}
+
+/// Read an unsigned LEB128 encoded integer.
+fn read_leb128(input: &mut &[u8]) -> Option<u32> {
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let (&byte, rest) = input.split_first()?;
+        *input = rest;
+        value |= ((byte & 0x7f) as u32).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Decode the state version of a scale-encoded runtime version.
+fn decode_state_version(input: &mut &[u8]) -> Result<StateVersion, codec::Error> {
+    // spec_name, impl_name
+    String::decode(input)?;
+    String::decode(input)?;
+    // authoring_version, spec_version, impl_version
+    <[u32; 3]>::decode(input)?;
+    // apis
+    Vec::<([u8; 8], u32)>::decode(input)?;
+
+    // The transaction and state versions were appended to the runtime version.
+    if input.is_empty() {
+        return Ok(StateVersion::V0);
+    }
+    u32::decode(input)?;
+    if input.is_empty() {
+        return Ok(StateVersion::V0);
+    }
+    match u8::decode(input)? {
+        0 => Ok(StateVersion::V0),
+        1 => Ok(StateVersion::V1),
+        _ => Err("Invalid state version".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::Encode;
+
+    /// Returns a wasm module that only contains the custom section.
+    fn wasm_with_section(name: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut section = vec![name.len() as u8];
+        section.extend(name);
+        section.extend(content);
+
+        let mut code = b"\0asm".to_vec();
+        code.extend([1, 0, 0, 0]);
+        // An empty type section precedes the custom section.
+        code.extend([1, 1, 0]);
+        code.push(0);
+        code.push(section.len() as u8);
+        code.extend(section);
+        code
+    }
+
+    fn runtime_version(state_version: Option<u8>) -> Vec<u8> {
+        let mut version = ("node".to_string(), "node".to_string(), [1u32, 100, 1]).encode();
+        version.extend(Vec::<([u8; 8], u32)>::new().encode());
+        if let Some(state_version) = state_version {
+            version.extend(1u32.encode());
+            version.push(state_version);
+        }
+        version
+    }
+
+    fn spec(code: &[u8]) -> String {
+        format!(
+            r#"{{
+                "name": "Local Testnet",
+                "id": "local_testnet",
+                "bootNodes": ["/ip4/127.0.0.1/tcp/30333/p2p/12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp"],
+                "protocolId": "dot",
+                "forkId": null,
+                "properties": {{ "ss58Format": 42, "tokenDecimals": 12 }},
+                "genesis": {{
+                    "raw": {{
+                        "top": {{ "0x3a636f6465": "0x{}", "0x0102": "0x{}" }},
+                        "childrenDefault": {{ "0x0a": {{ "0x0b": "0x0c" }} }}
+                    }}
+                }}
+            }}"#,
+            hex::encode(code),
+            hex::encode([7u8; 40])
+        )
+    }
+
+    #[test]
+    fn parse_chain_spec() {
+        let code = wasm_with_section(RUNTIME_VERSION_SECTION, &runtime_version(Some(1)));
+        let spec = ChainSpec::from_json_bytes(spec(&code).as_bytes()).unwrap();
+
+        assert_eq!(spec.name, "Local Testnet");
+        assert_eq!(spec.boot_nodes.len(), 1);
+        assert_eq!(spec.protocol_id.as_deref(), Some("dot"));
+        assert_eq!(spec.fork_id, None);
+        assert_eq!(spec.ss58_format, Some(42));
+
+        let genesis = spec.raw_genesis().unwrap();
+        assert_eq!(genesis.top.get(CODE_KEY), Some(&code));
+        assert_eq!(genesis.state_version().unwrap(), StateVersion::V1);
+
+        // The root of the child trie is part of the state root.
+        let mut top = genesis.top.clone();
+        let child = BTreeMap::from([(vec![0x0b], vec![0x0c])]);
+        top.insert(
+            b":child_storage:default:\x0a".to_vec(),
+            trie_root(&child, StateVersion::V1).as_bytes().to_vec(),
+        );
+        let header = Header {
+            parent_hash: BlockHash::zero(),
+            number: 0,
+            state_root: trie_root(&top, StateVersion::V1),
+            extrinsics_root: trie_root(&BTreeMap::new(), StateVersion::V1),
+            digest: Digest::default(),
+        };
+        assert_eq!(spec.genesis_hash().unwrap(), header.hash());
+        assert_ne!(
+            spec.genesis_hash_with(StateVersion::V0).unwrap(),
+            header.hash()
+        );
+    }
+
+    #[test]
+    fn state_version_of_runtime_code() {
+        let state_version = |code: Vec<u8>| {
+            RawGenesis {
+                top: BTreeMap::from([(CODE_KEY.to_vec(), code)]),
+                ..Default::default()
+            }
+            .state_version()
+        };
+
+        let code = wasm_with_section(RUNTIME_VERSION_SECTION, &runtime_version(Some(0)));
+        assert_eq!(state_version(code).unwrap(), StateVersion::V0);
+        // The runtime version predates the state versions.
+        let code = wasm_with_section(RUNTIME_VERSION_SECTION, &runtime_version(None));
+        assert_eq!(state_version(code).unwrap(), StateVersion::V0);
+        // The runtime predates the embedded runtime version.
+        let code = wasm_with_section(b"name", &[]);
+        assert_eq!(state_version(code).unwrap(), StateVersion::V0);
+
+        let mut code = COMPRESSED_CODE_PREFIX.to_vec();
+        code.extend([0; 16]);
+        assert!(matches!(
+            state_version(code),
+            Err(ChainSpecError::UnknownStateVersion)
+        ));
+    }
+
+    #[test]
+    fn parse_chain_spec_without_raw_genesis() {
+        let spec = ChainSpec::from_json_bytes(
+            br#"{ "name": "Dev", "bootNodes": [], "genesis": { "runtimeGenesis": {} } }"#,
+        )
+        .unwrap();
+
+        assert!(spec.boot_nodes.is_empty());
+        assert_eq!(spec.ss58_format, None);
+        assert!(matches!(
+            spec.genesis_hash(),
+            Err(ChainSpecError::MissingRawGenesis)
+        ));
+        assert!(matches!(
+            ChainSpec::from_json_bytes(br#"{ "name": "Dev", "bootNodes": [1] }"#),
+            Err(ChainSpecError::InvalidField("bootNodes"))
+        ));
+    }
+}
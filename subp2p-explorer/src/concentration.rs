@@ -0,0 +1,102 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Concentration of the peers on shared infrastructure.
+//!
+//! Peers hosted in the same country, by the same provider or in the same subnet
+//! are likely to fail together. The groups point to the infrastructure the
+//! network depends on.
+
+use ip_network::IpNetwork;
+use serde::Serialize;
+use std::{collections::HashMap, net::IpAddr};
+
+/// Returns the subnet of the IP address, /24 for IPv4 and /48 for IPv6.
+pub fn ip_subnet(ip: IpAddr) -> IpNetwork {
+    let netmask = match ip {
+        IpAddr::V4(_) => 24,
+        IpAddr::V6(_) => 48,
+    };
+    IpNetwork::new_truncate(ip, netmask).expect("Netmask is valid for the IP version; qed")
+}
+
+/// The number of peers and authorities sharing a country, a hosting provider or a subnet.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GroupCount {
+    /// The name of the group.
+    pub name: String,
+    /// The number of peers in the group.
+    pub peers: usize,
+    /// The number of authorities among the peers.
+    pub authorities: usize,
+}
+
+/// Group the peers by the provided key, in decreasing order by the number of peers.
+///
+/// The items are the key of every peer, if any, together with true for authorities.
+/// Peers without a key are not counted.
+pub fn group_peers(peers: impl IntoIterator<Item = (Option<String>, bool)>) -> Vec<GroupCount> {
+    let mut groups: HashMap<String, GroupCount> = HashMap::new();
+    for (name, authority) in peers {
+        let Some(name) = name else { continue };
+        let group = groups.entry(name.clone()).or_insert_with(|| GroupCount {
+            name,
+            peers: 0,
+            authorities: 0,
+        });
+        group.peers += 1;
+        if authority {
+            group.authorities += 1;
+        }
+    }
+
+    let mut groups: Vec<_> = groups.into_values().collect();
+    groups.sort_by(|a, b| b.peers.cmp(&a.peers).then_with(|| a.name.cmp(&b.name)));
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subnets_of_ips() {
+        assert_eq!(
+            ip_subnet("203.0.113.7".parse().unwrap()).to_string(),
+            "203.0.113.0/24"
+        );
+        assert_eq!(
+            ip_subnet("2001:db8:1234:5678::1".parse().unwrap()).to_string(),
+            "2001:db8:1234::/48"
+        );
+        assert_eq!(
+            ip_subnet("203.0.113.7".parse().unwrap()),
+            ip_subnet("203.0.113.250".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn group_peers_by_key() {
+        let name = |name: &str| Some(name.to_string());
+        let groups = group_peers([
+            (name("DE"), true),
+            (name("US"), false),
+            (name("DE"), false),
+            (None, true),
+            (name("FR"), true),
+        ]);
+
+        let count = |name: &str, peers, authorities| GroupCount {
+            name: name.to_string(),
+            peers,
+            authorities,
+        };
+        // Groups of the same size are ordered by name.
+        assert_eq!(
+            groups,
+            vec![count("DE", 2, 1), count("FR", 1, 1), count("US", 1, 0)]
+        );
+        assert!(group_peers([]).is_empty());
+    }
+}
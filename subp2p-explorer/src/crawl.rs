@@ -0,0 +1,282 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Systematic crawl of the routing tables of the peers.
+//!
+//! Kademlia stores a peer in the bucket of the common prefix length of the
+//! hashes of the two peer IDs. A `FIND_NODE` request for a key that shares
+//! exactly `n` bits with the remote returns the peers of the bucket `n`, since
+//! they are closer to the key than the peers of any other bucket. Requesting
+//! one key per bucket returns the full routing table of the remote.
+
+use crate::requests::kademlia::{FindNodeRequest, KademliaPeer};
+use libp2p::PeerId;
+use sha2::{Digest, Sha256};
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
+
+/// The number of prefix bits covered by the [`BucketKeys`].
+///
+/// The key of the last bucket also returns the peers of the deeper buckets,
+/// which are rarely populated in networks of less than tens of thousands of nodes.
+pub const BUCKET_PREFIX_BITS: usize = 12;
+
+/// Preimages of keys whose hashes start with every possible prefix of
+/// [`BUCKET_PREFIX_BITS`] bits.
+///
+/// The preimages are the big endian bytes of the first counter that hashes to
+/// the prefix, which makes the table deterministic.
+#[derive(Debug, Clone)]
+pub struct BucketKeys {
+    preimages: Vec<u64>,
+}
+
+impl BucketKeys {
+    /// Generate the preimages of every prefix.
+    pub fn generate() -> Self {
+        const NUM_PREFIXES: usize = 1 << BUCKET_PREFIX_BITS;
+
+        let mut preimages = vec![None; NUM_PREFIXES];
+        let mut missing = NUM_PREFIXES;
+        let mut counter = 0u64;
+        while missing > 0 {
+            let slot = &mut preimages[prefix(&counter.to_be_bytes())];
+            if slot.is_none() {
+                *slot = Some(counter);
+                missing -= 1;
+            }
+            counter += 1;
+        }
+
+        BucketKeys {
+            preimages: preimages.into_iter().flatten().collect(),
+        }
+    }
+
+    /// Returns the key of the bucket `common_prefix` of the routing table of the peer.
+    ///
+    /// The hash of the key shares exactly `common_prefix` bits with the hash of the peer ID.
+    pub fn key(&self, peer_id: &PeerId, common_prefix: usize) -> Vec<u8> {
+        assert!(common_prefix < BUCKET_PREFIX_BITS, "Bucket is not covered");
+
+        let target = prefix(&peer_id.to_bytes()) ^ (1 << (BUCKET_PREFIX_BITS - 1 - common_prefix));
+        self.preimages[target].to_be_bytes().to_vec()
+    }
+
+    /// Returns the [`FindNodeRequest`] of every bucket of the routing table of the peer.
+    pub fn requests(&self, peer_id: &PeerId) -> Vec<FindNodeRequest> {
+        (0..BUCKET_PREFIX_BITS)
+            .map(|common_prefix| FindNodeRequest {
+                key: self.key(peer_id, common_prefix),
+            })
+            .collect()
+    }
+}
+
+/// Returns the first [`BUCKET_PREFIX_BITS`] bits of the kademlia hash of the bytes.
+fn prefix(bytes: &[u8]) -> usize {
+    let hash = Sha256::digest(bytes);
+    (u16::from_be_bytes([hash[0], hash[1]]) >> (16 - BUCKET_PREFIX_BITS)) as usize
+}
+
+/// The crawl state of a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrawlState {
+    /// The peer is waiting to be crawled.
+    Queued,
+    /// The routing table of the peer is being requested.
+    InProgress {
+        /// The number of requests without a response or failure.
+        pending: usize,
+        /// The number of successful requests.
+        responses: usize,
+    },
+    /// At least one bucket of the routing table was returned.
+    Crawled,
+    /// Every request of the routing table failed.
+    Unreachable,
+}
+
+/// Crawl the routing table of every peer of the network.
+///
+/// Peers returned by a routing table are queued for crawling, such that the
+/// crawl finishes once the routing tables no longer contain new peers.
+#[derive(Debug)]
+pub struct RoutingTableCrawl {
+    /// The keys of the buckets.
+    keys: BucketKeys,
+    /// The crawl state of every discovered peer.
+    states: HashMap<PeerId, CrawlState>,
+    /// Peers waiting to be crawled, in discovery order.
+    queue: VecDeque<PeerId>,
+    /// The number of peers being crawled.
+    in_progress: usize,
+}
+
+impl RoutingTableCrawl {
+    /// Constructs a new [`RoutingTableCrawl`].
+    pub fn new(keys: BucketKeys) -> Self {
+        RoutingTableCrawl {
+            keys,
+            states: HashMap::with_capacity(1024),
+            queue: VecDeque::with_capacity(1024),
+            in_progress: 0,
+        }
+    }
+
+    /// Queue the peer for crawling, returns true if the peer is new.
+    pub fn add_peer(&mut self, peer_id: PeerId) -> bool {
+        match self.states.entry(peer_id) {
+            Entry::Occupied(_) => false,
+            Entry::Vacant(vacant) => {
+                vacant.insert(CrawlState::Queued);
+                self.queue.push_back(peer_id);
+                true
+            }
+        }
+    }
+
+    /// Start crawling the next queued peer, if less than `max_in_progress`
+    /// peers are being crawled.
+    ///
+    /// Returns the requests that cover the routing table of the peer.
+    pub fn next_peer(&mut self, max_in_progress: usize) -> Option<(PeerId, Vec<FindNodeRequest>)> {
+        if self.in_progress >= max_in_progress {
+            return None;
+        }
+
+        let peer_id = self.queue.pop_front()?;
+        let requests = self.keys.requests(&peer_id);
+        self.states.insert(
+            peer_id,
+            CrawlState::InProgress {
+                pending: requests.len(),
+                responses: 0,
+            },
+        );
+        self.in_progress += 1;
+        Some((peer_id, requests))
+    }
+
+    /// Record a bucket returned by the peer.
+    ///
+    /// Returns the peers discovered for the first time.
+    pub fn on_response(&mut self, peer_id: PeerId, peers: &[KademliaPeer]) -> Vec<PeerId> {
+        self.on_request_done(peer_id, true);
+
+        peers
+            .iter()
+            .map(|peer| peer.peer_id)
+            .filter(|peer| self.add_peer(*peer))
+            .collect()
+    }
+
+    /// Record a failed request of the peer.
+    pub fn on_failure(&mut self, peer_id: PeerId) {
+        self.on_request_done(peer_id, false);
+    }
+
+    fn on_request_done(&mut self, peer_id: PeerId, success: bool) {
+        let Some(state) = self.states.get_mut(&peer_id) else {
+            return;
+        };
+        let CrawlState::InProgress { pending, responses } = state else {
+            return;
+        };
+
+        *pending -= 1;
+        *responses += usize::from(success);
+        if *pending == 0 {
+            *state = if *responses > 0 {
+                CrawlState::Crawled
+            } else {
+                CrawlState::Unreachable
+            };
+            self.in_progress -= 1;
+        }
+    }
+
+    /// Returns the crawl state of the peer.
+    pub fn state(&self, peer_id: &PeerId) -> Option<CrawlState> {
+        self.states.get(peer_id).copied()
+    }
+
+    /// Returns true if every discovered peer was crawled.
+    pub fn is_finished(&self) -> bool {
+        self.queue.is_empty() && self.in_progress == 0
+    }
+
+    /// Returns the number of discovered peers.
+    pub fn num_discovered(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Returns the number of peers in the provided state.
+    pub fn num_in_state(&self, state: CrawlState) -> usize {
+        self.states.values().filter(|s| **s == state).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::kad::KBucketKey;
+
+    #[test]
+    fn bucket_keys_cover_every_bucket() {
+        let keys = BucketKeys::generate();
+        let peer_id = PeerId::random();
+        let peer_key = KBucketKey::from(peer_id);
+
+        for (common_prefix, request) in keys.requests(&peer_id).into_iter().enumerate() {
+            let distance = peer_key.distance(&KBucketKey::new(request.key));
+            assert_eq!(distance.ilog2(), Some(255 - common_prefix as u32));
+        }
+    }
+
+    #[test]
+    fn crawl_until_no_new_peers() {
+        let keys = BucketKeys::generate();
+        let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let peer = |peer_id| KademliaPeer {
+            peer_id,
+            addresses: vec![],
+        };
+
+        let mut crawl = RoutingTableCrawl::new(keys);
+        assert!(crawl.add_peer(a));
+        assert!(!crawl.add_peer(a));
+
+        let (peer_id, requests) = crawl.next_peer(1).unwrap();
+        assert_eq!(peer_id, a);
+        assert_eq!(requests.len(), BUCKET_PREFIX_BITS);
+
+        // The first bucket returns new peers, the others are empty or fail.
+        assert_eq!(crawl.on_response(a, &[peer(b), peer(c)]), vec![b, c]);
+        assert_eq!(crawl.on_response(a, &[peer(b)]), vec![]);
+        // Only one peer is crawled at a time.
+        assert!(crawl.next_peer(1).is_none());
+        for _ in 2..BUCKET_PREFIX_BITS {
+            crawl.on_failure(a);
+        }
+        assert_eq!(crawl.state(&a), Some(CrawlState::Crawled));
+
+        let (peer_id, _) = crawl.next_peer(1).unwrap();
+        assert_eq!(peer_id, b);
+        for _ in 0..BUCKET_PREFIX_BITS {
+            crawl.on_failure(b);
+        }
+        assert_eq!(crawl.state(&b), Some(CrawlState::Unreachable));
+
+        let (peer_id, _) = crawl.next_peer(1).unwrap();
+        assert_eq!(peer_id, c);
+        assert!(!crawl.is_finished());
+        for _ in 0..BUCKET_PREFIX_BITS {
+            crawl.on_response(c, &[peer(a)]);
+        }
+        assert!(crawl.is_finished());
+        assert_eq!(crawl.num_discovered(), 3);
+        assert_eq!(crawl.num_in_state(CrawlState::Crawled), 2);
+        assert_eq!(crawl.num_in_state(CrawlState::Unreachable), 1);
+    }
+}
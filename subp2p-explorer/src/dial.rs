@@ -0,0 +1,219 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Classify the outcome of the dial attempts.
+//!
+//! The errors of the transport are boxed into [`io::Error`]s, the class is
+//! found by walking the chain of errors down to the layer that failed.
+
+use libp2p::{
+    core::{transport::timeout::TransportTimeoutError, upgrade::NegotiationError, ConnectedPoint},
+    multiaddr::Protocol,
+    noise,
+    swarm::DialError,
+    Multiaddr, TransportError,
+};
+use std::{error::Error, fmt, io};
+
+/// The class of a dial attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DialClass {
+    /// The connection was established.
+    Success,
+    /// The remote refused the TCP connection.
+    ConnectionRefused,
+    /// The connection or the upgrades did not complete in time.
+    Timeout,
+    /// The noise handshake failed.
+    NoiseHandshake,
+    /// The remote authenticated with a different peer ID.
+    WrongPeerId,
+    /// The remote does not support the protocols of the upgrades (ie noise or yamux).
+    MultistreamNegotiation,
+    /// The address is not supported by the transport (ie QUIC or WebRTC).
+    TransportNotSupported,
+    /// Any other failure.
+    Other,
+}
+
+impl DialClass {
+    /// Returns the name of the class.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DialClass::Success => "success",
+            DialClass::ConnectionRefused => "connection_refused",
+            DialClass::Timeout => "timeout",
+            DialClass::NoiseHandshake => "noise_handshake",
+            DialClass::WrongPeerId => "wrong_peer_id",
+            DialClass::MultistreamNegotiation => "multistream_negotiation",
+            DialClass::TransportNotSupported => "transport_not_supported",
+            DialClass::Other => "other",
+        }
+    }
+}
+
+impl fmt::Display for DialClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Classify the failed dial attempts of a [`DialError`].
+///
+/// Returns the class of every attempted address. Failures that are not
+/// related to an address are reported without one, while dials that were
+/// not attempted are not reported.
+pub fn classify_dial_error(error: &DialError) -> Vec<(Option<Multiaddr>, DialClass)> {
+    match error {
+        DialError::Transport(errors) => errors
+            .iter()
+            .map(|(address, error)| (Some(address.clone()), classify_transport_error(error)))
+            .collect(),
+        DialError::WrongPeerId { endpoint, .. } => {
+            let address = match endpoint {
+                ConnectedPoint::Dialer { address, .. } => Some(address.clone()),
+                ConnectedPoint::Listener { .. } => None,
+            };
+            vec![(address, DialClass::WrongPeerId)]
+        }
+        DialError::DialPeerConditionFalse(_) => vec![],
+        DialError::LocalPeerId { .. }
+        | DialError::NoAddresses
+        | DialError::Aborted
+        | DialError::Denied { .. } => vec![(None, DialClass::Other)],
+    }
+}
+
+/// Classify the error of the transport for a single address.
+pub fn classify_transport_error(error: &TransportError<io::Error>) -> DialClass {
+    match error {
+        TransportError::MultiaddrNotSupported(_) => DialClass::TransportNotSupported,
+        TransportError::Other(error) => classify_error(error),
+    }
+}
+
+/// Walk the chain of errors to find the layer that failed.
+fn classify_error(error: &(dyn Error + 'static)) -> DialClass {
+    let mut current = Some(error);
+
+    while let Some(error) = current {
+        if let Some(io_error) = error.downcast_ref::<io::Error>() {
+            match io_error.kind() {
+                io::ErrorKind::ConnectionRefused => return DialClass::ConnectionRefused,
+                io::ErrorKind::TimedOut => return DialClass::Timeout,
+                _ => (),
+            }
+
+            // The source of an `io::Error` skips the wrapped error.
+            if let Some(inner) = io_error.get_ref() {
+                current = Some(inner);
+                continue;
+            }
+        }
+
+        if error.is::<noise::Error>() {
+            return DialClass::NoiseHandshake;
+        }
+        if error.is::<NegotiationError>() {
+            return DialClass::MultistreamNegotiation;
+        }
+        // The transport erases the inner error of its timeout.
+        if let Some(TransportTimeoutError::Timeout) =
+            error.downcast_ref::<TransportTimeoutError<io::Error>>()
+        {
+            return DialClass::Timeout;
+        }
+
+        current = error.source();
+    }
+
+    DialClass::Other
+}
+
+/// Returns the address without the trailing `/p2p/{peer_id}` component.
+pub fn strip_peer_id(address: &Multiaddr) -> Multiaddr {
+    let mut address = address.clone();
+    if let Some(Protocol::P2p(_)) = address.iter().last() {
+        address.pop();
+    }
+    address
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::{core::Endpoint, PeerId};
+
+    fn transport_error(error: impl Error + Send + Sync + 'static) -> TransportError<io::Error> {
+        TransportError::Other(io::Error::new(io::ErrorKind::Other, error))
+    }
+
+    #[test]
+    fn classify_transport_errors() {
+        let address: Multiaddr = "/ip4/1.2.3.4/udp/30333/quic-v1".parse().unwrap();
+        assert_eq!(
+            classify_transport_error(&TransportError::MultiaddrNotSupported(address)),
+            DialClass::TransportNotSupported
+        );
+
+        let refused = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+        assert_eq!(
+            classify_transport_error(&transport_error(TransportTimeoutError::Other(refused))),
+            DialClass::ConnectionRefused
+        );
+        assert_eq!(
+            classify_transport_error(&transport_error(
+                TransportTimeoutError::<io::Error>::Timeout
+            )),
+            DialClass::Timeout
+        );
+        assert_eq!(
+            classify_transport_error(&transport_error(noise::Error::AuthenticationFailed)),
+            DialClass::NoiseHandshake
+        );
+        assert_eq!(
+            classify_transport_error(&transport_error(NegotiationError::Failed)),
+            DialClass::MultistreamNegotiation
+        );
+        assert_eq!(
+            classify_transport_error(&transport_error(io::Error::new(
+                io::ErrorKind::Other,
+                "other"
+            ))),
+            DialClass::Other
+        );
+    }
+
+    #[test]
+    fn classify_dial_errors() {
+        let address: Multiaddr = "/ip4/1.2.3.4/tcp/30333".parse().unwrap();
+        let error = DialError::WrongPeerId {
+            obtained: PeerId::random(),
+            endpoint: ConnectedPoint::Dialer {
+                address: address.clone(),
+                role_override: Endpoint::Dialer,
+            },
+        };
+        assert_eq!(
+            classify_dial_error(&error),
+            vec![(Some(address.clone()), DialClass::WrongPeerId)]
+        );
+
+        let error = DialError::Transport(vec![(
+            address.clone(),
+            TransportError::MultiaddrNotSupported(address.clone()),
+        )]);
+        assert_eq!(
+            classify_dial_error(&error),
+            vec![(Some(address.clone()), DialClass::TransportNotSupported)]
+        );
+
+        let peer_id = PeerId::random();
+        assert_eq!(
+            strip_peer_id(&address.clone().with(Protocol::P2p(peer_id))),
+            address
+        );
+        assert_eq!(strip_peer_id(&address), address);
+    }
+}
@@ -5,7 +5,7 @@
 use std::time::Duration;
 
 use libp2p::{
-    kad::{store::MemoryStore, Kademlia, KademliaConfig},
+    kad::{store::MemoryStore, Behaviour as Kademlia, Config as KademliaConfig},
     PeerId, StreamProtocol,
 };
 
@@ -24,6 +24,16 @@ pub struct DiscoveryBuilder {
     provider_ttl: Option<Duration>,
     /// Sets the timeout for a single query.
     query_timeout: Duration,
+    /// Legacy protocol names supported after the genesis protocol.
+    fallback_names: Vec<String>,
+    /// The fork id of the chain.
+    fork_id: Option<String>,
+}
+
+impl Default for DiscoveryBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DiscoveryBuilder {
@@ -34,6 +44,8 @@ impl DiscoveryBuilder {
             record_ttl: None,
             provider_ttl: None,
             query_timeout: Duration::from_secs(60),
+            fallback_names: Vec::new(),
+            fork_id: None,
         }
     }
 
@@ -65,6 +77,23 @@ impl DiscoveryBuilder {
         self
     }
 
+    /// Sets the legacy protocol names (ie `/dot/kad`).
+    ///
+    /// Nodes that predate the genesis hash protocol names only support these.
+    /// Each name must start with '/'.
+    pub fn fallback_names(mut self, fallback_names: Vec<String>) -> Self {
+        self.fallback_names = fallback_names;
+        self
+    }
+
+    /// Sets the fork id of the chain.
+    ///
+    /// The genesis protocol becomes `/{genesis_hash}/{fork_id}/kad`.
+    pub fn fork_id(mut self, fork_id: Option<String>) -> Self {
+        self.fork_id = fork_id;
+        self
+    }
+
     /// Build the discovery protocol.
     pub fn build(self, local_peer_id: PeerId, genesis_hash: &str) -> Discovery {
         let mut config = KademliaConfig::default();
@@ -73,13 +102,15 @@ impl DiscoveryBuilder {
         config.set_provider_record_ttl(self.provider_ttl);
         config.set_query_timeout(self.query_timeout);
 
-        // Support only the genesis protocol for kademlia.
-        let kademlia_protocols =
-            vec![
-                StreamProtocol::try_from_owned(format!("/{genesis_hash}/kad"))
-                    .expect("Protocol name starts with '/'; qed"),
-            ];
-        config.set_protocol_names(kademlia_protocols.into_iter().map(Into::into).collect());
+        // The genesis protocol is preferred over the legacy protocols.
+        let genesis_protocol = crate::protocol_name(genesis_hash, self.fork_id.as_deref(), "kad");
+        let kademlia_protocols = std::iter::once(genesis_protocol)
+            .chain(self.fallback_names)
+            .map(|name| {
+                StreamProtocol::try_from_owned(name).expect("Protocol name starts with '/'; qed")
+            })
+            .collect();
+        config.set_protocol_names(kademlia_protocols);
 
         // Use memory store for kad.
         let store = MemoryStore::new(local_peer_id);
@@ -4,10 +4,31 @@
 
 use libp2p::swarm::NetworkBehaviour;
 
+pub mod address_hygiene;
+pub mod agent_version;
+pub mod chain_spec;
+pub mod concentration;
+pub mod crawl;
+pub mod dial;
 pub mod discovery;
 pub mod notifications;
 pub mod peer_behavior;
+pub mod peer_store;
+pub mod requests;
+pub mod topology;
 pub mod transport;
+pub mod trie;
+
+/// Returns the name of a protocol of the chain (ie `/{genesis_hash}/kad`).
+///
+/// Chains relaunched with the same genesis are distinguished by the fork id,
+/// which is placed after the genesis hash (ie `/{genesis_hash}/{fork_id}/kad`).
+pub fn protocol_name(genesis_hash: &str, fork_id: Option<&str>, protocol: &str) -> String {
+    match fork_id {
+        Some(fork_id) => format!("/{genesis_hash}/{fork_id}/{protocol}"),
+        None => format!("/{genesis_hash}/{protocol}"),
+    }
+}
 
 /// Network behavior for subtrate based chains.
 #[derive(NetworkBehaviour)]
@@ -18,9 +39,12 @@ pub struct Behaviour {
     pub peer_info: peer_behavior::PeerBehaviour,
     /// Discovers nodes of the network.
     pub discovery: discovery::Discovery,
+    /// Requests blocks on the `/{genesis}/sync/2` protocol.
+    pub block_requests: requests::block::BlockRequests,
+    /// Requests storage on the `/{genesis}/state/2` protocol.
+    pub state_requests: requests::state::StateRequests,
+    /// Requests storage proofs on the `/{genesis}/light/2` protocol.
+    pub light_requests: requests::light::LightRequests,
+    /// Requests the routing tables of peers on the `/{genesis}/kad` protocol.
+    pub kademlia_requests: requests::kademlia::KademliaRequests,
 }
-
-/// Protocol index for block-announces.
-pub const BLOCK_ANNOUNCES_INDEX: usize = 0;
-/// Protocol index for transactions.
-pub const TRANSACTIONS_INDEX: usize = 1;
@@ -3,14 +3,15 @@
 // see LICENSE for license details.
 
 use crate::notifications::{
+    chain_state::{BestBlockUpdate, ChainState},
     handler::{
         NotificationsHandler, NotificationsHandlerFromBehavior, NotificationsHandlerToBehavior,
     },
-    messages::BlockHash,
-    messages::ProtocolRole,
+    messages::{BlockAnnouncesHandshake, BlockHash, BlockNumber, ProtocolRole},
 };
 
 use bytes::BytesMut;
+use codec::{Decode, Encode};
 use futures::channel::mpsc;
 use libp2p::{
     core::{ConnectedPoint, Endpoint},
@@ -22,11 +23,34 @@ use libp2p::{
 };
 use std::{
     collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, RwLock},
     task::{Poll, Waker},
 };
 
 const LOG_TARGET: &str = "subp2p-behavior";
 
+/// Handle of a notification protocol registered with [`ProtocolsData::register`].
+///
+/// This is the position of the protocol in [`ProtocolsData::protocols`] and it is
+/// reported by every [`NotificationsToSwarm`] event.
+pub type ProtocolIndex = usize;
+
+/// The default maximum size of a notification in bytes.
+pub const DEFAULT_MAX_NOTIFICATION_SIZE: u64 = 1024 * 1024;
+
+/// The reason a notification protocol has been closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The remote closed its substream.
+    RemoteClosed,
+    /// Sending notifications to the remote failed.
+    FlushError,
+    /// The handshake could not be sent back to the remote.
+    HandshakeError,
+    /// The connection with the remote has been closed.
+    ConnectionClosed,
+}
+
 /// The events emitted by this network behavior back to the swarm.
 #[derive(Debug)]
 pub enum NotificationsToSwarm {
@@ -34,10 +58,16 @@ pub enum NotificationsToSwarm {
     CustomProtocolOpen {
         /// Id of the peer we are connected to.
         peer_id: PeerId,
+        /// The connection on which the protocol is opened.
+        connection_id: ConnectionId,
         /// The index of the protocol.
-        index: usize,
+        index: ProtocolIndex,
         /// Handshake that was received.
         received_handshake: Vec<u8>,
+        /// The protocol name negotiated with the remote.
+        ///
+        /// This is one of the fallback names if the remote only supports a legacy protocol.
+        negotiated_name: String,
         /// Is the connection inbound.
         inbound: bool,
         /// Channel to send data on this protocol.
@@ -50,8 +80,12 @@ pub enum NotificationsToSwarm {
     CustomProtocolClosed {
         /// Id of the peer we were connected to.
         peer_id: PeerId,
+        /// The connection on which the protocol was opened.
+        connection_id: ConnectionId,
         /// The index of the protocol.
-        index: usize,
+        index: ProtocolIndex,
+        /// The reason of closing the protocol.
+        reason: CloseReason,
     },
 
     /// A custom notification message has been received on the given protocol.
@@ -59,10 +93,194 @@ pub enum NotificationsToSwarm {
         /// Id of the peer the message came from.
         peer_id: PeerId,
         /// The index of the protocol.
-        index: usize,
+        index: ProtocolIndex,
         /// Message that has been received.
         message: BytesMut,
     },
+
+    /// Notifications submitted through the sender of [`CustomProtocolOpen`] have been
+    /// flushed to the remote.
+    NotificationsFlushed {
+        /// Id of the peer the notifications were sent to.
+        peer_id: PeerId,
+        /// The connection on which the protocol is opened.
+        connection_id: ConnectionId,
+        /// The index of the protocol.
+        index: ProtocolIndex,
+        /// The number of notifications flushed.
+        count: usize,
+    },
+
+    /// The best block of the peer has changed.
+    ///
+    /// Reported from the block announces handshake and from block announcements
+    /// marked as the new best block.
+    PeerBestBlockUpdated {
+        /// Id of the peer.
+        peer_id: PeerId,
+        /// Best block number of the peer.
+        best_number: BlockNumber,
+        /// Best block hash of the peer.
+        best_hash: BlockHash,
+    },
+}
+
+impl From<BestBlockUpdate> for NotificationsToSwarm {
+    fn from(update: BestBlockUpdate) -> Self {
+        NotificationsToSwarm::PeerBestBlockUpdated {
+            peer_id: update.peer_id,
+            best_number: update.best_number,
+            best_hash: update.best_hash,
+        }
+    }
+}
+
+/// Handshake of a notification protocol that can be updated while the swarm is running.
+///
+/// The handle is shared between the [`ProtocolsData`] and all connection handlers.
+/// Substreams opened after an update submit the new handshake.
+#[derive(Debug, Clone, Default)]
+pub struct SharedHandshake {
+    inner: Arc<RwLock<Vec<u8>>>,
+}
+
+impl SharedHandshake {
+    /// Constructs a new [`SharedHandshake`].
+    pub fn new(handshake: Vec<u8>) -> Self {
+        SharedHandshake {
+            inner: Arc::new(RwLock::new(handshake)),
+        }
+    }
+
+    /// Returns the current handshake.
+    pub fn get(&self) -> Vec<u8> {
+        self.inner
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Replace the handshake.
+    pub fn set(&self, handshake: Vec<u8>) {
+        self.update(|current| *current = handshake);
+    }
+
+    /// Update the handshake in place, while holding the lock.
+    pub fn update<R>(&self, f: impl FnOnce(&mut Vec<u8>) -> R) -> R {
+        f(&mut self
+            .inner
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+}
+
+impl PartialEq for SharedHandshake {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+
+impl Eq for SharedHandshake {}
+
+impl From<Vec<u8>> for SharedHandshake {
+    fn from(handshake: Vec<u8>) -> Self {
+        SharedHandshake::new(handshake)
+    }
+}
+
+/// Handle to the best block advertised by the block announces handshake.
+///
+/// Obtained from [`ProtocolsData::best_block`].
+#[derive(Debug, Clone)]
+pub struct BestBlockHandle {
+    /// The handshake of the block announces protocol.
+    handshake: SharedHandshake,
+}
+
+impl BestBlockHandle {
+    /// Returns the advertised best block number and hash.
+    ///
+    /// Fails if the handshake was replaced by bytes that are not a block announces handshake.
+    pub fn get(&self) -> Result<(BlockNumber, BlockHash), codec::Error> {
+        let handshake = BlockAnnouncesHandshake::decode(&mut &self.handshake.get()[..])?;
+        Ok((handshake.best_number, handshake.best_hash))
+    }
+
+    /// Advertise the given best block.
+    pub fn set(&self, best_number: BlockNumber, best_hash: BlockHash) -> Result<(), codec::Error> {
+        self.update(|_| true, best_number, best_hash).map(|_| ())
+    }
+
+    /// Advertise the given best block if it is higher than the current best block.
+    ///
+    /// The comparison and the update happen under the same lock, such that concurrent
+    /// updates are not lost. Returns true if the best block was updated.
+    pub fn advance(
+        &self,
+        best_number: BlockNumber,
+        best_hash: BlockHash,
+    ) -> Result<bool, codec::Error> {
+        self.update(|current| best_number > current, best_number, best_hash)
+    }
+
+    /// Replace the best block if `should_update` accepts the current best block number.
+    fn update(
+        &self,
+        should_update: impl FnOnce(BlockNumber) -> bool,
+        best_number: BlockNumber,
+        best_hash: BlockHash,
+    ) -> Result<bool, codec::Error> {
+        self.handshake.update(|bytes| {
+            let mut handshake = BlockAnnouncesHandshake::decode(&mut &bytes[..])?;
+            if !should_update(handshake.best_number) {
+                return Ok(false);
+            }
+
+            handshake.best_number = best_number;
+            handshake.best_hash = best_hash;
+            *bytes = handshake.encode();
+            Ok(true)
+        })
+    }
+}
+
+/// Configuration of a single notification protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotificationProtocolConfig {
+    /// The name of the notification protocol (ie `/{genesis}/block-announces/1`).
+    pub name: String,
+    /// Names that are accepted for the protocol in addition to [`Self::name`].
+    pub fallback_names: Vec<String>,
+    /// Handshake that is submitted upon opening a substream.
+    pub handshake: SharedHandshake,
+    /// The maximum size of a notification received on this protocol.
+    ///
+    /// Default: 1 MiB.
+    pub max_notification_size: u64,
+}
+
+impl NotificationProtocolConfig {
+    /// Constructs a new [`NotificationProtocolConfig`].
+    pub fn new(name: impl Into<String>, handshake: impl Into<Vec<u8>>) -> Self {
+        NotificationProtocolConfig {
+            name: name.into(),
+            fallback_names: Vec::new(),
+            handshake: SharedHandshake::new(handshake.into()),
+            max_notification_size: DEFAULT_MAX_NOTIFICATION_SIZE,
+        }
+    }
+
+    /// Set the fallback names of the protocol.
+    pub fn with_fallback_names(mut self, fallback_names: Vec<String>) -> Self {
+        self.fallback_names = fallback_names;
+        self
+    }
+
+    /// Set the maximum size of a notification received on this protocol.
+    pub fn with_max_notification_size(mut self, max_notification_size: u64) -> Self {
+        self.max_notification_size = max_notification_size;
+        self
+    }
 }
 
 /// Data needed by supported notification protocols.
@@ -79,6 +297,156 @@ pub struct ProtocolsData {
     /// Note that `LightClients` will not receive any notifications on the transaction protocol
     /// to avoid resource consumption.
     pub node_role: ProtocolRole,
+
+    /// The notification protocols opened with every connected peer.
+    ///
+    /// The position of a protocol in this list is its [`ProtocolIndex`].
+    pub protocols: Vec<NotificationProtocolConfig>,
+
+    /// The index of the block announces protocol, if registered.
+    ///
+    /// Used to track the chain state of the remote peers.
+    pub block_announces: Option<ProtocolIndex>,
+
+    /// The legacy protocol id of the chain (ie `dot`).
+    ///
+    /// Nodes that predate the genesis hash protocol names only support the
+    /// `/{protocol_id}/block-announces/1` family of protocols.
+    pub legacy_protocol_id: Option<String>,
+
+    /// The fork id of the chain.
+    ///
+    /// Chains relaunched with the same genesis use the `/{genesis}/{fork_id}/..`
+    /// protocol names.
+    pub fork_id: Option<String>,
+}
+
+impl ProtocolsData {
+    /// Constructs a new [`ProtocolsData`] without any notification protocols.
+    pub fn new(genesis_hash: BlockHash, node_role: ProtocolRole) -> Self {
+        ProtocolsData {
+            genesis_hash,
+            node_role,
+            protocols: Vec::new(),
+            block_announces: None,
+            legacy_protocol_id: None,
+            fork_id: None,
+        }
+    }
+
+    /// Set the fork id of the chain.
+    ///
+    /// Must be called before registering the protocols.
+    pub fn with_fork_id(mut self, fork_id: impl Into<String>) -> Self {
+        self.fork_id = Some(fork_id.into());
+        self
+    }
+
+    /// Returns the name `/{genesis}/{fork_id}/{protocol}` of the protocol.
+    fn protocol_name(&self, protocol: &str) -> String {
+        crate::protocol_name(
+            &hex::encode(self.genesis_hash),
+            self.fork_id.as_deref(),
+            protocol,
+        )
+    }
+
+    /// Set the legacy protocol id of the chain.
+    ///
+    /// Must be called before registering the protocols.
+    pub fn with_legacy_protocol_id(mut self, protocol_id: impl Into<String>) -> Self {
+        self.legacy_protocol_id = Some(protocol_id.into());
+        self
+    }
+
+    /// Returns the legacy name `/{protocol_id}/{suffix}` if a legacy protocol id is set.
+    fn legacy_names(&self, suffix: &str) -> Vec<String> {
+        self.legacy_protocol_id
+            .iter()
+            .map(|protocol_id| format!("/{protocol_id}/{suffix}"))
+            .collect()
+    }
+
+    /// Register a notification protocol and return its index.
+    pub fn register(&mut self, config: NotificationProtocolConfig) -> ProtocolIndex {
+        self.protocols.push(config);
+        self.protocols.len() - 1
+    }
+
+    /// Register the `/{genesis}/block-announces/1` protocol.
+    ///
+    /// Substrate peers must accept this protocol before any other notification protocol
+    /// is accepted. Therefore, it should be the first registered protocol.
+    pub fn register_block_announces(&mut self) -> ProtocolIndex {
+        let name = self.protocol_name("block-announces/1");
+        let handshake = BlockAnnouncesHandshake::from_genesis(self.genesis_hash).encode();
+        let fallback_names = self.legacy_names("block-announces/1");
+
+        let index = self.register(
+            NotificationProtocolConfig::new(name, handshake).with_fallback_names(fallback_names),
+        );
+        self.block_announces = Some(index);
+        index
+    }
+
+    /// Returns a handle to the best block advertised by the block announces protocol.
+    ///
+    /// The handshake advertises the genesis block until the handle is updated.
+    pub fn best_block(&self) -> Option<BestBlockHandle> {
+        let index = self.block_announces?;
+
+        Some(BestBlockHandle {
+            handshake: self.protocols[index].handshake.clone(),
+        })
+    }
+
+    /// Register the `/{genesis}/transactions/1` protocol.
+    ///
+    /// The substream broadcasts a scale-encoded vector of extrinsics.
+    pub fn register_transactions(&mut self) -> ProtocolIndex {
+        let name = self.protocol_name("transactions/1");
+        // Any protocol that doesn't have a handshake must submit the node role.
+        let handshake = vec![self.node_role.encoded()];
+        let fallback_names = self.legacy_names("transactions/1");
+
+        self.register(
+            NotificationProtocolConfig::new(name, handshake).with_fallback_names(fallback_names),
+        )
+    }
+
+    /// Register the `/{genesis}/grandpa/1` protocol.
+    ///
+    /// The substream gossips the finality votes and commits of the GRANDPA voters.
+    pub fn register_grandpa(&mut self) -> ProtocolIndex {
+        let name = self.protocol_name("grandpa/1");
+        // The handshake of the protocol is the node role.
+        let handshake = vec![self.node_role.encoded()];
+
+        self.register(
+            NotificationProtocolConfig::new(name, handshake)
+                .with_fallback_names(vec!["/paritytech/grandpa/1".into()]),
+        )
+    }
+
+    /// Register the `/{genesis}/statement/1` protocol.
+    ///
+    /// The substream gossips a scale-encoded vector of statements.
+    pub fn register_statements(&mut self) -> ProtocolIndex {
+        let name = self.protocol_name("statement/1");
+        // Any protocol that doesn't have a handshake must submit the node role.
+        let handshake = vec![self.node_role.encoded()];
+
+        self.register(NotificationProtocolConfig::new(name, handshake))
+    }
+}
+
+/// Notification protocols of a single connection.
+#[derive(Debug, Default)]
+struct ConnectionDetails {
+    /// Protocols reported as open to the swarm.
+    open: HashSet<ProtocolIndex>,
+    /// Protocols that the handler desires to close, together with the reason.
+    closing: HashMap<ProtocolIndex, CloseReason>,
 }
 
 /// Handles the notifications protocols.
@@ -89,9 +457,11 @@ pub struct Notifications {
     /// or `on_connection_handler_event` (triggered when requesting a substream).
     events: VecDeque<ToSwarm<NotificationsToSwarm, NotificationsHandlerFromBehavior>>,
     /// Peer details for valid connections.
-    peers_details: HashMap<PeerId, HashSet<ConnectionId>>,
+    peers_details: HashMap<PeerId, HashMap<ConnectionId, ConnectionDetails>>,
     /// Data needed by protocols.
     data: ProtocolsData,
+    /// Chain state of the peers with an open block announces protocol.
+    chain_state: ChainState,
     /// Ensure we wake up on events. Set by the poll function.
     waker: Option<Waker>,
 }
@@ -103,10 +473,48 @@ impl Notifications {
             events: VecDeque::with_capacity(16),
             peers_details: HashMap::default(),
             data,
+            chain_state: ChainState::new(),
             waker: None,
         }
     }
 
+    /// Returns a handle to the best block advertised in our block announces handshake.
+    pub fn best_block(&self) -> Option<BestBlockHandle> {
+        self.data.best_block()
+    }
+
+    /// Advertise the highest best block reported with the same hash by at least
+    /// `min_peers` peers of our genesis, if it is higher than the advertised best block.
+    ///
+    /// Requiring several peers to agree on the block prevents a single peer from pinning
+    /// the advertised best block to a fabricated hash or far ahead of the chain. Returns
+    /// true if the best block was updated.
+    pub fn advance_best_block(&self, min_peers: usize) -> Result<bool, codec::Error> {
+        let Some(best_block) = self.best_block() else {
+            return Ok(false);
+        };
+        let Some((best_number, best_hash, _)) = self.agreed_best_block(min_peers) else {
+            return Ok(false);
+        };
+
+        best_block.advance(best_number, best_hash)
+    }
+
+    /// Returns the highest best block reported with the same hash by at least `min_peers`
+    /// peers of our genesis, together with the peers that reported it.
+    pub fn agreed_best_block(
+        &self,
+        min_peers: usize,
+    ) -> Option<(BlockNumber, BlockHash, Vec<PeerId>)> {
+        self.chain_state
+            .agreed_best_block(&self.data.genesis_hash, min_peers)
+    }
+
+    /// Returns the chain state of the connected peers.
+    pub fn chain_state(&self) -> &ChainState {
+        &self.chain_state
+    }
+
     /// Propagate an event back to the swarm.
     fn propagate_event(
         &mut self,
@@ -200,17 +608,10 @@ impl NetworkBehaviour for Notifications {
 
                 self.peers_details
                     .entry(peer_id)
-                    .and_modify(|entry| {
-                        let _ = entry.insert(connection_id);
-                    })
-                    .or_insert_with(|| {
-                        let mut hash = HashSet::new();
-                        hash.insert(connection_id);
-                        hash
-                    });
+                    .or_default()
+                    .insert(connection_id, ConnectionDetails::default());
 
-                // Currently supports 2 protocols.
-                for index in 0..2 {
+                for index in 0..self.data.protocols.len() {
                     self.propagate_event(ToSwarm::NotifyHandler {
                         peer_id,
                         handler: NotifyHandler::One(connection_id),
@@ -229,30 +630,37 @@ impl NetworkBehaviour for Notifications {
                     connection_id
                 );
 
-                if let Some(details) = self.peers_details.get_mut(&peer_id) {
-                    let removed = details.remove(&connection_id);
-                    if !removed {
-                        log::trace!(target: LOG_TARGET,
-                            "Notifications swarm connection closed for untracked connection peer={:?} connection={:?}",
-                            peer_id,
-                            connection_id
-                        );
+                let details = match self.peers_details.get_mut(&peer_id) {
+                    Some(connections) => {
+                        let details = connections.remove(&connection_id);
+                        if connections.is_empty() {
+                            self.peers_details.remove(&peer_id);
+                            self.chain_state.remove_peer(&peer_id);
+                        }
+                        details
                     }
-                } else {
+                    None => None,
+                };
+
+                let Some(details) = details else {
                     log::trace!(target: LOG_TARGET,
-                        "Notifications swarm connection closed for untracked peer, peer={:?} connection={:?}",
+                        "Notifications swarm connection closed for untracked connection peer={:?} connection={:?}",
                         peer_id,
                         connection_id
                     );
-                }
+                    return;
+                };
 
-                // Currently supports 2 protocols.
-                for index in 0..2 {
-                    self.propagate_event(ToSwarm::NotifyHandler {
-                        peer_id,
-                        handler: NotifyHandler::One(connection_id),
-                        event: NotificationsHandlerFromBehavior::Close { index },
-                    });
+                // The handler is dropped together with the connection.
+                for index in details.open {
+                    self.propagate_event(ToSwarm::GenerateEvent(
+                        NotificationsToSwarm::CustomProtocolClosed {
+                            peer_id,
+                            connection_id,
+                            index,
+                            reason: CloseReason::ConnectionClosed,
+                        },
+                    ));
                 }
             }
             _ => (),
@@ -275,27 +683,57 @@ impl NetworkBehaviour for Notifications {
             NotificationsHandlerToBehavior::HandshakeCompleted {
                 index,
                 handshake,
+                negotiated_name,
                 is_inbound,
                 sender,
                 ..
             } => {
                 log::trace!(target: LOG_TARGET,
-                    "Notifications handler complited handshake peer={:?} connection={:?} index={:?} handshake={:?}",
+                    "Notifications handler complited handshake peer={:?} connection={:?} index={:?} name={:?} handshake={:?}",
                     peer_id,
                     connection_id,
                     index,
+                    negotiated_name,
                     handshake,
                 );
 
+                if let Some(details) = self
+                    .peers_details
+                    .get_mut(&peer_id)
+                    .and_then(|connections| connections.get_mut(&connection_id))
+                {
+                    details.open.insert(index);
+                }
+
+                let update = if self.data.block_announces == Some(index) {
+                    self.chain_state
+                        .on_handshake(peer_id, &handshake)
+                        .unwrap_or_else(|error| {
+                            log::debug!(target: LOG_TARGET,
+                                "Notifications invalid block announces handshake peer={:?} error={:?}",
+                                peer_id,
+                                error
+                            );
+                            None
+                        })
+                } else {
+                    None
+                };
+
                 self.propagate_event(ToSwarm::GenerateEvent(
                     NotificationsToSwarm::CustomProtocolOpen {
                         index,
                         peer_id,
+                        connection_id,
                         received_handshake: handshake,
+                        negotiated_name,
                         inbound: is_inbound,
                         sender,
                     },
                 ));
+                if let Some(update) = update {
+                    self.propagate_event(ToSwarm::GenerateEvent(update.into()));
+                }
             }
             NotificationsHandlerToBehavior::HandshakeError { index } => {
                 log::trace!(target: LOG_TARGET,
@@ -313,20 +751,103 @@ impl NetworkBehaviour for Notifications {
                     event: NotificationsHandlerFromBehavior::Open { index },
                 });
             }
-            NotificationsHandlerToBehavior::CloseDesired { index } => {
+            NotificationsHandlerToBehavior::CloseDesired { index, reason } => {
+                log::trace!(target: LOG_TARGET,
+                    "Notifications handler close desired peer={:?} connection={:?} index={:?} reason={:?}",
+                    peer_id,
+                    connection_id,
+                    index,
+                    reason,
+                );
+
+                if let Some(details) = self
+                    .peers_details
+                    .get_mut(&peer_id)
+                    .and_then(|connections| connections.get_mut(&connection_id))
+                {
+                    details.closing.entry(index).or_insert(reason);
+                }
+
                 self.propagate_event(ToSwarm::NotifyHandler {
                     peer_id,
                     handler: NotifyHandler::One(connection_id),
                     event: NotificationsHandlerFromBehavior::Close { index },
                 });
             }
-            NotificationsHandlerToBehavior::Close { .. } => {}
+            NotificationsHandlerToBehavior::Close { index } => {
+                let Some(details) = self
+                    .peers_details
+                    .get_mut(&peer_id)
+                    .and_then(|connections| connections.get_mut(&connection_id))
+                else {
+                    return;
+                };
+
+                let reason = details.closing.remove(&index);
+                // Only the protocols reported as open are reported as closed.
+                if !details.open.remove(&index) {
+                    return;
+                }
+
+                log::trace!(target: LOG_TARGET,
+                    "Notifications handler closed peer={:?} connection={:?} index={:?} reason={:?}",
+                    peer_id,
+                    connection_id,
+                    index,
+                    reason,
+                );
+
+                self.propagate_event(ToSwarm::GenerateEvent(
+                    NotificationsToSwarm::CustomProtocolClosed {
+                        peer_id,
+                        connection_id,
+                        index,
+                        reason: reason.unwrap_or(CloseReason::RemoteClosed),
+                    },
+                ));
+            }
             NotificationsHandlerToBehavior::Notification { bytes, index } => {
+                let update = if self.data.block_announces == Some(index) {
+                    self.chain_state
+                        .on_block_announce(peer_id, &bytes)
+                        .unwrap_or_else(|error| {
+                            log::debug!(target: LOG_TARGET,
+                                "Notifications invalid block announce peer={:?} error={:?}",
+                                peer_id,
+                                error
+                            );
+                            None
+                        })
+                } else {
+                    None
+                };
+
                 self.propagate_event(ToSwarm::GenerateEvent(NotificationsToSwarm::Notification {
                     peer_id,
                     index,
                     message: bytes,
                 }));
+                if let Some(update) = update {
+                    self.propagate_event(ToSwarm::GenerateEvent(update.into()));
+                }
+            }
+            NotificationsHandlerToBehavior::NotificationsFlushed { index, count } => {
+                log::trace!(target: LOG_TARGET,
+                    "Notifications handler flushed peer={:?} connection={:?} index={:?} count={:?}",
+                    peer_id,
+                    connection_id,
+                    index,
+                    count,
+                );
+
+                self.propagate_event(ToSwarm::GenerateEvent(
+                    NotificationsToSwarm::NotificationsFlushed {
+                        peer_id,
+                        connection_id,
+                        index,
+                        count,
+                    },
+                ));
             }
         }
     }
@@ -345,3 +866,390 @@ impl NetworkBehaviour for Notifications {
         Poll::Pending
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::swarm::{FromSwarm, THandlerOutEvent};
+
+    fn endpoint() -> ConnectedPoint {
+        ConnectedPoint::Dialer {
+            address: Multiaddr::empty(),
+            role_override: Endpoint::Dialer,
+        }
+    }
+
+    fn notifications() -> Notifications {
+        let mut data = ProtocolsData::new(BlockHash::zero(), ProtocolRole::FullNode);
+        data.register_block_announces();
+        data.register_transactions();
+        Notifications::new(data)
+    }
+
+    fn connect(notifications: &mut Notifications, peer_id: PeerId, connection_id: ConnectionId) {
+        let endpoint = endpoint();
+        notifications.on_swarm_event(FromSwarm::ConnectionEstablished(ConnectionEstablished {
+            peer_id,
+            connection_id,
+            endpoint: &endpoint,
+            failed_addresses: &[],
+            other_established: 0,
+        }));
+        notifications.events.clear();
+    }
+
+    fn handshake_completed(index: ProtocolIndex) -> THandlerOutEvent<Notifications> {
+        NotificationsHandlerToBehavior::HandshakeCompleted {
+            index,
+            endpoint: endpoint(),
+            handshake: vec![1],
+            negotiated_name: "/proto/1".into(),
+            is_inbound: false,
+            sender: mpsc::channel(1).0,
+        }
+    }
+
+    fn closed_events(notifications: &mut Notifications) -> Vec<(ProtocolIndex, CloseReason)> {
+        notifications
+            .events
+            .drain(..)
+            .filter_map(|event| match event {
+                ToSwarm::GenerateEvent(NotificationsToSwarm::CustomProtocolClosed {
+                    index,
+                    reason,
+                    ..
+                }) => Some((index, reason)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn protocol_closed_after_close_desired() {
+        let mut notifications = notifications();
+        let (peer_id, connection_id) = (PeerId::random(), ConnectionId::new_unchecked(0));
+        connect(&mut notifications, peer_id, connection_id);
+
+        notifications.on_connection_handler_event(peer_id, connection_id, handshake_completed(1));
+        notifications.on_connection_handler_event(
+            peer_id,
+            connection_id,
+            NotificationsHandlerToBehavior::CloseDesired {
+                index: 1,
+                reason: CloseReason::FlushError,
+            },
+        );
+        assert!(closed_events(&mut notifications).is_empty());
+
+        // The handler acknowledges the close request.
+        notifications.on_connection_handler_event(
+            peer_id,
+            connection_id,
+            NotificationsHandlerToBehavior::Close { index: 1 },
+        );
+        assert_eq!(
+            closed_events(&mut notifications),
+            vec![(1, CloseReason::FlushError)]
+        );
+
+        // Protocols are reported as closed only once.
+        notifications.on_connection_handler_event(
+            peer_id,
+            connection_id,
+            NotificationsHandlerToBehavior::Close { index: 1 },
+        );
+        assert!(closed_events(&mut notifications).is_empty());
+    }
+
+    #[test]
+    fn protocol_not_opened_is_not_reported() {
+        let mut notifications = notifications();
+        let (peer_id, connection_id) = (PeerId::random(), ConnectionId::new_unchecked(0));
+        connect(&mut notifications, peer_id, connection_id);
+
+        notifications.on_connection_handler_event(
+            peer_id,
+            connection_id,
+            NotificationsHandlerToBehavior::CloseDesired {
+                index: 0,
+                reason: CloseReason::HandshakeError,
+            },
+        );
+        notifications.on_connection_handler_event(
+            peer_id,
+            connection_id,
+            NotificationsHandlerToBehavior::Close { index: 0 },
+        );
+        assert!(closed_events(&mut notifications).is_empty());
+    }
+
+    #[test]
+    fn protocols_closed_on_connection_closed() {
+        let mut notifications = notifications();
+        let (peer_id, connection_id) = (PeerId::random(), ConnectionId::new_unchecked(0));
+        connect(&mut notifications, peer_id, connection_id);
+
+        notifications.on_connection_handler_event(peer_id, connection_id, handshake_completed(0));
+        notifications.on_connection_handler_event(peer_id, connection_id, handshake_completed(1));
+        notifications.events.clear();
+
+        let endpoint = endpoint();
+        let handler =
+            NotificationsHandler::new(peer_id, endpoint.clone(), notifications.data.clone());
+        notifications.on_swarm_event(FromSwarm::ConnectionClosed(ConnectionClosed {
+            peer_id,
+            connection_id,
+            endpoint: &endpoint,
+            handler,
+            remaining_established: 0,
+        }));
+
+        let mut closed = closed_events(&mut notifications);
+        closed.sort_by_key(|(index, _)| *index);
+        assert_eq!(
+            closed,
+            vec![
+                (0, CloseReason::ConnectionClosed),
+                (1, CloseReason::ConnectionClosed)
+            ]
+        );
+        assert!(notifications.peers_details.is_empty());
+    }
+
+    #[test]
+    fn best_block_updated_from_block_announces() {
+        let mut notifications = notifications();
+        let (peer_id, connection_id) = (PeerId::random(), ConnectionId::new_unchecked(0));
+        connect(&mut notifications, peer_id, connection_id);
+
+        let handshake = BlockAnnouncesHandshake {
+            roles: 1,
+            best_number: 10,
+            best_hash: BlockHash::repeat_byte(10),
+            genesis_hash: BlockHash::zero(),
+        };
+        notifications.on_connection_handler_event(
+            peer_id,
+            connection_id,
+            NotificationsHandlerToBehavior::HandshakeCompleted {
+                index: 0,
+                endpoint: endpoint(),
+                handshake: handshake.encode(),
+                negotiated_name: "/proto/1".into(),
+                is_inbound: false,
+                sender: mpsc::channel(1).0,
+            },
+        );
+        assert!(notifications.events.iter().any(|event| matches!(
+            event,
+            ToSwarm::GenerateEvent(NotificationsToSwarm::PeerBestBlockUpdated {
+                best_number: 10,
+                ..
+            })
+        )));
+        assert_eq!(
+            notifications
+                .chain_state()
+                .peer(&peer_id)
+                .map(|state| state.best_number),
+            Some(10)
+        );
+
+        // Notifications on other protocols are not decoded as announcements.
+        notifications.events.clear();
+        notifications.on_connection_handler_event(
+            peer_id,
+            connection_id,
+            NotificationsHandlerToBehavior::Notification {
+                index: 1,
+                bytes: BytesMut::from(&handshake.encode()[..]),
+            },
+        );
+        assert_eq!(notifications.events.len(), 1);
+    }
+
+    #[test]
+    fn best_block_advanced_when_peers_agree() {
+        let mut notifications = notifications();
+        let announce_best = |notifications: &mut Notifications, best_number, best_hash| {
+            let (peer_id, connection_id) = (PeerId::random(), ConnectionId::new_unchecked(0));
+            connect(notifications, peer_id, connection_id);
+            let handshake = BlockAnnouncesHandshake {
+                roles: 1,
+                best_number,
+                best_hash,
+                genesis_hash: BlockHash::zero(),
+            };
+            notifications.on_connection_handler_event(
+                peer_id,
+                connection_id,
+                NotificationsHandlerToBehavior::HandshakeCompleted {
+                    index: 0,
+                    endpoint: endpoint(),
+                    handshake: handshake.encode(),
+                    negotiated_name: "/proto/1".into(),
+                    is_inbound: false,
+                    sender: mpsc::channel(1).0,
+                },
+            );
+        };
+
+        // The peers agree on the best block number, but not on its hash.
+        announce_best(&mut notifications, 10, BlockHash::repeat_byte(1));
+        announce_best(&mut notifications, 10, BlockHash::repeat_byte(2));
+        announce_best(&mut notifications, u32::MAX, BlockHash::repeat_byte(3));
+        assert!(!notifications.advance_best_block(2).unwrap());
+        let best_block = notifications.best_block().unwrap();
+        assert_eq!(best_block.get().unwrap(), (0, BlockHash::zero()));
+
+        announce_best(&mut notifications, 10, BlockHash::repeat_byte(2));
+        assert!(notifications.advance_best_block(2).unwrap());
+        assert_eq!(best_block.get().unwrap(), (10, BlockHash::repeat_byte(2)));
+    }
+
+    #[test]
+    fn best_block_handle_updates_handshake() {
+        let genesis_hash = BlockHash::repeat_byte(1);
+        let mut data = ProtocolsData::new(genesis_hash, ProtocolRole::FullNode);
+        assert!(data.best_block().is_none());
+        data.register_block_announces();
+
+        // Handlers operate on a clone of the protocols data.
+        let handler_data = data.clone();
+        let best_block = data.best_block().unwrap();
+        assert_eq!(best_block.get().unwrap(), (0, genesis_hash));
+
+        best_block.set(10, BlockHash::repeat_byte(10)).unwrap();
+        assert!(!best_block.advance(5, BlockHash::repeat_byte(5)).unwrap());
+        assert!(best_block.advance(11, BlockHash::repeat_byte(11)).unwrap());
+
+        let handshake =
+            BlockAnnouncesHandshake::decode(&mut &handler_data.protocols[0].handshake.get()[..])
+                .unwrap();
+        assert_eq!(handshake.best_number, 11);
+        assert_eq!(handshake.best_hash, BlockHash::repeat_byte(11));
+        assert_eq!(handshake.genesis_hash, genesis_hash);
+
+        // Handshakes that are not block announces handshakes are reported, not decoded.
+        data.protocols[0].handshake.set(vec![1]);
+        assert!(best_block.get().is_err());
+        assert!(best_block.advance(12, BlockHash::repeat_byte(12)).is_err());
+    }
+
+    #[test]
+    fn register_protocols() {
+        let genesis_hash = BlockHash::repeat_byte(1);
+        let mut data = ProtocolsData::new(genesis_hash, ProtocolRole::FullNode);
+
+        assert_eq!(data.register_block_announces(), 0);
+        assert_eq!(data.block_announces, Some(0));
+        assert_eq!(data.register_transactions(), 1);
+
+        let grandpa = NotificationProtocolConfig::new(
+            format!("/{}/grandpa/1", hex::encode(genesis_hash)),
+            vec![ProtocolRole::FullNode.encoded()],
+        )
+        .with_fallback_names(vec!["/paritytech/grandpa/1".into()]);
+        assert_eq!(data.register(grandpa), 2);
+
+        let genesis = hex::encode(genesis_hash);
+        let names: Vec<_> = data.protocols.iter().map(|p| p.name.clone()).collect();
+        assert_eq!(
+            names,
+            vec![
+                format!("/{genesis}/block-announces/1"),
+                format!("/{genesis}/transactions/1"),
+                format!("/{genesis}/grandpa/1"),
+            ]
+        );
+        assert_eq!(
+            data.protocols[0].handshake.get(),
+            BlockAnnouncesHandshake::from_genesis(genesis_hash).encode()
+        );
+        assert_eq!(data.protocols[1].handshake.get(), vec![1]);
+        assert_eq!(data.protocols[2].fallback_names.len(), 1);
+    }
+
+    #[test]
+    fn register_grandpa_protocol() {
+        let genesis_hash = BlockHash::repeat_byte(1);
+        let mut data = ProtocolsData::new(genesis_hash, ProtocolRole::Authority);
+
+        assert_eq!(data.register_grandpa(), 0);
+        let config = &data.protocols[0];
+        assert_eq!(
+            config.name,
+            format!("/{}/grandpa/1", hex::encode(genesis_hash))
+        );
+        assert_eq!(config.fallback_names, vec!["/paritytech/grandpa/1"]);
+        assert_eq!(
+            config.handshake.get(),
+            vec![ProtocolRole::Authority.encoded()]
+        );
+    }
+
+    #[test]
+    fn register_statement_protocol() {
+        let genesis_hash = BlockHash::repeat_byte(1);
+        let mut data = ProtocolsData::new(genesis_hash, ProtocolRole::FullNode);
+
+        assert_eq!(data.register_statements(), 0);
+        let config = &data.protocols[0];
+        assert_eq!(
+            config.name,
+            format!("/{}/statement/1", hex::encode(genesis_hash))
+        );
+        assert!(config.fallback_names.is_empty());
+        assert_eq!(
+            config.handshake.get(),
+            vec![ProtocolRole::FullNode.encoded()]
+        );
+    }
+
+    #[test]
+    fn register_legacy_protocol_fallbacks() {
+        let genesis_hash = BlockHash::repeat_byte(1);
+        let mut data =
+            ProtocolsData::new(genesis_hash, ProtocolRole::FullNode).with_legacy_protocol_id("dot");
+
+        data.register_block_announces();
+        data.register_transactions();
+        data.register_statements();
+
+        assert_eq!(
+            data.protocols[0].fallback_names,
+            vec!["/dot/block-announces/1"]
+        );
+        assert_eq!(
+            data.protocols[1].fallback_names,
+            vec!["/dot/transactions/1"]
+        );
+        // The statement protocol postdates the genesis hash protocol names.
+        assert!(data.protocols[2].fallback_names.is_empty());
+    }
+
+    #[test]
+    fn register_protocols_with_fork_id() {
+        let genesis_hash = BlockHash::repeat_byte(1);
+        let mut data = ProtocolsData::new(genesis_hash, ProtocolRole::FullNode)
+            .with_fork_id("relaunch")
+            .with_legacy_protocol_id("dot");
+
+        data.register_block_announces();
+        data.register_grandpa();
+
+        let genesis = hex::encode(genesis_hash);
+        assert_eq!(
+            data.protocols[0].name,
+            format!("/{genesis}/relaunch/block-announces/1")
+        );
+        assert_eq!(
+            data.protocols[0].fallback_names,
+            vec!["/dot/block-announces/1"]
+        );
+        assert_eq!(
+            data.protocols[1].name,
+            format!("/{genesis}/relaunch/grandpa/1")
+        );
+    }
+}
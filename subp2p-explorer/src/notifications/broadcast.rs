@@ -0,0 +1,154 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Broadcast a notification to peers and track its delivery.
+
+use crate::notifications::messages::BlockHash;
+use futures::channel::mpsc;
+use libp2p::{swarm::ConnectionId, PeerId};
+use std::collections::{HashMap, HashSet};
+
+const LOG_TARGET: &str = "subp2p-broadcast";
+
+/// Report of a [`NotificationBroadcast`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BroadcastReport {
+    /// Peers that received the payload, together with the number of flushed notifications.
+    pub reached: Vec<(PeerId, usize)>,
+    /// The hashes of the broadcasted items, together with the peers that
+    /// relayed them back to us.
+    pub echoes: Vec<(BlockHash, Vec<PeerId>)>,
+}
+
+/// Broadcast a payload to every peer with an open notification protocol.
+///
+/// The payload is submitted as soon as the protocol is opened. A peer is reached
+/// once the notification has been flushed on its substream. The payload contains
+/// items identified by hash, which peers may relay back to us.
+#[derive(Debug)]
+pub struct NotificationBroadcast {
+    /// The notification submitted to peers.
+    payload: Vec<u8>,
+    /// The number of peers that must be reached.
+    target_peers: usize,
+    /// Connections on which the payload was submitted and not yet flushed.
+    pending: HashSet<(PeerId, ConnectionId)>,
+    /// The number of flushed notifications per peer.
+    flushes: HashMap<PeerId, usize>,
+    /// The hashes of the broadcasted items and the peers that relayed them back.
+    echoes: HashMap<BlockHash, HashSet<PeerId>>,
+}
+
+impl NotificationBroadcast {
+    /// Constructs a new [`NotificationBroadcast`] for the payload containing the
+    /// items with the provided hashes.
+    pub fn new(
+        payload: Vec<u8>,
+        hashes: impl IntoIterator<Item = BlockHash>,
+        target_peers: usize,
+    ) -> Self {
+        NotificationBroadcast {
+            payload,
+            target_peers,
+            pending: HashSet::new(),
+            flushes: HashMap::new(),
+            echoes: hashes
+                .into_iter()
+                .map(|hash| (hash, HashSet::new()))
+                .collect(),
+        }
+    }
+
+    /// Handle the protocol being opened with the peer.
+    ///
+    /// Returns true if the payload was submitted to the peer.
+    pub fn on_protocol_open(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        sender: &mut mpsc::Sender<Vec<u8>>,
+    ) -> bool {
+        // Submit once per peer, unless the submission on a previous connection failed.
+        if self.flushes.contains_key(&peer_id)
+            || self.pending.iter().any(|(peer, _)| *peer == peer_id)
+        {
+            return false;
+        }
+
+        if let Err(err) = sender.try_send(self.payload.clone()) {
+            log::debug!(target: LOG_TARGET, "Failed to submit payload peer={:?} error={:?}", peer_id, err);
+            return false;
+        }
+
+        self.pending.insert((peer_id, connection_id));
+        true
+    }
+
+    /// Handle the protocol being closed with the peer.
+    pub fn on_protocol_closed(&mut self, peer_id: PeerId, connection_id: ConnectionId) {
+        self.pending.remove(&(peer_id, connection_id));
+    }
+
+    /// Handle notifications flushed on the protocol.
+    ///
+    /// Returns true if the peer has been reached for the first time.
+    pub fn on_flushed(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        count: usize,
+    ) -> bool {
+        if !self.pending.remove(&(peer_id, connection_id)) {
+            return false;
+        }
+
+        let flushes = self.flushes.entry(peer_id).or_default();
+        *flushes += count;
+        *flushes == count
+    }
+
+    /// Handle the hashes of the items received from the peer.
+    ///
+    /// Returns the hashes of our items relayed back by the peer for the first time.
+    pub fn on_echo(
+        &mut self,
+        peer_id: PeerId,
+        hashes: impl IntoIterator<Item = BlockHash>,
+    ) -> Vec<BlockHash> {
+        hashes
+            .into_iter()
+            .filter(|hash| {
+                self.echoes
+                    .get_mut(hash)
+                    .is_some_and(|peers| peers.insert(peer_id))
+            })
+            .collect()
+    }
+
+    /// Returns the number of peers that received the payload.
+    pub fn reached(&self) -> usize {
+        self.flushes.len()
+    }
+
+    /// Returns true if the target number of peers has been reached.
+    pub fn is_complete(&self) -> bool {
+        self.reached() >= self.target_peers
+    }
+
+    /// Returns the report of the broadcast.
+    pub fn report(&self) -> BroadcastReport {
+        BroadcastReport {
+            reached: self
+                .flushes
+                .iter()
+                .map(|(peer_id, flushes)| (*peer_id, *flushes))
+                .collect(),
+            echoes: self
+                .echoes
+                .iter()
+                .map(|(hash, peers)| (*hash, peers.iter().copied().collect()))
+                .collect(),
+        }
+    }
+}
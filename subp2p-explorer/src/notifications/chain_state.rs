@@ -0,0 +1,450 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Track the chain state of remote peers from the block announces protocol.
+
+use crate::notifications::messages::{
+    BlockAnnounce, BlockAnnouncesHandshake, BlockHash, BlockNumber,
+};
+use codec::Decode;
+use libp2p::PeerId;
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::Entry, HashMap},
+    time::Instant,
+};
+
+const LOG_TARGET: &str = "subp2p-chain-state";
+
+/// The number of peers that must reach a best block before it is advertised.
+pub const DEFAULT_BEST_BLOCK_PEERS: usize = 3;
+
+/// The chain state of a remote peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerChainState {
+    /// Roles of the node as declared in the handshake.
+    pub roles: u8,
+    /// Genesis hash declared by the peer.
+    pub genesis_hash: BlockHash,
+    /// Best block number of the peer.
+    pub best_number: BlockNumber,
+    /// Best block hash of the peer.
+    pub best_hash: BlockHash,
+    /// The moment the best block was last updated.
+    pub last_update: Instant,
+}
+
+/// The best block of a peer has changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BestBlockUpdate {
+    /// The peer that reported the best block.
+    pub peer_id: PeerId,
+    /// Best block number of the peer.
+    pub best_number: BlockNumber,
+    /// Best block hash of the peer.
+    pub best_hash: BlockHash,
+}
+
+/// Per-peer chain state tracker.
+///
+/// The state is initialized from the [`BlockAnnouncesHandshake`] and updated by
+/// every [`BlockAnnounce`] that is marked as the new best block.
+#[derive(Debug, Default)]
+pub struct ChainState {
+    /// The chain state of the peers.
+    peers: HashMap<PeerId, PeerChainState>,
+}
+
+impl ChainState {
+    /// Constructs a new [`ChainState`].
+    pub fn new() -> Self {
+        ChainState::default()
+    }
+
+    /// Handle the handshake received on the block announces protocol.
+    ///
+    /// Returns the best block update if the best block of the peer has changed.
+    pub fn on_handshake(
+        &mut self,
+        peer_id: PeerId,
+        mut handshake: &[u8],
+    ) -> Result<Option<BestBlockUpdate>, codec::Error> {
+        let handshake = BlockAnnouncesHandshake::decode(&mut handshake)?;
+
+        log::trace!(target: LOG_TARGET,
+            "Chain state handshake peer={:?} best_number={:?} best_hash={:?}",
+            peer_id,
+            handshake.best_number,
+            handshake.best_hash
+        );
+
+        let state = match self.peers.entry(peer_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                entry.insert(PeerChainState {
+                    roles: handshake.roles,
+                    genesis_hash: handshake.genesis_hash,
+                    best_number: handshake.best_number,
+                    best_hash: handshake.best_hash,
+                    last_update: Instant::now(),
+                });
+
+                return Ok(Some(BestBlockUpdate {
+                    peer_id,
+                    best_number: handshake.best_number,
+                    best_hash: handshake.best_hash,
+                }));
+            }
+        };
+
+        // Multiple connections may open the protocol with the same peer.
+        state.roles = handshake.roles;
+        state.genesis_hash = handshake.genesis_hash;
+        Ok(Self::update_best(
+            peer_id,
+            state,
+            handshake.best_number,
+            handshake.best_hash,
+        ))
+    }
+
+    /// Handle a notification received on the block announces protocol.
+    ///
+    /// Returns the best block update if the best block of the peer has changed.
+    pub fn on_block_announce(
+        &mut self,
+        peer_id: PeerId,
+        mut message: &[u8],
+    ) -> Result<Option<BestBlockUpdate>, codec::Error> {
+        let announce = BlockAnnounce::decode(&mut message)?;
+
+        let Some(state) = self.peers.get_mut(&peer_id) else {
+            log::debug!(target: LOG_TARGET,
+                "Chain state block announce without handshake peer={:?}",
+                peer_id
+            );
+            return Ok(None);
+        };
+
+        if !announce.is_best() {
+            return Ok(None);
+        }
+
+        Ok(Self::update_best(
+            peer_id,
+            state,
+            announce.header.number,
+            announce.hash(),
+        ))
+    }
+
+    /// Update the best block of the peer.
+    fn update_best(
+        peer_id: PeerId,
+        state: &mut PeerChainState,
+        best_number: BlockNumber,
+        best_hash: BlockHash,
+    ) -> Option<BestBlockUpdate> {
+        if state.best_hash == best_hash {
+            return None;
+        }
+
+        state.best_number = best_number;
+        state.best_hash = best_hash;
+        state.last_update = Instant::now();
+
+        Some(BestBlockUpdate {
+            peer_id,
+            best_number,
+            best_hash,
+        })
+    }
+
+    /// Remove the chain state of a disconnected peer.
+    pub fn remove_peer(&mut self, peer_id: &PeerId) -> Option<PeerChainState> {
+        self.peers.remove(peer_id)
+    }
+
+    /// Returns the chain state of the given peer.
+    pub fn peer(&self, peer_id: &PeerId) -> Option<&PeerChainState> {
+        self.peers.get(peer_id)
+    }
+
+    /// Returns the chain state of all tracked peers.
+    pub fn peers(&self) -> impl Iterator<Item = (&PeerId, &PeerChainState)> {
+        self.peers.iter()
+    }
+
+    /// Returns the highest best block number reported by peers on the given genesis.
+    pub fn best_number(&self, genesis_hash: &BlockHash) -> Option<BlockNumber> {
+        self.peers
+            .values()
+            .filter(|state| &state.genesis_hash == genesis_hash)
+            .map(|state| state.best_number)
+            .max()
+    }
+
+    /// Returns the highest best block reported by at least `min_peers` peers on the given
+    /// genesis.
+    ///
+    /// The best block is the `min_peers`-th highest best block of the peers, which every
+    /// other selected peer reached. Returns `None` if fewer peers are known.
+    pub fn supported_best_block(
+        &self,
+        genesis_hash: &BlockHash,
+        min_peers: usize,
+    ) -> Option<(BlockNumber, BlockHash)> {
+        let mut best_blocks: Vec<_> = self
+            .peers
+            .values()
+            .filter(|state| &state.genesis_hash == genesis_hash)
+            .map(|state| (state.best_number, state.best_hash))
+            .collect();
+        best_blocks.sort_unstable_by_key(|(number, _)| Reverse(*number));
+
+        best_blocks.get(min_peers.max(1) - 1).copied()
+    }
+
+    /// Returns the highest best block reported with the same hash by at least `min_peers`
+    /// peers on the given genesis, together with the peers that reported it.
+    ///
+    /// The header of the block is vouched for by independent peers, unlike the best
+    /// block of a single peer which may be fabricated.
+    pub fn agreed_best_block(
+        &self,
+        genesis_hash: &BlockHash,
+        min_peers: usize,
+    ) -> Option<(BlockNumber, BlockHash, Vec<PeerId>)> {
+        let mut blocks: HashMap<(BlockNumber, BlockHash), Vec<PeerId>> = HashMap::new();
+        for (peer_id, state) in self
+            .peers
+            .iter()
+            .filter(|(_, state)| &state.genesis_hash == genesis_hash)
+        {
+            blocks
+                .entry((state.best_number, state.best_hash))
+                .or_default()
+                .push(*peer_id);
+        }
+
+        blocks
+            .into_iter()
+            .filter(|(_, peers)| peers.len() >= min_peers.max(1))
+            .max_by_key(|((number, _), peers)| (*number, peers.len()))
+            .map(|((number, hash), peers)| (number, hash, peers))
+    }
+
+    /// Returns the peers that are behind the best block reported by at least `min_peers`
+    /// peers by more than `threshold` blocks, together with their distance.
+    ///
+    /// See [`Self::supported_best_block`], a single peer announcing a far ahead best block
+    /// does not make the other peers lag.
+    pub fn lagging_peers(
+        &self,
+        genesis_hash: &BlockHash,
+        min_peers: usize,
+        threshold: BlockNumber,
+    ) -> Vec<(PeerId, BlockNumber)> {
+        let Some((best_number, _)) = self.supported_best_block(genesis_hash, min_peers) else {
+            return Vec::new();
+        };
+
+        self.peers
+            .iter()
+            .filter(|(_, state)| &state.genesis_hash == genesis_hash)
+            .filter_map(|(peer_id, state)| {
+                let distance = best_number.saturating_sub(state.best_number);
+                (distance > threshold).then_some((*peer_id, distance))
+            })
+            .collect()
+    }
+
+    /// Returns the peers that share the best block number with other peers, but
+    /// report a different best block hash than the majority.
+    ///
+    /// These peers are likely on a fork.
+    pub fn forked_peers(&self, genesis_hash: &BlockHash) -> Vec<PeerId> {
+        let mut hashes: HashMap<BlockNumber, HashMap<BlockHash, usize>> = HashMap::new();
+        for state in self
+            .peers
+            .values()
+            .filter(|state| &state.genesis_hash == genesis_hash)
+        {
+            *hashes
+                .entry(state.best_number)
+                .or_default()
+                .entry(state.best_hash)
+                .or_default() += 1;
+        }
+
+        self.peers
+            .iter()
+            .filter(|(_, state)| &state.genesis_hash == genesis_hash)
+            .filter_map(|(peer_id, state)| {
+                let hashes = hashes.get(&state.best_number)?;
+                let majority = hashes.iter().max_by_key(|(_, num)| **num)?;
+                let reported = hashes.get(&state.best_hash).copied().unwrap_or_default();
+
+                (reported < *majority.1).then_some(*peer_id)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::messages::{BlockState, Digest, Header};
+    use codec::Encode;
+
+    fn handshake(best_number: BlockNumber, best_hash: BlockHash) -> Vec<u8> {
+        BlockAnnouncesHandshake {
+            roles: 1,
+            best_number,
+            best_hash,
+            genesis_hash: BlockHash::zero(),
+        }
+        .encode()
+    }
+
+    fn announce(number: BlockNumber, state: Option<BlockState>) -> BlockAnnounce {
+        BlockAnnounce {
+            header: Header {
+                parent_hash: BlockHash::repeat_byte(number as u8),
+                number,
+                state_root: BlockHash::zero(),
+                extrinsics_root: BlockHash::zero(),
+                digest: Digest::default(),
+            },
+            state,
+            data: None,
+        }
+    }
+
+    #[test]
+    fn best_block_from_handshake_and_announces() {
+        let mut chain_state = ChainState::new();
+        let peer_id = PeerId::random();
+
+        let update = chain_state
+            .on_handshake(peer_id, &handshake(10, BlockHash::repeat_byte(10)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(update.best_number, 10);
+        assert_eq!(update.best_hash, BlockHash::repeat_byte(10));
+
+        // Non-best blocks do not change the best block.
+        let message = announce(11, Some(BlockState::Normal)).encode();
+        assert_eq!(
+            chain_state.on_block_announce(peer_id, &message).unwrap(),
+            None
+        );
+
+        let best = announce(11, Some(BlockState::Best));
+        let update = chain_state
+            .on_block_announce(peer_id, &best.encode())
+            .unwrap()
+            .unwrap();
+        assert_eq!(update.best_number, 11);
+        assert_eq!(update.best_hash, best.hash());
+        assert_eq!(chain_state.peer(&peer_id).unwrap().best_number, 11);
+
+        // Announcing the same block again is not reported.
+        assert_eq!(
+            chain_state
+                .on_block_announce(peer_id, &best.encode())
+                .unwrap(),
+            None
+        );
+
+        assert!(chain_state.remove_peer(&peer_id).is_some());
+        assert!(chain_state.peer(&peer_id).is_none());
+    }
+
+    #[test]
+    fn announce_without_handshake_is_ignored() {
+        let mut chain_state = ChainState::new();
+        let message = announce(1, None).encode();
+
+        assert_eq!(
+            chain_state
+                .on_block_announce(PeerId::random(), &message)
+                .unwrap(),
+            None
+        );
+        assert_eq!(chain_state.peers().count(), 0);
+        assert!(chain_state.on_handshake(PeerId::random(), &[1]).is_err());
+    }
+
+    #[test]
+    fn lagging_and_forked_peers() {
+        let mut chain_state = ChainState::new();
+        let genesis = BlockHash::zero();
+        let (first, second, third, lagging) = (
+            PeerId::random(),
+            PeerId::random(),
+            PeerId::random(),
+            PeerId::random(),
+        );
+
+        let _ = chain_state.on_handshake(first, &handshake(100, BlockHash::repeat_byte(1)));
+        let _ = chain_state.on_handshake(second, &handshake(100, BlockHash::repeat_byte(1)));
+        let _ = chain_state.on_handshake(third, &handshake(100, BlockHash::repeat_byte(2)));
+        let _ = chain_state.on_handshake(lagging, &handshake(50, BlockHash::repeat_byte(3)));
+
+        assert_eq!(chain_state.best_number(&genesis), Some(100));
+        assert_eq!(
+            chain_state.lagging_peers(&genesis, 2, 10),
+            vec![(lagging, 50)]
+        );
+        assert!(chain_state.lagging_peers(&genesis, 2, 50).is_empty());
+        assert_eq!(chain_state.forked_peers(&genesis), vec![third]);
+
+        // Peers on a different chain are not taken into account.
+        assert_eq!(chain_state.best_number(&BlockHash::repeat_byte(1)), None);
+
+        // A single peer cannot raise the best block supported by several peers.
+        let _ = chain_state.on_handshake(PeerId::random(), &handshake(u32::MAX, BlockHash::zero()));
+        assert_eq!(
+            chain_state
+                .supported_best_block(&genesis, 2)
+                .map(|(number, _)| number),
+            Some(100)
+        );
+        assert_eq!(
+            chain_state.supported_best_block(&genesis, 1),
+            Some((u32::MAX, BlockHash::zero()))
+        );
+        assert_eq!(chain_state.supported_best_block(&genesis, 6), None);
+
+        // Only the first two peers agree on the same best block.
+        let (number, hash, mut peers) = chain_state.agreed_best_block(&genesis, 2).unwrap();
+        peers.sort();
+        let mut expected = vec![first, second];
+        expected.sort();
+        assert_eq!(
+            (number, hash, peers),
+            (100, BlockHash::repeat_byte(1), expected)
+        );
+        assert_eq!(chain_state.agreed_best_block(&genesis, 3), None);
+    }
+
+    #[test]
+    fn outlier_does_not_make_peers_lag() {
+        let mut chain_state = ChainState::new();
+        let genesis = BlockHash::zero();
+        let (first, second, outlier) = (PeerId::random(), PeerId::random(), PeerId::random());
+
+        let _ = chain_state.on_handshake(first, &handshake(100, BlockHash::repeat_byte(1)));
+        let _ = chain_state.on_handshake(second, &handshake(95, BlockHash::repeat_byte(2)));
+        let _ = chain_state.on_handshake(outlier, &handshake(u32::MAX, BlockHash::zero()));
+
+        // The highest best block is announced by a single peer.
+        assert_eq!(chain_state.best_number(&genesis), Some(u32::MAX));
+        assert_eq!(chain_state.lagging_peers(&genesis, 2, 3), vec![(second, 5)]);
+        assert!(chain_state.lagging_peers(&genesis, 2, 10).is_empty());
+        // Not enough peers to establish the best block.
+        assert!(chain_state.lagging_peers(&genesis, 4, 0).is_empty());
+    }
+}
@@ -0,0 +1,991 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Decode and observe the finality gossip of the `/grandpa/1` protocol.
+
+use crate::{
+    notifications::messages::{BlockHash, BlockNumber},
+    trie::storage_value_key,
+};
+use codec::{Decode, Encode};
+use libp2p::{identity::ed25519, PeerId};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+const LOG_TARGET: &str = "subp2p-grandpa";
+
+/// The maximum number of rounds kept by the [`GrandpaObserver`].
+const MAX_ROUNDS: usize = 256;
+
+/// The well-known storage key of the authority set used by older runtimes.
+pub const GRANDPA_AUTHORITIES_KEY: &[u8] = b":grandpa_authorities";
+
+/// The number of a voting round.
+pub type RoundNumber = u64;
+/// The identifier of a voter set, incremented on every authority set change.
+pub type SetId = u64;
+/// The ed25519 public key of a GRANDPA authority.
+pub type AuthorityId = [u8; 32];
+/// The ed25519 signature of a GRANDPA authority.
+pub type AuthoritySignature = [u8; 64];
+
+/// The block targeted by a vote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct VoteTarget {
+    /// The hash of the block.
+    pub target_hash: BlockHash,
+    /// The number of the block.
+    pub target_number: BlockNumber,
+}
+
+/// A vote cast by an authority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum Vote {
+    /// A prevote for a block.
+    #[codec(index = 0)]
+    Prevote(VoteTarget),
+    /// A precommit for a block.
+    #[codec(index = 1)]
+    Precommit(VoteTarget),
+    /// A block proposal of the primary voter of the round.
+    #[codec(index = 2)]
+    PrimaryPropose(VoteTarget),
+}
+
+/// A vote together with the signature of the authority.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct SignedVote {
+    /// The vote.
+    pub vote: Vote,
+    /// The signature of the authority.
+    pub signature: AuthoritySignature,
+    /// The authority that cast the vote.
+    pub id: AuthorityId,
+}
+
+/// A vote gossiped during a round.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct VoteMessage {
+    /// The round of the vote.
+    pub round: RoundNumber,
+    /// The voter set of the round.
+    pub set_id: SetId,
+    /// The signed vote.
+    pub message: SignedVote,
+}
+
+/// The precommits that finalized a block.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct CompactCommit {
+    /// The hash of the finalized block.
+    pub target_hash: BlockHash,
+    /// The number of the finalized block.
+    pub target_number: BlockNumber,
+    /// The precommits of the commit.
+    pub precommits: Vec<VoteTarget>,
+    /// The signatures and authorities of the precommits, in the same order.
+    pub auth_data: Vec<(AuthoritySignature, AuthorityId)>,
+}
+
+/// A commit gossiped at the end of a round.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct CommitMessage {
+    /// The round of the commit.
+    pub round: RoundNumber,
+    /// The voter set of the round.
+    pub set_id: SetId,
+    /// The commit.
+    pub message: CompactCommit,
+}
+
+/// The view of a peer over the voting rounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct NeighborPacket {
+    /// The current round of the peer.
+    pub round: RoundNumber,
+    /// The current voter set of the peer.
+    pub set_id: SetId,
+    /// The highest block finalized by a commit known to the peer.
+    pub commit_finalized_height: BlockNumber,
+}
+
+/// Versioned [`NeighborPacket`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum VersionedNeighborPacket {
+    /// The first version of the packet.
+    #[codec(index = 1)]
+    V1(NeighborPacket),
+}
+
+/// Request the votes of a past round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub struct CatchUpRequest {
+    /// The requested round.
+    pub round: RoundNumber,
+    /// The voter set of the round.
+    pub set_id: SetId,
+}
+
+/// A vote target together with the signature of the authority.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct SignedTarget {
+    /// The vote target.
+    pub target: VoteTarget,
+    /// The signature of the authority.
+    pub signature: AuthoritySignature,
+    /// The authority that cast the vote.
+    pub id: AuthorityId,
+}
+
+/// The votes that completed a past round.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct CatchUp {
+    /// The round of the votes.
+    pub round_number: RoundNumber,
+    /// The prevotes of the round.
+    pub prevotes: Vec<SignedTarget>,
+    /// The precommits of the round.
+    pub precommits: Vec<SignedTarget>,
+    /// The hash of the base block of the round.
+    pub base_hash: BlockHash,
+    /// The number of the base block of the round.
+    pub base_number: BlockNumber,
+}
+
+/// Response to a [`CatchUpRequest`].
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub struct CatchUpMessage {
+    /// The voter set of the round.
+    pub set_id: SetId,
+    /// The votes of the round.
+    pub message: CatchUp,
+}
+
+/// Messages of the GRANDPA protocol.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode)]
+pub enum GossipMessage {
+    /// A vote of the current round.
+    #[codec(index = 0)]
+    Vote(VoteMessage),
+    /// A commit that finalized a block.
+    #[codec(index = 1)]
+    Commit(CommitMessage),
+    /// The view of the peer. Not repropagated.
+    #[codec(index = 2)]
+    Neighbor(VersionedNeighborPacket),
+    /// Request the votes of a past round. Not repropagated.
+    #[codec(index = 3)]
+    CatchUpRequest(CatchUpRequest),
+    /// The votes of a past round. Not repropagated.
+    #[codec(index = 4)]
+    CatchUp(CatchUpMessage),
+}
+
+/// Verify the signature of a vote cast in the given round.
+pub fn verify_vote(
+    vote: &Vote,
+    round: RoundNumber,
+    set_id: SetId,
+    signature: &AuthoritySignature,
+    id: &AuthorityId,
+) -> bool {
+    let Ok(public) = ed25519::PublicKey::try_from_bytes(id) else {
+        return false;
+    };
+
+    public.verify(&(vote, round, set_id).encode(), signature)
+}
+
+/// The authority set of the GRANDPA voters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthoritySet {
+    /// The voter set identifier.
+    pub set_id: SetId,
+    /// The authorities together with their voting weight.
+    pub authorities: Vec<(AuthorityId, u64)>,
+}
+
+impl AuthoritySet {
+    /// Returns the storage keys that contain the authority set.
+    ///
+    /// The values of the keys must be provided to [`AuthoritySet::decode_storage`] in the
+    /// same order.
+    pub fn storage_keys() -> Vec<Vec<u8>> {
+        vec![
+            storage_value_key("Grandpa", "CurrentSetId"),
+            storage_value_key("Grandpa", "Authorities"),
+            GRANDPA_AUTHORITIES_KEY.to_vec(),
+        ]
+    }
+
+    /// Decode the authority set from the values of [`AuthoritySet::storage_keys`].
+    ///
+    /// Returns `None` if the chain does not use GRANDPA.
+    pub fn decode_storage(values: &[Option<Vec<u8>>]) -> Result<Option<Self>, codec::Error> {
+        let [set_id, authorities, legacy_authorities] = values else {
+            return Err("Unexpected number of storage values".into());
+        };
+
+        let authorities = match (authorities, legacy_authorities) {
+            (Some(authorities), _) => Vec::decode(&mut &authorities[..])?,
+            // The legacy authorities are prefixed by the version of the list.
+            (None, Some(authorities)) => match authorities.split_first() {
+                Some((1, authorities)) => Vec::decode(&mut &authorities[..])?,
+                _ => return Err("Unknown authority list version".into()),
+            },
+            (None, None) => return Ok(None),
+        };
+        // The set id is missing until the first authority set change.
+        let set_id = match set_id {
+            Some(set_id) => SetId::decode(&mut &set_id[..])?,
+            None => 0,
+        };
+
+        Ok(Some(AuthoritySet {
+            set_id,
+            authorities,
+        }))
+    }
+
+    /// Returns the identifiers of the authorities.
+    pub fn ids(&self) -> impl Iterator<Item = &AuthorityId> {
+        self.authorities.iter().map(|(id, _)| id)
+    }
+
+    /// Returns the voting weight of the authority, if it belongs to the set.
+    pub fn weight(&self, id: &AuthorityId) -> Option<u64> {
+        self.authorities
+            .iter()
+            .find(|(authority, _)| authority == id)
+            .map(|(_, weight)| *weight)
+    }
+
+    /// Returns the total voting weight of the set.
+    pub fn total_weight(&self) -> u64 {
+        self.authorities
+            .iter()
+            .map(|(_, weight)| *weight)
+            .fold(0, u64::saturating_add)
+    }
+
+    /// Returns the weight of the supermajority needed to finalize a block.
+    ///
+    /// The weight is strictly above two thirds of the total weight, which tolerates
+    /// `(total - 1) / 3` of faulty weight.
+    pub fn threshold(&self) -> u64 {
+        let total = self.total_weight();
+        total - total.saturating_sub(1) / 3
+    }
+}
+
+/// The votes observed during a single round.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoundVotes {
+    /// The prevotes of the round by authority.
+    pub prevotes: HashMap<AuthorityId, VoteTarget>,
+    /// The precommits of the round by authority.
+    pub precommits: HashMap<AuthorityId, VoteTarget>,
+    /// The proposal of the primary voter of the round.
+    pub primary_propose: Option<(AuthorityId, VoteTarget)>,
+    /// The block finalized by the commit of the round.
+    pub commit: Option<VoteTarget>,
+}
+
+impl RoundVotes {
+    /// Returns the authorities that cast a prevote or a precommit.
+    pub fn voters(&self) -> HashSet<AuthorityId> {
+        self.prevotes
+            .keys()
+            .chain(self.precommits.keys())
+            .copied()
+            .collect()
+    }
+
+    /// Returns the provided authorities that did not vote during the round.
+    pub fn missing<'a>(
+        &self,
+        authorities: impl IntoIterator<Item = &'a AuthorityId>,
+    ) -> Vec<AuthorityId> {
+        let voters = self.voters();
+        authorities
+            .into_iter()
+            .filter(|id| !voters.contains(*id))
+            .copied()
+            .collect()
+    }
+}
+
+/// Events produced by the [`GrandpaObserver`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrandpaEvent {
+    /// Our view advanced.
+    ///
+    /// Peers gossip the votes of a round only to neighbors that declared the same
+    /// round and voter set. The encoded packet of [`GrandpaObserver::neighbor_packet`]
+    /// should be sent to the peers.
+    ViewUpdated(NeighborPacket),
+    /// A vote of an authority was observed for the first time.
+    Vote {
+        /// The voter set of the round.
+        set_id: SetId,
+        /// The round of the vote.
+        round: RoundNumber,
+        /// The authority that cast the vote.
+        authority: AuthorityId,
+        /// The vote.
+        vote: Vote,
+    },
+    /// A commit signed by a supermajority of the authorities finalized a block.
+    Commit {
+        /// The voter set of the round.
+        set_id: SetId,
+        /// The round of the commit.
+        round: RoundNumber,
+        /// The finalized block.
+        target: VoteTarget,
+        /// The number of authorities that signed the commit.
+        signers: usize,
+    },
+}
+
+/// Observe the votes and the commits of the GRANDPA voters.
+///
+/// Votes are recorded only if they carry a valid signature of an authority of the
+/// set provided to [`GrandpaObserver::set_authorities`]. Commits finalize a block only
+/// if they are signed by more than two thirds of the voting weight. Until the set is
+/// known, every vote and commit is dropped.
+#[derive(Debug, Default)]
+pub struct GrandpaObserver {
+    /// The authority set used to validate the votes.
+    authorities: Option<AuthoritySet>,
+    /// The votes observed by voter set and round.
+    rounds: BTreeMap<(SetId, RoundNumber), RoundVotes>,
+    /// The last view of every peer.
+    neighbors: HashMap<PeerId, NeighborPacket>,
+    /// Our view, advanced by validated commits and by the views of the peers.
+    view: Option<NeighborPacket>,
+    /// The highest block finalized by a validated commit.
+    finalized: Option<(SetId, RoundNumber, VoteTarget)>,
+    /// The number of messages with invalid signatures.
+    invalid_signatures: usize,
+    /// The number of messages signed by keys outside of the authority set.
+    unknown_voters: usize,
+    /// The number of commits without a supermajority.
+    rejected_commits: usize,
+}
+
+impl GrandpaObserver {
+    /// Constructs a new [`GrandpaObserver`].
+    pub fn new() -> Self {
+        GrandpaObserver::default()
+    }
+
+    /// Set the authority set used to validate the votes and commits.
+    ///
+    /// Returns the event of our view, which moves to the voter set of the authorities.
+    pub fn set_authorities(&mut self, authorities: AuthoritySet) -> Option<GrandpaEvent> {
+        let set_id = authorities.set_id;
+        self.authorities = Some(authorities);
+        self.update_view(set_id, 1)
+    }
+
+    /// Returns the authority set used to validate the votes and commits.
+    pub fn authorities(&self) -> Option<&AuthoritySet> {
+        self.authorities.as_ref()
+    }
+
+    /// Returns the weight of the authority in the voter set, if the authority belongs to it.
+    fn authority_weight(&self, set_id: SetId, id: &AuthorityId) -> Option<u64> {
+        self.authorities
+            .as_ref()
+            .filter(|set| set.set_id == set_id)
+            .and_then(|set| set.weight(id))
+    }
+
+    /// Handle a notification received on the GRANDPA protocol.
+    pub fn on_message(
+        &mut self,
+        peer_id: PeerId,
+        mut message: &[u8],
+    ) -> Result<Vec<GrandpaEvent>, codec::Error> {
+        let mut events = Vec::new();
+
+        match GossipMessage::decode(&mut message)? {
+            GossipMessage::Vote(VoteMessage {
+                round,
+                set_id,
+                message,
+            }) => {
+                if !verify_vote(
+                    &message.vote,
+                    round,
+                    set_id,
+                    &message.signature,
+                    &message.id,
+                ) {
+                    self.on_invalid_signature(peer_id);
+                    return Ok(events);
+                }
+                if self.authority_weight(set_id, &message.id).is_none() {
+                    self.on_unknown_voter(peer_id);
+                    return Ok(events);
+                }
+                events.extend(self.on_vote(set_id, round, message.id, message.vote));
+            }
+            GossipMessage::Commit(CommitMessage {
+                round,
+                set_id,
+                message,
+            }) => {
+                if message.precommits.len() != message.auth_data.len() {
+                    return Err("Commit precommits do not match the signatures".into());
+                }
+
+                let target = VoteTarget {
+                    target_hash: message.target_hash,
+                    target_number: message.target_number,
+                };
+                let mut signers = HashSet::new();
+                let mut weight = 0u64;
+                for (precommit, (signature, id)) in
+                    message.precommits.iter().zip(&message.auth_data)
+                {
+                    if !verify_vote(&Vote::Precommit(*precommit), round, set_id, signature, id) {
+                        self.on_invalid_signature(peer_id);
+                        return Ok(events);
+                    }
+                    let Some(authority_weight) = self.authority_weight(set_id, id) else {
+                        self.on_unknown_voter(peer_id);
+                        return Ok(events);
+                    };
+                    // Precommits for an ancestor of the target do not support it.
+                    if precommit.target_number >= target.target_number && signers.insert(*id) {
+                        weight = weight.saturating_add(authority_weight);
+                    }
+                }
+
+                let threshold = self
+                    .authorities
+                    .as_ref()
+                    .map_or(u64::MAX, AuthoritySet::threshold);
+                if weight < threshold {
+                    log::debug!(
+                        target: LOG_TARGET,
+                        "Commit without supermajority peer={:?} weight={} threshold={}",
+                        peer_id,
+                        weight,
+                        threshold
+                    );
+                    self.rejected_commits += 1;
+                    return Ok(events);
+                }
+
+                for (precommit, (_, id)) in message.precommits.iter().zip(&message.auth_data) {
+                    events.extend(self.on_vote(set_id, round, *id, Vote::Precommit(*precommit)));
+                }
+                events.extend(self.on_commit(set_id, round, target, signers.len()));
+            }
+            GossipMessage::Neighbor(VersionedNeighborPacket::V1(packet)) => {
+                self.neighbors.insert(peer_id, packet);
+                events.extend(self.on_neighbor());
+            }
+            GossipMessage::CatchUpRequest(_) => {}
+            GossipMessage::CatchUp(CatchUpMessage { set_id, message }) => {
+                let round = message.round_number;
+                let votes = message
+                    .prevotes
+                    .into_iter()
+                    .map(|signed| (Vote::Prevote(signed.target), signed))
+                    .chain(
+                        message
+                            .precommits
+                            .into_iter()
+                            .map(|signed| (Vote::Precommit(signed.target), signed)),
+                    );
+
+                for (vote, signed) in votes {
+                    if !verify_vote(&vote, round, set_id, &signed.signature, &signed.id) {
+                        self.on_invalid_signature(peer_id);
+                        return Ok(events);
+                    }
+                    if self.authority_weight(set_id, &signed.id).is_none() {
+                        self.on_unknown_voter(peer_id);
+                        return Ok(events);
+                    }
+                    events.extend(self.on_vote(set_id, round, signed.id, vote));
+                }
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Record a single vote of an authority.
+    fn on_vote(
+        &mut self,
+        set_id: SetId,
+        round: RoundNumber,
+        authority: AuthorityId,
+        vote: Vote,
+    ) -> Option<GrandpaEvent> {
+        let votes = self.rounds.entry((set_id, round)).or_default();
+        let is_new = match vote {
+            Vote::Prevote(target) => votes.prevotes.insert(authority, target).is_none(),
+            Vote::Precommit(target) => votes.precommits.insert(authority, target).is_none(),
+            Vote::PrimaryPropose(target) => {
+                votes.primary_propose.replace((authority, target)).is_none()
+            }
+        };
+        self.prune();
+
+        is_new.then_some(GrandpaEvent::Vote {
+            set_id,
+            round,
+            authority,
+            vote,
+        })
+    }
+
+    /// Record a commit signed by a supermajority of the authorities.
+    fn on_commit(
+        &mut self,
+        set_id: SetId,
+        round: RoundNumber,
+        target: VoteTarget,
+        signers: usize,
+    ) -> Vec<GrandpaEvent> {
+        let votes = self.rounds.entry((set_id, round)).or_default();
+        if votes.commit.replace(target).is_some() {
+            return Vec::new();
+        }
+
+        if self.finalized.map_or(true, |(_, _, finalized)| {
+            target.target_number > finalized.target_number
+        }) {
+            self.finalized = Some((set_id, round, target));
+        }
+        self.prune();
+
+        let mut events = vec![GrandpaEvent::Commit {
+            set_id,
+            round,
+            target,
+            signers,
+        }];
+        // The voters moved to the next round once the commit was produced.
+        events.extend(self.update_view(set_id, round.saturating_add(1)));
+        events
+    }
+
+    /// Advance our view to the median view of the peers.
+    ///
+    /// The median cannot be moved by a minority of peers declaring arbitrary views.
+    /// The voter set is allowed to advance at most by one past the authority set.
+    fn on_neighbor(&mut self) -> Option<GrandpaEvent> {
+        let max_set_id = self
+            .authorities
+            .as_ref()
+            .map(|set| set.set_id)
+            .or(self.view.map(|view| view.set_id))
+            .map(|set_id| set_id.saturating_add(1));
+
+        let mut views: Vec<_> = self
+            .neighbors
+            .values()
+            .map(|packet| (packet.set_id, packet.round))
+            .filter(|(set_id, _)| max_set_id.map_or(true, |max| *set_id <= max))
+            .collect();
+        if views.is_empty() {
+            return None;
+        }
+        views.sort_unstable();
+        let (set_id, round) = views[(views.len() - 1) / 2];
+
+        self.update_view(set_id, round)
+    }
+
+    /// Move our view forward to the given round.
+    fn update_view(&mut self, set_id: SetId, round: RoundNumber) -> Option<GrandpaEvent> {
+        let (set_id, round) = match self.view {
+            Some(view) => (view.set_id, view.round).max((set_id, round)),
+            None => (set_id, round),
+        };
+        // Peers consider a decreasing height a misbehavior, only validated commits raise it.
+        let view = NeighborPacket {
+            round,
+            set_id,
+            commit_finalized_height: self
+                .finalized
+                .map_or(0, |(_, _, target)| target.target_number),
+        };
+        if self.view == Some(view) {
+            return None;
+        }
+
+        log::debug!(target: LOG_TARGET, "View updated view={:?}", view);
+        self.view = Some(view);
+        Some(GrandpaEvent::ViewUpdated(view))
+    }
+
+    fn on_invalid_signature(&mut self, peer_id: PeerId) {
+        log::debug!(target: LOG_TARGET, "Invalid signature peer={:?}", peer_id);
+        self.invalid_signatures += 1;
+    }
+
+    fn on_unknown_voter(&mut self, peer_id: PeerId) {
+        log::debug!(target: LOG_TARGET, "Vote outside of the authority set peer={:?}", peer_id);
+        self.unknown_voters += 1;
+    }
+
+    /// Keep only the latest rounds.
+    fn prune(&mut self) {
+        while self.rounds.len() > MAX_ROUNDS {
+            self.rounds.pop_first();
+        }
+    }
+
+    /// Returns the encoded neighbor packet of our view, if our view is known.
+    pub fn neighbor_packet(&self) -> Option<Vec<u8>> {
+        self.view
+            .map(|view| GossipMessage::Neighbor(VersionedNeighborPacket::V1(view)).encode())
+    }
+
+    /// Returns our view over the voting rounds.
+    pub fn view(&self) -> Option<NeighborPacket> {
+        self.view
+    }
+
+    /// Returns the last view declared by the peer.
+    pub fn neighbor(&self, peer_id: &PeerId) -> Option<&NeighborPacket> {
+        self.neighbors.get(peer_id)
+    }
+
+    /// Forget the view of a disconnected peer.
+    pub fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.neighbors.remove(peer_id);
+    }
+
+    /// Returns the votes observed during the given round.
+    pub fn round(&self, set_id: SetId, round: RoundNumber) -> Option<&RoundVotes> {
+        self.rounds.get(&(set_id, round))
+    }
+
+    /// Returns the rounds of the given voter set in ascending order.
+    pub fn rounds(&self, set_id: SetId) -> impl Iterator<Item = (RoundNumber, &RoundVotes)> {
+        self.rounds
+            .range((set_id, 0)..=(set_id, RoundNumber::MAX))
+            .map(|((_, round), votes)| (*round, votes))
+    }
+
+    /// Returns the authorities that voted in any round of the given voter set.
+    pub fn set_voters(&self, set_id: SetId) -> HashSet<AuthorityId> {
+        self.rounds(set_id)
+            .flat_map(|(_, votes)| votes.voters())
+            .collect()
+    }
+
+    /// Returns the highest block finalized by a validated commit.
+    pub fn finalized(&self) -> Option<(SetId, RoundNumber, VoteTarget)> {
+        self.finalized
+    }
+
+    /// Returns the number of messages dropped due to invalid signatures.
+    pub fn invalid_signatures(&self) -> usize {
+        self.invalid_signatures
+    }
+
+    /// Returns the number of messages dropped due to signers outside of the authority set.
+    pub fn unknown_voters(&self) -> usize {
+        self.unknown_voters
+    }
+
+    /// Returns the number of commits dropped due to a missing supermajority.
+    pub fn rejected_commits(&self) -> usize {
+        self.rejected_commits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(number: BlockNumber) -> VoteTarget {
+        VoteTarget {
+            target_hash: BlockHash::repeat_byte(number as u8),
+            target_number: number,
+        }
+    }
+
+    fn sign(
+        keypair: &ed25519::Keypair,
+        vote: Vote,
+        round: RoundNumber,
+        set_id: SetId,
+    ) -> (AuthoritySignature, AuthorityId) {
+        let signature = keypair.sign(&(vote, round, set_id).encode());
+        (signature.try_into().unwrap(), keypair.public().to_bytes())
+    }
+
+    fn vote_message(
+        keypair: &ed25519::Keypair,
+        vote: Vote,
+        round: RoundNumber,
+        set_id: SetId,
+    ) -> Vec<u8> {
+        let (signature, id) = sign(keypair, vote, round, set_id);
+        GossipMessage::Vote(VoteMessage {
+            round,
+            set_id,
+            message: SignedVote {
+                vote,
+                signature,
+                id,
+            },
+        })
+        .encode()
+    }
+
+    fn commit_message(
+        signers: &[&ed25519::Keypair],
+        commit_target: VoteTarget,
+        round: RoundNumber,
+        set_id: SetId,
+    ) -> Vec<u8> {
+        GossipMessage::Commit(CommitMessage {
+            round,
+            set_id,
+            message: CompactCommit {
+                target_hash: commit_target.target_hash,
+                target_number: commit_target.target_number,
+                precommits: vec![commit_target; signers.len()],
+                auth_data: signers
+                    .iter()
+                    .map(|keypair| sign(keypair, Vote::Precommit(commit_target), round, set_id))
+                    .collect(),
+            },
+        })
+        .encode()
+    }
+
+    fn neighbor_message(set_id: SetId, round: RoundNumber) -> Vec<u8> {
+        GossipMessage::Neighbor(VersionedNeighborPacket::V1(NeighborPacket {
+            round,
+            set_id,
+            commit_finalized_height: 100,
+        }))
+        .encode()
+    }
+
+    #[test]
+    fn observe_votes_and_commits() {
+        let alice = ed25519::Keypair::generate();
+        let bob = ed25519::Keypair::generate();
+        let peer_id = PeerId::random();
+        let mut observer = GrandpaObserver::new();
+
+        // Neighbor packets advance our view, the finalized height is not trusted.
+        let events = observer
+            .on_message(peer_id, &neighbor_message(2, 10))
+            .unwrap();
+        let view = NeighborPacket {
+            round: 10,
+            set_id: 2,
+            commit_finalized_height: 0,
+        };
+        assert_eq!(events, vec![GrandpaEvent::ViewUpdated(view)]);
+        assert_eq!(
+            observer.neighbor_packet(),
+            Some(GossipMessage::Neighbor(VersionedNeighborPacket::V1(view)).encode())
+        );
+        assert!(observer
+            .on_message(peer_id, &neighbor_message(2, 10))
+            .unwrap()
+            .is_empty());
+
+        // Votes are dropped until the authority set is known.
+        let vote = Vote::Prevote(target(101));
+        let message = vote_message(&alice, vote, 10, 2);
+        assert!(observer.on_message(peer_id, &message).unwrap().is_empty());
+        assert_eq!(observer.unknown_voters(), 1);
+
+        let authorities = AuthoritySet {
+            set_id: 2,
+            authorities: vec![(alice.public().to_bytes(), 1), (bob.public().to_bytes(), 1)],
+        };
+        assert_eq!(observer.set_authorities(authorities), None);
+
+        // Votes are recorded once.
+        assert_eq!(
+            observer.on_message(peer_id, &message).unwrap(),
+            vec![GrandpaEvent::Vote {
+                set_id: 2,
+                round: 10,
+                authority: alice.public().to_bytes(),
+                vote,
+            }]
+        );
+        assert!(observer.on_message(peer_id, &message).unwrap().is_empty());
+
+        // Votes signed for a different round are dropped.
+        let (signature, id) = sign(&bob, vote, 9, 2);
+        let message = GossipMessage::Vote(VoteMessage {
+            round: 10,
+            set_id: 2,
+            message: SignedVote {
+                vote,
+                signature,
+                id,
+            },
+        });
+        assert!(observer
+            .on_message(peer_id, &message.encode())
+            .unwrap()
+            .is_empty());
+        assert_eq!(observer.invalid_signatures(), 1);
+
+        // Commits record the precommits and the finalized block.
+        let commit = commit_message(&[&alice, &bob], target(101), 10, 2);
+        let events = observer.on_message(peer_id, &commit).unwrap();
+        assert_eq!(
+            events[events.len() - 2..],
+            [
+                GrandpaEvent::Commit {
+                    set_id: 2,
+                    round: 10,
+                    target: target(101),
+                    signers: 2,
+                },
+                GrandpaEvent::ViewUpdated(NeighborPacket {
+                    round: 11,
+                    set_id: 2,
+                    commit_finalized_height: 101,
+                })
+            ]
+        );
+        assert_eq!(observer.finalized(), Some((2, 10, target(101))));
+
+        let round = observer.round(2, 10).unwrap();
+        assert_eq!(round.prevotes.len(), 1);
+        assert_eq!(round.precommits.len(), 2);
+        let charlie = [3; 32];
+        let authorities = [alice.public().to_bytes(), bob.public().to_bytes(), charlie];
+        assert_eq!(round.missing(&authorities), vec![charlie]);
+        assert_eq!(observer.set_voters(2).len(), 2);
+        assert_eq!(observer.rounds(1).count(), 0);
+    }
+
+    #[test]
+    fn reject_votes_outside_of_the_authority_set() {
+        let alice = ed25519::Keypair::generate();
+        let bob = ed25519::Keypair::generate();
+        let charlie = ed25519::Keypair::generate();
+        let mallory = ed25519::Keypair::generate();
+        let peer_id = PeerId::random();
+
+        let mut observer = GrandpaObserver::new();
+        observer.set_authorities(AuthoritySet {
+            set_id: 1,
+            authorities: [&alice, &bob, &charlie]
+                .iter()
+                .map(|keypair| (keypair.public().to_bytes(), 1))
+                .collect(),
+        });
+
+        // Votes of keys outside of the set, or of another set, are dropped.
+        let vote = Vote::Precommit(target(5));
+        for message in [
+            vote_message(&mallory, vote, 1, 1),
+            vote_message(&alice, vote, 1, 2),
+        ] {
+            assert!(observer.on_message(peer_id, &message).unwrap().is_empty());
+        }
+        assert_eq!(observer.unknown_voters(), 2);
+
+        // A single signer cannot finalize a block.
+        let commit = commit_message(&[&mallory], target(u32::MAX), 1, 1);
+        assert!(observer.on_message(peer_id, &commit).unwrap().is_empty());
+        assert_eq!(observer.unknown_voters(), 3);
+
+        // Two thirds of the weight is not a supermajority, duplicated signers count once.
+        let commit = commit_message(&[&alice, &bob, &bob], target(5), 1, 1);
+        assert!(observer.on_message(peer_id, &commit).unwrap().is_empty());
+        assert_eq!(observer.rejected_commits(), 1);
+        assert_eq!(observer.finalized(), None);
+
+        let commit = commit_message(&[&alice, &bob, &charlie], target(5), 1, 1);
+        let events = observer.on_message(peer_id, &commit).unwrap();
+        assert!(events.contains(&GrandpaEvent::Commit {
+            set_id: 1,
+            round: 1,
+            target: target(5),
+            signers: 3,
+        }));
+        assert_eq!(observer.finalized(), Some((1, 1, target(5))));
+    }
+
+    #[test]
+    fn view_follows_the_median_of_the_peers() {
+        let mut observer = GrandpaObserver::new();
+        observer.set_authorities(AuthoritySet {
+            set_id: 3,
+            authorities: vec![([1; 32], 1)],
+        });
+        let (honest, other, liar) = (PeerId::random(), PeerId::random(), PeerId::random());
+
+        // A single peer cannot move our view past the next voter set.
+        observer
+            .on_message(liar, &neighbor_message(u64::MAX, u64::MAX))
+            .unwrap();
+        assert_eq!(
+            observer.view().map(|view| (view.set_id, view.round)),
+            Some((3, 1))
+        );
+
+        observer
+            .on_message(honest, &neighbor_message(3, 7))
+            .unwrap();
+        observer
+            .on_message(liar, &neighbor_message(4, u64::MAX))
+            .unwrap();
+        assert_eq!(
+            observer.view().map(|view| (view.set_id, view.round)),
+            Some((3, 7))
+        );
+
+        // The majority of the peers moves our view.
+        observer.on_message(other, &neighbor_message(3, 8)).unwrap();
+        assert_eq!(
+            observer.view().map(|view| (view.set_id, view.round)),
+            Some((3, 8))
+        );
+        assert_eq!(observer.neighbor(&liar).map(|view| view.set_id), Some(4));
+    }
+
+    #[test]
+    fn decode_authority_set() {
+        let authorities = vec![([1; 32], 1u64), ([2; 32], 1u64)];
+
+        let values = vec![Some(5u64.encode()), Some(authorities.encode()), None];
+        assert_eq!(
+            AuthoritySet::decode_storage(&values).unwrap(),
+            Some(AuthoritySet {
+                set_id: 5,
+                authorities: authorities.clone(),
+            })
+        );
+
+        let values = vec![None, None, Some((1u8, authorities.clone()).encode())];
+        assert_eq!(
+            AuthoritySet::decode_storage(&values).unwrap(),
+            Some(AuthoritySet {
+                set_id: 0,
+                authorities,
+            })
+        );
+
+        assert_eq!(
+            AuthoritySet::decode_storage(&[None, None, None]).unwrap(),
+            None
+        );
+    }
+}
@@ -3,18 +3,16 @@
 // see LICENSE for license details.
 
 use crate::notifications::{
-    behavior::ProtocolsData,
-    messages::BlockAnnouncesHandshake,
+    behavior::{CloseReason, ProtocolsData, SharedHandshake},
     upgrades::{
         combine_upgrades::CombineUpgrades,
         handshake::{
-            HandshakeInbound, HandshakeInboundSubstream, HandshakeOutbound,
-            HandshakeOutboundSubstream,
+            HandshakeInbound, HandshakeInboundOpen, HandshakeInboundSubstream, HandshakeOutbound,
+            HandshakeOutboundOpen, HandshakeOutboundSubstream,
         },
     },
 };
 use bytes::BytesMut;
-use codec::Encode;
 use futures::{channel::mpsc, prelude::*, SinkExt};
 use libp2p::{
     core::ConnectedPoint,
@@ -35,19 +33,23 @@ use std::{
 const LOG_TARGET: &str = "subp2p-handler";
 
 /// Configuration for a notifications protocol.
-pub struct ProtocolDetails {
+pub struct ProtocolDetails<S = NegotiatedSubstream> {
     /// The name of the notification protocol.
     pub name: String,
+    /// Legacy names of the notification protocol.
+    pub fallback_names: Vec<String>,
     /// Handshake that is submitted upon connection.
-    pub handshake: Vec<u8>,
+    ///
+    /// Read every time a substream is opened.
+    pub handshake: SharedHandshake,
     /// Upgrades the protocol by submitting the handshake and
     pub upgrade: HandshakeInbound,
     /// The state of the protocol.
-    pub state: State,
+    pub state: State<S>,
 }
 
-pub struct NotificationsHandler {
-    protocols: Vec<ProtocolDetails>,
+pub struct NotificationsHandler<S = NegotiatedSubstream> {
+    protocols: Vec<ProtocolDetails<S>>,
 
     /// Events that are pending to be processed by `poll()`.
     pending_events: VecDeque<
@@ -84,6 +86,8 @@ pub enum NotificationsHandlerToBehavior {
         index: usize,
         endpoint: ConnectedPoint,
         handshake: Vec<u8>,
+        /// The protocol name negotiated on the outbound substream.
+        negotiated_name: String,
         is_inbound: bool,
         sender: mpsc::Sender<Vec<u8>>,
     },
@@ -96,10 +100,16 @@ pub enum NotificationsHandlerToBehavior {
     OpenDesiredByRemote {
         index: usize,
     },
+    /// The protocol cannot be used anymore and should be closed.
+    ///
+    /// The behavior acknowledges with [`NotificationsHandlerFromBehavior::Close`].
     CloseDesired {
         index: usize,
+        reason: CloseReason,
     },
     /// Response of [`NotificationsHandlerFromBehavior::Close`].
+    ///
+    /// The protocol is closed and the substreams are dropped.
     Close {
         index: usize,
     },
@@ -108,12 +118,19 @@ pub enum NotificationsHandlerToBehavior {
         index: usize,
         bytes: BytesMut,
     },
+    /// Notifications submitted by the user have been flushed to the remote.
+    NotificationsFlushed {
+        index: usize,
+        /// The number of notifications flushed.
+        count: usize,
+    },
 }
 
 /// The state of a notification protocol.
 ///
 /// ### Transitions
 ///
+/// ```text
 /// Closed -> OpenDesiredByRemote
 ///                 |
 ///                 |
@@ -123,7 +140,14 @@ pub enum NotificationsHandlerToBehavior {
 ///                 |
 ///           OpenDesiredByRemote -> Opening -> Open
 ///
-pub enum State {
+/// Open -> CloseDesired (remote closed, flush or handshake error)
+///                 |
+///                 |---------------  behavior ack
+///                 |
+///               Closed
+/// ```
+#[allow(clippy::large_enum_variant)]
+pub enum State<S = NegotiatedSubstream> {
     /// Protocol is closed.
     Closed {
         /// True if we should open the protocol.
@@ -132,66 +156,53 @@ pub enum State {
     /// Initiated a new substream.
     OpenDesiredByRemote {
         /// Handle handshake.
-        inbound_substream: HandshakeInboundSubstream<NegotiatedSubstream>,
+        inbound_substream: HandshakeInboundSubstream<S>,
         /// True if we should open the protocol.
         pending_opening: bool,
     },
     /// Opening the protocol by handshake negociation.
     Opening {
         /// Set the first time. Contains a value when the handshake is in progress.
-        inbound_substream: Option<HandshakeInboundSubstream<NegotiatedSubstream>>,
+        inbound_substream: Option<HandshakeInboundSubstream<S>>,
         /// Direction of substream.
         inbound: bool,
     },
     /// Protocol is opened, handshake has been negociated.
     Open {
         recv: stream::Peekable<mpsc::Receiver<Vec<u8>>>,
-        inbound_substream: Option<HandshakeInboundSubstream<NegotiatedSubstream>>,
-        outbound_substream: Option<HandshakeOutboundSubstream<NegotiatedSubstream>>,
+        inbound_substream: Option<HandshakeInboundSubstream<S>>,
+        outbound_substream: Option<HandshakeOutboundSubstream<S>>,
+        /// Notifications submitted on the outbound substream since the last flush.
+        unflushed: usize,
     },
 }
 
-impl NotificationsHandler {
+impl<S> NotificationsHandler<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
     pub fn new(peer: PeerId, endpoint: ConnectedPoint, data: ProtocolsData) -> Self {
-        // The blocks announces protocol is hardcoded on index 0.
-        // We must accept connections of this protocol to transition the substrate
-        // view of our peer into accepted state. To achive this, the provided genesis
+        // The protocols are opened in the order of registration. Substrate peers must
+        // accept the block announces protocol first to transition the substrate view
+        // of our peer into accepted state. To achive this, the provided genesis
         // hash and therefore the handshake must be valid.
         //
-        // This implementation does not fallback on the legacy supported protocols (ie `/dot/../1`).
-        // The genesis hash must be hex-encoded without the "0x" sufix.
-        let genesis_string = hex::encode(data.genesis_hash);
-        let blocks = format!("/{}/block-announces/1", genesis_string);
-
-        // Note:
-        // `../grandpa/1` and `../statement/1` are currently not registered.
-
-        // The transaction protocol substream will broadcast a vector of extrinsics that is scale-encoded.
-        let tx = format!("/{}/transactions/1", genesis_string);
-
-        let block_announces = BlockAnnouncesHandshake::from_genesis(data.genesis_hash);
-
-        let protocols = vec![
-            ProtocolDetails {
-                name: blocks.clone(),
-                handshake: block_announces.encode(),
-                upgrade: HandshakeInbound {
-                    name: blocks.clone(),
-                },
-                state: State::Closed {
-                    pending_opening: false,
-                },
-            },
-            ProtocolDetails {
-                name: tx.clone(),
-                // Any other protocol that doesn't have a handshake must submit the node role.
-                handshake: vec![data.node_role.encoded()],
-                upgrade: HandshakeInbound { name: tx.clone() },
+        // The fallback names are negotiated with peers that only support the legacy
+        // protocols (ie `/dot/block-announces/1`).
+        let protocols = data
+            .protocols
+            .into_iter()
+            .map(|config| ProtocolDetails {
+                upgrade: HandshakeInbound::new(config.name.clone(), config.max_notification_size)
+                    .with_fallback_names(config.fallback_names.clone()),
+                name: config.name,
+                fallback_names: config.fallback_names,
+                handshake: config.handshake,
                 state: State::Closed {
                     pending_opening: false,
                 },
-            },
-        ];
+            })
+            .collect();
 
         NotificationsHandler {
             peer,
@@ -200,235 +211,189 @@ impl NotificationsHandler {
             protocols,
         }
     }
-}
-
-/// Error specific to the collection of protocols.
-#[derive(Debug, thiserror::Error)]
-pub enum NotificationsHandlerError {}
 
-impl ConnectionHandler for NotificationsHandler {
-    // Received and submitted events.
-    type FromBehaviour = NotificationsHandlerFromBehavior;
-    type ToBehaviour = NotificationsHandlerToBehavior;
+    /// Handle a fully negotiated inbound substream of the given protocol.
+    fn on_inbound_negotiated(&mut self, index: usize, mut stream: HandshakeInboundOpen<S>) {
+        log::debug!(target: LOG_TARGET,
+            "Handler negotiated inbound peer={:?} index={:?}",
+            self.peer,
+            index
+        );
+
+        let proto = &mut self.protocols[index];
+        match proto.state {
+            State::Closed { pending_opening } => {
+                log::trace!(
+                    target: LOG_TARGET,
+                    "Handler negotiated inbound Closed -> OpenDesiredByRemote peer={:?} index={:?}",
+                    self.peer,
+                    index
+                );
 
-    type Error = NotificationsHandlerError;
+                self.pending_events
+                    .push_back(ConnectionHandlerEvent::NotifyBehaviour(
+                        NotificationsHandlerToBehavior::OpenDesiredByRemote { index },
+                    ));
 
-    // Handle handshakes.
-    type InboundProtocol = CombineUpgrades<HandshakeInbound>;
-    type OutboundProtocol = HandshakeOutbound;
+                proto.state = State::OpenDesiredByRemote {
+                    inbound_substream: stream.substream,
+                    pending_opening,
+                };
+            }
+            State::OpenDesiredByRemote { .. } => {
+                log::trace!(
+                    target: LOG_TARGET,
+                    "Handler negotiated inbound OpenDesiredByRemote peer={:?} index={:?}",
+                    self.peer,
+                    index
+                );
+            }
+            State::Opening {
+                ref mut inbound_substream,
+                ..
+            }
+            | State::Open {
+                ref mut inbound_substream,
+                ..
+            } => {
+                // Already handled.
+                if inbound_substream.is_some() {
+                    log::trace!(
+                        target: LOG_TARGET,
+                        "Handler negotiated inbound handshake already handled peer={:?} index={:?}",
+                        self.peer,
+                        index
+                    );
+                    return;
+                }
 
-    // Extra information upon connections.
-    type OutboundOpenInfo = usize;
-    type InboundOpenInfo = ();
+                log::trace!(
+                    target: LOG_TARGET,
+                    "Handler negotiated inbound setup handshake peer={:?} index={:?}",
+                    self.peer,
+                    index
+                );
 
-    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, ()> {
-        let protocol_upgrades: Vec<_> = self.protocols.iter().map(|p| p.upgrade.clone()).collect();
-        let combine_upgrades = CombineUpgrades::from(protocol_upgrades);
-        SubstreamProtocol::new(combine_upgrades, ())
+                let handshake_message = proto.handshake.get();
+                stream.substream.set_handshake(handshake_message);
+                *inbound_substream = Some(stream.substream);
+            }
+        }
     }
 
-    fn on_connection_event(
-        &mut self,
-        event: ConnectionEvent<
-            '_,
-            Self::InboundProtocol,
-            Self::OutboundProtocol,
-            Self::InboundOpenInfo,
-            Self::OutboundOpenInfo,
-        >,
-    ) {
-        match event {
-            ConnectionEvent::FullyNegotiatedInbound(FullyNegotiatedInbound {
-                protocol, ..
-            }) => {
-                let (mut stream, index) = (protocol.data, protocol.index);
-
-                log::debug!(target: LOG_TARGET,
-                    "Handler negotiated inbound peer={:?} index={:?}",
+    /// Handle a fully negotiated outbound substream of the given protocol.
+    fn on_outbound_negotiated(&mut self, index: usize, opened: HandshakeOutboundOpen<S>) {
+        log::debug!(
+            target: LOG_TARGET,
+            "Handler negotiated outbound peer={:?} index={:?}",
+            self.peer,
+            index
+        );
+
+        let proto = &mut self.protocols[index];
+        match proto.state {
+            State::Closed {
+                ref mut pending_opening,
+            }
+            | State::OpenDesiredByRemote {
+                ref mut pending_opening,
+                ..
+            } => {
+                log::trace!(
+                    target: LOG_TARGET,
+                    "Handler negotiated outbound Closed|OpenDesiredByRemote peer={:?} index={:?}",
                     self.peer,
                     index
                 );
 
-                let proto = &mut self.protocols[index];
-                match proto.state {
-                    State::Closed { pending_opening } => {
-                        log::trace!(
-                            target: LOG_TARGET,
-                            "Handler negotiated inbound Closed -> OpenDesiredByRemote peer={:?} index={:?}",
-                            self.peer,
-                            index
-                        );
-
-                        self.pending_events
-                            .push_back(ConnectionHandlerEvent::NotifyBehaviour(
-                                NotificationsHandlerToBehavior::OpenDesiredByRemote { index },
-                            ));
-
-                        proto.state = State::OpenDesiredByRemote {
-                            inbound_substream: stream.substream,
-                            pending_opening,
-                        };
-                    }
-                    State::OpenDesiredByRemote { .. } => {
-                        log::trace!(
-                            target: LOG_TARGET,
-                            "Handler negotiated inbound OpenDesiredByRemote peer={:?} index={:?}",
-                            self.peer,
-                            index
-                        );
-                    }
-                    State::Opening {
-                        ref mut inbound_substream,
-                        ..
-                    }
-                    | State::Open {
-                        ref mut inbound_substream,
-                        ..
-                    } => {
-                        // Already handled.
-                        if inbound_substream.is_some() {
-                            log::trace!(
-                                target: LOG_TARGET,
-                                "Handler negotiated inbound handshake already handled peer={:?} index={:?}",
-                                self.peer,
-                                index
-                            );
-                            return;
-                        }
-
-                        log::trace!(
-                            target: LOG_TARGET,
-                            "Handler negotiated inbound setup handshake peer={:?} index={:?}",
-                            self.peer,
-                            index
-                        );
-
-                        let handshake_message = proto.handshake.clone();
-                        stream.substream.set_handshake(handshake_message);
-                        *inbound_substream = Some(stream.substream);
-                    }
-                }
+                *pending_opening = false;
             }
-            ConnectionEvent::FullyNegotiatedOutbound(outbound) => {
-                let (opened, index) = (outbound.protocol, outbound.info);
-
-                log::debug!(
+            State::Opening {
+                ref mut inbound_substream,
+                inbound,
+            } => {
+                log::trace!(
                     target: LOG_TARGET,
-                    "Handler negotiated outbound peer={:?} index={:?}",
+                    "Handler negotiated outbound Opening successful peer={:?} index={:?}",
                     self.peer,
                     index
                 );
 
-                let proto = &mut self.protocols[index];
-                match proto.state {
-                    State::Closed {
-                        ref mut pending_opening,
-                    }
-                    | State::OpenDesiredByRemote {
-                        ref mut pending_opening,
-                        ..
-                    } => {
-                        log::trace!(
-                            target: LOG_TARGET,
-                            "Handler negotiated outbound Closed|OpenDesiredByRemote peer={:?} index={:?}",
-                            self.peer,
-                            index
-                        );
-
-                        *pending_opening = false;
-                    }
-                    State::Opening {
-                        ref mut inbound_substream,
-                        inbound,
-                    } => {
-                        log::trace!(
-                            target: LOG_TARGET,
-                            "Handler negotiated outbound Opening successful peer={:?} index={:?}",
-                            self.peer,
-                            index
-                        );
+                let (send, recv) = mpsc::channel(1024);
+                proto.state = State::Open {
+                    inbound_substream: inbound_substream.take(),
+                    outbound_substream: Some(opened.substream),
+                    recv: recv.peekable(),
+                    unflushed: 0,
+                };
 
-                        let (send, recv) = mpsc::channel(1024);
-                        proto.state = State::Open {
-                            inbound_substream: inbound_substream.take(),
-                            outbound_substream: Some(opened.substream),
-                            recv: recv.peekable(),
-                        };
-
-                        self.pending_events
-                            .push_back(ConnectionHandlerEvent::NotifyBehaviour(
-                                NotificationsHandlerToBehavior::HandshakeCompleted {
-                                    index,
-                                    endpoint: self.endpoint.clone(),
-                                    handshake: opened.handshake,
-                                    is_inbound: inbound,
-                                    sender: send,
-                                },
-                            ));
-                    }
-                    State::Open { .. } => {
-                        log::trace!(
-                            target: LOG_TARGET,
-                            "Handler negotiated outbound Open missmatch-state peer={:?} index={:?}",
-                            self.peer,
-                            index
-                        );
-                    }
-                }
+                self.pending_events
+                    .push_back(ConnectionHandlerEvent::NotifyBehaviour(
+                        NotificationsHandlerToBehavior::HandshakeCompleted {
+                            index,
+                            endpoint: self.endpoint.clone(),
+                            handshake: opened.handshake,
+                            negotiated_name: opened.negotiated_name,
+                            is_inbound: inbound,
+                            sender: send,
+                        },
+                    ));
             }
-            ConnectionEvent::DialUpgradeError(err) => {
-                log::debug!(
+            State::Open { .. } => {
+                log::trace!(
                     target: LOG_TARGET,
-                    "Handler DialError peer={:?} index={:?} error={:?}",
+                    "Handler negotiated outbound Open missmatch-state peer={:?} index={:?}",
                     self.peer,
-                    err.info,
-                    err.error,
+                    index
                 );
+            }
+        }
+    }
 
-                let proto = &mut self.protocols[err.info];
+    /// Handle a failure to negotiate an outbound substream of the given protocol.
+    fn on_dial_upgrade_error(&mut self, index: usize) {
+        let proto = &mut self.protocols[index];
 
-                match proto.state {
-                    State::Closed {
-                        ref mut pending_opening,
-                    }
-                    | State::OpenDesiredByRemote {
-                        ref mut pending_opening,
-                        ..
-                    } => {
-                        log::trace!(
-                            target: LOG_TARGET,
-                            "Handler DialError Closed|OpenDesiredByRemote peer={:?} info={:?}",
-                            self.peer,
-                            err.info,
-                        );
+        match proto.state {
+            State::Closed {
+                ref mut pending_opening,
+            }
+            | State::OpenDesiredByRemote {
+                ref mut pending_opening,
+                ..
+            } => {
+                log::trace!(
+                    target: LOG_TARGET,
+                    "Handler DialError Closed|OpenDesiredByRemote peer={:?} info={:?}",
+                    self.peer,
+                    index,
+                );
 
-                        *pending_opening = false;
-                    }
-                    State::Opening { .. } => {
-                        proto.state = State::Closed {
-                            pending_opening: false,
-                        };
+                *pending_opening = false;
+            }
+            State::Opening { .. } => {
+                proto.state = State::Closed {
+                    pending_opening: false,
+                };
 
-                        log::trace!(
-                            target: LOG_TARGET,
-                            "Handler DialError Opening -> Closed peer={:?} info={:?}",
-                            self.peer,
-                            err.info,
-                        );
+                log::trace!(
+                    target: LOG_TARGET,
+                    "Handler DialError Opening -> Closed peer={:?} info={:?}",
+                    self.peer,
+                    index,
+                );
 
-                        self.pending_events
-                            .push_back(ConnectionHandlerEvent::NotifyBehaviour(
-                                NotificationsHandlerToBehavior::HandshakeError { index: err.info },
-                            ));
-                    }
-                    State::Open { .. } => {}
-                }
+                self.pending_events
+                    .push_back(ConnectionHandlerEvent::NotifyBehaviour(
+                        NotificationsHandlerToBehavior::HandshakeError { index },
+                    ));
             }
-            _ => {}
+            State::Open { .. } => {}
         }
     }
 
-    fn on_behaviour_event(&mut self, message: NotificationsHandlerFromBehavior) {
+    /// Handle a message from the network behavior.
+    fn on_behaviour_message(&mut self, message: NotificationsHandlerFromBehavior) {
         match message {
             NotificationsHandlerFromBehavior::Open { index } => {
                 log::debug!(
@@ -443,10 +408,9 @@ impl ConnectionHandler for NotificationsHandler {
                 match &mut proto.state {
                     State::Closed { pending_opening } => {
                         if !*pending_opening {
-                            let protocol = HandshakeOutbound {
-                                name: proto.name.clone(),
-                                handshake: proto.handshake.clone(),
-                            };
+                            let protocol =
+                                HandshakeOutbound::new(proto.name.clone(), proto.handshake.get())
+                                    .with_fallback_names(proto.fallback_names.clone());
 
                             log::trace!(
                                 target: LOG_TARGET,
@@ -479,10 +443,9 @@ impl ConnectionHandler for NotificationsHandler {
                         pending_opening,
                     } => {
                         if !*pending_opening {
-                            let protocol = HandshakeOutbound {
-                                name: proto.name.clone(),
-                                handshake: proto.handshake.clone(),
-                            };
+                            let protocol =
+                                HandshakeOutbound::new(proto.name.clone(), proto.handshake.get())
+                                    .with_fallback_names(proto.fallback_names.clone());
 
                             log::trace!(
                                 target: LOG_TARGET,
@@ -505,7 +468,7 @@ impl ConnectionHandler for NotificationsHandler {
                             index
                         );
 
-                        let handshake = proto.handshake.clone();
+                        let handshake = proto.handshake.get();
                         inbound_substream.set_handshake(handshake);
 
                         let inbound_substream = match mem::replace(
@@ -585,27 +548,23 @@ impl ConnectionHandler for NotificationsHandler {
         }
     }
 
-    fn connection_keep_alive(&self) -> KeepAlive {
-        if self
-            .protocols
+    /// Returns true if any protocol is not closed.
+    fn keep_alive(&self) -> bool {
+        self.protocols
             .iter()
             .any(|p| !matches!(p.state, State::Closed { .. }))
-        {
-            return KeepAlive::Yes;
-        }
-
-        KeepAlive::No
     }
 
-    fn poll(
+    /// Advance the state of the protocols.
+    fn poll_protocols(
         &mut self,
         cx: &mut Context,
     ) -> Poll<
         ConnectionHandlerEvent<
-            Self::OutboundProtocol,
-            Self::OutboundOpenInfo,
-            Self::ToBehaviour,
-            Self::Error,
+            HandshakeOutbound,
+            usize,
+            NotificationsHandlerToBehavior,
+            NotificationsHandlerError,
         >,
     > {
         if let Some(ev) = self.pending_events.pop_front() {
@@ -617,20 +576,15 @@ impl ConnectionHandler for NotificationsHandler {
             if let State::Open {
                 outbound_substream: Some(outbound_substream),
                 recv,
+                unflushed,
                 ..
             } = &mut self.protocols[index].state
             {
-                loop {
-                    // Step 1. Check if we received a messages from the user.
-                    // Step 2. Check if the peer substream is ready to receive the message.
-                    // Step 3. Fetch the message from the user channel.
-                    // Step 4. Send the message on the peer substream.
-
-                    match Pin::new(&mut *recv).as_mut().poll_peek(cx) {
-                        Poll::Ready(Some(..)) => {}
-                        _ => break,
-                    };
-
+                // Step 1. Check if we received a messages from the user.
+                // Step 2. Check if the peer substream is ready to receive the message.
+                // Step 3. Fetch the message from the user channel.
+                // Step 4. Send the message on the peer substream.
+                while let Poll::Ready(Some(..)) = Pin::new(&mut *recv).as_mut().poll_peek(cx) {
                     match outbound_substream.poll_ready_unpin(cx) {
                         Poll::Ready(_) => {}
                         Poll::Pending => break,
@@ -654,7 +608,9 @@ impl ConnectionHandler for NotificationsHandler {
                     );
 
                     // Flush all outbound streams below.
-                    let _ = outbound_substream.start_send_unpin(message);
+                    if outbound_substream.start_send_unpin(message).is_ok() {
+                        *unflushed += 1;
+                    }
                 }
             }
         }
@@ -663,16 +619,33 @@ impl ConnectionHandler for NotificationsHandler {
         for index in 0..self.protocols.len() {
             if let State::Open {
                 outbound_substream: outbound_substream @ Some(_),
+                unflushed,
                 ..
             } = &mut self.protocols[index].state
             {
                 match Sink::poll_flush(Pin::new(outbound_substream.as_mut().unwrap()), cx) {
-                    Poll::Pending | Poll::Ready(Ok(())) => {}
+                    Poll::Pending => {}
+                    Poll::Ready(Ok(())) => {
+                        if *unflushed > 0 {
+                            let count = mem::take(unflushed);
+
+                            return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(
+                                NotificationsHandlerToBehavior::NotificationsFlushed {
+                                    index,
+                                    count,
+                                },
+                            ));
+                        }
+                    }
                     Poll::Ready(Err(_)) => {
                         *outbound_substream = None;
+                        *unflushed = 0;
 
                         return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(
-                            NotificationsHandlerToBehavior::CloseDesired { index },
+                            NotificationsHandlerToBehavior::CloseDesired {
+                                index,
+                                reason: CloseReason::FlushError,
+                            },
                         ));
                     }
                 }
@@ -685,14 +658,39 @@ impl ConnectionHandler for NotificationsHandler {
                 State::Open {
                     inbound_substream: inbound_substream @ Some(_),
                     ..
-                } => match Stream::poll_next(Pin::new(inbound_substream.as_mut().unwrap()), cx) {
-                    Poll::Pending => {}
-                    Poll::Ready(Some(Ok(bytes))) => {
-                        let event = NotificationsHandlerToBehavior::Notification { index, bytes };
-                        return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(event));
+                } => {
+                    let substream = inbound_substream.as_mut().unwrap();
+                    // The inbound substream can still be sending back our handshake.
+                    let reason = if substream.is_handshake_sent() {
+                        CloseReason::RemoteClosed
+                    } else {
+                        CloseReason::HandshakeError
+                    };
+
+                    match Stream::poll_next(Pin::new(substream), cx) {
+                        Poll::Pending => {}
+                        Poll::Ready(Some(Ok(bytes))) => {
+                            let event =
+                                NotificationsHandlerToBehavior::Notification { index, bytes };
+                            return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(event));
+                        }
+                        Poll::Ready(None) | Poll::Ready(Some(Err(_))) => {
+                            log::trace!(
+                                target: LOG_TARGET,
+                                "Handler poll inbound closed peer={:?} index={:?} reason={:?}",
+                                self.peer,
+                                index,
+                                reason
+                            );
+
+                            *inbound_substream = None;
+
+                            return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(
+                                NotificationsHandlerToBehavior::CloseDesired { index, reason },
+                            ));
+                        }
                     }
-                    Poll::Ready(None) | Poll::Ready(Some(Err(_))) => *inbound_substream = None,
-                },
+                }
 
                 State::OpenDesiredByRemote {
                     inbound_substream,
@@ -706,7 +704,10 @@ impl ConnectionHandler for NotificationsHandler {
                             pending_opening: *pending_opening,
                         };
                         return Poll::Ready(ConnectionHandlerEvent::NotifyBehaviour(
-                            NotificationsHandlerToBehavior::CloseDesired { index },
+                            NotificationsHandlerToBehavior::CloseDesired {
+                                index,
+                                reason: CloseReason::HandshakeError,
+                            },
                         ));
                     }
                 },
@@ -730,3 +731,668 @@ impl ConnectionHandler for NotificationsHandler {
         Poll::Pending
     }
 }
+
+/// Error specific to the collection of protocols.
+#[derive(Debug, thiserror::Error)]
+pub enum NotificationsHandlerError {}
+
+impl ConnectionHandler for NotificationsHandler {
+    // Received and submitted events.
+    type FromBehaviour = NotificationsHandlerFromBehavior;
+    type ToBehaviour = NotificationsHandlerToBehavior;
+
+    #[allow(deprecated)]
+    type Error = NotificationsHandlerError;
+
+    // Handle handshakes.
+    type InboundProtocol = CombineUpgrades<HandshakeInbound>;
+    type OutboundProtocol = HandshakeOutbound;
+
+    // Extra information upon connections.
+    type OutboundOpenInfo = usize;
+    type InboundOpenInfo = ();
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol, ()> {
+        let protocol_upgrades: Vec<_> = self.protocols.iter().map(|p| p.upgrade.clone()).collect();
+        let combine_upgrades = CombineUpgrades::from(protocol_upgrades);
+        SubstreamProtocol::new(combine_upgrades, ())
+    }
+
+    fn on_connection_event(
+        &mut self,
+        event: ConnectionEvent<
+            '_,
+            Self::InboundProtocol,
+            Self::OutboundProtocol,
+            Self::InboundOpenInfo,
+            Self::OutboundOpenInfo,
+        >,
+    ) {
+        match event {
+            ConnectionEvent::FullyNegotiatedInbound(FullyNegotiatedInbound {
+                protocol, ..
+            }) => self.on_inbound_negotiated(protocol.index, protocol.data),
+            ConnectionEvent::FullyNegotiatedOutbound(outbound) => {
+                self.on_outbound_negotiated(outbound.info, outbound.protocol)
+            }
+            ConnectionEvent::DialUpgradeError(err) => {
+                log::debug!(
+                    target: LOG_TARGET,
+                    "Handler DialError peer={:?} index={:?} error={:?}",
+                    self.peer,
+                    err.info,
+                    err.error,
+                );
+
+                self.on_dial_upgrade_error(err.info)
+            }
+            _ => {}
+        }
+    }
+
+    fn on_behaviour_event(&mut self, message: NotificationsHandlerFromBehavior) {
+        self.on_behaviour_message(message)
+    }
+
+    fn connection_keep_alive(&self) -> KeepAlive {
+        if self.keep_alive() {
+            return KeepAlive::Yes;
+        }
+
+        KeepAlive::No
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context,
+    ) -> Poll<
+        ConnectionHandlerEvent<
+            Self::OutboundProtocol,
+            Self::OutboundOpenInfo,
+            Self::ToBehaviour,
+            NotificationsHandlerError,
+        >,
+    > {
+        self.poll_protocols(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::{
+        behavior::NotificationProtocolConfig,
+        messages::{BlockHash, ProtocolRole},
+    };
+    use futures::{executor::block_on, task::noop_waker};
+    use libp2p::{
+        core::{
+            upgrade::{InboundUpgrade, OutboundUpgrade, UpgradeInfo},
+            Endpoint,
+        },
+        Multiaddr,
+    };
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    const PROTOCOL: &str = "/test/1";
+    const LOCAL_HANDSHAKE: [u8; 3] = [1, 2, 3];
+    const REMOTE_HANDSHAKE: [u8; 2] = [4, 5];
+
+    /// In-memory substream shared between the test and the handler.
+    #[derive(Clone, Default)]
+    struct MockSubstream(Arc<Mutex<MockState>>);
+
+    #[derive(Default)]
+    struct MockState {
+        /// Bytes that will be read by the handler.
+        read: VecDeque<u8>,
+        /// Bytes written by the handler.
+        written: Vec<u8>,
+        /// The remote closed its writing side.
+        eof: bool,
+        /// Writing to the substream fails.
+        broken: bool,
+    }
+
+    impl MockSubstream {
+        /// Push a length-prefixed frame for the handler to read.
+        fn push_frame(&self, data: &[u8]) {
+            let mut buf = unsigned_varint::encode::usize_buffer();
+            let len = unsigned_varint::encode::usize(data.len(), &mut buf);
+
+            let mut state = self.0.lock().unwrap();
+            state.read.extend(len.iter().chain(data.iter()));
+        }
+
+        fn close(&self) {
+            self.0.lock().unwrap().eof = true;
+        }
+
+        fn break_writes(&self) {
+            self.0.lock().unwrap().broken = true;
+        }
+
+        fn take_written(&self) -> Vec<u8> {
+            mem::take(&mut self.0.lock().unwrap().written)
+        }
+    }
+
+    impl AsyncRead for MockSubstream {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let mut state = self.0.lock().unwrap();
+            if state.read.is_empty() {
+                return if state.eof {
+                    Poll::Ready(Ok(0))
+                } else {
+                    Poll::Pending
+                };
+            }
+
+            let len = buf.len().min(state.read.len());
+            for (byte, value) in buf.iter_mut().zip(state.read.drain(..len)) {
+                *byte = value;
+            }
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    impl AsyncWrite for MockSubstream {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let mut state = self.0.lock().unwrap();
+            if state.broken {
+                return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+            }
+
+            state.written.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    type Event = ConnectionHandlerEvent<
+        HandshakeOutbound,
+        usize,
+        NotificationsHandlerToBehavior,
+        NotificationsHandlerError,
+    >;
+
+    fn handler() -> NotificationsHandler<MockSubstream> {
+        let mut data = ProtocolsData::new(BlockHash::zero(), ProtocolRole::FullNode);
+        data.register(NotificationProtocolConfig::new(PROTOCOL, LOCAL_HANDSHAKE));
+
+        NotificationsHandler::new(
+            PeerId::random(),
+            ConnectedPoint::Dialer {
+                address: Multiaddr::empty(),
+                role_override: Endpoint::Dialer,
+            },
+            data,
+        )
+    }
+
+    /// Negotiate an inbound substream that received the remote handshake.
+    fn inbound(substream: &MockSubstream) -> HandshakeInboundOpen<MockSubstream> {
+        substream.push_frame(&REMOTE_HANDSHAKE);
+
+        let upgrade = HandshakeInbound::new(PROTOCOL, 1024);
+        block_on(upgrade.upgrade_inbound(substream.clone(), PROTOCOL.to_string())).unwrap()
+    }
+
+    /// Negotiate an outbound substream that received the remote handshake.
+    fn outbound(substream: &MockSubstream) -> HandshakeOutboundOpen<MockSubstream> {
+        substream.push_frame(&REMOTE_HANDSHAKE);
+
+        let upgrade = HandshakeOutbound::new(PROTOCOL, LOCAL_HANDSHAKE);
+        let opened =
+            block_on(upgrade.upgrade_outbound(substream.clone(), PROTOCOL.to_string())).unwrap();
+        // Discard the handshake written by the upgrade.
+        substream.take_written();
+        opened
+    }
+
+    fn next_event(handler: &mut NotificationsHandler<MockSubstream>) -> Option<Event> {
+        let waker = noop_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        match handler.poll_protocols(&mut cx) {
+            Poll::Ready(event) => Some(event),
+            Poll::Pending => None,
+        }
+    }
+
+    fn expect_behaviour_event(
+        handler: &mut NotificationsHandler<MockSubstream>,
+    ) -> NotificationsHandlerToBehavior {
+        match next_event(handler) {
+            Some(ConnectionHandlerEvent::NotifyBehaviour(event)) => event,
+            event => panic!("Expected behaviour event, got {event:?}"),
+        }
+    }
+
+    fn expect_substream_request(handler: &mut NotificationsHandler<MockSubstream>) {
+        match next_event(handler) {
+            Some(ConnectionHandlerEvent::OutboundSubstreamRequest { protocol }) => {
+                assert_eq!(*protocol.info(), 0);
+                assert_eq!(protocol.upgrade().name, PROTOCOL);
+                assert_eq!(protocol.upgrade().handshake, LOCAL_HANDSHAKE);
+            }
+            event => panic!("Expected outbound substream request, got {event:?}"),
+        }
+    }
+
+    /// Bring the handler into the `Open` state by a remote initiated substream.
+    ///
+    /// Returns the inbound and outbound substreams and the notification sender.
+    fn open_handler(
+        handler: &mut NotificationsHandler<MockSubstream>,
+    ) -> (MockSubstream, MockSubstream, mpsc::Sender<Vec<u8>>) {
+        let (inbound_substream, outbound_substream) =
+            (MockSubstream::default(), MockSubstream::default());
+
+        handler.on_inbound_negotiated(0, inbound(&inbound_substream));
+        assert!(matches!(
+            expect_behaviour_event(handler),
+            NotificationsHandlerToBehavior::OpenDesiredByRemote { index: 0 }
+        ));
+
+        handler.on_behaviour_message(NotificationsHandlerFromBehavior::Open { index: 0 });
+        expect_substream_request(handler);
+
+        // Poll the inbound substream to send back our handshake.
+        assert!(next_event(handler).is_none());
+
+        handler.on_outbound_negotiated(0, outbound(&outbound_substream));
+        let sender = match expect_behaviour_event(handler) {
+            NotificationsHandlerToBehavior::HandshakeCompleted {
+                index: 0,
+                handshake,
+                is_inbound: true,
+                sender,
+                ..
+            } => {
+                assert_eq!(handshake, REMOTE_HANDSHAKE);
+                sender
+            }
+            event => panic!("Expected handshake completed, got {event:?}"),
+        };
+        assert!(matches!(handler.protocols[0].state, State::Open { .. }));
+
+        (inbound_substream, outbound_substream, sender)
+    }
+
+    #[test]
+    fn closed_to_opening() {
+        let mut handler = handler();
+        assert!(!handler.keep_alive());
+
+        handler.on_behaviour_message(NotificationsHandlerFromBehavior::Open { index: 0 });
+        assert!(matches!(
+            handler.protocols[0].state,
+            State::Opening {
+                inbound_substream: None,
+                inbound: false
+            }
+        ));
+        assert!(handler.keep_alive());
+        expect_substream_request(&mut handler);
+    }
+
+    #[test]
+    fn opening_negotiates_fallback_name() {
+        const LEGACY_PROTOCOL: &str = "/legacy/1";
+
+        let mut data = ProtocolsData::new(BlockHash::zero(), ProtocolRole::FullNode);
+        data.register(
+            NotificationProtocolConfig::new(PROTOCOL, LOCAL_HANDSHAKE)
+                .with_fallback_names(vec![LEGACY_PROTOCOL.into()]),
+        );
+        let mut handler = NotificationsHandler::<MockSubstream>::new(
+            PeerId::random(),
+            ConnectedPoint::Dialer {
+                address: Multiaddr::empty(),
+                role_override: Endpoint::Dialer,
+            },
+            data,
+        );
+        // The main name is preferred over the fallback names.
+        assert_eq!(
+            handler.protocols[0]
+                .upgrade
+                .protocol_info()
+                .collect::<Vec<_>>(),
+            vec![PROTOCOL, LEGACY_PROTOCOL]
+        );
+
+        handler.on_behaviour_message(NotificationsHandlerFromBehavior::Open { index: 0 });
+        match next_event(&mut handler) {
+            Some(ConnectionHandlerEvent::OutboundSubstreamRequest { protocol }) => {
+                assert_eq!(
+                    protocol.upgrade().protocol_info().collect::<Vec<_>>(),
+                    vec![PROTOCOL, LEGACY_PROTOCOL]
+                );
+            }
+            event => panic!("Expected outbound substream request, got {event:?}"),
+        }
+
+        // The remote only supports the legacy protocol.
+        let substream = MockSubstream::default();
+        substream.push_frame(&REMOTE_HANDSHAKE);
+        let opened = block_on(
+            HandshakeOutbound::new(PROTOCOL, LOCAL_HANDSHAKE)
+                .upgrade_outbound(substream.clone(), LEGACY_PROTOCOL.to_string()),
+        )
+        .unwrap();
+        handler.on_outbound_negotiated(0, opened);
+
+        match expect_behaviour_event(&mut handler) {
+            NotificationsHandlerToBehavior::HandshakeCompleted {
+                negotiated_name,
+                handshake,
+                ..
+            } => {
+                assert_eq!(negotiated_name, LEGACY_PROTOCOL);
+                assert_eq!(handshake, REMOTE_HANDSHAKE);
+            }
+            event => panic!("Expected handshake completed, got {event:?}"),
+        }
+    }
+
+    #[test]
+    fn closed_to_open_desired_by_remote() {
+        let mut handler = handler();
+
+        handler.on_inbound_negotiated(0, inbound(&MockSubstream::default()));
+        assert!(matches!(
+            handler.protocols[0].state,
+            State::OpenDesiredByRemote {
+                pending_opening: false,
+                ..
+            }
+        ));
+        assert!(matches!(
+            expect_behaviour_event(&mut handler),
+            NotificationsHandlerToBehavior::OpenDesiredByRemote { index: 0 }
+        ));
+        // Waiting for the behavior to accept the substream.
+        assert!(next_event(&mut handler).is_none());
+    }
+
+    #[test]
+    fn open_desired_by_remote_to_opening() {
+        let mut handler = handler();
+        let substream = MockSubstream::default();
+
+        handler.on_inbound_negotiated(0, inbound(&substream));
+        expect_behaviour_event(&mut handler);
+
+        handler.on_behaviour_message(NotificationsHandlerFromBehavior::Open { index: 0 });
+        assert!(matches!(
+            handler.protocols[0].state,
+            State::Opening {
+                inbound_substream: Some(_),
+                inbound: true
+            }
+        ));
+        expect_substream_request(&mut handler);
+
+        // The handshake is sent back on the inbound substream.
+        assert!(next_event(&mut handler).is_none());
+        assert_eq!(substream.take_written(), [3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn open_desired_by_remote_to_closed() {
+        let mut handler = handler();
+
+        handler.on_inbound_negotiated(0, inbound(&MockSubstream::default()));
+        expect_behaviour_event(&mut handler);
+
+        handler.on_behaviour_message(NotificationsHandlerFromBehavior::Close { index: 0 });
+        assert!(matches!(
+            handler.protocols[0].state,
+            State::Closed {
+                pending_opening: false
+            }
+        ));
+        assert!(matches!(
+            expect_behaviour_event(&mut handler),
+            NotificationsHandlerToBehavior::Close { index: 0 }
+        ));
+    }
+
+    #[test]
+    fn opening_to_open() {
+        let mut handler = handler();
+        let substream = MockSubstream::default();
+
+        handler.on_behaviour_message(NotificationsHandlerFromBehavior::Open { index: 0 });
+        expect_substream_request(&mut handler);
+
+        handler.on_outbound_negotiated(0, outbound(&substream));
+        assert!(matches!(
+            handler.protocols[0].state,
+            State::Open {
+                inbound_substream: None,
+                outbound_substream: Some(_),
+                ..
+            }
+        ));
+        assert!(matches!(
+            expect_behaviour_event(&mut handler),
+            NotificationsHandlerToBehavior::HandshakeCompleted {
+                index: 0,
+                is_inbound: false,
+                ..
+            }
+        ));
+
+        // The remote opens its substream after ours.
+        let inbound_substream = MockSubstream::default();
+        handler.on_inbound_negotiated(0, inbound(&inbound_substream));
+        assert!(matches!(
+            handler.protocols[0].state,
+            State::Open {
+                inbound_substream: Some(_),
+                ..
+            }
+        ));
+        assert!(next_event(&mut handler).is_none());
+        assert_eq!(inbound_substream.take_written(), [3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn opening_to_closed_on_dial_error() {
+        let mut handler = handler();
+
+        handler.on_behaviour_message(NotificationsHandlerFromBehavior::Open { index: 0 });
+        expect_substream_request(&mut handler);
+
+        handler.on_dial_upgrade_error(0);
+        assert!(matches!(
+            handler.protocols[0].state,
+            State::Closed {
+                pending_opening: false
+            }
+        ));
+        assert!(matches!(
+            expect_behaviour_event(&mut handler),
+            NotificationsHandlerToBehavior::HandshakeError { index: 0 }
+        ));
+        assert!(!handler.keep_alive());
+    }
+
+    #[test]
+    fn opening_to_closed_on_close() {
+        let mut handler = handler();
+
+        handler.on_behaviour_message(NotificationsHandlerFromBehavior::Open { index: 0 });
+        expect_substream_request(&mut handler);
+
+        handler.on_behaviour_message(NotificationsHandlerFromBehavior::Close { index: 0 });
+        // The outbound substream request is still in flight.
+        assert!(matches!(
+            handler.protocols[0].state,
+            State::Closed {
+                pending_opening: true
+            }
+        ));
+        assert!(matches!(
+            expect_behaviour_event(&mut handler),
+            NotificationsHandlerToBehavior::HandshakeError { index: 0 }
+        ));
+        assert!(matches!(
+            expect_behaviour_event(&mut handler),
+            NotificationsHandlerToBehavior::Close { index: 0 }
+        ));
+
+        // The in flight substream is discarded.
+        handler.on_outbound_negotiated(0, outbound(&MockSubstream::default()));
+        assert!(matches!(
+            handler.protocols[0].state,
+            State::Closed {
+                pending_opening: false
+            }
+        ));
+        assert!(next_event(&mut handler).is_none());
+    }
+
+    #[test]
+    fn open_exchanges_notifications() {
+        let mut handler = handler();
+        let (inbound_substream, outbound_substream, mut sender) = open_handler(&mut handler);
+
+        inbound_substream.push_frame(b"hello");
+        match expect_behaviour_event(&mut handler) {
+            NotificationsHandlerToBehavior::Notification { index: 0, bytes } => {
+                assert_eq!(&bytes[..], b"hello")
+            }
+            event => panic!("Expected notification, got {event:?}"),
+        }
+
+        sender.try_send(b"world".to_vec()).unwrap();
+        sender.try_send(b"!".to_vec()).unwrap();
+        assert!(matches!(
+            expect_behaviour_event(&mut handler),
+            NotificationsHandlerToBehavior::NotificationsFlushed { index: 0, count: 2 }
+        ));
+        assert!(next_event(&mut handler).is_none());
+        assert_eq!(outbound_substream.take_written(), b"\x05world\x01!");
+    }
+
+    #[test]
+    fn open_to_closed_on_close() {
+        let mut handler = handler();
+        let (_inbound, _outbound, mut sender) = open_handler(&mut handler);
+
+        handler.on_behaviour_message(NotificationsHandlerFromBehavior::Close { index: 0 });
+        assert!(matches!(
+            handler.protocols[0].state,
+            State::Closed {
+                pending_opening: false
+            }
+        ));
+        assert!(matches!(
+            expect_behaviour_event(&mut handler),
+            NotificationsHandlerToBehavior::Close { index: 0 }
+        ));
+        assert!(!handler.keep_alive());
+
+        // The receiver is dropped together with the state.
+        assert!(sender.try_send(vec![1]).is_err());
+    }
+
+    #[test]
+    fn open_close_desired_on_remote_closed() {
+        let mut handler = handler();
+        let (inbound_substream, _outbound, _sender) = open_handler(&mut handler);
+
+        inbound_substream.close();
+        assert!(matches!(
+            expect_behaviour_event(&mut handler),
+            NotificationsHandlerToBehavior::CloseDesired {
+                index: 0,
+                reason: CloseReason::RemoteClosed
+            }
+        ));
+        assert!(matches!(
+            handler.protocols[0].state,
+            State::Open {
+                inbound_substream: None,
+                ..
+            }
+        ));
+
+        handler.on_behaviour_message(NotificationsHandlerFromBehavior::Close { index: 0 });
+        assert!(matches!(handler.protocols[0].state, State::Closed { .. }));
+        assert!(matches!(
+            expect_behaviour_event(&mut handler),
+            NotificationsHandlerToBehavior::Close { index: 0 }
+        ));
+    }
+
+    #[test]
+    fn open_close_desired_on_flush_error() {
+        let mut handler = handler();
+        let (_inbound, outbound_substream, mut sender) = open_handler(&mut handler);
+
+        outbound_substream.break_writes();
+        sender.try_send(b"world".to_vec()).unwrap();
+        assert!(matches!(
+            expect_behaviour_event(&mut handler),
+            NotificationsHandlerToBehavior::CloseDesired {
+                index: 0,
+                reason: CloseReason::FlushError
+            }
+        ));
+        assert!(matches!(
+            handler.protocols[0].state,
+            State::Open {
+                outbound_substream: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn open_close_desired_on_handshake_error() {
+        let mut handler = handler();
+        let (inbound_substream, outbound_substream) =
+            (MockSubstream::default(), MockSubstream::default());
+
+        handler.on_inbound_negotiated(0, inbound(&inbound_substream));
+        expect_behaviour_event(&mut handler);
+        handler.on_behaviour_message(NotificationsHandlerFromBehavior::Open { index: 0 });
+        expect_substream_request(&mut handler);
+
+        // The outbound substream is opened before our handshake is sent back.
+        handler.on_outbound_negotiated(0, outbound(&outbound_substream));
+        expect_behaviour_event(&mut handler);
+
+        inbound_substream.break_writes();
+        assert!(matches!(
+            expect_behaviour_event(&mut handler),
+            NotificationsHandlerToBehavior::CloseDesired {
+                index: 0,
+                reason: CloseReason::HandshakeError
+            }
+        ));
+    }
+}
@@ -4,6 +4,7 @@
 
 //! Messages generated by the notification protocols over the wire.
 
+use blake2::{digest::consts::U32, Blake2b, Digest as _};
 use codec::{Decode, Encode, Input, Output};
 use hex::FromHexError;
 
@@ -48,6 +49,127 @@ impl BlockAnnouncesHandshake {
     }
 }
 
+/// Consensus engine unique ID (ie `*b"BABE"`).
+pub type ConsensusEngineId = [u8; 4];
+
+/// Compute the blake2-256 hash of the provided data.
+pub fn blake2_256(data: &[u8]) -> [u8; 32] {
+    Blake2b::<U32>::digest(data).into()
+}
+
+/// Digest item that is part of the block header.
+#[derive(Debug, PartialEq, Eq, Clone, Encode, Decode)]
+pub enum DigestItem {
+    /// A pre-runtime digest produced by the block author (ie BABE slot claims).
+    #[codec(index = 6)]
+    PreRuntime(ConsensusEngineId, Vec<u8>),
+    /// A message from the runtime to the consensus engine (ie authority set changes).
+    #[codec(index = 4)]
+    Consensus(ConsensusEngineId, Vec<u8>),
+    /// Seal of the block author, verified and removed by the consensus engine.
+    #[codec(index = 5)]
+    Seal(ConsensusEngineId, Vec<u8>),
+    /// Any other opaque digest.
+    #[codec(index = 0)]
+    Other(Vec<u8>),
+    /// The runtime code or heap pages were updated.
+    #[codec(index = 8)]
+    RuntimeEnvironmentUpdated,
+}
+
+/// Header digest.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Encode, Decode)]
+pub struct Digest {
+    /// Digest items of the header.
+    pub logs: Vec<DigestItem>,
+}
+
+/// Substrate block header.
+#[derive(Debug, PartialEq, Eq, Clone, Encode, Decode)]
+pub struct Header {
+    /// The parent hash.
+    pub parent_hash: BlockHash,
+    /// The block number.
+    #[codec(compact)]
+    pub number: BlockNumber,
+    /// The state trie merkle root.
+    pub state_root: BlockHash,
+    /// The merkle root of the extrinsics.
+    pub extrinsics_root: BlockHash,
+    /// A chain-specific digest of data useful for light clients or referencing auxiliary data.
+    pub digest: Digest,
+}
+
+impl Header {
+    /// Returns the blake2-256 hash of the scale-encoded header.
+    pub fn hash(&self) -> BlockHash {
+        BlockHash::from(blake2_256(&self.encode()))
+    }
+}
+
+/// The state of the announced block.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Encode, Decode)]
+pub enum BlockState {
+    /// The block is not the new best block.
+    Normal,
+    /// The block is the new best block.
+    Best,
+}
+
+/// Block announcement received on the `/block-announces/1` protocol.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BlockAnnounce {
+    /// The announced block header.
+    pub header: Header,
+    /// The state of the announced block.
+    ///
+    /// Older implementations do not provide this field.
+    pub state: Option<BlockState>,
+    /// Data associated with the announcement.
+    pub data: Option<Vec<u8>>,
+}
+
+impl BlockAnnounce {
+    /// Returns true if the announced block is the new best block of the remote.
+    ///
+    /// Announcements without a state are considered best blocks, similar to substrate.
+    pub fn is_best(&self) -> bool {
+        self.state.unwrap_or(BlockState::Best) == BlockState::Best
+    }
+
+    /// Returns the hash of the announced block.
+    pub fn hash(&self) -> BlockHash {
+        self.header.hash()
+    }
+}
+
+impl Encode for BlockAnnounce {
+    fn encode_to<T: Output + ?Sized>(&self, dest: &mut T) {
+        self.header.encode_to(dest);
+        if let Some(state) = &self.state {
+            state.encode_to(dest);
+        }
+        if let Some(data) = &self.data {
+            data.encode_to(dest)
+        }
+    }
+}
+
+impl Decode for BlockAnnounce {
+    fn decode<I: Input>(input: &mut I) -> Result<Self, codec::Error> {
+        let header = Header::decode(input)?;
+        // The optional fields are not prefixed by the `Option` encoding.
+        let state = BlockState::decode(input).ok();
+        let data = Vec::decode(input).ok();
+
+        Ok(Self {
+            header,
+            state,
+            data,
+        })
+    }
+}
+
 /// Protocol role representation over the wire.
 mod role_bytes {
     pub const FULL_NODE: u8 = 0b_0000_0001;
@@ -82,6 +204,15 @@ impl ProtocolRole {
             ProtocolRole::Authority => role_bytes::AUTHORITY,
         }
     }
+
+    /// Returns the name of the role.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProtocolRole::FullNode => "full",
+            ProtocolRole::LightNode => "light",
+            ProtocolRole::Authority => "authority",
+        }
+    }
 }
 
 impl Encode for ProtocolRole {
@@ -115,4 +246,59 @@ mod tests {
         assert_eq!(ProtocolRole::LightNode.encoded(), 2);
         assert_eq!(ProtocolRole::Authority.encoded(), 4);
     }
+
+    fn hash(hex: &str) -> BlockHash {
+        BlockHash::from_slice(&hex::decode(hex).unwrap())
+    }
+
+    #[test]
+    fn polkadot_genesis_hash() {
+        let header = Header {
+            parent_hash: BlockHash::zero(),
+            number: 0,
+            state_root: hash("29d0d972cd27cbc511e9589fcb7a4506d5eb6a9e8df205f00472e5ab354a4e17"),
+            extrinsics_root: hash(
+                "03170a2e7597b7b7e3d84c05391d139a62b157e78786d8c082f29dcf4c111314",
+            ),
+            digest: Digest::default(),
+        };
+
+        assert_eq!(
+            header.hash(),
+            hash("91b171bb158e2d3848fa23a9f1c25182fb8e20313b2c1eb49219da7a70ce90c3")
+        );
+    }
+
+    #[test]
+    fn block_announce_decode() {
+        let header = Header {
+            parent_hash: BlockHash::repeat_byte(1),
+            number: 100,
+            state_root: BlockHash::repeat_byte(2),
+            extrinsics_root: BlockHash::repeat_byte(3),
+            digest: Digest {
+                logs: vec![
+                    DigestItem::PreRuntime(*b"BABE", vec![1, 2]),
+                    DigestItem::Seal(*b"BABE", vec![3]),
+                ],
+            },
+        };
+        let announce = BlockAnnounce {
+            header: header.clone(),
+            state: Some(BlockState::Best),
+            data: Some(vec![4, 5]),
+        };
+
+        let decoded = BlockAnnounce::decode(&mut &announce.encode()[..]).unwrap();
+        assert_eq!(decoded, announce);
+        assert!(decoded.is_best());
+        assert_eq!(decoded.hash(), header.hash());
+
+        // Legacy announcements contain only the header.
+        let decoded = BlockAnnounce::decode(&mut &header.encode()[..]).unwrap();
+        assert_eq!(decoded.header, header);
+        assert_eq!(decoded.state, None);
+        assert_eq!(decoded.data, None);
+        assert!(decoded.is_best());
+    }
 }
@@ -3,6 +3,11 @@
 // see LICENSE for license details.
 
 pub mod behavior;
+pub mod broadcast;
+pub mod chain_state;
+pub mod grandpa;
 pub mod handler;
 pub mod messages;
+pub mod statements;
+pub mod transactions;
 pub mod upgrades;
@@ -0,0 +1,444 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Decode and submit the statements propagated on the `/statement/1` protocol.
+
+use crate::notifications::{
+    broadcast::{BroadcastReport, NotificationBroadcast},
+    messages::{blake2_256, BlockHash},
+};
+use codec::{Compact, Decode, Encode};
+use futures::channel::mpsc;
+use libp2p::{identity::ed25519, swarm::ConnectionId, PeerId};
+
+/// The maximum number of topics of a statement.
+pub const MAX_TOPICS: usize = 4;
+
+/// Statement hash.
+pub type StatementHash = BlockHash;
+/// Topic of a statement, used to query the statement store.
+pub type Topic = [u8; 32];
+/// Channel of a statement, a newer statement replaces the older one on the same channel.
+pub type Channel = [u8; 32];
+/// Key used to decrypt the data of a statement.
+pub type DecryptionKey = [u8; 32];
+/// The account that signed a statement.
+pub type AccountId = [u8; 32];
+
+/// Proof of the authenticity of a statement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode)]
+pub enum Proof {
+    /// Sr25519 signature.
+    #[codec(index = 0)]
+    Sr25519 {
+        /// The signature of the statement.
+        signature: [u8; 64],
+        /// The public key of the signer.
+        signer: [u8; 32],
+    },
+    /// Ed25519 signature.
+    #[codec(index = 1)]
+    Ed25519 {
+        /// The signature of the statement.
+        signature: [u8; 64],
+        /// The public key of the signer.
+        signer: [u8; 32],
+    },
+    /// Secp256k1 ECDSA signature.
+    #[codec(index = 2)]
+    Secp256k1Ecdsa {
+        /// The signature of the statement.
+        signature: [u8; 65],
+        /// The compressed public key of the signer.
+        signer: [u8; 33],
+    },
+    /// The statement was submitted on chain by an extrinsic.
+    #[codec(index = 3)]
+    OnChain {
+        /// The account that submitted the extrinsic.
+        who: AccountId,
+        /// The hash of the block that included the extrinsic.
+        block_hash: [u8; 32],
+        /// The index of the event that emitted the statement.
+        event_index: u64,
+    },
+}
+
+/// The result of [`Statement::verify_signature`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureVerification {
+    /// The signature is valid and was produced by the account.
+    Valid(AccountId),
+    /// The signature is invalid.
+    Invalid,
+    /// The statement is not signed, or the proof is not a signature.
+    NoSignature,
+    /// The signature scheme cannot be verified by the explorer.
+    Unsupported,
+}
+
+/// A statement of the statement store.
+///
+/// Encoded as a vector of fields, ordered by the field index.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Statement {
+    /// Proof of the authenticity of the statement.
+    pub proof: Option<Proof>,
+    /// Key used to decrypt the data.
+    pub decryption_key: Option<DecryptionKey>,
+    /// Priority of the statement, among the statements of the same account.
+    pub priority: Option<u32>,
+    /// Channel of the statement.
+    pub channel: Option<Channel>,
+    /// Topics of the statement, at most [`MAX_TOPICS`].
+    pub topics: Vec<Topic>,
+    /// The data of the statement.
+    pub data: Option<Vec<u8>>,
+}
+
+impl Statement {
+    /// Encode the fields of the statement, without the vector length.
+    fn encode_fields(&self, with_proof: bool, output: &mut Vec<u8>) {
+        if with_proof {
+            if let Some(proof) = &self.proof {
+                0u8.encode_to(output);
+                proof.encode_to(output);
+            }
+        }
+        if let Some(decryption_key) = &self.decryption_key {
+            1u8.encode_to(output);
+            decryption_key.encode_to(output);
+        }
+        if let Some(priority) = &self.priority {
+            2u8.encode_to(output);
+            priority.encode_to(output);
+        }
+        if let Some(channel) = &self.channel {
+            3u8.encode_to(output);
+            channel.encode_to(output);
+        }
+        for (index, topic) in self.topics.iter().take(MAX_TOPICS).enumerate() {
+            (4u8 + index as u8).encode_to(output);
+            topic.encode_to(output);
+        }
+        if let Some(data) = &self.data {
+            8u8.encode_to(output);
+            data.encode_to(output);
+        }
+    }
+
+    /// Returns the number of encoded fields.
+    fn num_fields(&self) -> u32 {
+        let fields = [
+            self.proof.is_some(),
+            self.decryption_key.is_some(),
+            self.priority.is_some(),
+            self.channel.is_some(),
+            self.data.is_some(),
+        ];
+        (fields.iter().filter(|present| **present).count() + self.topics.len().min(MAX_TOPICS))
+            as u32
+    }
+
+    /// Returns the message signed by the proof.
+    ///
+    /// Similar to substrate, these are the encoded fields without the proof and
+    /// without the length prefix.
+    pub fn signature_material(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        self.encode_fields(false, &mut output);
+        output
+    }
+
+    /// Returns the blake2-256 hash of the scale-encoded statement.
+    pub fn hash(&self) -> StatementHash {
+        StatementHash::from(blake2_256(&self.encode()))
+    }
+
+    /// Verify the signature of the statement.
+    pub fn verify_signature(&self) -> SignatureVerification {
+        match &self.proof {
+            Some(Proof::Sr25519 { signature, signer }) => {
+                let valid = sr25519_verify(signature, &self.signature_material(), signer);
+                verification(valid, *signer)
+            }
+            Some(Proof::Ed25519 { signature, signer }) => {
+                let valid = ed25519::PublicKey::try_from_bytes(signer)
+                    .is_ok_and(|public| public.verify(&self.signature_material(), signature));
+                verification(valid, *signer)
+            }
+            Some(Proof::Secp256k1Ecdsa { .. }) => SignatureVerification::Unsupported,
+            Some(Proof::OnChain { .. }) | None => SignatureVerification::NoSignature,
+        }
+    }
+}
+
+fn verification(valid: bool, signer: AccountId) -> SignatureVerification {
+    if valid {
+        SignatureVerification::Valid(signer)
+    } else {
+        SignatureVerification::Invalid
+    }
+}
+
+/// Verify a sr25519 signature produced in the substrate signing context.
+fn sr25519_verify(signature: &[u8; 64], message: &[u8], signer: &[u8; 32]) -> bool {
+    const SIGNING_CTX: &[u8] = b"substrate";
+
+    let Ok(signature) = schnorrkel::Signature::from_bytes(signature) else {
+        return false;
+    };
+    let Ok(public) = schnorrkel::PublicKey::from_bytes(signer) else {
+        return false;
+    };
+    public
+        .verify_simple(SIGNING_CTX, message, &signature)
+        .is_ok()
+}
+
+impl Encode for Statement {
+    fn encode_to<T: codec::Output + ?Sized>(&self, dest: &mut T) {
+        let mut output = Compact(self.num_fields()).encode();
+        self.encode_fields(true, &mut output);
+        dest.write(&output);
+    }
+}
+
+impl Decode for Statement {
+    fn decode<I: codec::Input>(input: &mut I) -> Result<Self, codec::Error> {
+        let num_fields = Compact::<u32>::decode(input)?.0;
+        let mut statement = Statement::default();
+        let mut last_index = None;
+
+        for _ in 0..num_fields {
+            let index = u8::decode(input)?;
+            // Fields are unique and sorted by index.
+            if last_index.is_some_and(|last| index <= last) {
+                return Err("Invalid field order or duplicate fields".into());
+            }
+            last_index = Some(index);
+
+            match index {
+                0 => statement.proof = Some(Proof::decode(input)?),
+                1 => statement.decryption_key = Some(Decode::decode(input)?),
+                2 => statement.priority = Some(Decode::decode(input)?),
+                3 => statement.channel = Some(Decode::decode(input)?),
+                4..=7 => {
+                    if statement.topics.len() != (index - 4) as usize {
+                        return Err("Unexpected topic".into());
+                    }
+                    statement.topics.push(Decode::decode(input)?);
+                }
+                8 => statement.data = Some(Decode::decode(input)?),
+                _ => return Err("Unknown statement field".into()),
+            }
+        }
+
+        Ok(statement)
+    }
+}
+
+/// Decode the notification received on the statement protocol.
+pub fn decode_statements(mut message: &[u8]) -> Result<Vec<Statement>, codec::Error> {
+    Vec::<Statement>::decode(&mut message)
+}
+
+/// Broadcast statements to every peer with an open statement protocol.
+///
+/// The statements are submitted as soon as the protocol is opened. A peer is reached
+/// once the notification has been flushed on its substream.
+#[derive(Debug)]
+pub struct StatementBroadcaster {
+    /// The delivery of the scale-encoded vector of statements.
+    broadcast: NotificationBroadcast,
+}
+
+impl StatementBroadcaster {
+    /// Constructs a new [`StatementBroadcaster`].
+    ///
+    /// The payload must be a scale-encoded vector of statements.
+    pub fn new(payload: Vec<u8>, target_peers: usize) -> Result<Self, codec::Error> {
+        let hashes: Vec<_> = decode_statements(&payload)?
+            .iter()
+            .map(Statement::hash)
+            .collect();
+
+        Ok(StatementBroadcaster {
+            broadcast: NotificationBroadcast::new(payload, hashes, target_peers),
+        })
+    }
+
+    /// Handle the statement protocol being opened with the peer.
+    ///
+    /// Returns true if the statements were submitted to the peer.
+    pub fn on_protocol_open(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        sender: &mut mpsc::Sender<Vec<u8>>,
+    ) -> bool {
+        self.broadcast
+            .on_protocol_open(peer_id, connection_id, sender)
+    }
+
+    /// Handle the statement protocol being closed with the peer.
+    pub fn on_protocol_closed(&mut self, peer_id: PeerId, connection_id: ConnectionId) {
+        self.broadcast.on_protocol_closed(peer_id, connection_id);
+    }
+
+    /// Handle notifications flushed on the statement protocol.
+    ///
+    /// Returns true if the peer has been reached for the first time.
+    pub fn on_flushed(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        count: usize,
+    ) -> bool {
+        self.broadcast.on_flushed(peer_id, connection_id, count)
+    }
+
+    /// Handle the statements received from the peer.
+    ///
+    /// Returns the hashes of our statements relayed back by the peer.
+    pub fn on_statements(
+        &mut self,
+        peer_id: PeerId,
+        statements: &[Statement],
+    ) -> Vec<StatementHash> {
+        self.broadcast
+            .on_echo(peer_id, statements.iter().map(Statement::hash))
+    }
+
+    /// Returns the number of peers that received the statements.
+    pub fn reached(&self) -> usize {
+        self.broadcast.reached()
+    }
+
+    /// Returns true if the target number of peers has been reached.
+    pub fn is_complete(&self) -> bool {
+        self.broadcast.is_complete()
+    }
+
+    /// Returns the report of the broadcast.
+    pub fn report(&self) -> BroadcastReport {
+        self.broadcast.report()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_statement_fields_in_order() {
+        let statement = Statement {
+            proof: Some(Proof::OnChain {
+                who: [1; 32],
+                block_hash: [2; 32],
+                event_index: 3,
+            }),
+            priority: Some(5),
+            topics: vec![[6; 32], [7; 32]],
+            data: Some(vec![8, 9]),
+            ..Default::default()
+        };
+
+        let encoded = statement.encode();
+        // Five fields, starting with the proof.
+        assert_eq!(&encoded[..3], &[5 << 2, 0, 3]);
+        assert_eq!(Statement::decode(&mut &encoded[..]).unwrap(), statement);
+        assert_eq!(
+            decode_statements(&vec![statement.clone()].encode()).unwrap(),
+            vec![statement.clone()]
+        );
+
+        // The signature material omits the length prefix and the proof.
+        let material = statement.signature_material();
+        assert_eq!(&material[..5], &[2, 5, 0, 0, 0]);
+        assert_eq!(material.len(), encoded.len() - 1 - 1 - 73);
+
+        // Duplicate or unordered fields are rejected.
+        let duplicate = [vec![2 << 2], (2u8, 5u32).encode(), (2u8, 5u32).encode()].concat();
+        assert!(Statement::decode(&mut &duplicate[..]).is_err());
+        let topic = [vec![1 << 2], (5u8, [6u8; 32]).encode()].concat();
+        assert!(Statement::decode(&mut &topic[..]).is_err());
+    }
+
+    #[test]
+    fn verify_statement_signatures() {
+        let mut statement = Statement {
+            channel: Some([1; 32]),
+            data: Some(vec![1, 2, 3]),
+            ..Default::default()
+        };
+        assert_eq!(
+            statement.verify_signature(),
+            SignatureVerification::NoSignature
+        );
+
+        let keypair = ed25519::Keypair::generate();
+        let signer = keypair.public().to_bytes();
+        statement.proof = Some(Proof::Ed25519 {
+            signature: keypair
+                .sign(&statement.signature_material())
+                .try_into()
+                .unwrap(),
+            signer,
+        });
+        assert_eq!(
+            statement.verify_signature(),
+            SignatureVerification::Valid(signer)
+        );
+
+        let keypair = schnorrkel::MiniSecretKey::from_bytes(&[1; 32])
+            .unwrap()
+            .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519);
+        let signer = keypair.public.to_bytes();
+        let signature = keypair
+            .sign_simple(b"substrate", &statement.signature_material())
+            .to_bytes();
+        statement.proof = Some(Proof::Sr25519 { signature, signer });
+        assert_eq!(
+            statement.verify_signature(),
+            SignatureVerification::Valid(signer)
+        );
+
+        // The signature covers the fields of the statement.
+        statement.data = Some(vec![4]);
+        assert_eq!(statement.verify_signature(), SignatureVerification::Invalid);
+    }
+
+    #[test]
+    fn broadcast_statements() {
+        let statement = Statement {
+            data: Some(vec![1]),
+            ..Default::default()
+        };
+        let payload = vec![statement.clone()].encode();
+        let mut broadcaster = StatementBroadcaster::new(payload.clone(), 1).unwrap();
+        assert!(StatementBroadcaster::new(vec![4, 9], 1).is_err());
+
+        let peer_id = PeerId::random();
+        let connection = ConnectionId::new_unchecked(0);
+        let (mut sender, mut receiver) = mpsc::channel(4);
+        assert!(broadcaster.on_protocol_open(peer_id, connection, &mut sender));
+        assert_eq!(receiver.try_next().unwrap(), Some(payload));
+        assert!(broadcaster.on_flushed(peer_id, connection, 1));
+        assert!(broadcaster.is_complete());
+
+        let other = Statement {
+            data: Some(vec![2]),
+            ..Default::default()
+        };
+        assert_eq!(
+            broadcaster.on_statements(peer_id, &[statement.clone(), other]),
+            vec![statement.hash()]
+        );
+        assert_eq!(
+            broadcaster.report().echoes,
+            vec![(statement.hash(), vec![peer_id])]
+        );
+    }
+}
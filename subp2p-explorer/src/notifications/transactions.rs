@@ -0,0 +1,602 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Decode and track the transactions propagated on the `/transactions/1` protocol.
+
+pub use crate::notifications::broadcast::BroadcastReport;
+
+use crate::{
+    notifications::{
+        broadcast::NotificationBroadcast,
+        messages::{blake2_256, BlockAnnounce, BlockHash, BlockNumber},
+    },
+    requests::block::{BlockAttributes, BlockData, BlockRequest, Direction, FromBlock},
+};
+use codec::{Decode, Encode};
+use futures::channel::mpsc;
+use libp2p::{swarm::ConnectionId, PeerId};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+const LOG_TARGET: &str = "subp2p-transactions";
+
+/// The maximum number of blocks requested at once by the [`InclusionWatcher`].
+const MAX_BLOCKS_PER_REQUEST: BlockNumber = 16;
+
+/// Transaction hash.
+pub type TransactionHash = BlockHash;
+
+/// Opaque scale-encoded extrinsic.
+#[derive(Debug, PartialEq, Eq, Clone, Encode, Decode)]
+pub struct Extrinsic(pub Vec<u8>);
+
+impl Extrinsic {
+    /// Returns the blake2-256 hash of the extrinsic.
+    ///
+    /// Similar to substrate, the hash includes the length prefix of the extrinsic.
+    pub fn hash(&self) -> TransactionHash {
+        TransactionHash::from(blake2_256(&self.encode()))
+    }
+}
+
+/// Decode the notification received on the transactions protocol.
+pub fn decode_transactions(mut message: &[u8]) -> Result<Vec<Extrinsic>, codec::Error> {
+    Vec::<Extrinsic>::decode(&mut message)
+}
+
+/// Details about the propagation of a single transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionRecord {
+    /// The peer that relayed the transaction first.
+    pub first_peer: PeerId,
+    /// The moment the transaction was seen for the first time.
+    pub first_seen: Instant,
+    /// Peers that relayed the transaction afterwards, together with the
+    /// latency from the moment the transaction was first seen.
+    pub relays: Vec<(PeerId, Duration)>,
+}
+
+/// Events produced by the [`TransactionTracker`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionEvent {
+    /// The transaction has been seen for the first time.
+    FirstSeen {
+        /// The hash of the transaction.
+        hash: TransactionHash,
+        /// The peer that relayed the transaction.
+        peer_id: PeerId,
+    },
+    /// The transaction has been relayed again by a different peer.
+    Relayed {
+        /// The hash of the transaction.
+        hash: TransactionHash,
+        /// The peer that relayed the transaction.
+        peer_id: PeerId,
+        /// Latency from the moment the transaction was first seen.
+        latency: Duration,
+    },
+}
+
+/// Track which peers relayed each transaction and when.
+#[derive(Debug, Default)]
+pub struct TransactionTracker {
+    /// Transactions seen on the network.
+    transactions: HashMap<TransactionHash, TransactionRecord>,
+}
+
+impl TransactionTracker {
+    /// Constructs a new [`TransactionTracker`].
+    pub fn new() -> Self {
+        TransactionTracker::default()
+    }
+
+    /// Handle a notification received on the transactions protocol.
+    pub fn on_transactions(
+        &mut self,
+        peer_id: PeerId,
+        message: &[u8],
+    ) -> Result<Vec<TransactionEvent>, codec::Error> {
+        let now = Instant::now();
+
+        let events = decode_transactions(message)?
+            .into_iter()
+            .filter_map(|extrinsic| self.on_extrinsic(peer_id, extrinsic.hash(), now))
+            .collect();
+
+        Ok(events)
+    }
+
+    /// Record a single extrinsic relayed by the peer.
+    fn on_extrinsic(
+        &mut self,
+        peer_id: PeerId,
+        hash: TransactionHash,
+        now: Instant,
+    ) -> Option<TransactionEvent> {
+        let Some(record) = self.transactions.get_mut(&hash) else {
+            log::trace!(target: LOG_TARGET, "Transaction first seen hash={:?} peer={:?}", hash, peer_id);
+
+            self.transactions.insert(
+                hash,
+                TransactionRecord {
+                    first_peer: peer_id,
+                    first_seen: now,
+                    relays: Vec::new(),
+                },
+            );
+            return Some(TransactionEvent::FirstSeen { hash, peer_id });
+        };
+
+        // Peers may relay the same transaction multiple times.
+        if record.first_peer == peer_id || record.relays.iter().any(|(peer, _)| *peer == peer_id) {
+            return None;
+        }
+
+        let latency = now.saturating_duration_since(record.first_seen);
+        record.relays.push((peer_id, latency));
+
+        Some(TransactionEvent::Relayed {
+            hash,
+            peer_id,
+            latency,
+        })
+    }
+
+    /// Returns the propagation details of the given transaction.
+    pub fn transaction(&self, hash: &TransactionHash) -> Option<&TransactionRecord> {
+        self.transactions.get(hash)
+    }
+
+    /// Returns the number of tracked transactions.
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    /// Returns true if no transactions are tracked.
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+
+    /// Forget the transactions first seen more than `max_age` ago.
+    pub fn prune(&mut self, max_age: Duration) {
+        let now = Instant::now();
+        self.transactions
+            .retain(|_, record| now.saturating_duration_since(record.first_seen) < max_age);
+    }
+}
+
+/// Broadcast extrinsics to every peer with an open transactions protocol.
+///
+/// The extrinsics are submitted as soon as the protocol is opened. A peer is reached
+/// once the notification has been flushed on its substream.
+#[derive(Debug)]
+pub struct TransactionBroadcaster {
+    /// The delivery of the scale-encoded vector of extrinsics.
+    broadcast: NotificationBroadcast,
+}
+
+impl TransactionBroadcaster {
+    /// Constructs a new [`TransactionBroadcaster`].
+    ///
+    /// The payload must be a scale-encoded vector of extrinsics.
+    pub fn new(payload: Vec<u8>, target_peers: usize) -> Result<Self, codec::Error> {
+        let hashes: Vec<_> = decode_transactions(&payload)?
+            .iter()
+            .map(Extrinsic::hash)
+            .collect();
+
+        Ok(TransactionBroadcaster {
+            broadcast: NotificationBroadcast::new(payload, hashes, target_peers),
+        })
+    }
+
+    /// Handle the transactions protocol being opened with the peer.
+    ///
+    /// Returns true if the extrinsics were submitted to the peer.
+    pub fn on_protocol_open(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        sender: &mut mpsc::Sender<Vec<u8>>,
+    ) -> bool {
+        self.broadcast
+            .on_protocol_open(peer_id, connection_id, sender)
+    }
+
+    /// Handle the transactions protocol being closed with the peer.
+    pub fn on_protocol_closed(&mut self, peer_id: PeerId, connection_id: ConnectionId) {
+        self.broadcast.on_protocol_closed(peer_id, connection_id);
+    }
+
+    /// Handle notifications flushed on the transactions protocol.
+    ///
+    /// Returns true if the peer has been reached for the first time.
+    pub fn on_flushed(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        count: usize,
+    ) -> bool {
+        self.broadcast.on_flushed(peer_id, connection_id, count)
+    }
+
+    /// Handle a notification received on the transactions protocol.
+    ///
+    /// Returns the hashes of our extrinsics relayed back by the peer.
+    pub fn on_transactions(
+        &mut self,
+        peer_id: PeerId,
+        message: &[u8],
+    ) -> Result<Vec<TransactionHash>, codec::Error> {
+        let hashes = decode_transactions(message)?
+            .iter()
+            .map(Extrinsic::hash)
+            .collect::<Vec<_>>();
+
+        Ok(self.broadcast.on_echo(peer_id, hashes))
+    }
+
+    /// Returns the number of peers that received the extrinsics.
+    pub fn reached(&self) -> usize {
+        self.broadcast.reached()
+    }
+
+    /// Returns true if the target number of peers has been reached.
+    pub fn is_complete(&self) -> bool {
+        self.broadcast.is_complete()
+    }
+
+    /// Returns the report of the broadcast.
+    pub fn report(&self) -> BroadcastReport {
+        self.broadcast.report()
+    }
+}
+
+/// The block that included an extrinsic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Inclusion {
+    /// The hash of the extrinsic.
+    pub hash: TransactionHash,
+    /// The number of the block.
+    pub block_number: BlockNumber,
+    /// The hash of the block.
+    pub block_hash: BlockHash,
+}
+
+/// Confirm the inclusion of extrinsics from the p2p network.
+///
+/// The bodies of the announced blocks are requested on the block request protocol
+/// and searched for the hashes of the extrinsics.
+#[derive(Debug)]
+pub struct InclusionWatcher {
+    /// The hashes of the extrinsics not yet included.
+    pending: HashSet<TransactionHash>,
+    /// The extrinsics included so far.
+    included: Vec<Inclusion>,
+    /// Blocks that are requested or already searched.
+    requested: HashSet<BlockHash>,
+    /// The highest block number searched.
+    highest_searched: Option<BlockNumber>,
+}
+
+impl InclusionWatcher {
+    /// Constructs a new [`InclusionWatcher`].
+    ///
+    /// The payload must be a scale-encoded vector of extrinsics.
+    pub fn new(payload: &[u8]) -> Result<Self, codec::Error> {
+        let pending = decode_transactions(payload)?
+            .into_iter()
+            .map(|extrinsic| extrinsic.hash())
+            .collect();
+
+        Ok(InclusionWatcher {
+            pending,
+            included: Vec::new(),
+            requested: HashSet::new(),
+            highest_searched: None,
+        })
+    }
+
+    /// Handle a block announcement.
+    ///
+    /// Returns the request of the announced block, including the blocks skipped
+    /// since the highest searched block.
+    pub fn on_block_announce(&mut self, announce: &BlockAnnounce) -> Option<BlockRequest> {
+        if self.is_complete() {
+            return None;
+        }
+
+        let hash = announce.hash();
+        if !self.requested.insert(hash) {
+            return None;
+        }
+
+        let number = announce.header.number;
+        let max_blocks = self
+            .highest_searched
+            .map_or(1, |highest| number.saturating_sub(highest))
+            .clamp(1, MAX_BLOCKS_PER_REQUEST);
+
+        Some(BlockRequest {
+            fields: BlockAttributes::HEADER | BlockAttributes::BODY,
+            from: FromBlock::Hash(hash),
+            direction: Direction::Descending,
+            max_blocks,
+        })
+    }
+
+    /// Handle a failed request of the given block.
+    ///
+    /// The block is requested again on the next announcement.
+    pub fn on_request_failed(&mut self, block_hash: &BlockHash) {
+        self.requested.remove(block_hash);
+    }
+
+    /// Search the blocks received in response to a block request.
+    ///
+    /// Blocks whose header or body do not match the block hash are ignored.
+    /// Returns the extrinsics included in the blocks.
+    pub fn on_blocks(&mut self, blocks: &[BlockData]) -> Vec<Inclusion> {
+        let mut included = Vec::new();
+
+        for block in blocks {
+            if !block.is_valid() {
+                continue;
+            }
+            let (Some(header), Some(body)) = (&block.header, &block.body) else {
+                continue;
+            };
+
+            self.requested.insert(block.hash);
+            self.highest_searched = self.highest_searched.max(Some(header.number));
+
+            for extrinsic in body {
+                let hash = extrinsic.hash();
+                if self.pending.remove(&hash) {
+                    included.push(Inclusion {
+                        hash,
+                        block_number: header.number,
+                        block_hash: block.hash,
+                    });
+                }
+            }
+        }
+
+        self.included.extend_from_slice(&included);
+        included
+    }
+
+    /// Returns true if all the extrinsics are included.
+    pub fn is_complete(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Returns the extrinsics included so far.
+    pub fn included(&self) -> &[Inclusion] {
+        &self.included
+    }
+
+    /// Returns the hashes of the extrinsics not yet included.
+    pub fn pending(&self) -> impl Iterator<Item = &TransactionHash> {
+        self.pending.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        notifications::messages::{Digest, Header},
+        trie::{ordered_trie_root, StateVersion},
+    };
+
+    #[test]
+    fn decode_and_hash_transactions() {
+        let extrinsics = vec![Extrinsic(vec![1, 2, 3]), Extrinsic(vec![4])];
+        let message = extrinsics.encode();
+        assert_eq!(message, vec![8, 12, 1, 2, 3, 4, 4]);
+
+        let decoded = decode_transactions(&message).unwrap();
+        assert_eq!(decoded, extrinsics);
+        assert_eq!(
+            decoded[0].hash(),
+            TransactionHash::from(blake2_256(&[12, 1, 2, 3]))
+        );
+
+        assert!(decode_transactions(&[8, 12, 1]).is_err());
+    }
+
+    #[test]
+    fn track_first_seen_and_relays() {
+        let mut tracker = TransactionTracker::new();
+        let (first, second) = (PeerId::random(), PeerId::random());
+        let extrinsic = Extrinsic(vec![1, 2, 3]);
+        let message = vec![extrinsic.clone()].encode();
+        let hash = extrinsic.hash();
+
+        assert_eq!(
+            tracker.on_transactions(first, &message).unwrap(),
+            vec![TransactionEvent::FirstSeen {
+                hash,
+                peer_id: first
+            }]
+        );
+        // Duplicates of the same peer are ignored.
+        assert!(tracker.on_transactions(first, &message).unwrap().is_empty());
+
+        let events = tracker.on_transactions(second, &message).unwrap();
+        assert!(matches!(
+            events[..],
+            [TransactionEvent::Relayed { peer_id, .. }] if peer_id == second
+        ));
+        assert!(tracker
+            .on_transactions(second, &message)
+            .unwrap()
+            .is_empty());
+
+        let record = tracker.transaction(&hash).unwrap();
+        assert_eq!(record.first_peer, first);
+        assert_eq!(record.relays.len(), 1);
+
+        tracker.prune(Duration::from_secs(60));
+        assert_eq!(tracker.len(), 1);
+        tracker.prune(Duration::ZERO);
+        assert!(tracker.is_empty());
+    }
+
+    #[test]
+    fn broadcast_until_target_reached() {
+        let extrinsic = Extrinsic(vec![1, 2, 3]);
+        let payload = vec![extrinsic.clone()].encode();
+        let mut broadcaster = TransactionBroadcaster::new(payload.clone(), 2).unwrap();
+        assert!(TransactionBroadcaster::new(vec![8, 12, 1], 2).is_err());
+
+        let (first, second) = (PeerId::random(), PeerId::random());
+        let (connection, other_connection) = (
+            ConnectionId::new_unchecked(0),
+            ConnectionId::new_unchecked(1),
+        );
+        let (mut sender, mut receiver) = mpsc::channel(4);
+
+        assert!(broadcaster.on_protocol_open(first, connection, &mut sender));
+        assert_eq!(receiver.try_next().unwrap(), Some(payload.clone()));
+        // The payload is submitted once per peer.
+        assert!(!broadcaster.on_protocol_open(first, other_connection, &mut sender));
+        assert!(receiver.try_next().is_err());
+
+        // Not reached until the notification is flushed.
+        assert_eq!(broadcaster.reached(), 0);
+        assert!(broadcaster.on_flushed(first, connection, 1));
+        assert!(!broadcaster.on_flushed(first, connection, 1));
+        assert_eq!(broadcaster.reached(), 1);
+        assert!(!broadcaster.is_complete());
+
+        // Closed before flushing, the payload is submitted again on a new connection.
+        assert!(broadcaster.on_protocol_open(second, connection, &mut sender));
+        broadcaster.on_protocol_closed(second, connection);
+        assert!(broadcaster.on_protocol_open(second, other_connection, &mut sender));
+        assert!(broadcaster.on_flushed(second, other_connection, 1));
+        assert!(broadcaster.is_complete());
+
+        // Only our extrinsics are reported as echoed.
+        let message = vec![extrinsic.clone(), Extrinsic(vec![4])].encode();
+        assert_eq!(
+            broadcaster.on_transactions(second, &message).unwrap(),
+            vec![extrinsic.hash()]
+        );
+        assert!(broadcaster
+            .on_transactions(second, &message)
+            .unwrap()
+            .is_empty());
+
+        let mut report = broadcaster.report();
+        report.reached.sort();
+        let mut expected = vec![(first, 1), (second, 1)];
+        expected.sort();
+        assert_eq!(report.reached, expected);
+        assert_eq!(report.echoes, vec![(extrinsic.hash(), vec![second])]);
+    }
+
+    /// Construct a header whose extrinsics root matches the provided body.
+    fn header(number: BlockNumber, body: &[Extrinsic]) -> Header {
+        Header {
+            parent_hash: BlockHash::repeat_byte(number as u8),
+            number,
+            state_root: BlockHash::zero(),
+            extrinsics_root: ordered_trie_root(body.iter().map(Encode::encode), StateVersion::V0),
+            digest: Digest::default(),
+        }
+    }
+
+    #[test]
+    fn confirm_inclusion_from_block_bodies() {
+        let extrinsic = Extrinsic(vec![1, 2, 3]);
+        let mut watcher = InclusionWatcher::new(&vec![extrinsic.clone()].encode()).unwrap();
+
+        let body = |number| match number {
+            10 => vec![Extrinsic(vec![4])],
+            12 => vec![extrinsic.clone()],
+            _ => vec![],
+        };
+        let header = |number| header(number, &body(number));
+        let announce = |number| BlockAnnounce {
+            header: header(number),
+            state: None,
+            data: None,
+        };
+
+        let request = watcher.on_block_announce(&announce(10)).unwrap();
+        assert_eq!(request.from, FromBlock::Hash(announce(10).hash()));
+        assert_eq!(request.max_blocks, 1);
+        // Announced blocks are requested once.
+        assert!(watcher.on_block_announce(&announce(10)).is_none());
+
+        let block = |number| BlockData {
+            hash: header(number).hash(),
+            header: Some(header(number)),
+            body: Some(body(number)),
+            justifications: None,
+        };
+        assert!(watcher.on_blocks(&[block(10)]).is_empty());
+
+        // Skipped blocks are requested together with the announced block.
+        let request = watcher.on_block_announce(&announce(13)).unwrap();
+        assert_eq!(request.max_blocks, 3);
+        watcher.on_request_failed(&announce(13).hash());
+        assert!(watcher.on_block_announce(&announce(13)).is_some());
+
+        let included = watcher.on_blocks(&[block(13), block(12)]);
+        assert_eq!(
+            included,
+            vec![Inclusion {
+                hash: extrinsic.hash(),
+                block_number: 12,
+                block_hash: header(12).hash(),
+            }]
+        );
+        assert!(watcher.is_complete());
+        assert_eq!(watcher.included(), &included[..]);
+        assert!(watcher.on_block_announce(&announce(14)).is_none());
+    }
+
+    #[test]
+    fn reject_blocks_not_matching_their_hash() {
+        let extrinsic = Extrinsic(vec![1, 2, 3]);
+        let mut watcher = InclusionWatcher::new(&vec![extrinsic.clone()].encode()).unwrap();
+
+        // The header does not hash to the block hash.
+        let valid = header(10, std::slice::from_ref(&extrinsic));
+        let block = BlockData {
+            hash: BlockHash::repeat_byte(1),
+            header: Some(valid.clone()),
+            body: Some(vec![extrinsic.clone()]),
+            justifications: None,
+        };
+        assert!(!block.is_valid());
+        assert!(watcher.on_blocks(&[block]).is_empty());
+
+        // The body does not match the extrinsics root of the header.
+        let forged = header(10, &[]);
+        let block = BlockData {
+            hash: forged.hash(),
+            header: Some(forged),
+            body: Some(vec![extrinsic.clone()]),
+            justifications: None,
+        };
+        assert!(!block.is_valid());
+        assert!(watcher.on_blocks(&[block]).is_empty());
+        assert!(!watcher.is_complete());
+
+        let block = BlockData {
+            hash: valid.hash(),
+            header: Some(valid),
+            body: Some(vec![extrinsic]),
+            justifications: None,
+        };
+        assert!(block.is_valid());
+        assert_eq!(watcher.on_blocks(&[block]).len(), 1);
+    }
+}
@@ -47,6 +47,10 @@ const MAX_HANDSHAKE_SIZE: usize = 1024;
 pub struct HandshakeInbound {
     /// Protocol name.
     pub name: String,
+    /// Legacy protocol names, negotiated when the remote does not support `name`.
+    pub fallback_names: Vec<String>,
+    /// Maximum allowed size of the notifications received on the substream.
+    pub max_notification_size: u64,
 }
 
 /// A substream for incoming notification messages.
@@ -84,6 +88,8 @@ pub enum HandshakeInboundSubstreamState {
 pub struct HandshakeOutbound {
     /// Protocol name.
     pub name: String,
+    /// Legacy protocol names, negotiated when the remote does not support `name`.
+    pub fallback_names: Vec<String>,
     /// Handshake message.
     pub handshake: Vec<u8>,
 }
@@ -98,8 +104,18 @@ pub struct HandshakeOutboundSubstream<TSubstream> {
 
 impl HandshakeInbound {
     /// Constructs a new [`HandshakeInbound`].
-    pub fn new(name: impl Into<String>) -> Self {
-        Self { name: name.into() }
+    pub fn new(name: impl Into<String>, max_notification_size: u64) -> Self {
+        Self {
+            name: name.into(),
+            fallback_names: Vec::new(),
+            max_notification_size,
+        }
+    }
+
+    /// Set the fallback names of the protocol.
+    pub fn with_fallback_names(mut self, fallback_names: Vec<String>) -> Self {
+        self.fallback_names = fallback_names;
+        self
     }
 }
 
@@ -108,7 +124,12 @@ impl UpgradeInfo for HandshakeInbound {
     type InfoIter = vec::IntoIter<Self::Info>;
 
     fn protocol_info(&self) -> Self::InfoIter {
-        vec![self.name.clone()].into_iter()
+        // The main name is preferred during the negotiation.
+        std::iter::once(&self.name)
+            .chain(&self.fallback_names)
+            .cloned()
+            .collect::<Vec<_>>()
+            .into_iter()
     }
 }
 
@@ -159,17 +180,18 @@ where
             );
 
             let mut codec: UviBytes<io::Cursor<Vec<u8>>> = UviBytes::default();
-            codec.set_max_len(usize::MAX);
+            codec.set_max_len(usize::try_from(self.max_notification_size).unwrap_or(usize::MAX));
 
             // Create a handshake substream that waits the handshake from the higher level.
             let substream = HandshakeInboundSubstream {
                 socket: Framed::new(socket, codec),
                 state: HandshakeInboundSubstreamState::Waiting,
-                negotiated_name,
+                negotiated_name: negotiated_name.clone(),
             };
 
             Ok(HandshakeInboundOpen {
                 handshake,
+                negotiated_name,
                 substream,
             })
         })
@@ -180,6 +202,8 @@ where
 pub struct HandshakeInboundOpen<TSubstream> {
     /// Handshake sent by the remote.
     pub handshake: Vec<u8>,
+    /// The protocol name negotiated with the remote.
+    pub negotiated_name: String,
     /// Implementation of `Stream` receives messages from the substream.
     pub substream: HandshakeInboundSubstream<TSubstream>,
 }
@@ -197,6 +221,16 @@ where
         self.state = HandshakeInboundSubstreamState::Sending(handshake);
     }
 
+    /// Returns true if the handshake has been sent back to the remote.
+    pub fn is_handshake_sent(&self) -> bool {
+        matches!(
+            self.state,
+            HandshakeInboundSubstreamState::Done
+                | HandshakeInboundSubstreamState::NeedsClose
+                | HandshakeInboundSubstreamState::FullyClosed
+        )
+    }
+
     /// Similar to [`poll_next`] without event generation.
     ///
     /// Returns `Poll::Ready` only for errors.
@@ -365,9 +399,16 @@ impl HandshakeOutbound {
     pub fn new(name: impl Into<String>, handshake: impl Into<Vec<u8>>) -> Self {
         Self {
             name: name.into(),
+            fallback_names: Vec::new(),
             handshake: handshake.into(),
         }
     }
+
+    /// Set the fallback names of the protocol.
+    pub fn with_fallback_names(mut self, fallback_names: Vec<String>) -> Self {
+        self.fallback_names = fallback_names;
+        self
+    }
 }
 
 impl UpgradeInfo for HandshakeOutbound {
@@ -375,7 +416,12 @@ impl UpgradeInfo for HandshakeOutbound {
     type InfoIter = vec::IntoIter<Self::Info>;
 
     fn protocol_info(&self) -> Self::InfoIter {
-        vec![self.name.clone()].into_iter()
+        // The main name is preferred during the negotiation.
+        std::iter::once(&self.name)
+            .chain(&self.fallback_names)
+            .cloned()
+            .collect::<Vec<_>>()
+            .into_iter()
     }
 }
 
@@ -437,6 +483,7 @@ where
 
             Ok(HandshakeOutboundOpen {
                 handshake,
+                negotiated_name,
                 substream: HandshakeOutboundSubstream {
                     socket: Framed::new(socket, codec),
                 },
@@ -449,6 +496,8 @@ where
 pub struct HandshakeOutboundOpen<TSubstream> {
     /// Handshake returned by the remote.
     pub handshake: Vec<u8>,
+    /// The protocol name negotiated with the remote.
+    pub negotiated_name: String,
     /// Implementation of `Sink` that allows sending messages on the substream.
     pub substream: HandshakeOutboundSubstream<TSubstream>,
 }
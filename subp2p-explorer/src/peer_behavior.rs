@@ -292,11 +292,9 @@ impl NetworkBehaviour for PeerBehaviour {
                 self.identify.on_swarm_event(FromSwarm::AddressChange(e));
 
                 self.details.entry(peer_id).and_modify(|details| {
-                    details
-                        .connections
-                        .iter_mut()
-                        .find(|conn| conn == &old)
-                        .map(|conn| *conn = new.clone());
+                    if let Some(conn) = details.connections.iter_mut().find(|conn| conn == &old) {
+                        *conn = new.clone();
+                    }
                 });
             }
             FromSwarm::NewListenAddr(e) => {
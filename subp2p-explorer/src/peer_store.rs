@@ -0,0 +1,519 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Persistent store of the peers observed on the network.
+//!
+//! The store records when a peer was first observed, discovered and reached, its addresses, the
+//! identify details, the role and the outcome of the dials. Stores are pluggable
+//! through the [`PeerStore`] trait. The [`JsonLinesPeerStore`] keeps one record
+//! per peer in a JSON-lines file, which is loaded on the next run to seed the
+//! crawler and to track the churn of the network over time.
+
+use crate::notifications::messages::ProtocolRole;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const LOG_TARGET: &str = "subp2p-peer-store";
+
+/// Errors encountered by the peer stores.
+#[derive(Debug, thiserror::Error)]
+pub enum PeerStoreError {
+    /// Reading or writing the store failed.
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    /// An entry of the store is not valid JSON.
+    #[error("Invalid entry: {0}")]
+    Json(#[from] serde_json::Error),
+    /// A field of an entry has an unexpected format.
+    #[error("Invalid `{0}` field")]
+    InvalidField(&'static str),
+}
+
+/// The outcome of dialing a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DialOutcome {
+    /// The connection was established.
+    Success,
+    /// The dial failed with the provided error.
+    Failure(String),
+}
+
+/// An observation of a peer on the network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerObservation {
+    /// The peer was discovered with the provided addresses.
+    Discovered {
+        /// The addresses of the peer.
+        addresses: Vec<Multiaddr>,
+    },
+    /// The peer responded to the identify protocol.
+    Identified {
+        /// The agent version of the peer.
+        agent_version: String,
+        /// The protocols supported by the peer.
+        protocols: Vec<String>,
+        /// The addresses the peer listens on.
+        listen_addrs: Vec<Multiaddr>,
+    },
+    /// The peer announced its role in the block announces handshake.
+    Role(ProtocolRole),
+    /// The peer was dialed.
+    Dialed(DialOutcome),
+}
+
+/// The details known about a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerRecord {
+    /// The unix timestamp in seconds of the first observation.
+    pub first_seen: u64,
+    /// The unix timestamp in seconds of the last successful dial or identify.
+    ///
+    /// Peers only discovered or failing to dial were not seen on the network.
+    pub last_seen: Option<u64>,
+    /// The unix timestamp in seconds of the last discovery of the peer addresses.
+    pub last_discovered: Option<u64>,
+    /// The addresses of the peer.
+    pub addresses: HashSet<Multiaddr>,
+    /// The agent version reported by the identify protocol.
+    pub agent_version: Option<String>,
+    /// The protocols reported by the identify protocol.
+    pub protocols: Vec<String>,
+    /// The role of the peer.
+    pub role: Option<ProtocolRole>,
+    /// The number of successful dials.
+    pub dial_successes: usize,
+    /// The number of failed dials.
+    pub dial_failures: usize,
+    /// The error of the last failed dial.
+    pub last_dial_error: Option<String>,
+}
+
+impl PeerRecord {
+    /// Constructs a new [`PeerRecord`] first seen at the provided time.
+    pub fn new(time: u64) -> Self {
+        PeerRecord {
+            first_seen: time,
+            last_seen: None,
+            last_discovered: None,
+            addresses: HashSet::new(),
+            agent_version: None,
+            protocols: Vec::new(),
+            role: None,
+            dial_successes: 0,
+            dial_failures: 0,
+            last_dial_error: None,
+        }
+    }
+
+    /// Update the record with an observation made at the provided time.
+    pub fn apply(&mut self, time: u64, observation: PeerObservation) {
+        self.first_seen = self.first_seen.min(time);
+
+        match observation {
+            PeerObservation::Discovered { addresses } => {
+                self.last_discovered = self.last_discovered.max(Some(time));
+                self.addresses.extend(addresses);
+            }
+            PeerObservation::Identified {
+                agent_version,
+                protocols,
+                listen_addrs,
+            } => {
+                self.last_seen = self.last_seen.max(Some(time));
+                self.agent_version = Some(agent_version);
+                self.protocols = protocols;
+                self.addresses.extend(listen_addrs);
+            }
+            PeerObservation::Role(role) => self.role = Some(role),
+            PeerObservation::Dialed(DialOutcome::Success) => {
+                self.last_seen = self.last_seen.max(Some(time));
+                self.dial_successes += 1;
+            }
+            PeerObservation::Dialed(DialOutcome::Failure(error)) => {
+                self.dial_failures += 1;
+                self.last_dial_error = Some(error);
+            }
+        }
+    }
+}
+
+/// A store of the peers observed on the network.
+pub trait PeerStore {
+    /// Record an observation of the peer made at the provided unix time in seconds.
+    fn record_at(
+        &mut self,
+        peer_id: PeerId,
+        time: u64,
+        observation: PeerObservation,
+    ) -> Result<(), PeerStoreError>;
+
+    /// Returns the known peers.
+    fn peers(&self) -> &HashMap<PeerId, PeerRecord>;
+
+    /// Record an observation of the peer made now.
+    fn record(
+        &mut self,
+        peer_id: PeerId,
+        observation: PeerObservation,
+    ) -> Result<(), PeerStoreError> {
+        self.record_at(peer_id, unix_time(), observation)
+    }
+
+    /// Flush the pending observations to the underlying storage.
+    fn flush(&mut self) -> Result<(), PeerStoreError> {
+        Ok(())
+    }
+}
+
+/// Returns the current unix time in seconds.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Peer store that keeps the peers in memory.
+#[derive(Debug, Default)]
+pub struct MemoryPeerStore {
+    peers: HashMap<PeerId, PeerRecord>,
+}
+
+impl PeerStore for MemoryPeerStore {
+    fn record_at(
+        &mut self,
+        peer_id: PeerId,
+        time: u64,
+        observation: PeerObservation,
+    ) -> Result<(), PeerStoreError> {
+        self.peers
+            .entry(peer_id)
+            .or_insert_with(|| PeerRecord::new(time))
+            .apply(time, observation);
+        Ok(())
+    }
+
+    fn peers(&self) -> &HashMap<PeerId, PeerRecord> {
+        &self.peers
+    }
+}
+
+/// Peer store that keeps the records of the peers in a JSON-lines file.
+///
+/// Each line contains the record of one peer. The file is loaded when the store is
+/// opened, and rewritten with the updated records when the store is flushed. The
+/// records are written to a temporary file which replaces the store, such that an
+/// interrupted run leaves the previous records intact.
+#[derive(Debug)]
+pub struct JsonLinesPeerStore {
+    /// The peers loaded from the file and recorded since.
+    memory: MemoryPeerStore,
+    /// The path of the file.
+    path: PathBuf,
+    /// True if the peers were updated since the last flush.
+    dirty: bool,
+}
+
+impl JsonLinesPeerStore {
+    /// Open the store at the provided path, the file is created on the first flush.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, PeerStoreError> {
+        let path = path.as_ref().to_path_buf();
+        let mut memory = MemoryPeerStore::default();
+
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        for (index, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match decode_entry(line) {
+                Ok((peer_id, record)) => {
+                    memory.peers.insert(peer_id, record);
+                }
+                Err(err) => {
+                    log::warn!(target: LOG_TARGET, "Skipping line={} error={}", index + 1, err)
+                }
+            }
+        }
+
+        Ok(JsonLinesPeerStore {
+            memory,
+            path,
+            dirty: false,
+        })
+    }
+}
+
+impl PeerStore for JsonLinesPeerStore {
+    fn record_at(
+        &mut self,
+        peer_id: PeerId,
+        time: u64,
+        observation: PeerObservation,
+    ) -> Result<(), PeerStoreError> {
+        self.dirty = true;
+        self.memory.record_at(peer_id, time, observation)
+    }
+
+    fn peers(&self) -> &HashMap<PeerId, PeerRecord> {
+        self.memory.peers()
+    }
+
+    fn flush(&mut self) -> Result<(), PeerStoreError> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        let mut peers: Vec<_> = self.memory.peers().iter().collect();
+        peers.sort_by_key(|(_, record)| record.first_seen);
+
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        for (peer_id, record) in peers {
+            serde_json::to_writer(&mut writer, &encode_entry(peer_id, record))?;
+            writer.write_all(b"\n")?;
+        }
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl Drop for JsonLinesPeerStore {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            log::warn!(target: LOG_TARGET, "Failed to flush the peer store error={}", err);
+        }
+    }
+}
+
+/// A line of the [`JsonLinesPeerStore`].
+#[derive(Debug, Serialize, Deserialize)]
+struct PeerEntry {
+    peer: String,
+    first_seen: u64,
+    last_seen: Option<u64>,
+    #[serde(default)]
+    last_discovered: Option<u64>,
+    addresses: Vec<String>,
+    agent_version: Option<String>,
+    protocols: Vec<String>,
+    role: Option<String>,
+    dial_successes: usize,
+    dial_failures: usize,
+    last_dial_error: Option<String>,
+}
+
+/// Encode the record of a peer as a line of the store.
+fn encode_entry(peer_id: &PeerId, record: &PeerRecord) -> PeerEntry {
+    let mut addresses: Vec<_> = record
+        .addresses
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect();
+    addresses.sort();
+
+    PeerEntry {
+        peer: peer_id.to_string(),
+        first_seen: record.first_seen,
+        last_seen: record.last_seen,
+        last_discovered: record.last_discovered,
+        addresses,
+        agent_version: record.agent_version.clone(),
+        protocols: record.protocols.clone(),
+        role: record.role.as_ref().map(|role| role.name().to_string()),
+        dial_successes: record.dial_successes,
+        dial_failures: record.dial_failures,
+        last_dial_error: record.last_dial_error.clone(),
+    }
+}
+
+/// Decode the record of a peer from a line of the store.
+fn decode_entry(line: &str) -> Result<(PeerId, PeerRecord), PeerStoreError> {
+    let entry: PeerEntry = serde_json::from_str(line)?;
+
+    let peer_id = entry
+        .peer
+        .parse()
+        .map_err(|_| PeerStoreError::InvalidField("peer"))?;
+    let addresses = entry
+        .addresses
+        .iter()
+        .map(|addr| {
+            addr.parse()
+                .map_err(|_| PeerStoreError::InvalidField("addresses"))
+        })
+        .collect::<Result<_, _>>()?;
+    let role = entry
+        .role
+        .map(|role| match role.as_str() {
+            "full" => Ok(ProtocolRole::FullNode),
+            "light" => Ok(ProtocolRole::LightNode),
+            "authority" => Ok(ProtocolRole::Authority),
+            _ => Err(PeerStoreError::InvalidField("role")),
+        })
+        .transpose()?;
+
+    let record = PeerRecord {
+        first_seen: entry.first_seen,
+        last_seen: entry.last_seen,
+        last_discovered: entry.last_discovered,
+        addresses,
+        agent_version: entry.agent_version,
+        protocols: entry.protocols,
+        role,
+        dial_successes: entry.dial_successes,
+        dial_failures: entry.dial_failures,
+        last_dial_error: entry.last_dial_error,
+    };
+    Ok((peer_id, record))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn observations() -> Vec<(u64, PeerObservation)> {
+        vec![
+            (
+                10,
+                PeerObservation::Discovered {
+                    addresses: vec!["/ip4/1.2.3.4/tcp/30333".parse().unwrap()],
+                },
+            ),
+            (
+                12,
+                PeerObservation::Identified {
+                    agent_version: "Parity Polkadot/v1.0.0".to_string(),
+                    protocols: vec!["/ipfs/ping/1.0.0".to_string()],
+                    listen_addrs: vec!["/dns/example.com/tcp/30333".parse().unwrap()],
+                },
+            ),
+            (11, PeerObservation::Role(ProtocolRole::Authority)),
+            (13, PeerObservation::Dialed(DialOutcome::Success)),
+            (
+                14,
+                PeerObservation::Dialed(DialOutcome::Failure("Timeout".to_string())),
+            ),
+        ]
+    }
+
+    #[test]
+    fn record_observations() {
+        let peer_id = PeerId::random();
+        let mut store = MemoryPeerStore::default();
+        for (time, observation) in observations() {
+            store.record_at(peer_id, time, observation).unwrap();
+        }
+
+        let record = &store.peers()[&peer_id];
+        assert_eq!(record.first_seen, 10);
+        assert_eq!(record.last_discovered, Some(10));
+        // The failed dial does not count as seen.
+        assert_eq!(record.last_seen, Some(13));
+        assert_eq!(record.addresses.len(), 2);
+        assert_eq!(
+            record.agent_version.as_deref(),
+            Some("Parity Polkadot/v1.0.0")
+        );
+        assert_eq!(record.role, Some(ProtocolRole::Authority));
+        assert_eq!(record.dial_successes, 1);
+        assert_eq!(record.dial_failures, 1);
+        assert_eq!(record.last_dial_error.as_deref(), Some("Timeout"));
+    }
+
+    #[test]
+    fn failed_dials_are_not_seen() {
+        let peer_id = PeerId::random();
+        let mut store = MemoryPeerStore::default();
+        let addresses = vec!["/ip4/1.2.3.4/tcp/30333".parse().unwrap()];
+        store
+            .record_at(peer_id, 10, PeerObservation::Discovered { addresses })
+            .unwrap();
+        store
+            .record_at(
+                peer_id,
+                11,
+                PeerObservation::Dialed(DialOutcome::Failure("Timeout".to_string())),
+            )
+            .unwrap();
+        assert_eq!(store.peers()[&peer_id].last_seen, None);
+
+        store
+            .record_at(peer_id, 12, PeerObservation::Dialed(DialOutcome::Success))
+            .unwrap();
+        store
+            .record_at(
+                peer_id,
+                13,
+                PeerObservation::Dialed(DialOutcome::Failure("Timeout".to_string())),
+            )
+            .unwrap();
+        let record = &store.peers()[&peer_id];
+        assert_eq!(record.last_seen, Some(12));
+        assert_eq!(record.last_discovered, Some(10));
+        assert_eq!(record.dial_failures, 2);
+    }
+
+    #[test]
+    fn json_lines_store_resumes() {
+        let path = std::env::temp_dir().join(format!("peer-store-{}.jsonl", PeerId::random()));
+        let peer_id = PeerId::random();
+
+        let mut memory = MemoryPeerStore::default();
+        {
+            let mut store = JsonLinesPeerStore::open(&path).unwrap();
+            for (time, observation) in observations() {
+                memory
+                    .record_at(peer_id, time, observation.clone())
+                    .unwrap();
+                store.record_at(peer_id, time, observation).unwrap();
+            }
+        }
+
+        // The store keeps a single line per peer with snake case fields.
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.contains("\"agent_version\":\"Parity Polkadot/v1.0.0\""));
+
+        // Invalid lines are skipped.
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"peer\":")
+            .unwrap();
+
+        {
+            let mut store = JsonLinesPeerStore::open(&path).unwrap();
+            assert_eq!(store.peers(), memory.peers());
+
+            let observation = PeerObservation::Role(ProtocolRole::FullNode);
+            memory.record_at(peer_id, 20, observation.clone()).unwrap();
+            store.record_at(peer_id, 20, observation).unwrap();
+        }
+
+        let store = JsonLinesPeerStore::open(&path).unwrap();
+        assert_eq!(store.peers(), memory.peers());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,333 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Block requests of the `/{genesis}/sync/2` protocol.
+
+use crate::{
+    notifications::{
+        messages::{BlockHash, BlockNumber, ConsensusEngineId, Header},
+        transactions::Extrinsic,
+    },
+    requests::{schema, RequestError, RequestProtocol, Requests, RequestsEvent},
+    trie::{ordered_trie_root, StateVersion},
+};
+use codec::{Decode, Encode};
+use prost::Message;
+use std::ops::BitOr;
+
+/// The engine ID of the GRANDPA justifications.
+pub const GRANDPA_ENGINE_ID: ConsensusEngineId = *b"FRNK";
+
+/// The maximum number of blocks substrate peers return for a single request.
+pub const MAX_BLOCKS_IN_RESPONSE: u32 = 128;
+
+/// Returns the name of the block request protocol.
+pub fn protocol_name(genesis_hash: &str, fork_id: Option<&str>) -> String {
+    crate::protocol_name(genesis_hash, fork_id, "sync/2")
+}
+
+/// Bits of block data to request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockAttributes(u8);
+
+impl BlockAttributes {
+    /// Request the block header.
+    pub const HEADER: BlockAttributes = BlockAttributes(0b0000_0001);
+    /// Request the block body.
+    pub const BODY: BlockAttributes = BlockAttributes(0b0000_0010);
+    /// Request the block justifications.
+    pub const JUSTIFICATION: BlockAttributes = BlockAttributes(0b0001_0000);
+
+    /// Returns true if all the provided attributes are requested.
+    pub fn contains(&self, other: BlockAttributes) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The attributes are placed in the most significant byte over the wire.
+    fn to_be_u32(self) -> u32 {
+        u32::from_be_bytes([self.0, 0, 0, 0])
+    }
+}
+
+impl BitOr for BlockAttributes {
+    type Output = BlockAttributes;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        BlockAttributes(self.0 | rhs.0)
+    }
+}
+
+/// The first block of a [`BlockRequest`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FromBlock {
+    /// Start with the given hash.
+    Hash(BlockHash),
+    /// Start with the given number.
+    Number(BlockNumber),
+}
+
+/// Block enumeration direction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Enumerate from parent to child.
+    Ascending,
+    /// Enumerate from child to parent.
+    Descending,
+}
+
+/// Request a sequence of blocks from a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockRequest {
+    /// Bits of block data to request.
+    pub fields: BlockAttributes,
+    /// Start from this block.
+    pub from: FromBlock,
+    /// Sequence direction.
+    pub direction: Direction,
+    /// Maximum number of blocks to return.
+    pub max_blocks: u32,
+}
+
+impl BlockRequest {
+    /// Encode the request as a protobuf message.
+    pub fn encode(&self) -> Vec<u8> {
+        let from_block = match self.from {
+            FromBlock::Hash(hash) => schema::block_request::FromBlock::Hash(hash.0.to_vec()),
+            FromBlock::Number(number) => {
+                schema::block_request::FromBlock::Number(Encode::encode(&number))
+            }
+        };
+        let direction = match self.direction {
+            Direction::Ascending => schema::Direction::Ascending,
+            Direction::Descending => schema::Direction::Descending,
+        };
+
+        schema::BlockRequest {
+            fields: self.fields.to_be_u32(),
+            from_block: Some(from_block),
+            direction: direction as i32,
+            max_blocks: self.max_blocks,
+            support_multiple_justifications: true,
+        }
+        .encode_to_vec()
+    }
+}
+
+/// Block data received in the response of a [`BlockRequest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockData {
+    /// The hash of the block.
+    pub hash: BlockHash,
+    /// The header of the block, if requested.
+    pub header: Option<Header>,
+    /// The extrinsics of the block, if requested.
+    pub body: Option<Vec<Extrinsic>>,
+    /// The justifications of the block, if requested and available.
+    pub justifications: Option<Justifications>,
+}
+
+impl BlockData {
+    /// Returns true if the header and body of the block match its hash.
+    ///
+    /// The hash must be the hash of the header, and the body must produce the
+    /// extrinsics root of the header. Blocks without a header or body are rejected.
+    pub fn is_valid(&self) -> bool {
+        let (Some(header), Some(body)) = (&self.header, &self.body) else {
+            return false;
+        };
+        if header.hash() != self.hash {
+            return false;
+        }
+
+        // The state version of the block is not known, accept either layout.
+        let extrinsics = || body.iter().map(Encode::encode);
+        [StateVersion::V0, StateVersion::V1]
+            .into_iter()
+            .any(|version| ordered_trie_root(extrinsics(), version) == header.extrinsics_root)
+    }
+}
+
+/// Justifications of a block together with the engine that produced them.
+pub type Justifications = Vec<(ConsensusEngineId, Vec<u8>)>;
+
+/// Errors encountered while decoding a block response.
+#[derive(Debug, thiserror::Error)]
+pub enum BlockResponseError {
+    /// The protobuf message is invalid.
+    #[error(transparent)]
+    Protobuf(#[from] prost::DecodeError),
+    /// The header or body of a block is invalid.
+    #[error(transparent)]
+    Scale(#[from] codec::Error),
+    /// The block hash is not 32 bytes long.
+    #[error("Invalid block hash length: {0}")]
+    InvalidHash(usize),
+}
+
+/// Decode the response of a [`BlockRequest`].
+pub fn decode_block_response(response: &[u8]) -> Result<Vec<BlockData>, BlockResponseError> {
+    let response = schema::BlockResponse::decode(response)?;
+
+    response
+        .blocks
+        .into_iter()
+        .map(|block| {
+            if block.hash.len() != BlockHash::len_bytes() {
+                return Err(BlockResponseError::InvalidHash(block.hash.len()));
+            }
+
+            // Missing fields are encoded as empty. Every block contains at least
+            // the timestamp inherent, therefore an empty body was not requested.
+            let header = if block.header.is_empty() {
+                None
+            } else {
+                Some(Header::decode(&mut &block.header[..])?)
+            };
+            let body = if block.body.is_empty() {
+                None
+            } else {
+                let body = block
+                    .body
+                    .iter()
+                    .map(|extrinsic| Extrinsic::decode(&mut &extrinsic[..]))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Some(body)
+            };
+            // Older implementations only provide the GRANDPA justification.
+            let justifications = if !block.justifications.is_empty() {
+                Some(Justifications::decode(&mut &block.justifications[..])?)
+            } else if !block.justification.is_empty() || block.is_empty_justification {
+                Some(vec![(GRANDPA_ENGINE_ID, block.justification)])
+            } else {
+                None
+            };
+
+            Ok(BlockData {
+                hash: BlockHash::from_slice(&block.hash),
+                header,
+                body,
+                justifications,
+            })
+        })
+        .collect()
+}
+
+/// The `/{genesis}/sync/2` block request protocol.
+#[derive(Debug, Clone, Copy)]
+pub struct BlockProtocol;
+
+impl RequestProtocol for BlockProtocol {
+    type Request = BlockRequest;
+    type Response = Vec<BlockData>;
+    type Error = BlockResponseError;
+
+    fn protocol_name(genesis_hash: &str, fork_id: Option<&str>) -> String {
+        protocol_name(genesis_hash, fork_id)
+    }
+
+    fn encode_request(request: &Self::Request) -> Vec<u8> {
+        request.encode()
+    }
+
+    fn decode_response(response: &[u8]) -> Result<Self::Response, Self::Error> {
+        decode_block_response(response)
+    }
+}
+
+/// Request blocks from peers on the `/{genesis}/sync/2` protocol.
+pub type BlockRequests = Requests<BlockProtocol>;
+
+/// Events produced by the [`BlockRequests`] behavior.
+pub type BlockRequestsEvent = RequestsEvent<BlockProtocol>;
+
+/// Errors of a block request.
+pub type BlockRequestError = RequestError<BlockResponseError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::messages::Digest;
+
+    #[test]
+    fn encode_block_request() {
+        let request = BlockRequest {
+            fields: BlockAttributes::HEADER | BlockAttributes::BODY,
+            from: FromBlock::Number(10),
+            direction: Direction::Descending,
+            max_blocks: 2,
+        };
+        assert!(request.fields.contains(BlockAttributes::BODY));
+        assert!(!request.fields.contains(BlockAttributes::JUSTIFICATION));
+
+        let decoded = schema::BlockRequest::decode(&request.encode()[..]).unwrap();
+        assert_eq!(decoded.fields, 0x0300_0000);
+        assert_eq!(
+            decoded.from_block,
+            Some(schema::block_request::FromBlock::Number(vec![10, 0, 0, 0]))
+        );
+        assert_eq!(decoded.direction, schema::Direction::Descending as i32);
+        assert_eq!(decoded.max_blocks, 2);
+    }
+
+    #[test]
+    fn decode_block_data() {
+        let header = Header {
+            parent_hash: BlockHash::repeat_byte(1),
+            number: 10,
+            state_root: BlockHash::repeat_byte(2),
+            extrinsics_root: BlockHash::repeat_byte(3),
+            digest: Digest::default(),
+        };
+        let extrinsic = Extrinsic(vec![1, 2, 3]);
+
+        let response = schema::BlockResponse {
+            blocks: vec![
+                schema::BlockData {
+                    hash: header.hash().0.to_vec(),
+                    header: header.encode(),
+                    body: vec![extrinsic.encode()],
+                    justifications: vec![(*b"BABE", vec![1u8])].encode(),
+                    ..Default::default()
+                },
+                schema::BlockData {
+                    hash: BlockHash::repeat_byte(4).0.to_vec(),
+                    justification: vec![2],
+                    ..Default::default()
+                },
+            ],
+        }
+        .encode_to_vec();
+
+        let blocks = decode_block_response(&response).unwrap();
+        assert_eq!(
+            blocks,
+            vec![
+                BlockData {
+                    hash: header.hash(),
+                    header: Some(header),
+                    body: Some(vec![extrinsic]),
+                    justifications: Some(vec![(*b"BABE", vec![1])]),
+                },
+                BlockData {
+                    hash: BlockHash::repeat_byte(4),
+                    header: None,
+                    body: None,
+                    justifications: Some(vec![(GRANDPA_ENGINE_ID, vec![2])]),
+                }
+            ]
+        );
+
+        let invalid = schema::BlockResponse {
+            blocks: vec![schema::BlockData {
+                hash: vec![1],
+                ..Default::default()
+            }],
+        }
+        .encode_to_vec();
+        assert!(matches!(
+            decode_block_response(&invalid),
+            Err(BlockResponseError::InvalidHash(1))
+        ));
+    }
+}
@@ -0,0 +1,199 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Kademlia `FIND_NODE` requests of the `/{genesis}/kad` protocol.
+//!
+//! The [`crate::discovery::Discovery`] behavior merges the responses of the peers
+//! into the result of its queries. Submitting the requests directly exposes the
+//! peers returned by every remote, which describes the routing tables of the network.
+
+use crate::{
+    requests::{RequestError, RequestProtocol, RequestResponseBuilder, Requests, RequestsEvent},
+    transport::KIB,
+};
+use libp2p::{Multiaddr, PeerId};
+use prost::Message;
+use std::time::Duration;
+
+/// Protobuf schema of the kademlia messages.
+#[allow(clippy::enum_variant_names)]
+mod schema {
+    include!(concat!(env!("OUT_DIR"), "/kademlia.rs"));
+}
+
+/// Returns the name of the kademlia protocol.
+pub fn protocol_name(genesis_hash: &str, fork_id: Option<&str>) -> String {
+    crate::protocol_name(genesis_hash, fork_id, "kad")
+}
+
+/// Request the peers closest to the key from the routing table of the remote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FindNodeRequest {
+    /// The key of the request, usually the bytes of a peer ID.
+    pub key: Vec<u8>,
+}
+
+impl FindNodeRequest {
+    /// Request the peers closest to the provided peer ID.
+    pub fn new(peer_id: &PeerId) -> Self {
+        FindNodeRequest {
+            key: peer_id.to_bytes(),
+        }
+    }
+
+    /// Encode the request as a protobuf message.
+    pub fn encode(&self) -> Vec<u8> {
+        schema::Message {
+            r#type: schema::message::MessageType::FindNode as i32,
+            key: self.key.clone(),
+            ..Default::default()
+        }
+        .encode_to_vec()
+    }
+}
+
+/// A peer returned by a [`FindNodeRequest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KademliaPeer {
+    /// The ID of the peer.
+    pub peer_id: PeerId,
+    /// The addresses of the peer, as known by the remote.
+    pub addresses: Vec<Multiaddr>,
+}
+
+/// Errors encountered while decoding a kademlia response.
+#[derive(Debug, thiserror::Error)]
+pub enum KademliaResponseError {
+    /// The protobuf message is invalid.
+    #[error(transparent)]
+    Protobuf(#[from] prost::DecodeError),
+}
+
+/// Decode the response of a [`FindNodeRequest`].
+///
+/// Invalid addresses are skipped, since peers may advertise protocols unknown
+/// to this crate. Peers with an invalid peer ID are skipped as well, the other
+/// peers of the response remain valid.
+pub fn decode_find_node_response(
+    response: &[u8],
+) -> Result<Vec<KademliaPeer>, KademliaResponseError> {
+    Ok(schema::Message::decode(response)?
+        .closer_peers
+        .into_iter()
+        .filter_map(|peer| {
+            let Ok(peer_id) = PeerId::from_bytes(&peer.id) else {
+                log::debug!("Invalid peer ID in a kademlia response id={:?}", peer.id);
+                return None;
+            };
+
+            Some(KademliaPeer {
+                peer_id,
+                addresses: peer
+                    .addrs
+                    .into_iter()
+                    .filter_map(|addr| Multiaddr::try_from(addr).ok())
+                    .collect(),
+            })
+        })
+        .collect())
+}
+
+/// The `FIND_NODE` requests of the `/{genesis}/kad` protocol.
+#[derive(Debug, Clone, Copy)]
+pub struct KademliaProtocol;
+
+impl RequestProtocol for KademliaProtocol {
+    type Request = FindNodeRequest;
+    type Response = Vec<KademliaPeer>;
+    type Error = KademliaResponseError;
+
+    fn protocol_name(genesis_hash: &str, fork_id: Option<&str>) -> String {
+        protocol_name(genesis_hash, fork_id)
+    }
+
+    fn builder() -> RequestResponseBuilder {
+        RequestResponseBuilder::new()
+            .max_request_size(16 * KIB)
+            .max_response_size(256 * KIB)
+            .request_timeout(Duration::from_secs(10))
+    }
+
+    fn encode_request(request: &Self::Request) -> Vec<u8> {
+        request.encode()
+    }
+
+    fn decode_response(response: &[u8]) -> Result<Self::Response, Self::Error> {
+        decode_find_node_response(response)
+    }
+}
+
+/// Request the routing tables of peers on the `/{genesis}/kad` protocol.
+pub type KademliaRequests = Requests<KademliaProtocol>;
+
+/// Events produced by the [`KademliaRequests`] behavior.
+pub type KademliaRequestsEvent = RequestsEvent<KademliaProtocol>;
+
+/// Errors of a kademlia request.
+pub type KademliaRequestError = RequestError<KademliaResponseError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_find_node_request() {
+        let peer_id = PeerId::random();
+        let decoded =
+            schema::Message::decode(&FindNodeRequest::new(&peer_id).encode()[..]).unwrap();
+        assert_eq!(decoded.r#type(), schema::message::MessageType::FindNode);
+        assert_eq!(decoded.key, peer_id.to_bytes());
+    }
+
+    #[test]
+    fn decode_find_node_responses() {
+        let peer_id = PeerId::random();
+        let address: Multiaddr = "/ip4/1.2.3.4/tcp/30333".parse().unwrap();
+        let response = schema::Message {
+            r#type: schema::message::MessageType::FindNode as i32,
+            closer_peers: vec![schema::message::Peer {
+                id: peer_id.to_bytes(),
+                addrs: vec![address.to_vec(), vec![0xff, 0xff]],
+                connection: schema::message::ConnectionType::Connected as i32,
+            }],
+            ..Default::default()
+        }
+        .encode_to_vec();
+
+        assert_eq!(
+            decode_find_node_response(&response).unwrap(),
+            vec![KademliaPeer {
+                peer_id,
+                addresses: vec![address],
+            }]
+        );
+
+        // Invalid peer IDs are skipped.
+        let response = schema::Message {
+            closer_peers: vec![
+                schema::message::Peer {
+                    id: vec![1, 2, 3],
+                    ..Default::default()
+                },
+                schema::message::Peer {
+                    id: peer_id.to_bytes(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+        .encode_to_vec();
+        assert_eq!(
+            decode_find_node_response(&response).unwrap(),
+            vec![KademliaPeer {
+                peer_id,
+                addresses: Vec::new(),
+            }]
+        );
+    }
+}
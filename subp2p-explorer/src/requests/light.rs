@@ -0,0 +1,281 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Light client requests of the `/{genesis}/light/2` protocol.
+
+use crate::{
+    notifications::messages::BlockHash,
+    requests::{RequestError, RequestProtocol, Requests, RequestsEvent},
+    trie::{ProofError, StorageProof, TrieProof},
+};
+use codec::Decode;
+use prost::Message;
+
+/// Protobuf schema of the light client messages.
+#[allow(clippy::enum_variant_names)]
+mod schema {
+    include!(concat!(env!("OUT_DIR"), "/light.v1.rs"));
+}
+
+/// Returns the name of the light client protocol.
+pub fn protocol_name(genesis_hash: &str, fork_id: Option<&str>) -> String {
+    crate::protocol_name(genesis_hash, fork_id, "light/2")
+}
+
+/// Request of the light client protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LightRequest {
+    /// Execute a runtime call at the given block and return the execution proof.
+    RemoteCall {
+        /// The hash of the block.
+        block: BlockHash,
+        /// The name of the runtime API method.
+        method: String,
+        /// The scale-encoded arguments of the method.
+        data: Vec<u8>,
+    },
+    /// Read storage values at the given block.
+    RemoteRead {
+        /// The hash of the block.
+        block: BlockHash,
+        /// The storage keys to read.
+        keys: Vec<Vec<u8>>,
+    },
+    /// Read storage values of a child trie at the given block.
+    RemoteReadChild {
+        /// The hash of the block.
+        block: BlockHash,
+        /// The prefixed storage key of the child trie.
+        storage_key: Vec<u8>,
+        /// The storage keys to read from the child trie.
+        keys: Vec<Vec<u8>>,
+    },
+}
+
+impl LightRequest {
+    /// Encode the request as a protobuf message.
+    pub fn encode(&self) -> Vec<u8> {
+        let request = match self {
+            LightRequest::RemoteCall {
+                block,
+                method,
+                data,
+            } => schema::request::Request::RemoteCallRequest(schema::RemoteCallRequest {
+                block: block.0.to_vec(),
+                method: method.clone(),
+                data: data.clone(),
+            }),
+            LightRequest::RemoteRead { block, keys } => {
+                schema::request::Request::RemoteReadRequest(schema::RemoteReadRequest {
+                    block: block.0.to_vec(),
+                    keys: keys.clone(),
+                })
+            }
+            LightRequest::RemoteReadChild {
+                block,
+                storage_key,
+                keys,
+            } => schema::request::Request::RemoteReadChildRequest(schema::RemoteReadChildRequest {
+                block: block.0.to_vec(),
+                storage_key: storage_key.clone(),
+                keys: keys.clone(),
+            }),
+        };
+
+        schema::Request {
+            request: Some(request),
+        }
+        .encode_to_vec()
+    }
+}
+
+/// Response of a [`LightRequest`].
+///
+/// The proof is missing when the peer cannot answer, for example because the
+/// state of the block is pruned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LightResponse {
+    /// The execution proof of a [`LightRequest::RemoteCall`].
+    RemoteCall(Option<StorageProof>),
+    /// The read proof of a [`LightRequest::RemoteRead`] or [`LightRequest::RemoteReadChild`].
+    RemoteRead(Option<StorageProof>),
+}
+
+/// Errors encountered while decoding a light client response.
+#[derive(Debug, thiserror::Error)]
+pub enum LightResponseError {
+    /// The protobuf message is invalid.
+    #[error(transparent)]
+    Protobuf(#[from] prost::DecodeError),
+    /// The storage proof is invalid.
+    #[error(transparent)]
+    Scale(#[from] codec::Error),
+    /// The response message is empty.
+    #[error("Missing light client response")]
+    MissingResponse,
+}
+
+/// Decode the response of a [`LightRequest`].
+pub fn decode_light_response(response: &[u8]) -> Result<LightResponse, LightResponseError> {
+    let decode_proof = |proof: Option<Vec<u8>>| {
+        proof
+            .map(|proof| StorageProof::decode(&mut &proof[..]))
+            .transpose()
+    };
+
+    match schema::Response::decode(response)?.response {
+        Some(schema::response::Response::RemoteCallResponse(response)) => {
+            Ok(LightResponse::RemoteCall(decode_proof(response.proof)?))
+        }
+        Some(schema::response::Response::RemoteReadResponse(response)) => {
+            Ok(LightResponse::RemoteRead(decode_proof(response.proof)?))
+        }
+        None => Err(LightResponseError::MissingResponse),
+    }
+}
+
+/// Verify the proof of a [`LightRequest::RemoteRead`] against the state root of the block.
+///
+/// Returns the values of the keys in the order of the request.
+pub fn verify_read(
+    state_root: &BlockHash,
+    keys: &[Vec<u8>],
+    proof: StorageProof,
+) -> Result<Vec<Option<Vec<u8>>>, ProofError> {
+    let proof = TrieProof::new(proof);
+    keys.iter().map(|key| proof.read(state_root, key)).collect()
+}
+
+/// Verify the proof of a [`LightRequest::RemoteReadChild`] against the state root of the block.
+///
+/// Returns the values of the keys in the order of the request.
+pub fn verify_read_child(
+    state_root: &BlockHash,
+    storage_key: &[u8],
+    keys: &[Vec<u8>],
+    proof: StorageProof,
+) -> Result<Vec<Option<Vec<u8>>>, ProofError> {
+    let proof = TrieProof::new(proof);
+    keys.iter()
+        .map(|key| proof.read_child(state_root, storage_key, key))
+        .collect()
+}
+
+/// Check that the proof of a [`LightRequest::RemoteCall`] contains the state root of the block.
+///
+/// This does not verify the call: the runtime is not executed on top of the proof, so
+/// neither the result of the call nor the completeness of the proof are checked. The
+/// proof is returned to read the storage entries accessed by the call.
+pub fn check_proof_root(
+    state_root: &BlockHash,
+    proof: StorageProof,
+) -> Result<TrieProof, ProofError> {
+    let proof = TrieProof::new(proof);
+    if !proof.contains(state_root) {
+        return Err(ProofError::MissingNode(*state_root));
+    }
+    Ok(proof)
+}
+
+/// The `/{genesis}/light/2` light client protocol.
+#[derive(Debug, Clone, Copy)]
+pub struct LightProtocol;
+
+impl RequestProtocol for LightProtocol {
+    type Request = LightRequest;
+    type Response = LightResponse;
+    type Error = LightResponseError;
+
+    fn protocol_name(genesis_hash: &str, fork_id: Option<&str>) -> String {
+        protocol_name(genesis_hash, fork_id)
+    }
+
+    fn encode_request(request: &Self::Request) -> Vec<u8> {
+        request.encode()
+    }
+
+    fn decode_response(response: &[u8]) -> Result<Self::Response, Self::Error> {
+        decode_light_response(response)
+    }
+}
+
+/// Request storage proofs from peers on the `/{genesis}/light/2` protocol.
+pub type LightRequests = Requests<LightProtocol>;
+
+/// Events produced by the [`LightRequests`] behavior.
+pub type LightRequestsEvent = RequestsEvent<LightProtocol>;
+
+/// Errors of a light client request.
+pub type LightRequestError = RequestError<LightResponseError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notifications::messages::blake2_256;
+    use codec::Encode;
+
+    #[test]
+    fn encode_light_request() {
+        let request = LightRequest::RemoteReadChild {
+            block: BlockHash::repeat_byte(1),
+            storage_key: vec![2],
+            keys: vec![vec![3]],
+        };
+
+        let decoded = schema::Request::decode(&request.encode()[..]).unwrap();
+        assert_eq!(
+            decoded.request,
+            Some(schema::request::Request::RemoteReadChildRequest(
+                schema::RemoteReadChildRequest {
+                    block: vec![1; 32],
+                    storage_key: vec![2],
+                    keys: vec![vec![3]],
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn verify_remote_read() {
+        // Leaf node of the key 0xaa with the value 0xbb.
+        let root = vec![0x42, 0xaa, 0x04, 0xbb];
+        let state_root = BlockHash::from(blake2_256(&root));
+        let proof = StorageProof {
+            trie_nodes: vec![root],
+        };
+
+        let response = schema::Response {
+            response: Some(schema::response::Response::RemoteReadResponse(
+                schema::RemoteReadResponse {
+                    proof: Some(proof.encode()),
+                },
+            )),
+        }
+        .encode_to_vec();
+        let LightResponse::RemoteRead(Some(proof)) = decode_light_response(&response).unwrap()
+        else {
+            panic!("Expected a remote read proof");
+        };
+
+        let values = verify_read(&state_root, &[vec![0xaa], vec![0xab]], proof.clone()).unwrap();
+        assert_eq!(values, vec![Some(vec![0xbb]), None]);
+        assert!(check_proof_root(&state_root, proof.clone()).is_ok());
+        assert!(matches!(
+            verify_read(&BlockHash::zero(), &[vec![0xaa]], proof),
+            Err(ProofError::MissingNode(_))
+        ));
+
+        // Peers that cannot answer omit the proof.
+        let response = schema::Response {
+            response: Some(schema::response::Response::RemoteCallResponse(
+                schema::RemoteCallResponse { proof: None },
+            )),
+        }
+        .encode_to_vec();
+        assert_eq!(
+            decode_light_response(&response).unwrap(),
+            LightResponse::RemoteCall(None)
+        );
+    }
+}
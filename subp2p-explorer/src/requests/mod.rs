@@ -0,0 +1,500 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Request-response protocols of the p2p network.
+//!
+//! Substrate frames both the request and the response with an unsigned-varint
+//! length prefix. Each protocol is handled by a dedicated [`Requests`] behavior,
+//! such that requests are never negotiated on a different protocol.
+
+use crate::transport::MIB;
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::{
+    core::Endpoint,
+    request_response::{self, Codec, OutboundFailure, ProtocolSupport, RequestId},
+    swarm::{
+        ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, PollParameters, THandler,
+        THandlerInEvent, THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId, StreamProtocol,
+};
+use std::{
+    fmt, io,
+    marker::PhantomData,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+pub mod block;
+pub mod kademlia;
+pub mod light;
+pub mod state;
+
+mod schema {
+    include!(concat!(env!("OUT_DIR"), "/api.v1.rs"));
+}
+
+const LOG_TARGET: &str = "subp2p-requests";
+
+/// Request-response protocol exchanging raw bytes.
+pub type RequestResponse = request_response::Behaviour<RequestResponseCodec>;
+
+/// Events produced by the [`RequestResponse`] protocol.
+pub type RequestResponseEvent = request_response::Event<Vec<u8>, Vec<u8>>;
+
+/// Codec of the substrate request-response protocols.
+#[derive(Debug, Clone)]
+pub struct RequestResponseCodec {
+    /// The maximum size of a request in bytes.
+    max_request_size: usize,
+    /// The maximum size of a response in bytes.
+    max_response_size: usize,
+}
+
+impl RequestResponseCodec {
+    /// Constructs a new [`RequestResponseCodec`].
+    pub fn new(max_request_size: usize, max_response_size: usize) -> Self {
+        RequestResponseCodec {
+            max_request_size,
+            max_response_size,
+        }
+    }
+}
+
+/// Read a length-prefixed message of at most `max_size` bytes.
+async fn read_message<T>(io: &mut T, max_size: usize) -> io::Result<Vec<u8>>
+where
+    T: AsyncRead + Unpin + Send,
+{
+    let length = unsigned_varint::aio::read_usize(&mut *io)
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    if length > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Message size {length} exceeds the maximum of {max_size}"),
+        ));
+    }
+
+    let mut message = vec![0; length];
+    io.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+/// Write a length-prefixed message.
+async fn write_message<T>(io: &mut T, message: Vec<u8>) -> io::Result<()>
+where
+    T: AsyncWrite + Unpin + Send,
+{
+    let mut buffer = unsigned_varint::encode::usize_buffer();
+    io.write_all(unsigned_varint::encode::usize(message.len(), &mut buffer))
+        .await?;
+    io.write_all(&message).await
+}
+
+#[async_trait]
+impl Codec for RequestResponseCodec {
+    type Protocol = StreamProtocol;
+    type Request = Vec<u8>;
+    type Response = Vec<u8>;
+
+    async fn read_request<T>(
+        &mut self,
+        _protocol: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Request>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io, self.max_request_size).await
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _protocol: &Self::Protocol,
+        io: &mut T,
+    ) -> io::Result<Self::Response>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        read_message(io, self.max_response_size).await
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _protocol: &Self::Protocol,
+        io: &mut T,
+        request: Self::Request,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, request).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _protocol: &Self::Protocol,
+        io: &mut T,
+        response: Self::Response,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        write_message(io, response).await
+    }
+}
+
+/// Builder for a request-response protocol.
+pub struct RequestResponseBuilder {
+    /// The maximum size of a request in bytes.
+    max_request_size: usize,
+    /// The maximum size of a response in bytes.
+    max_response_size: usize,
+    /// The timeout of a single request.
+    request_timeout: Duration,
+    /// The legacy protocol names.
+    fallback_names: Vec<String>,
+}
+
+impl Default for RequestResponseBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestResponseBuilder {
+    /// Create a new [`RequestResponseBuilder`].
+    pub fn new() -> RequestResponseBuilder {
+        RequestResponseBuilder {
+            max_request_size: MIB,
+            max_response_size: 16 * MIB,
+            request_timeout: Duration::from_secs(20),
+            fallback_names: Vec::new(),
+        }
+    }
+
+    /// Sets the maximum size of a request.
+    ///
+    /// Default: 1 MiB.
+    pub fn max_request_size(mut self, max_request_size: usize) -> Self {
+        self.max_request_size = max_request_size;
+        self
+    }
+
+    /// Sets the maximum size of a response.
+    ///
+    /// Default: 16 MiB.
+    pub fn max_response_size(mut self, max_response_size: usize) -> Self {
+        self.max_response_size = max_response_size;
+        self
+    }
+
+    /// Sets the timeout of a single request.
+    ///
+    /// Default: 20 seconds.
+    pub fn request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// Sets the legacy protocol names (ie `/dot/kad`).
+    ///
+    /// Nodes that predate the genesis hash protocol names only support these.
+    /// Each name must start with '/'.
+    pub fn fallback_names(mut self, fallback_names: Vec<String>) -> Self {
+        self.fallback_names = fallback_names;
+        self
+    }
+
+    /// Build the request-response protocol.
+    ///
+    /// The protocol is only used to submit requests, inbound requests are not accepted.
+    /// The fallback names are negotiated after the protocol name.
+    pub fn build(self, protocol_name: String) -> RequestResponse {
+        let protocols = std::iter::once(protocol_name)
+            .chain(self.fallback_names)
+            .map(|name| {
+                let protocol =
+                    StreamProtocol::try_from_owned(name).expect("Protocol name starts with '/'");
+                (protocol, ProtocolSupport::Outbound)
+            });
+
+        let mut config = request_response::Config::default();
+        config.set_request_timeout(self.request_timeout);
+
+        RequestResponse::with_codec(
+            RequestResponseCodec::new(self.max_request_size, self.max_response_size),
+            protocols,
+            config,
+        )
+    }
+}
+
+/// Request-response protocol with typed requests and responses.
+pub trait RequestProtocol {
+    /// The request submitted to peers.
+    type Request: fmt::Debug;
+    /// The decoded response.
+    type Response: fmt::Debug + Send + 'static;
+    /// Errors encountered while decoding the response.
+    type Error: std::error::Error + Send + 'static;
+
+    /// Returns the name of the protocol for the chain with the given genesis hash
+    /// and optional fork id.
+    fn protocol_name(genesis_hash: &str, fork_id: Option<&str>) -> String;
+
+    /// Returns the builder of the underlying request-response protocol.
+    fn builder() -> RequestResponseBuilder {
+        RequestResponseBuilder::new()
+    }
+
+    /// Encode the request.
+    fn encode_request(request: &Self::Request) -> Vec<u8>;
+
+    /// Decode the response.
+    fn decode_response(response: &[u8]) -> Result<Self::Response, Self::Error>;
+}
+
+/// Errors of a request submitted by [`Requests`].
+#[derive(Debug, thiserror::Error)]
+pub enum RequestError<E: std::error::Error + 'static> {
+    /// The request could not be completed.
+    #[error(transparent)]
+    Request(#[from] OutboundFailure),
+    /// The response could not be decoded.
+    #[error(transparent)]
+    InvalidResponse(E),
+}
+
+/// Events produced by the [`Requests`] behavior.
+#[derive(Debug)]
+pub enum RequestsEvent<P: RequestProtocol> {
+    /// The peer responded to a request.
+    Response {
+        /// The peer that responded.
+        peer_id: PeerId,
+        /// The ID returned by [`Requests::send_request`].
+        request_id: RequestId,
+        /// The decoded response.
+        response: P::Response,
+    },
+    /// The request failed.
+    RequestFailed {
+        /// The peer the request was sent to.
+        peer_id: PeerId,
+        /// The ID returned by [`Requests::send_request`].
+        request_id: RequestId,
+        /// The reason of the failure.
+        error: RequestError<P::Error>,
+    },
+}
+
+/// Submit typed requests to peers on the protocol `P`.
+///
+/// Inbound requests are not accepted.
+pub struct Requests<P> {
+    /// The request-response protocol.
+    inner: RequestResponse,
+    _marker: PhantomData<fn() -> P>,
+}
+
+impl<P: RequestProtocol> Requests<P> {
+    /// Constructs a new [`Requests`] for the chain with the given genesis hash
+    /// and optional fork id.
+    pub fn new(genesis_hash: &str, fork_id: Option<&str>) -> Self {
+        Self::with_fallback_names(genesis_hash, fork_id, Vec::new())
+    }
+
+    /// Constructs a new [`Requests`] that also negotiates the legacy protocol
+    /// names (ie `/dot/kad`).
+    pub fn with_fallback_names(
+        genesis_hash: &str,
+        fork_id: Option<&str>,
+        fallback_names: Vec<String>,
+    ) -> Self {
+        Requests {
+            inner: P::builder()
+                .fallback_names(fallback_names)
+                .build(P::protocol_name(genesis_hash, fork_id)),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Send a request to the peer.
+    ///
+    /// The peer is dialed if it is not connected.
+    pub fn send_request(&mut self, peer_id: &PeerId, request: &P::Request) -> RequestId {
+        log::trace!(target: LOG_TARGET, "Send request peer={:?} request={:?}", peer_id, request);
+
+        self.inner.send_request(peer_id, P::encode_request(request))
+    }
+
+    /// Add an address of the peer used to dial it for the requests.
+    ///
+    /// Required for peers that are unknown to the other behaviors.
+    pub fn add_address(&mut self, peer_id: &PeerId, address: Multiaddr) {
+        self.inner.add_address(peer_id, address);
+    }
+
+    /// Translate the events of the request-response protocol.
+    fn on_event(&mut self, event: RequestResponseEvent) -> Option<RequestsEvent<P>> {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+            } => {
+                let event = match P::decode_response(&response) {
+                    Ok(response) => RequestsEvent::Response {
+                        peer_id: peer,
+                        request_id,
+                        response,
+                    },
+                    Err(error) => RequestsEvent::RequestFailed {
+                        peer_id: peer,
+                        request_id,
+                        error: RequestError::InvalidResponse(error),
+                    },
+                };
+                Some(event)
+            }
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+            } => Some(RequestsEvent::RequestFailed {
+                peer_id: peer,
+                request_id,
+                error: error.into(),
+            }),
+            // Inbound requests are not supported.
+            event => {
+                log::trace!(target: LOG_TARGET, "Requests ignored event {:?}", event);
+                None
+            }
+        }
+    }
+}
+
+impl<P: RequestProtocol + 'static> NetworkBehaviour for Requests<P> {
+    type ConnectionHandler = <RequestResponse as NetworkBehaviour>::ConnectionHandler;
+    type ToSwarm = RequestsEvent<P>;
+
+    fn handle_pending_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<(), ConnectionDenied> {
+        self.inner
+            .handle_pending_inbound_connection(connection_id, local_addr, remote_addr)
+    }
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner.handle_established_inbound_connection(
+            connection_id,
+            peer,
+            local_addr,
+            remote_addr,
+        )
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        self.inner.handle_pending_outbound_connection(
+            connection_id,
+            maybe_peer,
+            addresses,
+            effective_role,
+        )
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.inner
+            .handle_established_outbound_connection(connection_id, peer, addr, role_override)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm<Self::ConnectionHandler>) {
+        self.inner.on_swarm_event(event)
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        self.inner
+            .on_connection_handler_event(peer_id, connection_id, event)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context<'_>,
+        params: &mut impl PollParameters,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        while let Poll::Ready(event) = self.inner.poll(cx, params) {
+            match event {
+                ToSwarm::GenerateEvent(event) => {
+                    if let Some(event) = self.on_event(event) {
+                        return Poll::Ready(ToSwarm::GenerateEvent(event));
+                    }
+                }
+                event => {
+                    return Poll::Ready(
+                        event.map_out(|_| unreachable!("Events are generated above; qed")),
+                    )
+                }
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor::block_on, io::Cursor};
+
+    #[test]
+    fn length_prefixed_messages() {
+        let mut codec = RequestResponseCodec::new(4, 4);
+        let protocol = StreamProtocol::new("/test/1");
+
+        let mut io = Cursor::new(Vec::new());
+        block_on(codec.write_request(&protocol, &mut io, vec![1, 2, 3])).unwrap();
+        assert_eq!(io.get_ref(), &vec![3, 1, 2, 3]);
+
+        io.set_position(0);
+        let request = block_on(codec.read_request(&protocol, &mut io)).unwrap();
+        assert_eq!(request, vec![1, 2, 3]);
+
+        // Messages above the maximum size are rejected.
+        let mut io = Cursor::new(vec![5, 1, 2, 3, 4, 5]);
+        assert!(block_on(codec.read_response(&protocol, &mut io)).is_err());
+    }
+}
@@ -0,0 +1,392 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! State requests of the `/{genesis}/state/2` protocol.
+
+use crate::{
+    notifications::messages::BlockHash,
+    requests::{schema, RequestError, RequestProtocol, Requests, RequestsEvent},
+    trie::{decode_compact_proof, ProofError},
+};
+use codec::{Decode, Encode};
+use prost::Message;
+
+/// Returns the name of the state request protocol.
+pub fn protocol_name(genesis_hash: &str, fork_id: Option<&str>) -> String {
+    crate::protocol_name(genesis_hash, fork_id, "state/2")
+}
+
+/// Request the storage of a block from a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateRequest {
+    /// The hash of the block.
+    pub block: BlockHash,
+    /// Start after this key.
+    ///
+    /// The second key is the start key of a child trie.
+    pub start: Vec<Vec<u8>>,
+    /// Request the raw key-values instead of a storage proof.
+    pub no_proof: bool,
+}
+
+impl StateRequest {
+    /// Encode the request as a protobuf message.
+    pub fn encode(&self) -> Vec<u8> {
+        schema::StateRequest {
+            block: self.block.0.to_vec(),
+            start: self.start.clone(),
+            no_proof: self.no_proof,
+        }
+        .encode_to_vec()
+    }
+}
+
+/// Key-values of a single trie.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateEntries {
+    /// The root of the child trie, empty for the top trie.
+    pub state_root: Vec<u8>,
+    /// The key-value pairs of the trie.
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+    /// True if there are no more keys in the trie.
+    pub complete: bool,
+}
+
+impl StateEntries {
+    /// Returns true if the entries belong to the top trie.
+    pub fn is_top_trie(&self) -> bool {
+        self.state_root.is_empty()
+    }
+}
+
+/// Compact storage proof of a state response.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encode, Decode)]
+pub struct StorageProof {
+    /// The encoded trie nodes.
+    pub encoded_nodes: Vec<Vec<u8>>,
+}
+
+/// Response of a [`StateRequest`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateResponse {
+    /// The key-values, populated when the request sets [`StateRequest::no_proof`].
+    pub entries: Vec<StateEntries>,
+    /// The storage proof, populated otherwise.
+    pub proof: Option<StorageProof>,
+}
+
+/// Errors encountered while decoding a state response.
+#[derive(Debug, thiserror::Error)]
+pub enum StateResponseError {
+    /// The protobuf message is invalid.
+    #[error(transparent)]
+    Protobuf(#[from] prost::DecodeError),
+    /// The storage proof is invalid.
+    #[error(transparent)]
+    Scale(#[from] codec::Error),
+}
+
+/// Decode the response of a [`StateRequest`].
+pub fn decode_state_response(response: &[u8]) -> Result<StateResponse, StateResponseError> {
+    let response = schema::StateResponse::decode(response)?;
+
+    let proof = if response.proof.is_empty() {
+        None
+    } else {
+        Some(StorageProof::decode(&mut &response.proof[..])?)
+    };
+    let entries = response
+        .entries
+        .into_iter()
+        .map(|entry| StateEntries {
+            state_root: entry.state_root,
+            entries: entry
+                .entries
+                .into_iter()
+                .map(|entry| (entry.key, entry.value))
+                .collect(),
+            complete: entry.complete,
+        })
+        .collect();
+
+    Ok(StateResponse { entries, proof })
+}
+
+/// The `/{genesis}/state/2` state request protocol.
+#[derive(Debug, Clone, Copy)]
+pub struct StateProtocol;
+
+impl RequestProtocol for StateProtocol {
+    type Request = StateRequest;
+    type Response = StateResponse;
+    type Error = StateResponseError;
+
+    fn protocol_name(genesis_hash: &str, fork_id: Option<&str>) -> String {
+        protocol_name(genesis_hash, fork_id)
+    }
+
+    fn encode_request(request: &Self::Request) -> Vec<u8> {
+        request.encode()
+    }
+
+    fn decode_response(response: &[u8]) -> Result<Self::Response, Self::Error> {
+        decode_state_response(response)
+    }
+}
+
+/// Request the storage of blocks from peers on the `/{genesis}/state/2` protocol.
+pub type StateRequests = Requests<StateProtocol>;
+
+/// Events produced by the [`StateRequests`] behavior.
+pub type StateRequestsEvent = RequestsEvent<StateProtocol>;
+
+/// Errors of a state request.
+pub type StateRequestError = RequestError<StateResponseError>;
+
+/// Collect the top trie key-values under a prefix with multiple state requests.
+///
+/// Peers return the key-values in lexicographic order, starting after the provided
+/// key and limited in size. The start key is excluded, therefore the first request
+/// starts before the prefix and the keys below the prefix are skipped. The query
+/// continues from the last received key until the keys leave the prefix.
+///
+/// The key-values are trusted as served by the peer. Instead, the storage proofs of
+/// [`StoragePrefixQuery::on_proof`] are verified against the state root of the block.
+#[derive(Debug, Clone)]
+pub struct StoragePrefixQuery {
+    /// The hash of the block.
+    block: BlockHash,
+    /// The prefix of the keys.
+    prefix: Vec<u8>,
+    /// The last received key, or the key before the prefix.
+    last_key: Vec<u8>,
+    /// The key-values collected so far.
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+    /// True if all the keys under the prefix have been received.
+    complete: bool,
+}
+
+impl StoragePrefixQuery {
+    /// Constructs a new [`StoragePrefixQuery`].
+    pub fn new(block: BlockHash, prefix: Vec<u8>) -> Self {
+        // The last byte of the prefix is decremented, or removed if it is null.
+        let mut last_key = prefix.clone();
+        match last_key.pop() {
+            Some(byte) if byte > 0 => last_key.push(byte - 1),
+            _ => (),
+        }
+
+        StoragePrefixQuery {
+            block,
+            prefix,
+            last_key,
+            entries: Vec::new(),
+            complete: false,
+        }
+    }
+
+    /// Returns the next request of the query, or `None` if the query is complete.
+    pub fn next_request(&self) -> Option<StateRequest> {
+        if self.complete {
+            return None;
+        }
+
+        Some(StateRequest {
+            block: self.block,
+            start: if self.last_key.is_empty() {
+                Vec::new()
+            } else {
+                vec![self.last_key.clone()]
+            },
+            no_proof: true,
+        })
+    }
+
+    /// Handle the response of the last request.
+    pub fn on_response(&mut self, response: &StateResponse) {
+        let Some(top) = response
+            .entries
+            .iter()
+            .find(|entries| entries.is_top_trie())
+        else {
+            // The block state is empty or not available.
+            self.complete = true;
+            return;
+        };
+
+        let mut progress = false;
+        for (key, value) in &top.entries {
+            // Peers may return the start key, which was already collected.
+            if *key <= self.last_key {
+                continue;
+            }
+            progress = true;
+            self.last_key = key.clone();
+
+            if key.starts_with(&self.prefix) {
+                self.entries.push((key.clone(), value.clone()));
+            } else if *key > self.prefix {
+                self.complete = true;
+                return;
+            }
+        }
+
+        self.complete = top.complete || !progress;
+    }
+
+    /// Handle the storage proof of the last request, sent without [`StateRequest::no_proof`].
+    ///
+    /// The proof is verified against the state root of the block. Returns the number of
+    /// key-values read from the proof.
+    pub fn on_proof(
+        &mut self,
+        state_root: &BlockHash,
+        proof: &StorageProof,
+    ) -> Result<usize, ProofError> {
+        let (root, proof) = decode_compact_proof(&proof.encoded_nodes)?;
+        if root != *state_root {
+            return Err(ProofError::MissingNode(*state_root));
+        }
+
+        let (entries, complete) =
+            proof.read_prefix(state_root, &self.prefix, Some(&self.last_key))?;
+        if let Some((key, _)) = entries.last() {
+            self.last_key = key.clone();
+        }
+
+        let read = entries.len();
+        self.entries.extend(entries);
+        self.complete = complete;
+        Ok(read)
+    }
+
+    /// Returns true if all the keys under the prefix have been received.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Returns the key-values collected so far.
+    pub fn entries(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(entries: &[(&[u8], &[u8])], complete: bool) -> StateResponse {
+        StateResponse {
+            entries: vec![StateEntries {
+                state_root: Vec::new(),
+                entries: entries
+                    .iter()
+                    .map(|(key, value)| (key.to_vec(), value.to_vec()))
+                    .collect(),
+                complete,
+            }],
+            proof: None,
+        }
+    }
+
+    #[test]
+    fn decode_state_response_entries_and_proof() {
+        let message = schema::StateResponse {
+            entries: vec![schema::KeyValueStateEntry {
+                state_root: Vec::new(),
+                entries: vec![schema::StateEntry {
+                    key: vec![1],
+                    value: vec![2],
+                }],
+                complete: true,
+            }],
+            proof: StorageProof {
+                encoded_nodes: vec![vec![3, 4]],
+            }
+            .encode(),
+        }
+        .encode_to_vec();
+
+        let mut expected = response(&[(&[1], &[2])], true);
+        expected.proof = Some(StorageProof {
+            encoded_nodes: vec![vec![3, 4]],
+        });
+        assert_eq!(decode_state_response(&message).unwrap(), expected);
+
+        let invalid = schema::StateResponse {
+            entries: Vec::new(),
+            proof: vec![0xff],
+        }
+        .encode_to_vec();
+        assert!(matches!(
+            decode_state_response(&invalid),
+            Err(StateResponseError::Scale(_))
+        ));
+    }
+
+    #[test]
+    fn prefix_query_continues_from_last_key() {
+        let block = BlockHash::repeat_byte(1);
+        let mut query = StoragePrefixQuery::new(block, vec![1]);
+
+        // The start key is excluded, the query starts before the prefix.
+        let request = query.next_request().unwrap();
+        assert_eq!(request.start, vec![vec![0]]);
+        assert!(request.no_proof);
+
+        // Keys below the prefix are skipped and the prefix itself is a key.
+        query.on_response(&response(
+            &[
+                (&[0, 9], &[0]),
+                (&[1], &[9]),
+                (&[1, 1], &[1]),
+                (&[1, 2], &[2]),
+            ],
+            false,
+        ));
+        assert!(!query.is_complete());
+        assert_eq!(query.next_request().unwrap().start, vec![vec![1, 2]]);
+
+        // The start key is not collected twice and keys outside the prefix end the query.
+        query.on_response(&response(
+            &[(&[1, 2], &[2]), (&[1, 3], &[3]), (&[2], &[4])],
+            false,
+        ));
+        assert!(query.is_complete());
+        assert!(query.next_request().is_none());
+        assert_eq!(
+            query.entries(),
+            &[
+                (vec![1], vec![9]),
+                (vec![1, 1], vec![1]),
+                (vec![1, 2], vec![2]),
+                (vec![1, 3], vec![3])
+            ]
+        );
+    }
+
+    #[test]
+    fn prefix_query_verifies_proofs() {
+        use crate::notifications::messages::blake2_256;
+
+        // Branch without value with two inlined leaf children of the keys 0x1314 and 0x4819.
+        let root = vec![
+            0x80, 0x12, 0x00, 0x14, 0x43, 0x03, 0x14, 0x04, 0xff, 0x14, 0x43, 0x08, 0x19, 0x04,
+            0xfe,
+        ];
+        let state_root = BlockHash::from(blake2_256(&root));
+        let proof = StorageProof {
+            encoded_nodes: vec![root],
+        };
+
+        let mut query = StoragePrefixQuery::new(BlockHash::repeat_byte(1), vec![0x13]);
+        assert!(matches!(
+            query.on_proof(&BlockHash::zero(), &proof),
+            Err(ProofError::MissingNode(_))
+        ));
+        assert_eq!(query.on_proof(&state_root, &proof).unwrap(), 1);
+        assert!(query.is_complete());
+        assert_eq!(query.entries(), &[(vec![0x13, 0x14], vec![0xff])]);
+    }
+}
@@ -0,0 +1,274 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! The topology of the network, as described by the routing tables of the peers.
+//!
+//! Each edge `referrer -> peer` records that `referrer` returned `peer` in
+//! response to a kademlia `FIND_NODE` request.
+
+use libp2p::PeerId;
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write,
+};
+
+/// Directed graph of the peers returned by each referrer.
+#[derive(Debug, Clone, Default)]
+pub struct TopologyGraph {
+    /// The peers returned by each referrer.
+    edges: BTreeMap<PeerId, BTreeSet<PeerId>>,
+}
+
+/// Statistics of a [`TopologyGraph`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopologyStats {
+    /// The number of peers in the graph.
+    pub nodes: usize,
+    /// The number of distinct edges.
+    pub edges: usize,
+    /// The number of peers that responded to a request.
+    pub responders: usize,
+    /// The maximum number of referrers of a peer.
+    pub max_in_degree: usize,
+    /// The average number of referrers of a peer.
+    pub mean_in_degree: f64,
+    /// The size of the weakly connected components, in decreasing order.
+    pub components: Vec<usize>,
+    /// Peers known only through a single referrer.
+    pub single_referrer_peers: Vec<PeerId>,
+    /// Responders that are not returned by any other peer.
+    pub unreferenced_responders: Vec<PeerId>,
+}
+
+impl TopologyGraph {
+    /// Constructs an empty [`TopologyGraph`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the peers returned by the referrer.
+    ///
+    /// The referrer is recorded even if the response is empty. Self references
+    /// are ignored.
+    pub fn add_response(&mut self, referrer: PeerId, peers: impl IntoIterator<Item = PeerId>) {
+        self.edges
+            .entry(referrer)
+            .or_default()
+            .extend(peers.into_iter().filter(|peer| *peer != referrer));
+    }
+
+    /// Returns true if the referrer responded to a request.
+    pub fn has_responded(&self, peer: &PeerId) -> bool {
+        self.edges.contains_key(peer)
+    }
+
+    /// Returns all peers of the graph, either referrers or returned peers.
+    pub fn nodes(&self) -> BTreeSet<PeerId> {
+        self.edges
+            .iter()
+            .flat_map(|(referrer, peers)| std::iter::once(referrer).chain(peers))
+            .copied()
+            .collect()
+    }
+
+    /// Returns the edges of the graph as `(referrer, peer)` pairs.
+    pub fn edges(&self) -> impl Iterator<Item = (&PeerId, &PeerId)> {
+        self.edges
+            .iter()
+            .flat_map(|(referrer, peers)| peers.iter().map(move |peer| (referrer, peer)))
+    }
+
+    /// Returns the number of referrers of every peer in the graph.
+    pub fn in_degrees(&self) -> BTreeMap<PeerId, usize> {
+        let mut degrees: BTreeMap<_, _> = self.nodes().into_iter().map(|peer| (peer, 0)).collect();
+        for (_, peer) in self.edges() {
+            *degrees.entry(*peer).or_default() += 1;
+        }
+        degrees
+    }
+
+    /// Returns the size of the weakly connected components, in decreasing order.
+    pub fn components(&self) -> Vec<usize> {
+        let nodes: Vec<_> = self.nodes().into_iter().collect();
+        let index: HashMap<_, _> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, peer)| (*peer, i))
+            .collect();
+
+        // Union-find over the undirected edges.
+        let mut parents: Vec<_> = (0..nodes.len()).collect();
+        fn find(parents: &mut [usize], mut node: usize) -> usize {
+            while parents[node] != node {
+                parents[node] = parents[parents[node]];
+                node = parents[node];
+            }
+            node
+        }
+        for (referrer, peer) in self.edges() {
+            let a = find(&mut parents, index[referrer]);
+            let b = find(&mut parents, index[peer]);
+            parents[a] = b;
+        }
+
+        let mut sizes: HashMap<usize, usize> = HashMap::new();
+        for node in 0..nodes.len() {
+            *sizes.entry(find(&mut parents, node)).or_default() += 1;
+        }
+        let mut sizes: Vec<_> = sizes.into_values().collect();
+        sizes.sort_by(|a, b| b.cmp(a));
+        sizes
+    }
+
+    /// Compute the statistics of the graph.
+    pub fn stats(&self) -> TopologyStats {
+        let degrees = self.in_degrees();
+        let edges = self.edges().count();
+
+        TopologyStats {
+            nodes: degrees.len(),
+            edges,
+            responders: self.edges.len(),
+            max_in_degree: degrees.values().copied().max().unwrap_or_default(),
+            mean_in_degree: if degrees.is_empty() {
+                0.0
+            } else {
+                edges as f64 / degrees.len() as f64
+            },
+            components: self.components(),
+            single_referrer_peers: degrees
+                .iter()
+                .filter(|(_, degree)| **degree == 1)
+                .map(|(peer, _)| *peer)
+                .collect(),
+            unreferenced_responders: self
+                .edges
+                .keys()
+                .filter(|peer| degrees.get(*peer) == Some(&0))
+                .copied()
+                .collect(),
+        }
+    }
+
+    /// Export the graph in the Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph topology {\n");
+        for peer in self.nodes() {
+            let _ = writeln!(dot, "  \"{peer}\";");
+        }
+        for (referrer, peer) in self.edges() {
+            let _ = writeln!(dot, "  \"{referrer}\" -> \"{peer}\";");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Export the graph in the GraphML format.
+    ///
+    /// Nodes are annotated with their in-degree and whether they responded.
+    pub fn to_graphml(&self) -> String {
+        let mut xml = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"in_degree\" for=\"node\" attr.name=\"in_degree\" attr.type=\"int\"/>\n",
+            "  <key id=\"responded\" for=\"node\" attr.name=\"responded\" attr.type=\"boolean\"/>\n",
+            "  <graph id=\"topology\" edgedefault=\"directed\">\n",
+        ));
+        for (peer, degree) in self.in_degrees() {
+            let _ = writeln!(
+                xml,
+                "    <node id=\"{peer}\"><data key=\"in_degree\">{degree}</data><data key=\"responded\">{}</data></node>",
+                self.has_responded(&peer)
+            );
+        }
+        for (referrer, peer) in self.edges() {
+            let _ = writeln!(xml, "    <edge source=\"{referrer}\" target=\"{peer}\"/>");
+        }
+        xml.push_str("  </graph>\n</graphml>\n");
+        xml
+    }
+
+    /// Export the graph as a JSON object with `nodes` and `edges` arrays.
+    pub fn to_json(&self) -> String {
+        let nodes: Vec<_> = self
+            .in_degrees()
+            .into_iter()
+            .map(|(peer, degree)| {
+                json!({
+                    "id": peer.to_string(),
+                    "inDegree": degree,
+                    "responded": self.has_responded(&peer),
+                })
+            })
+            .collect();
+        let edges: Vec<_> = self
+            .edges()
+            .map(|(referrer, peer)| {
+                json!({
+                    "source": referrer.to_string(),
+                    "target": peer.to_string(),
+                })
+            })
+            .collect();
+
+        json!({ "nodes": nodes, "edges": edges }).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn topology_stats() {
+        let peers: Vec<_> = (0..6).map(|_| PeerId::random()).collect();
+
+        let mut graph = TopologyGraph::new();
+        // 0 -> {1, 2}, 1 -> {0, 2}, 3 -> {4}, 5 responded with nothing.
+        graph.add_response(peers[0], [peers[1], peers[2], peers[0]]);
+        graph.add_response(peers[1], [peers[0], peers[2]]);
+        graph.add_response(peers[3], [peers[4]]);
+        graph.add_response(peers[5], []);
+        // Duplicate responses do not add edges.
+        graph.add_response(peers[3], [peers[4]]);
+
+        let stats = graph.stats();
+        assert_eq!(stats.nodes, 6);
+        assert_eq!(stats.edges, 5);
+        assert_eq!(stats.responders, 4);
+        assert_eq!(stats.max_in_degree, 2);
+        assert_eq!(stats.components, vec![3, 2, 1]);
+
+        let mut single: Vec<_> = vec![peers[0], peers[1], peers[4]];
+        single.sort();
+        assert_eq!(stats.single_referrer_peers, single);
+
+        let mut unreferenced = vec![peers[3], peers[5]];
+        unreferenced.sort();
+        assert_eq!(stats.unreferenced_responders, unreferenced);
+    }
+
+    #[test]
+    fn topology_exports() {
+        let (a, b) = (PeerId::random(), PeerId::random());
+        let mut graph = TopologyGraph::new();
+        graph.add_response(a, [b]);
+
+        let dot = graph.to_dot();
+        assert!(dot.starts_with("digraph topology {"));
+        assert!(dot.contains(&format!("\"{a}\" -> \"{b}\";")));
+
+        let graphml = graph.to_graphml();
+        assert!(graphml.contains(&format!("<edge source=\"{a}\" target=\"{b}\"/>")));
+        assert!(graphml.contains(&format!(
+            "<node id=\"{b}\"><data key=\"in_degree\">1</data><data key=\"responded\">false</data></node>"
+        )));
+
+        let json: serde_json::Value = serde_json::from_str(&graph.to_json()).unwrap();
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 2);
+        assert_eq!(json["edges"][0]["source"], a.to_string());
+        assert_eq!(json["edges"][0]["target"], b.to_string());
+    }
+}
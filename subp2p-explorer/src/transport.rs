@@ -9,10 +9,14 @@
 //! Users can customize various parameters to tailor the transport behavior to their needs.
 
 use libp2p::{
-    core::{muxing::StreamMuxerBox, transport::Boxed, upgrade},
+    core::{
+        muxing::StreamMuxerBox,
+        transport::{timeout::TransportTimeoutError, Boxed},
+        upgrade,
+    },
     dns, identity, noise, tcp, websocket, PeerId, Transport,
 };
-use std::time::Duration;
+use std::{io, time::Duration};
 
 /// The value of one kibibyte (KiB) in bytes.
 pub const KIB: usize = 1024;
@@ -26,6 +30,12 @@ pub struct TransportBuilder {
     yamux_maximum_buffer_size: usize,
 }
 
+impl Default for TransportBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TransportBuilder {
     /// Create a new [`TransportBuilder`].
     pub fn new() -> TransportBuilder {
@@ -73,12 +83,12 @@ impl TransportBuilder {
         // The main transport is DNS(TCP).
         let tcp_config = tcp::Config::new().nodelay(true);
         let tcp_trans = tcp::tokio::Transport::new(tcp_config.clone());
-        let dns = dns::TokioDnsConfig::system(tcp_trans).expect("Can construct DNS; qed");
+        let dns = dns::tokio::Transport::system(tcp_trans).expect("Can construct DNS; qed");
 
         // Support for WS and WSS.
         let tcp_trans = tcp::tokio::Transport::new(tcp_config);
         let dns_for_wss =
-            dns::TokioDnsConfig::system(tcp_trans).expect("Valid config provided; qed");
+            dns::tokio::Transport::system(tcp_trans).expect("Valid config provided; qed");
 
         let transport = websocket::WsConfig::new(dns_for_wss).or_transport(dns);
 
@@ -102,6 +112,18 @@ impl TransportBuilder {
             .authenticate(authentication_config)
             .multiplex(multiplexing_config)
             .timeout(self.timeout)
+            // Erase the upgrade errors, the timeout is then downcast as
+            // `TransportTimeoutError<io::Error>` from the boxed error.
+            .map_err(|error| match error {
+                TransportTimeoutError::Timeout => TransportTimeoutError::Timeout,
+                TransportTimeoutError::TimerError(error) => {
+                    TransportTimeoutError::TimerError(error)
+                }
+                TransportTimeoutError::Other(error) => {
+                    TransportTimeoutError::Other(io::Error::new(io::ErrorKind::Other, error))
+                }
+            })
+            .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
             .boxed()
     }
 }
@@ -0,0 +1,940 @@
+// Copyright 2023 Alexandru Vasile
+// This file is dual-licensed as Apache-2.0 or GPL-3.0.
+// see LICENSE for license details.
+
+//! Verification of substrate storage proofs.
+//!
+//! A storage proof contains the trie nodes visited while accessing the storage of a
+//! block. The storage values are verified by walking the nodes from the state root
+//! found in the header of the block. The compact proofs of state responses are decoded
+//! by [`decode_compact_proof`].
+//!
+//! The state root of a storage, such as the genesis storage of a chain spec, is computed
+//! by [`trie_root`], and the extrinsics root of a block by [`ordered_trie_root`].
+
+use crate::notifications::messages::{blake2_256, BlockHash};
+use codec::{Compact, Decode, Encode};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hasher,
+};
+use twox_hash::XxHash64;
+
+/// The prefix of the storage keys that contain the roots of the default child tries.
+pub const DEFAULT_CHILD_STORAGE_KEY_PREFIX: &[u8] = b":child_storage:default:";
+
+/// Compute the twox-128 hash of the provided data.
+pub fn twox_128(data: &[u8]) -> [u8; 16] {
+    let mut output = [0u8; 16];
+    for (seed, chunk) in output.chunks_exact_mut(8).enumerate() {
+        let mut hasher = XxHash64::with_seed(seed as u64);
+        hasher.write(data);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    output
+}
+
+/// Returns the storage key of a plain storage value declared by a pallet.
+pub fn storage_value_key(pallet: &str, item: &str) -> Vec<u8> {
+    let mut key = twox_128(pallet.as_bytes()).to_vec();
+    key.extend(twox_128(item.as_bytes()));
+    key
+}
+
+/// The version of the trie layout used by the state of a chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateVersion {
+    /// All values are stored in the trie nodes.
+    V0,
+    /// Values of at least 33 bytes are stored separately under their hash.
+    V1,
+}
+
+impl StateVersion {
+    /// Returns true if the value is stored separately under its hash.
+    fn is_hashed(&self, value: &[u8]) -> bool {
+        match self {
+            StateVersion::V0 => false,
+            StateVersion::V1 => value.len() >= 33,
+        }
+    }
+}
+
+/// Compute the root of the trie that contains the provided key-values.
+pub fn trie_root(storage: &BTreeMap<Vec<u8>, Vec<u8>>, version: StateVersion) -> BlockHash {
+    build_trie(storage, version, &mut Vec::new())
+}
+
+/// Compute the root of the trie that contains the provided values keyed by their
+/// compact-encoded index, such as the extrinsics root of a block.
+pub fn ordered_trie_root<I>(values: I, version: StateVersion) -> BlockHash
+where
+    I: IntoIterator<Item = Vec<u8>>,
+{
+    let storage = values
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| (Compact(index as u32).encode(), value))
+        .collect();
+    trie_root(&storage, version)
+}
+
+/// A storage entry of the trie, with the key split into nibbles.
+type NibbleEntry<'a> = (Vec<u8>, &'a [u8]);
+
+/// Build the trie that contains the provided key-values and return its root.
+///
+/// The nodes stored under their hash are collected into `nodes`.
+fn build_trie(
+    storage: &BTreeMap<Vec<u8>, Vec<u8>>,
+    version: StateVersion,
+    nodes: &mut Vec<Vec<u8>>,
+) -> BlockHash {
+    let entries: Vec<NibbleEntry> = storage
+        .iter()
+        .map(|(key, value)| {
+            let key = key
+                .iter()
+                .flat_map(|byte| [byte >> 4, byte & 0xf])
+                .collect();
+            (key, &value[..])
+        })
+        .collect();
+
+    // The root is hashed regardless of the length of its encoding.
+    let root = match entries.is_empty() {
+        // The empty trie is represented by a single null byte.
+        true => vec![0],
+        false => encode_node(&entries, 0, version, nodes),
+    };
+    let hash = BlockHash::from(blake2_256(&root));
+    nodes.push(root);
+    hash
+}
+
+/// Encode the node of the sorted entries that share the first `depth` nibbles of their keys.
+fn encode_node(
+    entries: &[NibbleEntry],
+    depth: usize,
+    version: StateVersion,
+    nodes: &mut Vec<Vec<u8>>,
+) -> Vec<u8> {
+    let (first, last) = (&entries[0].0, &entries[entries.len() - 1].0);
+    // The keys are sorted, therefore the first and last keys share the longest common prefix.
+    let end = depth
+        + first[depth..]
+            .iter()
+            .zip(&last[depth..])
+            .take_while(|(a, b)| a == b)
+            .count();
+    let partial_key = &first[depth..end];
+
+    // The value of the node is the entry whose key ends at the partial key.
+    let (value, children) = match entries.split_first() {
+        Some(((key, value), children)) if key.len() == end => (Some(*value), children),
+        _ => (None, entries),
+    };
+    let hashed_value = value.is_some_and(|value| version.is_hashed(value));
+
+    let mut encoded = encode_header(
+        partial_key,
+        !children.is_empty(),
+        value.map(|_| hashed_value),
+    );
+
+    // Group the children by their nibble after the partial key.
+    let mut groups: Vec<(u8, &[NibbleEntry])> = Vec::new();
+    let mut remaining = children;
+    while let Some((key, _)) = remaining.first() {
+        let nibble = key[end];
+        let len = remaining
+            .iter()
+            .take_while(|(key, _)| key[end] == nibble)
+            .count();
+        let (group, rest) = remaining.split_at(len);
+        groups.push((nibble, group));
+        remaining = rest;
+    }
+
+    if !children.is_empty() {
+        let bitmap = groups
+            .iter()
+            .fold(0u16, |bitmap, (nibble, _)| bitmap | 1 << nibble);
+        encoded.extend(bitmap.to_le_bytes());
+    }
+
+    match value {
+        Some(value) if hashed_value => {
+            encoded.extend(blake2_256(value));
+            nodes.push(value.to_vec());
+        }
+        Some(value) => value.encode_to(&mut encoded),
+        None => (),
+    }
+
+    for (_, group) in groups {
+        let child = encode_node(group, end + 1, version, nodes);
+        // Nodes shorter than a hash are inlined in their parent.
+        if child.len() < BlockHash::len_bytes() {
+            child.encode_to(&mut encoded);
+        } else {
+            blake2_256(&child)[..].encode_to(&mut encoded);
+            nodes.push(child);
+        }
+    }
+
+    encoded
+}
+
+/// Encode the header and the partial key of a node.
+///
+/// The value is `Some(true)` if the value of the node is stored under its hash.
+fn encode_header(partial_key: &[u8], has_children: bool, value: Option<bool>) -> Vec<u8> {
+    // (header bits, number of header bits for the partial key length)
+    let (header, len_bits) = match (has_children, value) {
+        (false, None | Some(false)) => (0b01 << 6, 6),
+        (false, Some(true)) => (0b001 << 5, 5),
+        (true, None) => (0b10 << 6, 6),
+        (true, Some(false)) => (0b11 << 6, 6),
+        (true, Some(true)) => (0b0001 << 4, 4),
+    };
+
+    let mut encoded = Vec::new();
+    let max_len = (1usize << len_bits) - 1;
+    if partial_key.len() < max_len {
+        encoded.push(header | partial_key.len() as u8);
+    } else {
+        encoded.push(header | max_len as u8);
+        let mut remaining = partial_key.len() - max_len;
+        while remaining >= u8::MAX as usize {
+            encoded.push(u8::MAX);
+            remaining -= u8::MAX as usize;
+        }
+        encoded.push(remaining as u8);
+    }
+
+    // Odd partial keys are padded with a zero nibble.
+    if partial_key.len() % 2 == 1 {
+        encoded.push(partial_key[0]);
+    }
+    for pair in partial_key[partial_key.len() % 2..].chunks_exact(2) {
+        encoded.push(pair[0] << 4 | pair[1]);
+    }
+    encoded
+}
+
+/// The header of a compact proof node followed by its value.
+const ESCAPE_COMPACT_HEADER: u8 = 0x01;
+
+/// Decode the compact storage proof of a state response.
+///
+/// A compact proof lists the trie nodes in the order of a depth-first traversal. The
+/// children included in the proof are replaced by empty references and follow their
+/// parent, the values stored under their hash follow their node. The nodes of child
+/// tries follow the top trie.
+///
+/// Returns the root of the top trie together with the trie nodes.
+pub fn decode_compact_proof(
+    encoded_nodes: &[Vec<u8>],
+) -> Result<(BlockHash, TrieProof), ProofError> {
+    let mut input = encoded_nodes.iter();
+    let mut nodes = HashMap::new();
+
+    let mut root = None;
+    while input.len() > 0 {
+        // The root is hashed regardless of the length of its encoding.
+        let node = decode_compact_node(&mut input, &mut nodes)?;
+        let hash = BlockHash::from(blake2_256(&node));
+        nodes.insert(hash, node);
+        root.get_or_insert(hash);
+    }
+
+    let root = root.ok_or(ProofError::InvalidNode("empty compact proof"))?;
+    Ok((root, TrieProof { nodes }))
+}
+
+/// Decode the next node of a compact proof together with its omitted children.
+///
+/// Returns the encoding of the node.
+fn decode_compact_node(
+    input: &mut std::slice::Iter<Vec<u8>>,
+    nodes: &mut HashMap<BlockHash, Vec<u8>>,
+) -> Result<Vec<u8>, ProofError> {
+    let encoded = input
+        .next()
+        .ok_or(ProofError::InvalidNode("missing compact node"))?;
+    let (encoded, value) = match encoded.split_first() {
+        Some((&ESCAPE_COMPACT_HEADER, encoded)) => {
+            let value = input
+                .next()
+                .ok_or(ProofError::InvalidNode("missing compact value"))?;
+            (encoded, Some(value))
+        }
+        _ => (&encoded[..], None),
+    };
+
+    let mut children: [Option<Vec<u8>>; 16] = Default::default();
+    let mut node = Node::decode(encoded)?;
+
+    if let Some(value) = value {
+        // The value of an escaped node is replaced by an empty inline value.
+        if !matches!(node.value, Some(Value::Inline([]))) {
+            return Err(ProofError::InvalidNode("invalid compact value"));
+        }
+        let hash = BlockHash::from(blake2_256(value));
+        nodes.insert(hash, value.clone());
+        node.value = Some(Value::Hashed(hash));
+    }
+
+    for (child, decoded) in node.children.iter().zip(children.iter_mut()) {
+        if !child.is_some_and(|child| child.is_empty()) {
+            continue;
+        }
+
+        let child = decode_compact_node(input, nodes)?;
+        // Nodes shorter than a hash are inlined in their parent.
+        *decoded = Some(if child.len() < BlockHash::len_bytes() {
+            child
+        } else {
+            let hash = BlockHash::from(blake2_256(&child));
+            nodes.insert(hash, child);
+            hash.as_bytes().to_vec()
+        });
+    }
+    for (child, decoded) in node.children.iter_mut().zip(&children) {
+        if let Some(decoded) = decoded {
+            *child = Some(decoded);
+        }
+    }
+
+    Ok(node.encode())
+}
+
+/// Storage proof of the light client protocol.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encode, Decode)]
+pub struct StorageProof {
+    /// The encoded trie nodes, in no particular order.
+    pub trie_nodes: Vec<Vec<u8>>,
+}
+
+/// Errors encountered while verifying a storage proof.
+#[derive(Debug, thiserror::Error)]
+pub enum ProofError {
+    /// The proof does not contain a node required to access the storage.
+    #[error("Trie node {0:?} is missing from the proof")]
+    MissingNode(BlockHash),
+    /// The proof contains an invalid trie node.
+    #[error("Invalid trie node: {0}")]
+    InvalidNode(&'static str),
+    /// The root of a child trie is not 32 bytes long.
+    #[error("Invalid child trie root length: {0}")]
+    InvalidChildRoot(usize),
+}
+
+/// A storage key with its value.
+type KeyValue = (Vec<u8>, Vec<u8>);
+
+/// The trie nodes of a storage proof indexed by their hash.
+#[derive(Debug, Clone, Default)]
+pub struct TrieProof {
+    nodes: HashMap<BlockHash, Vec<u8>>,
+}
+
+impl TrieProof {
+    /// Constructs a new [`TrieProof`].
+    pub fn new(proof: StorageProof) -> Self {
+        let nodes = proof
+            .trie_nodes
+            .into_iter()
+            .map(|node| (BlockHash::from(blake2_256(&node)), node))
+            .collect();
+
+        TrieProof { nodes }
+    }
+
+    /// Returns true if the proof contains the node with the provided hash.
+    pub fn contains(&self, hash: &BlockHash) -> bool {
+        self.nodes.contains_key(hash)
+    }
+
+    /// Read the value of a key from the trie with the provided root.
+    ///
+    /// Returns `None` if the proof shows the key is not part of the trie.
+    pub fn read(&self, root: &BlockHash, key: &[u8]) -> Result<Option<Vec<u8>>, ProofError> {
+        let key: Vec<u8> = key
+            .iter()
+            .flat_map(|byte| [byte >> 4, byte & 0xf])
+            .collect();
+        let mut key = &key[..];
+        let mut encoded = self.node(root)?;
+
+        loop {
+            let node = Node::decode(encoded)?;
+            if !key.starts_with(&node.partial_key) {
+                return Ok(None);
+            }
+            key = &key[node.partial_key.len()..];
+
+            let Some((&nibble, remaining)) = key.split_first() else {
+                return match node.value {
+                    Some(Value::Inline(value)) => Ok(Some(value.to_vec())),
+                    Some(Value::Hashed(hash)) => self.node(&hash).map(|value| Some(value.to_vec())),
+                    None => Ok(None),
+                };
+            };
+            let Some(child) = node.children[nibble as usize] else {
+                return Ok(None);
+            };
+            key = remaining;
+
+            // Nodes shorter than a hash are inlined in their parent.
+            encoded = if child.len() == BlockHash::len_bytes() {
+                self.node(&BlockHash::from_slice(child))?
+            } else {
+                child
+            };
+        }
+    }
+
+    /// Read the value of a key from a default child trie.
+    ///
+    /// The `storage_key` is the key of the child trie root in the trie with the provided root,
+    /// prefixed by [`DEFAULT_CHILD_STORAGE_KEY_PREFIX`].
+    pub fn read_child(
+        &self,
+        root: &BlockHash,
+        storage_key: &[u8],
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, ProofError> {
+        let Some(child_root) = self.read(root, storage_key)? else {
+            return Ok(None);
+        };
+        if child_root.len() != BlockHash::len_bytes() {
+            return Err(ProofError::InvalidChildRoot(child_root.len()));
+        }
+
+        self.read(&BlockHash::from_slice(&child_root), key)
+    }
+
+    /// Read the key-values under a prefix from the trie with the provided root.
+    ///
+    /// Only the keys after `start` are read, in lexicographic order. The reading stops at
+    /// the first node missing from the proof. Returns the key-values together with true if
+    /// the proof contains all the keys under the prefix.
+    pub fn read_prefix(
+        &self,
+        root: &BlockHash,
+        prefix: &[u8],
+        start: Option<&[u8]>,
+    ) -> Result<(Vec<KeyValue>, bool), ProofError> {
+        let to_nibbles = |key: &[u8]| -> Vec<u8> {
+            key.iter()
+                .flat_map(|byte| [byte >> 4, byte & 0xf])
+                .collect()
+        };
+        let prefix = to_nibbles(prefix);
+        let start = start.map(to_nibbles);
+
+        let mut entries = Vec::new();
+        let complete = self.read_prefix_node(
+            self.node(root)?,
+            Vec::new(),
+            &prefix,
+            start.as_deref(),
+            &mut entries,
+        )?;
+        Ok((entries, complete))
+    }
+
+    /// Collect the key-values under the prefix of the node reached by the nibbles of `path`.
+    ///
+    /// Returns false if a node is missing from the proof.
+    fn read_prefix_node(
+        &self,
+        encoded: &[u8],
+        mut path: Vec<u8>,
+        prefix: &[u8],
+        start: Option<&[u8]>,
+        entries: &mut Vec<KeyValue>,
+    ) -> Result<bool, ProofError> {
+        // The keys of the subtree start with the path.
+        let relevant = |path: &[u8]| {
+            (path.starts_with(prefix) || prefix.starts_with(path))
+                && start.map_or(true, |start| start.starts_with(path) || path > start)
+        };
+
+        let node = Node::decode(encoded)?;
+        path.extend(&node.partial_key);
+        if !relevant(&path) {
+            return Ok(true);
+        }
+
+        let is_key = path.len() % 2 == 0
+            && path.starts_with(prefix)
+            && start.map_or(true, |start| &path[..] > start);
+        match node.value {
+            Some(value) if is_key => {
+                let value = match value {
+                    Value::Inline(value) => value,
+                    Value::Hashed(hash) => match self.nodes.get(&hash) {
+                        Some(value) => value,
+                        None => return Ok(false),
+                    },
+                };
+                let key = path.chunks_exact(2).map(|pair| pair[0] << 4 | pair[1]);
+                entries.push((key.collect(), value.to_vec()));
+            }
+            _ => (),
+        }
+
+        for (nibble, child) in node.children.iter().enumerate() {
+            let Some(child) = child else {
+                continue;
+            };
+            let mut child_path = path.clone();
+            child_path.push(nibble as u8);
+            if !relevant(&child_path) {
+                continue;
+            }
+
+            // Nodes shorter than a hash are inlined in their parent.
+            let encoded = if child.len() == BlockHash::len_bytes() {
+                match self.nodes.get(&BlockHash::from_slice(child)) {
+                    Some(encoded) => &encoded[..],
+                    None => return Ok(false),
+                }
+            } else {
+                child
+            };
+            if !self.read_prefix_node(encoded, child_path, prefix, start, entries)? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn node(&self, hash: &BlockHash) -> Result<&[u8], ProofError> {
+        self.nodes
+            .get(hash)
+            .map(|node| &node[..])
+            .ok_or(ProofError::MissingNode(*hash))
+    }
+}
+
+/// The storage value of a trie node.
+#[derive(Debug)]
+enum Value<'a> {
+    /// The value is stored in the node.
+    Inline(&'a [u8]),
+    /// The value is stored separately under its hash.
+    Hashed(BlockHash),
+}
+
+/// A decoded trie node.
+#[derive(Debug, Default)]
+struct Node<'a> {
+    /// The nibbles of the partial key.
+    partial_key: Vec<u8>,
+    /// The storage value of the node.
+    value: Option<Value<'a>>,
+    /// The encoded children or their hashes.
+    children: [Option<&'a [u8]>; 16],
+}
+
+impl<'a> Node<'a> {
+    /// Decode a node of the trie layouts V0 and V1.
+    fn decode(mut input: &'a [u8]) -> Result<Self, ProofError> {
+        let header = read(&mut input, 1)?[0];
+        // The empty trie is represented by a single null byte.
+        if header == 0 && input.is_empty() {
+            return Ok(Node::default());
+        }
+
+        // (has children, value is hashed, number of header bits for the partial key length)
+        let (has_children, hashed_value, len_bits) = match header >> 6 {
+            0b01 => (false, Some(false), 6),
+            0b10 => (true, None, 6),
+            0b11 => (true, Some(false), 6),
+            _ if header >> 5 == 0b001 => (false, Some(true), 5),
+            _ if header >> 4 == 0b0001 => (true, Some(true), 4),
+            _ => return Err(ProofError::InvalidNode("invalid header")),
+        };
+
+        let max_len = (1 << len_bits) - 1;
+        let mut partial_len = (header & max_len) as usize;
+        if partial_len == max_len as usize {
+            loop {
+                let byte = read(&mut input, 1)?[0];
+                partial_len += byte as usize;
+                if byte != u8::MAX {
+                    break;
+                }
+            }
+        }
+
+        // Odd partial keys are padded with a zero nibble.
+        let partial = read(&mut input, (partial_len + 1) / 2)?;
+        let mut partial_key: Vec<u8> = partial
+            .iter()
+            .flat_map(|byte| [byte >> 4, byte & 0xf])
+            .collect();
+        if partial_len % 2 == 1 {
+            if partial_key[0] != 0 {
+                return Err(ProofError::InvalidNode("invalid partial key padding"));
+            }
+            partial_key.remove(0);
+        }
+
+        let bitmap = if has_children {
+            let bitmap = read(&mut input, 2)?;
+            u16::from_le_bytes([bitmap[0], bitmap[1]])
+        } else {
+            0
+        };
+
+        let value = match hashed_value {
+            Some(false) => {
+                let len = read_len(&mut input)?;
+                Some(Value::Inline(read(&mut input, len)?))
+            }
+            Some(true) => Some(Value::Hashed(BlockHash::from_slice(read(
+                &mut input,
+                BlockHash::len_bytes(),
+            )?))),
+            None => None,
+        };
+
+        let mut children = [None; 16];
+        for (index, child) in children.iter_mut().enumerate() {
+            if bitmap & (1 << index) == 0 {
+                continue;
+            }
+
+            let len = read_len(&mut input)?;
+            if len > BlockHash::len_bytes() {
+                return Err(ProofError::InvalidNode("child too large"));
+            }
+            *child = Some(read(&mut input, len)?);
+        }
+
+        if !input.is_empty() {
+            return Err(ProofError::InvalidNode("trailing bytes"));
+        }
+
+        Ok(Node {
+            partial_key,
+            value,
+            children,
+        })
+    }
+}
+
+impl Node<'_> {
+    /// Encode a node of the trie layouts V0 and V1.
+    fn encode(&self) -> Vec<u8> {
+        let has_children = self.children.iter().any(Option::is_some);
+        // The empty trie is represented by a single null byte.
+        if self.partial_key.is_empty() && self.value.is_none() && !has_children {
+            return vec![0];
+        }
+
+        let hashed_value = self
+            .value
+            .as_ref()
+            .map(|value| matches!(value, Value::Hashed(_)));
+        let mut encoded = encode_header(&self.partial_key, has_children, hashed_value);
+
+        if has_children {
+            let bitmap = self
+                .children
+                .iter()
+                .enumerate()
+                .filter(|(_, child)| child.is_some())
+                .fold(0u16, |bitmap, (nibble, _)| bitmap | 1 << nibble);
+            encoded.extend(bitmap.to_le_bytes());
+        }
+
+        match self.value {
+            Some(Value::Inline(value)) => value.encode_to(&mut encoded),
+            Some(Value::Hashed(hash)) => encoded.extend(hash.as_bytes()),
+            None => (),
+        }
+
+        for child in self.children.iter().flatten() {
+            child.encode_to(&mut encoded);
+        }
+
+        encoded
+    }
+}
+
+/// Read exactly `len` bytes from the input.
+fn read<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8], ProofError> {
+    if input.len() < len {
+        return Err(ProofError::InvalidNode("unexpected end of node"));
+    }
+
+    let (bytes, remaining) = input.split_at(len);
+    *input = remaining;
+    Ok(bytes)
+}
+
+/// Read a compact encoded length from the input.
+fn read_len(input: &mut &[u8]) -> Result<usize, ProofError> {
+    Compact::<u32>::decode(input)
+        .map(|len| len.0 as usize)
+        .map_err(|_| ProofError::InvalidNode("invalid length"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn storage_keys() {
+        assert_eq!(
+            hex::encode(twox_128(b"System")),
+            "26aa394eea5630e07c48ae0c9558cef7"
+        );
+        assert_eq!(
+            hex::encode(storage_value_key("System", "Number")),
+            "26aa394eea5630e07c48ae0c9558cef702a5c1b19ab7a04f536c519aca4983ac"
+        );
+    }
+
+    #[test]
+    fn read_inline_children() {
+        // Branch without value with two inlined leaf children at the nibbles 1 and 4.
+        let root = vec![
+            0x80, 0x12, 0x00, 0x14, 0x43, 0x03, 0x14, 0x04, 0xff, 0x14, 0x43, 0x08, 0x19, 0x04,
+            0xfe,
+        ];
+        let root_hash = BlockHash::from(blake2_256(&root));
+        let proof = TrieProof::new(StorageProof {
+            trie_nodes: vec![root],
+        });
+
+        assert_eq!(
+            proof.read(&root_hash, &[0x13, 0x14]).unwrap(),
+            Some(vec![0xff])
+        );
+        assert_eq!(
+            proof.read(&root_hash, &[0x48, 0x19]).unwrap(),
+            Some(vec![0xfe])
+        );
+        assert_eq!(proof.read(&root_hash, &[0x13, 0x15]).unwrap(), None);
+        assert_eq!(proof.read(&root_hash, &[0x20]).unwrap(), None);
+        assert!(matches!(
+            proof.read(&BlockHash::zero(), &[0x13, 0x14]),
+            Err(ProofError::MissingNode(_))
+        ));
+    }
+
+    #[test]
+    fn trie_root_of_inline_children() {
+        // Same trie as `read_inline_children`.
+        let root = [
+            0x80, 0x12, 0x00, 0x14, 0x43, 0x03, 0x14, 0x04, 0xff, 0x14, 0x43, 0x08, 0x19, 0x04,
+            0xfe,
+        ];
+        let storage = BTreeMap::from([
+            (vec![0x13, 0x14], vec![0xff]),
+            (vec![0x48, 0x19], vec![0xfe]),
+        ]);
+
+        assert_eq!(
+            trie_root(&storage, StateVersion::V0),
+            BlockHash::from(blake2_256(&root))
+        );
+        // Short values are inlined in both layouts.
+        assert_eq!(
+            trie_root(&storage, StateVersion::V1),
+            trie_root(&storage, StateVersion::V0)
+        );
+        assert_eq!(
+            hex::encode(trie_root(&BTreeMap::new(), StateVersion::V0)),
+            "03170a2e7597b7b7e3d84c05391d139a62b157e78786d8c082f29dcf4c111314"
+        );
+    }
+
+    #[test]
+    fn trie_root_roundtrip() {
+        let storage: BTreeMap<_, _> = [
+            (b"a".to_vec(), vec![1; 40]),
+            (b"ab".to_vec(), vec![2; 4]),
+            (b"abc".to_vec(), vec![3; 64]),
+            (b"b".to_vec(), vec![4; 32]),
+            (vec![0xff; 40], vec![5; 2]),
+        ]
+        .into();
+
+        for version in [StateVersion::V0, StateVersion::V1] {
+            let mut nodes = Vec::new();
+            let root = build_trie(&storage, version, &mut nodes);
+            assert_eq!(root, trie_root(&storage, version));
+
+            let proof = TrieProof::new(StorageProof { trie_nodes: nodes });
+            for (key, value) in &storage {
+                assert_eq!(proof.read(&root, key).unwrap().as_ref(), Some(value));
+            }
+            assert_eq!(proof.read(&root, b"abcd").unwrap(), None);
+        }
+
+        // Values of at least 33 bytes are hashed by the V1 layout.
+        assert_ne!(
+            trie_root(&storage, StateVersion::V0),
+            trie_root(&storage, StateVersion::V1)
+        );
+    }
+
+    /// Encode the trie of the node as a compact proof.
+    fn encode_compact(proof: &TrieProof, encoded: &[u8], output: &mut Vec<Vec<u8>>) {
+        let mut node = Node::decode(encoded).unwrap();
+        let value = match node.value {
+            Some(Value::Hashed(hash)) => {
+                node.value = Some(Value::Inline(&[]));
+                Some(proof.nodes[&hash].clone())
+            }
+            _ => None,
+        };
+
+        let mut omitted = Vec::new();
+        for child in node.children.iter_mut() {
+            if let Some(hash) = child.filter(|child| child.len() == BlockHash::len_bytes()) {
+                omitted.push(&proof.nodes[&BlockHash::from_slice(hash)]);
+                *child = Some(&[]);
+            }
+        }
+
+        let mut encoded = node.encode();
+        if let Some(value) = value {
+            encoded.insert(0, ESCAPE_COMPACT_HEADER);
+            output.extend([encoded, value]);
+        } else {
+            output.push(encoded);
+        }
+        for child in omitted {
+            encode_compact(proof, child, output);
+        }
+    }
+
+    fn storage() -> BTreeMap<Vec<u8>, Vec<u8>> {
+        [
+            (b"a".to_vec(), vec![1; 40]),
+            (b"ab".to_vec(), vec![2; 4]),
+            (b"abc".to_vec(), vec![3; 64]),
+            (b"abd".to_vec(), vec![4; 2]),
+            (b"b".to_vec(), vec![5; 32]),
+        ]
+        .into()
+    }
+
+    #[test]
+    fn decode_compact_proof_roundtrip() {
+        for version in [StateVersion::V0, StateVersion::V1] {
+            let mut nodes = Vec::new();
+            let root = build_trie(&storage(), version, &mut nodes);
+            let proof = TrieProof::new(StorageProof { trie_nodes: nodes });
+
+            let mut compact = Vec::new();
+            encode_compact(&proof, proof.node(&root).unwrap(), &mut compact);
+            // Every node and value is listed once, without the hashes of the children.
+            assert_eq!(compact.len(), proof.nodes.len());
+
+            let (decoded_root, decoded) = decode_compact_proof(&compact).unwrap();
+            assert_eq!(decoded_root, root);
+            assert_eq!(decoded.nodes, proof.nodes);
+        }
+
+        assert!(decode_compact_proof(&[]).is_err());
+        // The value of an escaped node is missing.
+        assert!(decode_compact_proof(&[vec![ESCAPE_COMPACT_HEADER, 0x41, 0xaa, 0x00]]).is_err());
+    }
+
+    #[test]
+    fn read_prefix_after_start() {
+        let storage = storage();
+        let mut nodes = Vec::new();
+        let root = build_trie(&storage, StateVersion::V1, &mut nodes);
+        let entry = |key: &[u8]| (key.to_vec(), storage[key].clone());
+
+        let proof = TrieProof::new(StorageProof {
+            trie_nodes: nodes.clone(),
+        });
+        assert_eq!(
+            proof.read_prefix(&root, b"ab", None).unwrap(),
+            (vec![entry(b"ab"), entry(b"abc"), entry(b"abd")], true)
+        );
+        // The start key is excluded.
+        assert_eq!(
+            proof.read_prefix(&root, b"ab", Some(b"ab")).unwrap(),
+            (vec![entry(b"abc"), entry(b"abd")], true)
+        );
+        assert_eq!(
+            proof.read_prefix(&root, b"", Some(b"abd")).unwrap(),
+            (vec![entry(b"b")], true)
+        );
+
+        // The reading stops at the value of `abc` missing from the proof.
+        let value = vec![3; 64];
+        nodes.retain(|node| *node != value);
+        let proof = TrieProof::new(StorageProof { trie_nodes: nodes });
+        assert_eq!(
+            proof.read_prefix(&root, b"a", None).unwrap(),
+            (vec![entry(b"a"), entry(b"ab")], false)
+        );
+    }
+
+    #[test]
+    fn read_polkadot_proof() {
+        // Storage proof of a polkadot block.
+        let proof = [
+            12, 17, 1, 158, 195, 101, 195, 207, 89, 214, 113, 235, 114, 218, 14, 122, 65, 19, 196,
+            0, 3, 88, 95, 7, 141, 67, 77, 97, 37, 180, 4, 67, 254, 17, 253, 41, 45, 19, 164, 16, 2,
+            0, 0, 0, 104, 95, 15, 31, 5, 21, 244, 98, 205, 207, 132, 224, 241, 214, 4, 93, 252,
+            187, 32, 80, 82, 127, 41, 119, 1, 0, 0, 185, 5, 128, 175, 188, 128, 15, 126, 137, 9,
+            189, 204, 29, 117, 244, 124, 194, 9, 181, 214, 119, 106, 91, 55, 85, 146, 101, 112, 37,
+            46, 31, 42, 133, 72, 101, 38, 60, 66, 128, 28, 186, 118, 76, 106, 111, 232, 204, 106,
+            88, 52, 218, 113, 2, 76, 119, 132, 172, 202, 215, 130, 198, 184, 230, 206, 134, 44,
+            171, 25, 86, 243, 121, 128, 233, 10, 145, 50, 95, 100, 17, 213, 147, 28, 9, 142, 56,
+            95, 33, 40, 56, 9, 39, 3, 193, 79, 169, 207, 115, 80, 61, 217, 4, 106, 172, 152, 128,
+            12, 255, 241, 157, 249, 219, 101, 33, 139, 178, 174, 121, 165, 33, 175, 0, 232, 230,
+            129, 23, 89, 219, 21, 35, 23, 48, 18, 153, 124, 96, 81, 66, 128, 30, 174, 194, 227,
+            100, 149, 97, 237, 23, 238, 114, 178, 106, 158, 238, 48, 166, 82, 19, 210, 129, 122,
+            70, 165, 94, 186, 31, 28, 80, 29, 73, 252, 128, 16, 56, 19, 158, 188, 178, 192, 234,
+            12, 251, 221, 107, 119, 243, 74, 155, 111, 53, 36, 107, 183, 204, 174, 253, 183, 67,
+            77, 199, 47, 121, 185, 162, 128, 17, 217, 226, 195, 240, 113, 144, 201, 129, 184, 240,
+            237, 204, 79, 68, 191, 165, 29, 219, 170, 152, 134, 160, 153, 245, 38, 181, 131, 83,
+            209, 245, 194, 128, 137, 217, 3, 84, 1, 224, 52, 199, 112, 213, 150, 42, 51, 214, 103,
+            194, 225, 224, 210, 84, 84, 53, 31, 159, 82, 201, 3, 104, 118, 212, 110, 7, 128, 240,
+            251, 81, 190, 126, 80, 60, 139, 88, 152, 39, 153, 231, 178, 31, 184, 56, 44, 133, 31,
+            47, 98, 234, 107, 15, 248, 64, 78, 36, 89, 9, 149, 128, 233, 75, 238, 120, 212, 149,
+            223, 135, 48, 174, 211, 219, 223, 217, 20, 172, 212, 172, 3, 234, 54, 130, 55, 225, 63,
+            17, 255, 217, 150, 252, 93, 15, 128, 89, 54, 254, 99, 202, 80, 50, 27, 92, 48, 57, 174,
+            8, 211, 44, 58, 108, 207, 129, 245, 129, 80, 170, 57, 130, 80, 166, 250, 214, 40, 156,
+            181, 21, 1, 128, 65, 0, 128, 182, 204, 71, 61, 83, 76, 85, 166, 19, 22, 212, 242, 236,
+            229, 51, 88, 16, 191, 227, 125, 217, 54, 7, 31, 36, 176, 211, 111, 72, 220, 181, 241,
+            128, 149, 2, 12, 26, 95, 9, 193, 115, 207, 253, 90, 218, 0, 41, 140, 119, 189, 166,
+            101, 244, 74, 171, 53, 248, 82, 113, 79, 110, 25, 72, 62, 65,
+        ];
+        let state_root = BlockHash::from([
+            43, 100, 198, 174, 1, 66, 26, 95, 93, 119, 43, 242, 5, 176, 153, 134, 193, 74, 159,
+            215, 134, 15, 252, 135, 67, 129, 21, 16, 20, 211, 97, 217,
+        ]);
+        let key = hex::decode("f0c365c3cf59d671eb72da0e7a4113c49f1f0515f462cdcf84e0f1d6045dfcbb")
+            .unwrap();
+
+        let proof = TrieProof::new(StorageProof::decode(&mut &proof[..]).unwrap());
+        assert!(proof.contains(&state_root));
+        assert_eq!(
+            proof.read(&state_root, &key).unwrap(),
+            Some(vec![80, 82, 127, 41, 119, 1, 0, 0])
+        );
+        // The value is not the root of a child trie.
+        assert!(matches!(
+            proof.read_child(&state_root, &key, &[]),
+            Err(ProofError::InvalidChildRoot(8))
+        ));
+    }
+}